@@ -1,3 +1,5 @@
+pub mod print;
+
 use types::{Span, ValType};
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -18,9 +20,8 @@ pub enum UnaryOperator {
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct Branch {
-    pub cond_left: LocatedExpression,
-    pub cond: types::CompareOperator,
-    pub cond_right: LocatedExpression,
+    // any boolean expression, usually a comparison
+    pub cond: LocatedExpression,
     pub val: LocatedExpression,
 }
 
@@ -78,6 +79,31 @@ pub enum Expression {
         val: Box<LocatedExpression>,
         ind: Box<LocatedExpression>,
     },
+    // `a > b`, a boolean
+    Comparison {
+        left: Box<LocatedExpression>,
+        op: types::CompareOperator,
+        right: Box<LocatedExpression>,
+    },
+    // `a < b < c`, a double inequality. Both operators point the same way.
+    DoubleComparison {
+        left: Box<LocatedExpression>,
+        left_op: types::CompareOperator,
+        middle: Box<LocatedExpression>,
+        right_op: types::CompareOperator,
+        right: Box<LocatedExpression>,
+    },
+    // `[n^2 for n = [1...10]]`, a list comprehension with a single generator
+    Comprehension {
+        body: Box<LocatedExpression>,
+        var: Spanned<String>,
+        list: Box<LocatedExpression>,
+    },
+    // `(x, y)`
+    Point {
+        x: Box<LocatedExpression>,
+        y: Box<LocatedExpression>,
+    },
 }
 
 pub type Spanned<T> = (Span, T);
@@ -115,11 +141,80 @@ pub enum Statement {
     FuncDef(FunctionDefinition, LocatedExpression),
     Expression(Expression),
     Import(Import),
+    // A text item in the expression list: `@note("...")`
+    Note(String),
+    // `@ticker(handler, min_step)` runs the handler on every tick of the graph
+    Ticker {
+        handler: LocatedExpression,
+        min_step: Option<LocatedExpression>,
+    },
+    // `with { color: "...", hidden: true } { ... }`
+    With(types::Style, LStatements),
 }
 
 pub type LocatedStatement = Spanned<Statement>;
 pub type LStatements = Vec<LocatedStatement>;
 
+impl Expression {
+    // The expressions directly inside this one, in source order
+    pub fn children(&self) -> Vec<&LocatedExpression> {
+        match self {
+            Expression::Error
+            | Expression::Num(_)
+            | Expression::Variable(_)
+            | Expression::RawLatex(..)
+            | Expression::FullyQualifiedVariable { .. } => vec![],
+            Expression::BinaryExpr { left, right, .. }
+            | Expression::Comparison { left, right, .. } => vec![left, right],
+            Expression::UnaryExpr { val, .. } | Expression::Map(val) => vec![val],
+            Expression::Call { args, .. } => args.iter().collect(),
+            Expression::List(items) => items.iter().collect(),
+            Expression::Range { first, second, end } => std::iter::once(first.as_ref())
+                .chain(second.as_deref())
+                .chain(std::iter::once(end.as_ref()))
+                .collect(),
+            Expression::Piecewise {
+                first,
+                rest,
+                default,
+            } => std::iter::once(first.as_ref())
+                .chain(rest.iter())
+                .flat_map(|(_, b)| [&b.cond, &b.val])
+                .chain(std::iter::once(default.as_ref()))
+                .collect(),
+            Expression::Index { val, ind } => vec![val, ind],
+            Expression::DoubleComparison {
+                left,
+                middle,
+                right,
+                ..
+            } => vec![left, middle, right],
+            Expression::Comprehension { body, list, .. } => vec![body, list],
+            Expression::Point { x, y } => vec![x, y],
+        }
+    }
+
+    // Whether parser recovery left an error node anywhere in the expression
+    pub fn has_error(&self) -> bool {
+        matches!(self, Expression::Error) || self.children().into_iter().any(|(_, e)| e.has_error())
+    }
+}
+
+impl Statement {
+    pub fn has_error(&self) -> bool {
+        match self {
+            Statement::VarDef { val: (_, e), .. }
+            | Statement::FuncDef(_, (_, e))
+            | Statement::Expression(e) => e.has_error(),
+            Statement::Import(_) | Statement::Note(_) => false,
+            Statement::Ticker { handler, min_step } => {
+                handler.1.has_error() || min_step.as_ref().is_some_and(|(_, e)| e.has_error())
+            }
+            Statement::With(_, stmts) => stmts.iter().any(|(_, s)| s.has_error()),
+        }
+    }
+}
+
 // Syntax-dependent formatting functions are defined here rather than in the compiler
 
 pub fn fmt_namespace(path: &Vec<String>) -> String {
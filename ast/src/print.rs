@@ -0,0 +1,256 @@
+use crate::{BinaryOperator, Expression, LocatedExpression, UnaryOperator};
+use types::{CompareOperator, ValType};
+
+// How tightly each level of the expression grammar binds, from loosest to tightest.
+//  An expression is parenthesized when its parent needs a tighter level.
+pub const PIECEWISE: u8 = 0;
+pub const COMPARISON: u8 = 1;
+pub const SUM: u8 = 2;
+pub const PRODUCT: u8 = 3;
+pub const MAP: u8 = 4;
+pub const NEGATE: u8 = 5;
+pub const IMPLICIT_MULT: u8 = 6;
+pub const POWER: u8 = 7;
+// postfix `!` and indexing
+pub const FACTORIAL: u8 = 8;
+pub const ATOM: u8 = 9;
+
+pub fn binop_str(op: BinaryOperator) -> &'static str {
+    match op {
+        BinaryOperator::Add => "+",
+        BinaryOperator::Subtract => "-",
+        BinaryOperator::Multiply => "*",
+        BinaryOperator::Divide => "/",
+        BinaryOperator::Mod => "%",
+        BinaryOperator::Exponent => "^",
+    }
+}
+
+pub fn compareop_str(op: CompareOperator) -> &'static str {
+    match op {
+        CompareOperator::Equal => "==",
+        CompareOperator::GreaterThan => ">",
+        CompareOperator::LessThan => "<",
+        CompareOperator::GreaterThanEqual => ">=",
+        CompareOperator::LessThanEqual => "<=",
+        CompareOperator::NotEqual => "!=",
+    }
+}
+
+pub fn type_str(t: ValType) -> &'static str {
+    match t {
+        ValType::Number => "num",
+        // point lists can't be written as annotations
+        ValType::List(_) => "list",
+        ValType::Point => "point",
+        ValType::Bool => "bool",
+        ValType::Polygon => "polygon",
+    }
+}
+
+// Turns expressions back into source, with only the parentheses the parser needs
+pub trait Printer {
+    // Products are printed with `*` unless this writes them another way
+    fn implicit_mult(&self, _e: &Expression) -> Option<String> {
+        None
+    }
+
+    fn expr(&self, e: &Expression, min: u8) -> String {
+        let (s, prec) = self.expr_prec(e);
+        if prec < min {
+            format!("({})", s)
+        } else {
+            s
+        }
+    }
+
+    fn list(&self, items: &[LocatedExpression]) -> String {
+        items
+            .iter()
+            .map(|(_, e)| self.expr(e, COMPARISON))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn expr_prec(&self, e: &Expression) -> (String, u8) {
+        match e {
+            Expression::Error => ("<error>".to_string(), ATOM),
+            Expression::Num(n) => (n.clone(), ATOM),
+            Expression::Variable(v) => (v.clone(), ATOM),
+            Expression::RawLatex(ValType::Number, l) => (format!("latex \"{}\"", l), PIECEWISE),
+            Expression::RawLatex(ValType::List(_), l) => {
+                (format!("latex_list \"{}\"", l), PIECEWISE)
+            }
+            // only `raw` can declare other types
+            Expression::RawLatex(t, l) => (format!("raw<{}>(\"{}\")", type_str(*t), l), ATOM),
+            Expression::FullyQualifiedVariable { path, item } => {
+                (format!("{}.{}", crate::fmt_namespace(path), item), ATOM)
+            }
+            Expression::BinaryExpr {
+                left,
+                operator,
+                right,
+            } => {
+                if let Some(s) = self.implicit_mult(e) {
+                    return (s, IMPLICIT_MULT);
+                }
+                let (prec, l, r) = match operator {
+                    BinaryOperator::Add | BinaryOperator::Subtract => (SUM, SUM, PRODUCT),
+                    BinaryOperator::Multiply | BinaryOperator::Divide | BinaryOperator::Mod => {
+                        (PRODUCT, PRODUCT, MAP)
+                    }
+                    // right associative
+                    BinaryOperator::Exponent => {
+                        return (
+                            format!(
+                                "{}^{}",
+                                self.expr(&left.1, FACTORIAL),
+                                self.expr(&right.1, NEGATE)
+                            ),
+                            POWER,
+                        )
+                    }
+                };
+                (
+                    format!(
+                        "{} {} {}",
+                        self.expr(&left.1, l),
+                        binop_str(*operator),
+                        self.expr(&right.1, r)
+                    ),
+                    prec,
+                )
+            }
+            Expression::UnaryExpr {
+                val,
+                operator: UnaryOperator::Negate,
+            } => {
+                let v = self.expr(&val.1, NEGATE);
+                let sep = if v.starts_with('-') { " " } else { "" };
+                (format!("-{}{}", sep, v), NEGATE)
+            }
+            Expression::UnaryExpr {
+                val,
+                operator: UnaryOperator::Factorial,
+            } => (format!("{}!", self.expr(&val.1, FACTORIAL)), FACTORIAL),
+            Expression::Map(v) => (format!("@{}", self.expr(&v.1, NEGATE)), MAP),
+            Expression::Call { func, args } => (
+                format!("{}({})", crate::func_name(func.clone()), self.list(args)),
+                ATOM,
+            ),
+            Expression::List(items) => (format!("[{}]", self.list(items)), ATOM),
+            Expression::Range { first, second, end } => (
+                match second {
+                    Some(second) => format!(
+                        "[{}, {}, ..., {}]",
+                        self.expr(&first.1, COMPARISON),
+                        self.expr(&second.1, COMPARISON),
+                        self.expr(&end.1, COMPARISON)
+                    ),
+                    None => format!(
+                        "[{}...{}]",
+                        self.expr(&first.1, COMPARISON),
+                        self.expr(&end.1, COMPARISON)
+                    ),
+                },
+                ATOM,
+            ),
+            Expression::Piecewise {
+                first,
+                rest,
+                default,
+            } => {
+                let branches = std::iter::once(first.as_ref())
+                    .chain(rest.iter())
+                    .map(|(_, b)| {
+                        format!(
+                            "{} -> {}",
+                            self.expr(&b.cond.1, COMPARISON),
+                            self.expr(&b.val.1, COMPARISON)
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                (
+                    format!(
+                        "where {}, else {}",
+                        branches.join(", "),
+                        self.expr(&default.1, COMPARISON)
+                    ),
+                    PIECEWISE,
+                )
+            }
+            Expression::Index { val, ind } => {
+                let ind = match &ind.1 {
+                    // `L[2...5]`
+                    Expression::Range {
+                        first,
+                        second: None,
+                        end,
+                    } => format!(
+                        "{}...{}",
+                        self.expr(&first.1, COMPARISON),
+                        self.expr(&end.1, COMPARISON)
+                    ),
+                    i => self.expr(i, COMPARISON),
+                };
+                (
+                    format!("{}[{}]", self.expr(&val.1, FACTORIAL), ind),
+                    FACTORIAL,
+                )
+            }
+            Expression::Comparison { left, op, right } => (
+                format!(
+                    "{} {} {}",
+                    self.expr(&left.1, SUM),
+                    compareop_str(*op),
+                    self.expr(&right.1, SUM)
+                ),
+                COMPARISON,
+            ),
+            Expression::DoubleComparison {
+                left,
+                left_op,
+                middle,
+                right_op,
+                right,
+            } => (
+                format!(
+                    "{} {} {} {} {}",
+                    self.expr(&left.1, SUM),
+                    compareop_str(*left_op),
+                    self.expr(&middle.1, SUM),
+                    compareop_str(*right_op),
+                    self.expr(&right.1, SUM)
+                ),
+                COMPARISON,
+            ),
+            Expression::Comprehension { body, var, list } => (
+                format!(
+                    "[{} for {} = {}]",
+                    self.expr(&body.1, COMPARISON),
+                    var.1,
+                    self.expr(&list.1, COMPARISON)
+                ),
+                ATOM,
+            ),
+            Expression::Point { x, y } => (
+                format!(
+                    "({}, {})",
+                    self.expr(&x.1, COMPARISON),
+                    self.expr(&y.1, COMPARISON)
+                ),
+                ATOM,
+            ),
+        }
+    }
+}
+
+struct Plain;
+
+impl Printer for Plain {}
+
+impl std::fmt::Display for Expression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", Plain.expr_prec(self).0)
+    }
+}
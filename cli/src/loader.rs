@@ -0,0 +1,203 @@
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::{print_parse_err_report, Sources, SrcFile};
+
+// Resolves local imports relative to a root directory, normally the one containing
+//  the file being compiled
+#[derive(Clone)]
+pub struct FilesystemLoader {
+    pub root: PathBuf,
+    // Shared with the caller, so errors in loaded files can be reported against them
+    pub sources: Rc<RefCell<Sources>>,
+}
+
+impl std::fmt::Debug for FilesystemLoader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FilesystemLoader")
+            .field("root", &self.root)
+            .finish()
+    }
+}
+
+impl FilesystemLoader {
+    pub fn new(root: impl Into<PathBuf>, sources: Rc<RefCell<Sources>>) -> Self {
+        Self {
+            root: root.into(),
+            sources,
+        }
+    }
+
+    // Each loaded file gets its own id, so spans in it don't point into the main file.
+    //  Parse errors are reported here instead of being turned into a module not found error.
+    fn parse_file(&self, name: String, source: &str) -> Option<compiler::LStatements> {
+        let mut sources = self.sources.borrow_mut();
+        let id = sources.files.insert(SrcFile {
+            name,
+            src: Rc::new(ariadne::Source::from(source)),
+        });
+        let (ast, errs) = parser::lex_and_parse(id, source.to_string());
+        if !errs.is_empty() {
+            print_parse_err_report(&mut sources, &errs);
+            return None;
+        }
+        ast
+    }
+}
+
+impl compiler::Loader for FilesystemLoader {
+    fn load(&self, path: &str) -> Option<compiler::LStatements> {
+        let path = self.root.join(path);
+        let source = std::fs::read_to_string(&path).ok()?;
+        self.parse_file(path.display().to_string(), &source)
+    }
+
+    fn parse_source(&self, source: &str) -> Option<compiler::LStatements> {
+        self.parse_file("<stdlib>".to_string(), source)
+    }
+
+    // The imports of a file are relative to its own directory
+    fn for_module(&self, path: &str) -> Box<dyn compiler::Loader> {
+        let file = self.root.join(path);
+        Box::new(Self {
+            root: file
+                .parent()
+                .unwrap_or_else(|| Path::new("."))
+                .to_path_buf(),
+            sources: self.sources.clone(),
+        })
+    }
+
+    fn resolve(&self, path: &str) -> String {
+        let path = self.root.join(path);
+        path.canonicalize().unwrap_or(path).display().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use compiler::{compile_stmts, Context, Loader};
+
+    fn loader(root: &Path) -> FilesystemLoader {
+        FilesystemLoader::new(root, Rc::new(RefCell::new(Sources::new())))
+    }
+
+    fn temp_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("desmosc-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        root
+    }
+
+    #[test]
+    fn import_from_root() {
+        let root = temp_root("import");
+        std::fs::write(root.join("consts.desmos"), "a = 1;\nb = 2;\n").unwrap();
+        let loader = loader(&root);
+        assert_eq!(loader.load("./consts.desmos").map(|ast| ast.len()), Some(2));
+        assert_eq!(loader.load("./missing.desmos"), None);
+
+        let main = "import consts from \"./consts.desmos\";\nc = consts.b;";
+        let ast = parser::lex_and_parse(0, main.to_string()).0.unwrap();
+        let out = compile_stmts(&mut Context::new_with_loader(Box::new(loader)), ast).unwrap();
+        assert_eq!(
+            out.into_iter()
+                .map(latex::latex_stmt_to_str)
+                .collect::<Vec<_>>(),
+            vec!["a=1", "b=2", "c=b"]
+        );
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn parse_errors() {
+        let root = temp_root("errors");
+        std::fs::write(root.join("broken.desmos"), "a = (1;\n").unwrap();
+        assert_eq!(loader(&root).load("./broken.desmos"), None);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn nested_imports() {
+        let root = temp_root("nested");
+        std::fs::create_dir_all(root.join("lib")).unwrap();
+        std::fs::write(root.join("lib/a"), "include \"./c\"\na = c").unwrap();
+        std::fs::write(root.join("lib/c"), "c = 1").unwrap();
+        let ast = parser::lex_and_parse(0, "include \"./lib/a\"".to_string())
+            .0
+            .unwrap();
+        let out =
+            compile_stmts(&mut Context::new_with_loader(Box::new(loader(&root))), ast).unwrap();
+        assert_eq!(
+            out.into_iter()
+                .map(latex::latex_stmt_to_str)
+                .collect::<Vec<_>>(),
+            vec!["c=1", "a=c"]
+        );
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn nested_cycles() {
+        let root = temp_root("cycles");
+        std::fs::create_dir_all(root.join("lib/lib")).unwrap();
+        // lib/x's `./lib/x` is lib/lib/x, a different file than the one the main file names
+        std::fs::write(root.join("lib/x"), "include \"./lib/x\"\na = b").unwrap();
+        std::fs::write(root.join("lib/lib/x"), "b = 1").unwrap();
+        let compile = |src: &str| {
+            let ast = parser::lex_and_parse(0, src.to_string()).0.unwrap();
+            compile_stmts(&mut Context::new_with_loader(Box::new(loader(&root))), ast)
+                .map(|out| out.into_iter().map(latex::latex_stmt_to_str).collect())
+                .map_err(|e| Box::new(e.kind))
+        };
+        assert_eq!(
+            compile("include \"./lib/x\""),
+            Ok(vec!["b=1".to_string(), "a=b".to_string()])
+        );
+
+        // a cycle written with different relative paths is caught where it closes
+        std::fs::write(root.join("a"), "include \"./lib/b\"").unwrap();
+        std::fs::write(root.join("lib/b"), "include \"../a\"").unwrap();
+        let a = root.join("a").canonicalize().unwrap().display().to_string();
+        let b = root
+            .join("lib/b")
+            .canonicalize()
+            .unwrap()
+            .display()
+            .to_string();
+        assert_eq!(
+            compile("include \"./a\""),
+            Err(Box::new(
+                compiler::error::CompileErrorKind::CircularImport {
+                    path: a.clone(),
+                    chain: vec![a.clone(), b, a],
+                }
+            ))
+        );
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn loaded_file_ids() {
+        let root = temp_root("ids");
+        std::fs::write(root.join("bad"), "q = zzz").unwrap();
+        let sources = Rc::new(RefCell::new(Sources::new()));
+        let main_id = sources.borrow_mut().files.insert(SrcFile {
+            name: "main".to_string(),
+            src: Rc::new(ariadne::Source::from("include \"./bad\"")),
+        });
+        let loader = FilesystemLoader::new(&root, sources.clone());
+        let ast = parser::lex_and_parse(main_id, "include \"./bad\"".to_string())
+            .0
+            .unwrap();
+        let err = compile_stmts(&mut Context::new_with_loader(Box::new(loader)), ast).unwrap_err();
+        // the error points into the imported file, not the main one
+        assert_ne!(err.span.file_id, main_id);
+        let sources = sources.borrow();
+        let file = &sources.files[err.span.file_id];
+        assert!(file.name.ends_with("bad"));
+        assert_eq!(err.span.range, 4..7);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -1,16 +1,28 @@
-use ariadne::{Color, Fmt, Label, Report, ReportKind};
+use ariadne::{Color, Label, Report, ReportKind};
 use clap::{App, Arg};
-use compiler::{compile_stmts, error::CompileError, Context};
-use std::fs::File;
-use std::io::prelude::*;
+use compiler::{
+    compile_stmts,
+    error::{CompileError, CompileWarning},
+    latex_stmts_to_graph, Context,
+};
+use loader::FilesystemLoader;
+use std::cell::RefCell;
+use std::io::Write;
+use std::path::Path;
 use std::rc::Rc;
+use std::time::Duration;
 use types::FileID;
 
+mod loader;
+mod watch;
+
 #[derive(Debug, Default)]
 pub struct EvalError {
     pub lex_errors: Vec<parser::LexErr>,
     pub parse_errors: Vec<parser::ParseErr>,
     pub compile_error: Option<CompileError>,
+    // warnings from the statements compiled before the error
+    pub warnings: Vec<CompileWarning>,
 }
 
 impl EvalError {
@@ -62,25 +74,13 @@ struct Flags {
     dump_errs: bool,
 }
 
-#[derive(Clone, Debug)]
-struct CliLoader;
-
-impl compiler::Loader for CliLoader {
-    fn load(&self, _path: &str) -> Option<compiler::LStatements> {
-        unimplemented!()
-    }
-
-    fn parse_source(&self, source: &str) -> Option<compiler::LStatements> {
-        parser::lex_and_parse(0, source.to_string()).0
-    }
-}
-
 fn try_eval(
     id: types::FileID,
     inp: &str,
     flags: &Flags,
+    loader: FilesystemLoader,
     mut out: impl std::io::Write + Sized,
-) -> Result<(), EvalError> {
+) -> Result<Vec<CompileWarning>, EvalError> {
     let mut err = EvalError {
         ..Default::default()
     };
@@ -104,14 +104,20 @@ fn try_eval(
         None => return Err(err),
         Some(ast) => ast,
     };
+    // a recovered AST would compile without its broken statements, so don't output it
+    if !err.is_empty() {
+        return Err(err);
+    }
 
     if flags.ast {
         eprintln!("{:#?}", ast);
     }
 
-    let ir = match compile_stmts(&mut Context::new_with_loader(Box::new(CliLoader)), ast) {
+    let mut ctx = Context::new_with_loader(Box::new(loader));
+    let ir = match compile_stmts(&mut ctx, ast) {
         Err(compile_error) => {
             err.compile_error = Some(compile_error);
+            err.warnings = ctx.warnings;
             return Err(err);
         }
         Ok(ir) => ir,
@@ -125,123 +131,61 @@ fn try_eval(
         return Err(err);
     }
 
-    let r = ir
-        .into_iter()
-        .map(|l| latex::latex_stmt_to_str(l))
-        .collect::<Vec<_>>();
-    Ok(match flags.output {
-        Output::Latex => write!(&mut out, "{}", r.join("\n")).unwrap(),
-        Output::State => serde_json::to_writer(
-            out,
-            &graph::CalcState {
-                expressions: graph::Expressions::from_latex_strings(r),
-                ..Default::default()
-            },
-        )
-        .unwrap(),
-    })
+    match flags.output {
+        Output::Latex => {
+            let r = ir
+                .into_iter()
+                .map(|l| latex::latex_stmt_to_str(l))
+                .collect::<Vec<_>>();
+            write!(&mut out, "{}", r.join("\n")).unwrap()
+        }
+        Output::State => {
+            let mut state = latex_stmts_to_graph(ir);
+            // the ticker is graph state rather than an expression, so it isn't in the IR
+            if let Some((handler, min_step)) = ctx.ticker {
+                state.expressions = state.expressions.with_ticker(handler, min_step);
+            }
+            serde_json::to_writer(out, &state).unwrap()
+        }
+    };
+    Ok(ctx.warnings)
 }
 
-// Format the error's input type as a String
-type GenericParseErr = chumsky::error::Simple<String, types::Span>;
+fn render_report(
+    sources: &mut Sources,
+    kind: ReportKind,
+    color: Color,
+    span: types::Span,
+    message: &str,
+) -> String {
+    let mut out = Vec::new();
+    Report::<types::Span>::build(kind, span.file_id, span.range.start)
+        .with_message(message)
+        .with_label(Label::new(span).with_color(color))
+        .finish()
+        .write(sources, &mut out)
+        .unwrap();
+    String::from_utf8(out).expect("reports are utf-8")
+}
 
-pub fn fmt_lex_errs<I>(lex_errs: I) -> impl IntoIterator<Item = GenericParseErr>
-where
-    I: IntoIterator<Item = parser::LexErr>,
-{
-    lex_errs.into_iter().map(|e| e.map(|c| format!("`{}`", c)))
+// The source line with the span underlined and the message, in color
+pub fn render_error(sources: &mut Sources, span: types::Span, message: &str) -> String {
+    render_report(sources, ReportKind::Error, Color::Red, span, message)
 }
 
-pub fn fmt_parse_errs<I>(parse_errs: I) -> impl IntoIterator<Item = GenericParseErr>
-where
-    I: IntoIterator<Item = parser::ParseErr>,
-{
-    parse_errs
-        .into_iter()
-        .map(|e| e.map(|c| c.to_str().to_string()))
+pub fn render_warning(sources: &mut Sources, span: types::Span, message: &str) -> String {
+    render_report(sources, ReportKind::Warning, Color::Yellow, span, message)
 }
 
-pub fn fmt_lexparse_errs(
-    lex_errs: Vec<parser::LexErr>,
-    parse_errs: Vec<parser::ParseErr>,
-) -> impl IntoIterator<Item = GenericParseErr> {
-    fmt_lex_errs(lex_errs)
-        .into_iter()
-        .chain(fmt_parse_errs(parse_errs))
+fn print_warnings(sources: &mut Sources, warnings: Vec<CompileWarning>) {
+    for w in warnings {
+        eprint!("{}", render_warning(sources, w.span, &w.kind.as_msg()));
+    }
 }
 
-pub fn print_parse_err_report<I>(sources: &mut Sources, errs: I)
-where
-    I: IntoIterator<Item = GenericParseErr>,
-{
-    // stack copy to avoid clones
-    let mut sources = sources;
-    for e in errs.into_iter() {
-        let report =
-            Report::<types::Span>::build(ReportKind::Error, e.span().file_id, e.span().range.start);
-        match e.reason() {
-            chumsky::error::SimpleReason::Unclosed { span, delimiter } => report
-                .with_message(format!(
-                    "Unclosed delimiter {}",
-                    delimiter.fg(Color::Yellow)
-                ))
-                .with_label(
-                    Label::new(span.clone())
-                        .with_message(format!(
-                            "Unclosed delimiter {}",
-                            delimiter.fg(Color::Yellow)
-                        ))
-                        .with_color(Color::Yellow),
-                )
-                .with_label(
-                    Label::new(e.span())
-                        .with_message(format!(
-                            "Must be closed before this {}",
-                            e.found()
-                                .unwrap_or(&"end of file".to_string())
-                                .fg(Color::Red)
-                        ))
-                        .with_color(Color::Red),
-                ),
-            chumsky::error::SimpleReason::Unexpected => report
-                .with_message(format!(
-                    "Unexpected {}, expected {}",
-                    if e.found().is_some() {
-                        "token in input"
-                    } else {
-                        "end of input"
-                    },
-                    if e.expected().len() == 0 {
-                        "something else".to_string()
-                    } else {
-                        e.expected()
-                            .map(|expected| match expected {
-                                Some(v) => v,
-                                None => "end of input",
-                            })
-                            .collect::<Vec<_>>()
-                            .join(", ")
-                    }
-                ))
-                .with_label(
-                    Label::new(e.span())
-                        .with_message(format!(
-                            "Unexpected {}",
-                            e.found()
-                                .unwrap_or(&"end of file".to_string())
-                                .fg(Color::Red)
-                        ))
-                        .with_color(Color::Red),
-                ),
-            chumsky::error::SimpleReason::Custom(msg) => report.with_message(msg).with_label(
-                Label::new(e.span())
-                    .with_message(format!("{}", msg.fg(Color::Red)))
-                    .with_color(Color::Red),
-            ),
-        }
-        .finish()
-        .eprint(&mut sources)
-        .unwrap();
+pub fn print_parse_err_report(sources: &mut Sources, errs: &parser::LexParseErrors) {
+    for (span, message) in errs.messages() {
+        eprint!("{}", render_error(sources, span, &message));
     }
 }
 
@@ -254,9 +198,9 @@ fn print_compile_error_report(sources: &mut Sources, err: CompileError) {
     .with_message(format!("{}", err.kind))
     .with_label(Label::new(err.span).with_color(Color::Red));
 
-    /*if let Some(help) = err.kind.help() {
+    if let Some(help) = err.kind.help().into_iter().next() {
         report.set_help(help);
-    }*/
+    }
     let mut colors = ariadne::ColorGenerator::new();
     err.kind.labels().into_iter().for_each(|(span, msg)| {
         report.add_label(Label::new(span).with_color(colors.next()).with_message(msg))
@@ -264,21 +208,37 @@ fn print_compile_error_report(sources: &mut Sources, err: CompileError) {
     report.finish().eprint(sources).unwrap();
 }
 
-fn process(name: String, inp: &str, flags: &Flags) -> i32 {
-    let mut sources = Sources::new();
-    let id = sources.files.insert(SrcFile {
+fn process(
+    name: String,
+    inp: &str,
+    flags: &Flags,
+    root: &Path,
+    out: impl std::io::Write + Sized,
+) -> i32 {
+    let sources = Rc::new(RefCell::new(Sources::new()));
+    let id = sources.borrow_mut().files.insert(SrcFile {
         name,
         src: Rc::new(ariadne::Source::from(inp)),
     });
-    match try_eval(id, inp, &flags, std::io::stdout()) {
-        Ok(()) => 0,
+    let loader = FilesystemLoader::new(root, sources.clone());
+    let result = try_eval(id, inp, &flags, loader, out);
+    let mut sources = sources.borrow_mut();
+    match result {
+        Ok(warnings) => {
+            print_warnings(&mut sources, warnings);
+            0
+        }
         Err(e) => {
             if flags.dump_errs {
                 eprintln!("{:#?}", e);
             }
+            print_warnings(&mut sources, e.warnings);
             print_parse_err_report(
                 &mut sources,
-                fmt_lexparse_errs(e.lex_errors, e.parse_errors),
+                &parser::LexParseErrors {
+                    lex_errors: e.lex_errors,
+                    parse_errors: e.parse_errors,
+                },
             );
             if let Some(compile_error) = e.compile_error {
                 print_compile_error_report(&mut sources, compile_error);
@@ -288,6 +248,50 @@ fn process(name: String, inp: &str, flags: &Flags) -> i32 {
     }
 }
 
+fn write_output(out: &[u8], path: Option<&str>) -> i32 {
+    let written = match path {
+        Some(path) => {
+            std::fs::write(path, out).map_err(|e| format!("Unable to write {}: {}", path, e))
+        }
+        None => std::io::stdout()
+            .write_all(out)
+            .and_then(|_| std::io::stdout().flush())
+            .map_err(|e| format!("Unable to write output: {}", e)),
+    };
+    match written {
+        Ok(()) => 0,
+        Err(msg) => {
+            eprintln!("error: {}", msg);
+            1
+        }
+    }
+}
+
+fn compile_file(filename: &str, flags: &Flags, out_path: Option<&str>) -> i32 {
+    let contents = match std::fs::read_to_string(filename) {
+        Ok(contents) => contents,
+        Err(e) => {
+            eprintln!("error: Unable to read {}: {}", filename, e);
+            return 1;
+        }
+    };
+    // imports are relative to the file doing the importing
+    let root = Path::new(filename)
+        .parent()
+        .unwrap_or_else(|| Path::new("."));
+    let mut out = Vec::new();
+    match process(
+        filename.to_string(),
+        contents.as_str(),
+        flags,
+        root,
+        &mut out,
+    ) {
+        0 => write_output(&out, out_path),
+        code => code,
+    }
+}
+
 fn main() {
     let app = App::new("desmosc")
         .version("0.1")
@@ -309,6 +313,26 @@ fn main() {
                 .takes_value(true)
                 .conflicts_with("eval"),
         )
+        .arg(
+            Arg::with_name("input")
+                .help("Compile code from a file, same as --file")
+                .index(1)
+                .conflicts_with_all(&["eval", "file"]),
+        )
+        .arg(
+            Arg::with_name("out")
+                .short("o")
+                .long("out")
+                .help("Write the output to a file instead of stdout")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("watch")
+                .short("w")
+                .long("watch")
+                .help("Recompile the input file whenever it changes")
+                .conflicts_with("eval"),
+        )
         .arg(Arg::with_name("tokens").long("tokens").help("Dump tokens"))
         .arg(
             Arg::with_name("token spans")
@@ -349,17 +373,101 @@ fn main() {
         dump_errs: matches.is_present("dump errors"),
     };
 
-    let exit_code = if let Some(input) = matches.value_of("eval") {
-        process("<string>".to_string(), input, &flags)
-    } else if let Some(filename) = matches.value_of("file") {
-        // TODO: Better error handling here?
-        let mut file = File::open(filename).expect("Unable to read input");
-        let mut contents = String::new();
-        file.read_to_string(&mut contents)
-            .expect("Unable to decode file contents");
-        process(filename.to_string(), contents.as_str(), &flags)
-    } else {
-        unimplemented!("REPL/pipe unimplemented")
-    };
+    let out_path = matches.value_of("out");
+    // compiling recurses as deep as the tree, which a long chain like `1+1+...` makes deep
+    let exit_code = parser::with_large_stack(|| {
+        if let Some(input) = matches.value_of("eval") {
+            // buffered so that a failed compile doesn't leave an empty output file
+            let mut out = Vec::new();
+            match process(
+                "<string>".to_string(),
+                input,
+                &flags,
+                Path::new("."),
+                &mut out,
+            ) {
+                0 => write_output(&out, out_path),
+                code => code,
+            }
+        } else if let Some(filename) = matches
+            .value_of("file")
+            .or_else(|| matches.value_of("input"))
+        {
+            let exit_code = compile_file(filename, &flags, out_path);
+            if matches.is_present("watch") {
+                let delay = Duration::from_millis(100);
+                let watched = watch::watch(Path::new(filename), delay, || {
+                    // keep each output on its own line
+                    if out_path.is_none() {
+                        println!();
+                    }
+                    compile_file(filename, &flags, out_path);
+                });
+                if let Err(e) = watched {
+                    eprintln!("error: Unable to watch {}: {}", filename, e);
+                    std::process::exit(1)
+                }
+            }
+            exit_code
+        } else {
+            unimplemented!("REPL/pipe unimplemented")
+        }
+    });
     std::process::exit(exit_code)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Drops ANSI color codes
+    fn strip_colors(s: &str) -> String {
+        let mut out = String::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                chars.by_ref().take_while(|c| *c != 'm').for_each(drop);
+            } else {
+                out.push(c);
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn render() {
+        let src = "a = 1\nb = a + c\n";
+        let mut sources = Sources::new();
+        let id = sources.files.insert(SrcFile {
+            name: "main.desmos".to_string(),
+            src: Rc::new(ariadne::Source::from(src)),
+        });
+        let rendered = strip_colors(&render_error(
+            &mut sources,
+            types::Span::new(id, 14..15),
+            "Undefined variable 'c'",
+        ));
+        assert!(rendered.contains("Error: Undefined variable 'c'"));
+        assert!(rendered.contains("main.desmos:2:9"));
+        assert!(rendered.contains("b = a + c"));
+        assert!(!rendered.contains("a = 1"));
+
+        // each error gets its own snippet
+        let src = "a = $\nb = 1\nc = $";
+        let id = sources.files.insert(SrcFile {
+            name: "lex.desmos".to_string(),
+            src: Rc::new(ariadne::Source::from(src)),
+        });
+        let errs = parser::lex_and_parse(id, src.to_string()).1;
+        let rendered = errs
+            .messages()
+            .into_iter()
+            .map(|(span, msg)| strip_colors(&render_error(&mut sources, span, &msg)))
+            .collect::<Vec<_>>();
+        assert_eq!(rendered.len(), 2);
+        assert!(rendered[0].contains("lex.desmos:1:5"));
+        assert!(rendered[0].contains("a = $"));
+        assert!(rendered[1].contains("lex.desmos:3:5"));
+        assert!(rendered[1].contains("c = $"));
+    }
+}
@@ -0,0 +1,86 @@
+use std::path::Path;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+use notify::{RawEvent, RecursiveMode, Watcher};
+
+// Editors often write a file several times per save, so changes are reported once no
+//  relevant event has arrived for `delay`. Returns false once the sender is gone.
+fn wait_for_change<T>(rx: &Receiver<T>, delay: Duration, relevant: impl Fn(&T) -> bool) -> bool {
+    loop {
+        match rx.recv() {
+            Ok(event) if relevant(&event) => break,
+            Ok(_) => continue,
+            Err(_) => return false,
+        }
+    }
+    loop {
+        match rx.recv_timeout(delay) {
+            Ok(_) => continue,
+            Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => return true,
+        }
+    }
+}
+
+fn on_changes<T>(
+    rx: &Receiver<T>,
+    delay: Duration,
+    relevant: impl Fn(&T) -> bool,
+    mut on_change: impl FnMut(),
+) {
+    while wait_for_change(rx, delay, &relevant) {
+        on_change()
+    }
+}
+
+// Calls on_change after each save of the file, until watching fails
+pub fn watch(path: &Path, delay: Duration, on_change: impl FnMut()) -> notify::Result<()> {
+    let path = path.canonicalize()?;
+    let (tx, rx) = channel();
+    let mut watcher = notify::raw_watcher(tx)?;
+    // Saving can replace the file, which would end a watch on the file itself
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    watcher.watch(dir, RecursiveMode::NonRecursive)?;
+    on_changes(
+        &rx,
+        delay,
+        |e: &RawEvent| e.path.as_deref() == Some(path.as_path()),
+        on_change,
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debounce() {
+        let (tx, rx) = channel();
+        // a burst of writes to the file and one to another file
+        for event in ["file", "file", "other", "file"] {
+            tx.send(event).unwrap();
+        }
+        drop(tx);
+        let mut recompiles = 0;
+        on_changes(
+            &rx,
+            Duration::from_millis(10),
+            |e| *e == "file",
+            || recompiles += 1,
+        );
+        assert_eq!(recompiles, 1);
+
+        let (tx, rx) = channel();
+        tx.send("other").unwrap();
+        drop(tx);
+        let mut recompiles = 0;
+        on_changes(
+            &rx,
+            Duration::from_millis(10),
+            |e| *e == "file",
+            || recompiles += 1,
+        );
+        assert_eq!(recompiles, 0);
+    }
+}
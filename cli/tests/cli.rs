@@ -0,0 +1,100 @@
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+fn fixture(name: &str) -> String {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+        .display()
+        .to_string()
+}
+
+fn desmosc(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_desmosc"))
+        .args(args)
+        .output()
+        .expect("desmosc should run")
+}
+
+fn expression_latex(json: &[u8]) -> Vec<String> {
+    let state: serde_json::Value = serde_json::from_slice(json).expect("output should be JSON");
+    state["expressions"]["list"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["latex"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[test]
+fn compile_file() {
+    let out = desmosc(&[&fixture("graph.desmos")]);
+    assert!(out.status.success());
+    assert_eq!(
+        expression_latex(&out.stdout),
+        vec!["a=1", "f\\left(x\\right)=((x)^{2})+a"]
+    );
+}
+
+#[test]
+fn compile_to_file() {
+    let path = std::env::temp_dir().join(format!("desmosc-test-{}.json", std::process::id()));
+    let out = desmosc(&[&fixture("graph.desmos"), "-o", path.to_str().unwrap()]);
+    assert!(out.status.success());
+    assert!(out.stdout.is_empty());
+    let json = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(expression_latex(&json).len(), 2);
+}
+
+#[test]
+fn ticker() {
+    let out = desmosc(&[&fixture("ticker.desmos")]);
+    assert!(out.status.success());
+    let state: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(state["expressions"]["ticker"]["handlerLatex"], "a\\to a+1");
+    assert_eq!(state["expressions"]["ticker"]["minStepLatex"], "100");
+    assert_eq!(expression_latex(&out.stdout), vec!["a=0"]);
+}
+
+#[test]
+fn warnings() {
+    let path = fixture("shadows.desmos");
+    let out = desmosc(&[&path]);
+    // warnings are reported but still produce output
+    assert!(out.status.success());
+    assert_eq!(expression_latex(&out.stdout).len(), 2);
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(stderr.contains("Warning"));
+    assert!(stderr.contains("Function 'sin' shadows the builtin 'sin'"));
+    assert!(stderr.contains(&format!("{}:1:1", path)));
+}
+
+#[test]
+fn list_length_warning() {
+    let path = fixture("list_lengths.desmos");
+    let out = desmosc(&[&path]);
+    assert!(out.status.success());
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(stderr
+        .contains("Combining lists of length 2 and 3 drops the extra elements of the longer one"));
+    assert!(stderr.contains(&format!("{}:1:5", path)));
+}
+
+#[test]
+fn errors() {
+    let path = fixture("undefined.desmos");
+    let out = desmosc(&["--file", &path]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(out.stdout.is_empty());
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(stderr.contains("Undefined variable 'c'"));
+    // the line and column of the undefined variable
+    assert!(stderr.contains(&format!("{}:2:9", path)));
+
+    let out = desmosc(&[&fixture("missing.desmos")]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(String::from_utf8(out.stderr)
+        .unwrap()
+        .contains("Unable to read"));
+}
@@ -0,0 +1,24 @@
+use compiler::{stmts_to_graph, Context};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+
+// A large program, a definition and many expressions that use it
+fn program(lines: usize) -> String {
+    let uses = (0..lines)
+        .map(|i| format!("sin(a) + {}\n", i))
+        .collect::<String>();
+    format!("a = 1\n{}", uses)
+}
+
+fn bench_stmts_to_graph(c: &mut Criterion) {
+    let ast = parser::lex_and_parse(0, program(10_000)).0.unwrap();
+    c.bench_function("stmts_to_graph 10000 statements", |b| {
+        b.iter_batched(
+            || ast.clone(),
+            |ast| stmts_to_graph(&mut Context::new(), ast).unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+criterion_group!(benches, bench_stmts_to_graph);
+criterion_main!(benches);
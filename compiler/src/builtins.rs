@@ -1,6 +1,6 @@
 use phf::{phf_map, Map};
 use types::{
-    Args, Function,
+    Args, ElemType, Function, ValType,
     ValType::{List, Number as Num},
 };
 
@@ -25,18 +25,29 @@ macro_rules! nn {
     };
 }
 
+// Reductions of lists of numbers, or of the given ElemType like `l!(Point)`
 macro_rules! l {
     () => {
-        f!(&[List], Num)
+        l!(Number)
+    };
+    ($elem:ident) => {
+        f!(&[List(ElemType::$elem)], Num)
     };
 }
 
 macro_rules! ll {
     () => {
-        f!(&[List, List], Num)
+        ll!(Number)
+    };
+    ($elem:ident) => {
+        f!(&[List(ElemType::$elem), List(ElemType::$elem)], Num)
     };
 }
 
+// `sum(n, 1, 10, n^2)`, `prod` and `integral` bind their first argument in their last, so
+//  they are compiled separately instead of having a signature in BUILTIN_FUNCTIONS
+pub static BINDING_FUNCTIONS: [&str; 3] = ["sum", "prod", "integral"];
+
 // Map of desmos builtin functions.
 // Source: https://support.desmos.com/hc/en-us/articles/212235786-Supported-Functions
 pub static BUILTIN_FUNCTIONS: Map<&'static str, Function> = phf_map! {
@@ -64,8 +75,15 @@ pub static BUILTIN_FUNCTIONS: Map<&'static str, Function> = phf_map! {
 
     // Statistics
     "total" => l!(),
-    "min" => l!(),
-    "max" => l!(),
+    // like lcm and gcd, these take either one list or any number of numbers
+    "min" => Function {
+        args: Args::Variadic(ElemType::Number),
+        ret: Num,
+    },
+    "max" => Function {
+        args: Args::Variadic(ElemType::Number),
+        ret: Num,
+    },
     "length" => l!(),
     "mean" => l!(),
     "median" => l!(),
@@ -77,7 +95,7 @@ pub static BUILTIN_FUNCTIONS: Map<&'static str, Function> = phf_map! {
 
     "corr" => ll!(),
 
-    "quantile" => f!(&[List, Num], Num),
+    "quantile" => f!(&[List(ElemType::Number), Num], Num),
 
     "nCr" => nn!(),
     "nPr" => nn!(),
@@ -89,11 +107,11 @@ pub static BUILTIN_FUNCTIONS: Map<&'static str, Function> = phf_map! {
     "shuffle" => l!(),
 
     "lcm" => Function {
-        args: Args::Variadic,
+        args: Args::Variadic(ElemType::Number),
         ret: Num,
     },
     "gcd" => Function {
-        args: Args::Variadic,
+        args: Args::Variadic(ElemType::Number),
         ret: Num,
     },
 
@@ -109,16 +127,25 @@ pub static BUILTIN_FUNCTIONS: Map<&'static str, Function> = phf_map! {
 
     // supported through a special case in IR output
     "sqrt" => n!(),
+    // nthroot(x, n) is the nth root of x, matching the order of \sqrt[n]{x}
     "nthroot" => nn!(),
 
-    // TODO: Support integral
-    // TODO: Support sum
-    // TODO: Support prod
-    // theses are going to be pretty hard as they require
-    //  adding variables to scope based on presence of d$var
+    // sum, prod and integral bind a variable, so they're compiled by
+    //  call::compile_binding_call
+
+    // polygon((0, 0), (1, 0), (1, 1)) or polygon(P)
+    "polygon" => Function {
+        args: Args::Variadic(ElemType::Point),
+        ret: ValType::Polygon,
+    },
 
-    // TODO: Support for optional arguments.
-    // Round takes either one or two arguments
-    "round" => n!(),
+    // round(x, n) rounds to n decimal places
+    "round" => Function {
+        args: Args::Optional {
+            args: &[Num, Num],
+            required: 1,
+        },
+        ret: Num,
+    },
 
 };
@@ -1,14 +1,13 @@
 use ast;
 use latex::{self, Latex};
-use std::{collections::HashMap, rc::Rc};
-use types::ValType;
+use std::{collections::HashMap, convert::TryInto, rc::Rc};
+use types::{ElemType, ValType};
 
 use crate::{
     builtins,
-    error::{CompileError, CompileErrorKind, ExpectedArgCount},
-    types::{
-        combine_types, Context, FunctionArgs, FunctionSignature, ResolvedFunction, Typ, TypInfo,
-    },
+    compiler::{comp_expect, compile_expr},
+    error::{CompileError, CompileErrorKind, CompileWarning, CompileWarningKind, ExpectedArgCount},
+    types::{Context, FunctionArgs, FunctionSignature, ResolvedFunction, Typ, TypInfo},
 };
 
 pub fn func_to_latex(func: ast::Function) -> latex::Function {
@@ -29,6 +28,7 @@ pub fn resolve_function<'a>(
             return Some(ResolvedFunction::Normal {
                 func: Rc::new(FunctionSignature {
                     args: FunctionArgs::Static(vec![types::ValType::Number]),
+                    arg_names: vec![],
                     ret: (ValType::Number, TypInfo::Builtin(span, func)),
                 }),
                 is_builtin: true,
@@ -50,8 +50,13 @@ pub fn resolve_function<'a>(
             func: Rc::new(FunctionSignature {
                 args: match f.args {
                     types::Args::Static(args) => FunctionArgs::Static(args.to_vec()),
-                    types::Args::Variadic => FunctionArgs::Variadic,
+                    types::Args::Optional { args, required } => FunctionArgs::Optional {
+                        args: args.to_vec(),
+                        required,
+                    },
+                    types::Args::Variadic(elem) => FunctionArgs::Variadic(elem),
                 },
+                arg_names: vec![],
                 ret: (
                     f.ret.into(),
                     TypInfo::Builtin(span, ast::Function::Normal { name }),
@@ -63,6 +68,41 @@ pub fn resolve_function<'a>(
     None
 }
 
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let sub = prev[j] + if ca == *cb { 0 } else { 1 };
+            cur.push(sub.min(prev[j + 1] + 1).min(cur[j] + 1));
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+// The closest known function name to a misspelled one. Ties prefer names with the same
+//  first letter, so `tn` suggests `tan` rather than `ln`.
+pub fn suggest_function(ctx: &Context, name: &str) -> Option<String> {
+    let max_distance = name.len() / 3 + 1;
+    builtins::BUILTIN_FUNCTIONS
+        .keys()
+        .copied()
+        .chain(ctx.defined_functions.keys().map(String::as_str))
+        .chain(ctx.inline_fns.keys().map(String::as_str))
+        .map(|candidate| {
+            (
+                edit_distance(name, candidate),
+                candidate.chars().next() != name.chars().next(),
+                candidate,
+            )
+        })
+        .filter(|(d, _, _)| *d <= max_distance)
+        .min()
+        .map(|(_, _, candidate)| candidate.to_string())
+}
+
 fn check_arg_types(
     args: Vec<(types::Span, latex::Latex, Typ, TypInfo)>,
     rargs: &Vec<types::ValType>,
@@ -79,8 +119,9 @@ fn check_arg_types(
     let (args_latex, args_types) = args
         .into_iter()
         .zip(rargs.iter())
+        .enumerate()
         .map(
-            |(got_type, expect_type)| -> Result<(latex::Latex, (types::Span, Typ, TypInfo)), _> {
+            |(i, (got_type, expect_type))| -> Result<(latex::Latex, (types::Span, Typ, TypInfo)), _> {
                 let (aspan, arg_latex, gt, gi) = got_type;
                 let et = (*expect_type).into();
                 if !gt.eq_weak(et) {
@@ -88,6 +129,7 @@ fn check_arg_types(
                         kind: CompileErrorKind::ArgTypeMismatch {
                             got: (gt, gi),
                             expected: *expect_type,
+                            index: Some(i),
                         },
                         span: aspan,
                     });
@@ -105,7 +147,7 @@ fn check_arg_types(
         .unzip();
 
     // Map does not occur if function returns a list anyway
-    let mapped_arg = if ret == ValType::List {
+    let mapped_arg = if matches!(ret, ValType::List(_)) {
         None
     } else {
         mapped_arg
@@ -118,11 +160,17 @@ where
     I: IntoIterator<Item = Typ>,
 {
     let ret: Typ = ret.into();
-    args_types
-        .into_iter()
-        .chain(std::iter::once(ret))
-        .reduce(|l, r| combine_types(l, r))
-        .unwrap_or(ret)
+    let args_types: Vec<Typ> = args_types.into_iter().collect();
+    // an explicitly mapped argument keeps the result mapped, so it can't be mixed
+    //  with a plain list later on
+    if ret == Typ::Num && args_types.contains(&Typ::MappedList) {
+        return Typ::MappedList;
+    }
+    // passing a list for a number maps the call over the list
+    if args_types.into_iter().any(Typ::is_list_weak) {
+        return ret.list_of().unwrap_or(ret);
+    }
+    ret
 }
 
 pub fn compile_static_call(
@@ -131,18 +179,23 @@ pub fn compile_static_call(
     args: Vec<(types::Span, latex::Latex, Typ, TypInfo)>,
     rfunc: FunctionSignature,
     rargs: &Vec<types::ValType>,
+    required: usize,
     is_builtin: bool,
 ) -> Result<(latex::Latex, Typ, TypInfo), CompileError> {
     // Validate arg count
     {
         let got = args.len();
-        let expect = rargs.len();
+        let max = rargs.len();
 
-        if got != expect {
+        if got < required || got > max {
             return Err(CompileError {
                 kind: CompileErrorKind::WrongArgCount {
                     got,
-                    expected: ExpectedArgCount::Exact(expect),
+                    expected: if required == max {
+                        ExpectedArgCount::Exact(max)
+                    } else {
+                        ExpectedArgCount::Between(required, max)
+                    },
                 },
                 span,
             });
@@ -150,7 +203,14 @@ pub fn compile_static_call(
     }
 
     let (args_latex, args_types, mapped_arg) = check_arg_types(args, rargs, rfunc.ret.0)?;
-    let rt = ret_type(args_types.into_iter().map(|i| i.1), rfunc.ret.0);
+    // a list passed for a list parameter, like in `total(L)`, doesn't map the call
+    let mapped_types = args_types
+        .into_iter()
+        .zip(rargs)
+        .map(|((_, t, _), expected)| (t, Typ::from(*expected)))
+        .filter(|(t, expected)| t != expected)
+        .map(|(t, _)| t);
+    let rt = ret_type(mapped_types, rfunc.ret.0);
     let ri = match mapped_arg {
         Some(mapped_arg) => TypInfo::MappedCall {
             call_span: span,
@@ -176,40 +236,68 @@ pub fn compile_static_call(
 
 fn variadic_call_types(
     args: Vec<(types::Span, latex::Latex, Typ, TypInfo)>,
+    elem: ElemType,
     rfunc: FunctionSignature,
 ) -> Result<(Vec<Latex>, Typ, TypInfo), CompileError> {
     if args.len() == 1 {
         let first = args.first().unwrap();
-        if first.2.is_list_weak() {
+        let is_list = match elem {
+            ElemType::Number => first.2.is_list_weak(),
+            ElemType::Point => first.2 == Typ::List(ElemType::Point),
+        };
+        if is_list {
             let (rt, ri) = rfunc.ret;
             return Ok((vec![first.1.clone()], rt.into(), ri));
         }
     }
-    todo!();
-    /*let (args_latex, args_types) = args
-        .into_iter()
-        .map(
-            |(span, latex, t, ti)| -> Result<(Latex, (types::Span, Typ, TypInfo)), CompileError> {
-                if !t.is_num_weak() {
-                    return Err(CompileError {
-                        span,
-                        kind: CompileErrorKind::VariadicList,
-                    });
-                }
-                Ok((latex, (span, t, ti)))
-            },
-        )
-        .collect::<Result<Vec<_>, CompileError>>()?
-        .into_iter()
-        .unzip();
-    let (rt, ri) = resolve_func_type(args_types, rfunc.ret);
-    Ok((args_latex, rt, ri))*/
+    let (rt, ri) = rfunc.ret;
+    match elem {
+        ElemType::Number => {
+            let (args_latex, args_types): (Vec<_>, Vec<_>) = args
+                .into_iter()
+                .map(|(span, latex, t, _)| {
+                    if !t.is_num_weak() {
+                        return Err(CompileError {
+                            span,
+                            kind: CompileErrorKind::VariadicList,
+                        });
+                    }
+                    Ok((latex, t))
+                })
+                .collect::<Result<Vec<_>, CompileError>>()?
+                .into_iter()
+                .unzip();
+            Ok((args_latex, ret_type(args_types, rt), ri))
+        }
+        // points aren't mapped over, so each one is a vertex
+        ElemType::Point => {
+            let args_latex = args
+                .into_iter()
+                .enumerate()
+                .map(|(i, (aspan, latex, t, ti))| {
+                    if t != Typ::Point {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::ArgTypeMismatch {
+                                got: (t, ti),
+                                expected: ValType::Point,
+                                index: Some(i),
+                            },
+                            span: aspan,
+                        });
+                    }
+                    Ok(latex)
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok((args_latex, rt.into(), ri))
+        }
+    }
 }
 
 pub fn compile_variadic_call(
     span: types::Span,
     func: ast::Function,
     args: Vec<(types::Span, latex::Latex, Typ, TypInfo)>,
+    elem: ElemType,
     rfunc: FunctionSignature,
     is_builtin: bool,
 ) -> Result<(latex::Latex, Typ, TypInfo), CompileError> {
@@ -224,7 +312,7 @@ pub fn compile_variadic_call(
     }
     // if variadic functions are passed a single list, they will return a single value
     // if passed multiple lists, normal automatic mapping applies
-    let (args_latex, ret, ri) = variadic_call_types(args, rfunc)?;
+    let (args_latex, ret, ri) = variadic_call_types(args, elem, rfunc)?;
     Ok((
         latex::Latex::Call {
             func: func_to_latex(func),
@@ -250,6 +338,7 @@ where
         left: proc(c.left),
         op: c.op,
         right: proc(c.right),
+        chain: c.chain.map(|(op, l)| (op, proc(l))),
         result: proc(c.result),
     };
     match node {
@@ -293,6 +382,60 @@ where
             default: Box::new(proc(*default)),
         },
         Latex::Raw(l) => Latex::Raw(l),
+        // TODO: var shadows the replaced names inside body
+        Latex::Summation {
+            kind,
+            var,
+            lower,
+            upper,
+            body,
+        } => Latex::Summation {
+            kind,
+            var,
+            lower: Box::new(proc(*lower)),
+            upper: Box::new(proc(*upper)),
+            body: Box::new(proc(*body)),
+        },
+        // TODO: var shadows the replaced names inside body
+        Latex::Comprehension { body, var, list } => Latex::Comprehension {
+            body: Box::new(proc(*body)),
+            var,
+            list: Box::new(proc(*list)),
+        },
+        Latex::Integral {
+            var,
+            lower,
+            upper,
+            body,
+        } => Latex::Integral {
+            var,
+            lower: Box::new(proc(*lower)),
+            upper: Box::new(proc(*upper)),
+            body: Box::new(proc(*body)),
+        },
+        Latex::Point { x, y } => Latex::Point {
+            x: Box::new(proc(*x)),
+            y: Box::new(proc(*y)),
+        },
+        Latex::Group(inner) => Latex::Group(Box::new(proc(*inner))),
+        Latex::Spanned(span, inner) => Latex::Spanned(span, Box::new(proc(*inner))),
+    }
+}
+
+// Wraps l in a group unless it can't be split up by the operators around it, so that
+//  substituting `1-2` for x in `-x` gives `-(1-2)` instead of `-1-2`
+fn group(l: Latex) -> Latex {
+    match l.unspanned() {
+        Latex::Num(n) if !n.starts_with('-') => l,
+        Latex::Variable(_)
+        | Latex::Call { .. }
+        | Latex::List(_)
+        | Latex::Range { .. }
+        | Latex::Piecewise { .. }
+        | Latex::Point { .. }
+        | Latex::Comprehension { .. }
+        | Latex::Group(_) => l,
+        _ => Latex::Group(Box::new(l)),
     }
 }
 
@@ -303,16 +446,151 @@ pub fn replace_variables(node: Latex, vars: &HashMap<String, Latex>) -> Latex {
     })
 }
 
+// nthroot(x, 0) and nthroot(x, 1) are valid but almost certainly a mistake
+fn check_root_degree(
+    ctx: &mut Context,
+    func: &ast::Function,
+    args: &[(types::Span, Latex, Typ, TypInfo)],
+) {
+    if let ast::Function::Normal { name } = func {
+        if name != "nthroot" || args.len() != 2 {
+            return;
+        }
+        let (span, degree, _, _) = &args[1];
+        if let Latex::Num(n) = degree.unspanned() {
+            if n == "0" || n == "1" {
+                ctx.warnings.push(CompileWarning {
+                    kind: CompileWarningKind::DegenerateRootDegree(n.clone()),
+                    span: span.clone(),
+                });
+            }
+        }
+    }
+}
+
+// sum(n, 1, 10, n^2) and integral(x, 0, 1, x^2) bind a variable while compiling their body,
+//  so they can't be compiled like a normal call
+pub fn compile_binding_call(
+    ctx: &mut Context,
+    span: types::Span,
+    name: String,
+    args: Vec<ast::LocatedExpression>,
+) -> Result<(latex::Latex, Typ, TypInfo), CompileError> {
+    let [var, lower, upper, body]: [ast::LocatedExpression; 4] =
+        args.try_into().map_err(|args: Vec<_>| CompileError {
+            kind: CompileErrorKind::WrongArgCount {
+                got: args.len(),
+                expected: ExpectedArgCount::Exact(4),
+            },
+            span: span.clone(),
+        })?;
+    let (var_span, var) = match var {
+        (s, ast::Expression::Variable(v)) => (s, v),
+        (s, _) => {
+            return Err(CompileError {
+                kind: CompileErrorKind::BoundVariableNotIdentifier,
+                span: s,
+            })
+        }
+    };
+    let bound = |ctx: &mut Context, e| {
+        comp_expect(
+            ctx,
+            e,
+            |t| t == Typ::Num,
+            |t, ti| CompileErrorKind::ArgTypeMismatch {
+                got: (t, ti),
+                expected: ValType::Number,
+                index: None,
+            },
+        )
+    };
+    let (lower, _, _) = bound(ctx, lower)?;
+    let (upper, _, _) = bound(ctx, upper)?;
+
+    ctx.push_scope(vec![(
+        var.clone(),
+        (ValType::Number, TypInfo::InlineFuncArg(var_span)),
+    )]);
+    let body_span = body.0.clone();
+    let body = compile_expr(ctx, body);
+    ctx.pop_scope();
+    let (body, bt, bi) = body?;
+    let (lower, upper, body) = (Box::new(lower), Box::new(upper), Box::new(body));
+
+    let (latex, t) = match name.as_str() {
+        "integral" => {
+            if bt != Typ::Num {
+                return Err(CompileError {
+                    kind: CompileErrorKind::ArgTypeMismatch {
+                        got: (bt, bi),
+                        expected: ValType::Number,
+                        index: None,
+                    },
+                    span: body_span,
+                });
+            }
+            (
+                Latex::Integral {
+                    var,
+                    lower,
+                    upper,
+                    body,
+                },
+                Typ::Num,
+            )
+        }
+        _ => (
+            Latex::Summation {
+                kind: if name == "sum" {
+                    latex::SummationKind::Sum
+                } else {
+                    latex::SummationKind::Prod
+                },
+                var,
+                lower,
+                upper,
+                body,
+            },
+            bt,
+        ),
+    };
+    Ok((
+        latex,
+        t,
+        TypInfo::Builtin(span, ast::Function::Normal { name }),
+    ))
+}
+
 pub fn compile_call(
     ctx: &mut Context,
     span: types::Span,
     func: ast::Function,
     args: Vec<(types::Span, latex::Latex, Typ, TypInfo)>,
 ) -> Result<(latex::Latex, Typ, TypInfo), CompileError> {
-    let rfunc = resolve_function(ctx, span.clone(), func.clone()).ok_or(CompileError {
-        kind: CompileErrorKind::UnknownFunction(func.clone()),
-        span: span.clone(),
-    })?;
+    if let ast::Function::Normal { name } = &func {
+        if ctx.inline_def.as_ref() == Some(name) {
+            return Err(CompileError {
+                kind: CompileErrorKind::RecursiveInline(name.clone()),
+                span,
+            });
+        }
+    }
+    let rfunc = match resolve_function(ctx, span.clone(), func.clone()) {
+        Some(rfunc) => rfunc,
+        None => {
+            return Err(CompileError {
+                kind: CompileErrorKind::UnknownFunction {
+                    suggestion: match &func {
+                        ast::Function::Normal { name } => suggest_function(ctx, name),
+                        ast::Function::Log { .. } => None,
+                    },
+                    func,
+                },
+                span,
+            })
+        }
+    };
     match rfunc {
         ResolvedFunction::Inline(f_rc) => {
             let rfunc = (*f_rc).clone();
@@ -330,46 +608,88 @@ pub fn compile_call(
                 }
             }
 
-            let (entries, args_types): (Vec<_>, Vec<_>) =
-                args.into_iter()
-                    .zip(rfunc.args.into_iter())
-                    .map(
-                        |((arg_span, arg_lat, got_typ, got_info), (name, typ))| -> Result<
-                            ((String, Latex), (types::Span, Typ, TypInfo)),
-                            CompileError,
-                        > {
-                            if !got_typ.eq_weak(typ.into()) {
-                                return Err(CompileError {
-                                    kind: CompileErrorKind::ArgTypeMismatch {
-                                        got: (got_typ, got_info),
-                                        expected: typ,
-                                    },
-                                    span: arg_span,
-                                });
-                            }
-                            Ok(((name, arg_lat), (arg_span, got_typ, got_info)))
-                        },
-                    )
-                    .collect::<Result<Vec<_>, CompileError>>()?
-                    .into_iter()
-                    .unzip();
-
-            let vars: HashMap<String, Latex> = entries.into_iter().collect();
-            todo!();
-            //let (rt, ri) = resolve_func_type(args_types, rfunc.ret);
-            //Ok((replace_variables(rfunc.body, &vars), rt, ri))
+            let mut mapped_arg = None;
+            let vars = args
+                .into_iter()
+                .zip(rfunc.args.into_iter())
+                .enumerate()
+                .map(
+                    |(i, ((arg_span, arg_lat, got_typ, got_info), (name, typ)))| {
+                        if !got_typ.eq_weak(typ.into()) {
+                            return Err(CompileError {
+                                kind: CompileErrorKind::ArgTypeMismatch {
+                                    got: (got_typ, got_info),
+                                    expected: typ,
+                                    index: Some(i),
+                                },
+                                span: arg_span,
+                            });
+                        }
+                        if mapped_arg.is_none() && got_typ != typ.into() {
+                            mapped_arg = Some(got_info);
+                        }
+                        Ok((name, group(arg_lat)))
+                    },
+                )
+                .collect::<Result<HashMap<_, _>, CompileError>>()?;
+
+            // the body is substituted at the call site, so Desmos never sees the function
+            let body = group(replace_variables(rfunc.body, &vars));
+            let (rt, ri) = rfunc.ret;
+            Ok(match mapped_arg {
+                Some(mapped_arg) => (
+                    body,
+                    rt.list_of().unwrap_or(rt),
+                    TypInfo::MappedCall {
+                        call_span: span,
+                        func,
+                        mapped_arg: Box::new(mapped_arg),
+                    },
+                ),
+                None => (
+                    body,
+                    rt,
+                    TypInfo::Call {
+                        call_span: span,
+                        ret: Box::new(ri),
+                    },
+                ),
+            })
         }
         ResolvedFunction::Normal {
             func: rfunc,
             is_builtin,
-        } => match &rfunc.args {
-            FunctionArgs::Static(rargs) => {
-                compile_static_call(span, func, args, (*rfunc).clone(), rargs, is_builtin)
+        } => {
+            if is_builtin {
+                check_root_degree(ctx, &func, &args);
             }
-            FunctionArgs::Variadic => {
-                compile_variadic_call(span, func, args, (*rfunc).clone(), is_builtin)
+            match &rfunc.args {
+                FunctionArgs::Static(rargs) => compile_static_call(
+                    span,
+                    func,
+                    args,
+                    (*rfunc).clone(),
+                    rargs,
+                    rargs.len(),
+                    is_builtin,
+                ),
+                FunctionArgs::Optional {
+                    args: rargs,
+                    required,
+                } => compile_static_call(
+                    span,
+                    func,
+                    args,
+                    (*rfunc).clone(),
+                    rargs,
+                    *required,
+                    is_builtin,
+                ),
+                FunctionArgs::Variadic(elem) => {
+                    compile_variadic_call(span, func, args, *elem, (*rfunc).clone(), is_builtin)
+                }
             }
-        },
+        }
     }
 }
 
@@ -377,7 +697,10 @@ pub fn compile_call(
 mod tests {
     use super::*;
     use crate::{
-        compiler::tests::{check, compile, spn},
+        compiler::{
+            compile_expr,
+            tests::{check, compile, compile_with_ctx, new_ctx, spn, tinfo},
+        },
         types::Literal,
     };
     use ast::Expression;
@@ -427,7 +750,7 @@ mod tests {
     fn call_variadic() {
         assert_eq!(
             BUILTIN_FUNCTIONS.get("lcm").unwrap().args,
-            types::Args::Variadic
+            types::Args::Variadic(ElemType::Number)
         );
         assert_eq!(
             compile(Expression::Call {
@@ -473,7 +796,7 @@ mod tests {
     fn map_variadic() {
         assert_eq!(
             BUILTIN_FUNCTIONS.get("lcm").unwrap().args,
-            types::Args::Variadic
+            types::Args::Variadic(ElemType::Number)
         );
         let inp = Expression::Call {
             func: ast::Function::Normal {
@@ -492,8 +815,12 @@ mod tests {
             compile(inp.clone()),
             Err(CompileError {
                 kind: CompileErrorKind::ArgTypeMismatch {
-                    got: (Typ::List, TypInfo::Literal(Literal::List, spn())),
-                    expected: ValType::Number
+                    got: (
+                        Typ::List(ElemType::Number),
+                        TypInfo::Literal(Literal::List, spn())
+                    ),
+                    expected: ValType::Number,
+                    index: Some(0),
                 },
                 span: spn()
             })
@@ -526,6 +853,466 @@ mod tests {
         );
     }
 
+    fn nthroot(x: &str, n: &str) -> Expression {
+        Expression::Call {
+            func: ast::Function::Normal {
+                name: "nthroot".to_string(),
+            },
+            args: vec![
+                (spn(), Expression::Num(x.to_string())),
+                (spn(), Expression::Num(n.to_string())),
+            ],
+        }
+    }
+
+    #[test]
+    fn nthroot_order() {
+        let mut ctx = new_ctx();
+        let l = compile_with_ctx(&mut ctx, nthroot("8", "3")).unwrap();
+        assert_eq!(latex::latex_to_str(l), "\\sqrt[3]{8}");
+        assert_eq!(ctx.warnings, vec![]);
+    }
+
+    // Compiles the last statement of a program as an expression
+    fn compile_src(src: &str) -> Result<(Latex, Typ), CompileErrorKind> {
+        let mut stmts = parser::lex_and_parse(0, src.to_string()).0.unwrap();
+        let (span, last) = stmts.pop().unwrap();
+        let mut ctx = new_ctx();
+        crate::compile_stmts(&mut ctx, stmts).map_err(|e| e.kind)?;
+        match last {
+            ast::Statement::Expression(e) => compile_expr(&mut ctx, (span, e))
+                .map(|(l, t, _)| (l, t))
+                .map_err(|e| e.kind),
+            s => panic!("Expected expression, got {:?}", s),
+        }
+    }
+
+    #[test]
+    fn inline_call() {
+        let (l, t) = compile_src("inline f(x) = x + 1\nf(3)").unwrap();
+        assert_eq!(latex::latex_to_str(l), "\\left(3+1\\right)");
+        assert_eq!(t, Typ::Num);
+        // arguments are substituted by position
+        let (l, _) = compile_src("inline f(x, y) = x - y\nf(2, 1)").unwrap();
+        assert_eq!(latex::latex_to_str(l), "\\left(2-1\\right)");
+        // the value of a statement needs no group
+        assert_eq!(
+            crate::compile_source(0, "inline f(x) = x + 1\na = f(3)").map(|s| s.expressions),
+            Ok(graph::Expressions::from_latex_strings(vec![
+                "a=3+1".to_string()
+            ]))
+        );
+        assert_eq!(
+            compile_src("inline f(x) = x * 2\nf(@[1, 2])").map(|(_, t)| t),
+            Ok(Typ::List(ElemType::Number))
+        );
+
+        assert!(matches!(
+            compile_src("inline f(L: list) = total(L)\nf(1)"),
+            Err(CompileErrorKind::ArgTypeMismatch {
+                got: (Typ::Num, _),
+                expected: ValType::List(ElemType::Number),
+                index: Some(0),
+            })
+        ));
+        assert_eq!(
+            compile_src("inline f(x) = f(x) + 1\nf(1)"),
+            Err(CompileErrorKind::RecursiveInline("f".to_string()))
+        );
+    }
+
+    #[test]
+    fn inline_precedence() {
+        for (src, expected) in [
+            // the expanded body is kept together inside a larger expression
+            (
+                "inline f(x) = x - 1\na = 2 - f(3)",
+                "a=2-\\left(3-1\\right)",
+            ),
+            ("inline f(x) = x + 1\na = 2 * f(3)", "a=2\\left(3+1\\right)"),
+            (
+                "inline f(x) = x + 1\na = f(3)^2",
+                "a=\\left(3+1\\right)^{2}",
+            ),
+            // and so is each non-atomic argument
+            ("inline f(x) = -x\na = f(1 - 2)", "a=-\\left(1-2\\right)"),
+            ("inline f(x) = x!\na = f(1 + 2)", "a=\\left(1+2\\right)!"),
+            ("inline f(x) = x^2\na = f(-1)", "a=\\left(-1\\right)^{2}"),
+            (
+                "inline f(x, y) = x * y\na = f(1 + 2, 3 + 4)",
+                "a=\\left(1+2\\right)\\left(3+4\\right)",
+            ),
+            (
+                "inline g(x) = x - 1\ninline f(x) = 2 - g(x)\na = f(1 + 2)",
+                "a=2-\\left(\\left(1+2\\right)-1\\right)",
+            ),
+            // atoms are substituted as they are
+            ("inline f(x) = -x\na = f(3)", "a=-3"),
+            (
+                "inline f(x) = x!\na = f(sin(1))",
+                "a=\\sin\\left(1\\right)!",
+            ),
+            (
+                "inline f(L: list) = L[1]\na = f([1, 2])",
+                "a=(\\left[1,2\\right])\\left[1\\right]",
+            ),
+        ] {
+            assert_eq!(
+                crate::compile_source(0, src).map(|s| s.expressions),
+                Ok(graph::Expressions::from_latex_strings(vec![
+                    expected.to_string()
+                ])),
+                "{}",
+                src
+            );
+        }
+    }
+
+    #[test]
+    fn map_call() {
+        let (l, t) = compile_src("sin@([1, 2, 3])").unwrap();
+        assert_eq!(
+            latex::latex_to_str(l),
+            "\\sin\\left(\\left[1,2,3\\right]\\right)"
+        );
+        assert_eq!(t, Typ::MappedList);
+        assert_eq!(
+            compile_src("f(x) = x\nf@([1, 2])").map(|(_, t)| t),
+            Ok(Typ::MappedList)
+        );
+
+        // the function must take one number
+        assert!(matches!(
+            compile_src("total@([1, 2])"),
+            Err(CompileErrorKind::ArgTypeMismatch {
+                got: (Typ::MappedList, _),
+                expected: ValType::List(ElemType::Number),
+                index: Some(0),
+            })
+        ));
+        assert_eq!(
+            compile_src("f(x, y) = x\nf@([1, 2])"),
+            Err(CompileErrorKind::WrongArgCount {
+                got: 1,
+                expected: ExpectedArgCount::Exact(2),
+            })
+        );
+        assert_eq!(compile_src("sin@(1)"), Err(CompileErrorKind::MapNonList));
+        // arithmetic on a mapped list stays mapped
+        assert_eq!(
+            compile_src("sin(@[1, 2] + 1)").map(|(_, t)| t),
+            Ok(Typ::MappedList)
+        );
+        assert_eq!(
+            compile_src("sin(@[1, 2] * @[3, 4])").map(|(_, t)| t),
+            Ok(Typ::MappedList)
+        );
+        assert!(matches!(
+            compile_src("sin(@[1, 2] + [3, 4])"),
+            Err(CompileErrorKind::ExpectedSameTypes { .. })
+        ));
+    }
+
+    #[test]
+    fn abs() {
+        let latex = |src| latex::latex_to_str(compile_src(src).unwrap().0);
+        assert_eq!(latex("a = 1\nabs(a - 2)"), "\\left|(a)-2\\right|");
+        assert_eq!(
+            latex("a = 1\nabs(abs(a))"),
+            "\\left|\\left|a\\right|\\right|"
+        );
+        assert_eq!(latex("sign(-3)"), "\\operatorname{sign}\\left(-3\\right)");
+        // a user defined abs is a normal call
+        assert_eq!(latex("abs(x) = x\nabs(1)"), "a_{bs}\\left(1\\right)");
+    }
+
+    #[test]
+    fn roots() {
+        let sqrt = |args| Expression::Call {
+            func: ast::Function::Normal {
+                name: "sqrt".to_string(),
+            },
+            args,
+        };
+        let mut ctx = new_ctx();
+        let l = compile_with_ctx(&mut ctx, sqrt(vec![(spn(), nthroot("8", "3"))])).unwrap();
+        assert_eq!(latex::latex_to_str(l), "\\sqrt{\\sqrt[3]{8}}");
+
+        // the special case doesn't hide arity errors
+        let arity = |e, got, expected| {
+            assert_eq!(
+                compile_with_ctx(&mut new_ctx(), e).unwrap_err().kind,
+                CompileErrorKind::WrongArgCount {
+                    got,
+                    expected: ExpectedArgCount::Exact(expected),
+                }
+            )
+        };
+        let num = |n: &str| (spn(), Expression::Num(n.to_string()));
+        arity(sqrt(vec![num("1"), num("2")]), 2, 1);
+        arity(sqrt(vec![]), 0, 1);
+        arity(
+            Expression::Call {
+                func: ast::Function::Normal {
+                    name: "nthroot".to_string(),
+                },
+                args: vec![num("8")],
+            },
+            1,
+            2,
+        );
+    }
+
+    #[test]
+    fn nthroot_degenerate_degree() {
+        for n in ["0", "1"] {
+            let mut ctx = new_ctx();
+            compile_with_ctx(&mut ctx, nthroot("8", n)).unwrap();
+            assert_eq!(
+                ctx.warnings,
+                vec![CompileWarning {
+                    kind: CompileWarningKind::DegenerateRootDegree(n.to_string()),
+                    span: spn(),
+                }]
+            );
+        }
+    }
+
+    fn round(args: &[&str]) -> Expression {
+        Expression::Call {
+            func: ast::Function::Normal {
+                name: "round".to_string(),
+            },
+            args: args
+                .iter()
+                .map(|a| (spn(), Expression::Num(a.to_string())))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn round_optional_arg() {
+        let l = compile(round(&["1.25"])).unwrap();
+        assert_eq!(
+            latex::latex_to_str(l),
+            "\\operatorname{round}\\left(1.25\\right)"
+        );
+        let l = compile(round(&["1.25", "1"])).unwrap();
+        assert_eq!(
+            latex::latex_to_str(l),
+            "\\operatorname{round}\\left(1.25,1\\right)"
+        );
+        assert_eq!(
+            compile(round(&["1.25", "1", "2"])),
+            Err(CompileError {
+                kind: CompileErrorKind::WrongArgCount {
+                    got: 3,
+                    expected: ExpectedArgCount::Between(1, 2),
+                },
+                span: spn(),
+            })
+        );
+    }
+
+    #[test]
+    fn min_max_forms() {
+        let call = |name: &str, args: Vec<Expression>| Expression::Call {
+            func: ast::Function::Normal {
+                name: name.to_string(),
+            },
+            args: args.into_iter().map(|a| (spn(), a)).collect(),
+        };
+        let num = |n: &str| Expression::Num(n.to_string());
+        let list = Expression::List(vec![(spn(), num("1")), (spn(), num("2"))]);
+        for name in ["min", "max"] {
+            let (l, t, _) =
+                compile_expr(&mut new_ctx(), (spn(), call(name, vec![list.clone()]))).unwrap();
+            assert_eq!(t, Typ::Num);
+            assert_eq!(
+                latex::latex_to_str(l),
+                format!("\\{}\\left(\\left[1,2\\right]\\right)", name)
+            );
+            let (l, t, _) = compile_expr(
+                &mut new_ctx(),
+                (spn(), call(name, vec![num("1"), num("2"), num("3")])),
+            )
+            .unwrap();
+            assert_eq!(t, Typ::Num);
+            assert_eq!(
+                latex::latex_to_str(l),
+                format!("\\{}\\left(1,2,3\\right)", name)
+            );
+            // lists can't be mixed into the scalar form
+            assert_eq!(
+                compile(call(name, vec![num("1"), list.clone()])),
+                Err(CompileError {
+                    kind: CompileErrorKind::VariadicList,
+                    span: spn(),
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn wrong_arg_count_span() {
+        // the error points at the whole call, not one of its arguments
+        let call_span = types::Span::new(0, 4..16);
+        let arg =
+            |r: std::ops::Range<usize>| (types::Span::new(0, r), Expression::Num("1".to_string()));
+        let call = Expression::Call {
+            func: ast::Function::Normal {
+                name: "sin".to_string(),
+            },
+            args: vec![arg(8..9), arg(11..12)],
+        };
+        assert_eq!(
+            compile_expr(&mut Context::new(), (call_span.clone(), call)),
+            Err(CompileError {
+                kind: CompileErrorKind::WrongArgCount {
+                    got: 2,
+                    expected: ExpectedArgCount::Exact(1),
+                },
+                span: call_span,
+            })
+        );
+    }
+
+    fn summation(name: &str, var: Expression, body: Expression) -> Expression {
+        Expression::Call {
+            func: ast::Function::Normal {
+                name: name.to_string(),
+            },
+            args: vec![
+                (spn(), var),
+                (spn(), Expression::Num("1".to_string())),
+                (spn(), Expression::Num("10".to_string())),
+                (spn(), body),
+            ],
+        }
+    }
+
+    fn n_squared() -> Expression {
+        Expression::BinaryExpr {
+            left: Box::new((spn(), Expression::Variable("n".to_string()))),
+            operator: ast::BinaryOperator::Exponent,
+            right: Box::new((spn(), Expression::Num("2".to_string()))),
+        }
+    }
+
+    #[test]
+    fn sum_prod() {
+        let n = || Expression::Variable("n".to_string());
+        let l = compile(summation("sum", n(), n_squared())).unwrap();
+        assert_eq!(latex::latex_to_str(l), "\\sum_{n=1}^{10}(n)^{2}");
+        let l = compile(summation("prod", n(), n())).unwrap();
+        assert_eq!(latex::latex_to_str(l), "\\prod_{n=1}^{10}n");
+        assert_eq!(
+            compile(summation("sum", Expression::Num("1".to_string()), n())),
+            Err(CompileError {
+                kind: CompileErrorKind::BoundVariableNotIdentifier,
+                span: spn(),
+            })
+        );
+    }
+
+    #[test]
+    fn integral() {
+        let x = || Expression::Variable("x".to_string());
+        let integral = |var, lower, body| Expression::Call {
+            func: ast::Function::Normal {
+                name: "integral".to_string(),
+            },
+            args: vec![
+                (spn(), var),
+                (spn(), lower),
+                (spn(), Expression::Num("1".to_string())),
+                (spn(), body),
+            ],
+        };
+        let x_squared = Expression::BinaryExpr {
+            left: Box::new((spn(), x())),
+            operator: ast::BinaryOperator::Exponent,
+            right: Box::new((spn(), Expression::Num("2".to_string()))),
+        };
+        let zero = || Expression::Num("0".to_string());
+        let (l, t, _) = compile_expr(
+            &mut new_ctx(),
+            (spn(), integral(x(), zero(), x_squared.clone())),
+        )
+        .unwrap();
+        assert_eq!(latex::latex_to_str(l), "\\int_{0}^{1}(x)^{2}\\,dx");
+        assert_eq!(t, Typ::Num);
+        // the variable must be a bare identifier
+        assert_eq!(
+            compile(integral(x_squared, zero(), x())),
+            Err(CompileError {
+                kind: CompileErrorKind::BoundVariableNotIdentifier,
+                span: spn(),
+            })
+        );
+        // bounds must be numbers
+        assert_eq!(
+            compile(integral(x(), Expression::List(vec![(spn(), zero())]), x())),
+            Err(CompileError {
+                kind: CompileErrorKind::ArgTypeMismatch {
+                    got: (
+                        Typ::List(ElemType::Number),
+                        TypInfo::Literal(Literal::List, spn())
+                    ),
+                    expected: ValType::Number,
+                    index: None,
+                },
+                span: spn(),
+            })
+        );
+        // a user or included stdlib function named integral is called like any other
+        let mut ctx = new_ctx();
+        ctx.defined_functions.insert(
+            "integral".to_string(),
+            Rc::new(FunctionSignature {
+                args: FunctionArgs::Static(vec![ValType::Number]),
+                arg_names: vec!["x".to_string()],
+                ret: (ValType::Number, tinfo()),
+            }),
+        );
+        let call = Expression::Call {
+            func: ast::Function::Normal {
+                name: "integral".to_string(),
+            },
+            args: vec![(spn(), zero())],
+        };
+        let (_, t, _) = compile_expr(&mut ctx, (spn(), call)).unwrap();
+        assert_eq!(t, Typ::Num);
+    }
+
+    #[test]
+    fn sum_scope() {
+        let n = || Expression::Variable("n".to_string());
+        // the outer n is a list, but the bound n is a number inside the body
+        let mut ctx = new_ctx();
+        ctx.variables
+            .insert("n".to_string(), (ValType::List(ElemType::Number), tinfo()));
+        let (_, t, _) =
+            compile_expr(&mut ctx, (spn(), summation("sum", n(), n_squared()))).unwrap();
+        assert_eq!(t, Typ::Num);
+        assert!(ctx.locals.is_empty());
+        let (_, t, _) = compile_expr(&mut ctx, (spn(), n())).unwrap();
+        assert_eq!(t, Typ::List(ElemType::Number));
+        // not leaked to the outer context
+        assert_eq!(compile(summation("sum", n(), n())).map(|_| ()), Ok(()));
+        assert_eq!(
+            compile(Expression::BinaryExpr {
+                left: Box::new((spn(), summation("sum", n(), n()))),
+                operator: ast::BinaryOperator::Add,
+                right: Box::new((spn(), n())),
+            }),
+            Err(CompileError {
+                kind: CompileErrorKind::UndefinedVariable("n".to_string()),
+                span: spn(),
+            })
+        );
+    }
+
     #[test]
     fn call_resolution() {
         check(
@@ -552,10 +1339,57 @@ mod tests {
             })
             .unwrap_err()
             .kind,
-            CompileErrorKind::UnknownFunction(ast::Function::Normal {
-                name: "abc".to_string()
-            })
+            CompileErrorKind::UnknownFunction {
+                func: ast::Function::Normal {
+                    name: "abc".to_string()
+                },
+                suggestion: Some("abs".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn unknown_function_suggestion() {
+        let span = types::Span::new(1234, 0..5);
+        let err = compile_expr(
+            &mut new_ctx(),
+            (
+                span.clone(),
+                Expression::Call {
+                    func: ast::Function::Normal {
+                        name: "tn".to_string(),
+                    },
+                    args: vec![(spn(), Expression::Num("1".to_string()))],
+                },
+            ),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            CompileError {
+                kind: CompileErrorKind::UnknownFunction {
+                    func: ast::Function::Normal {
+                        name: "tn".to_string()
+                    },
+                    suggestion: Some("tan".to_string()),
+                },
+                span,
+            }
+        );
+        assert_eq!(err.kind.help(), vec!["Did you mean 'tan'?".to_string()]);
+
+        // user defined functions are suggested too
+        let mut ctx = new_ctx();
+        ctx.defined_functions.insert(
+            "myfunc".to_string(),
+            Rc::new(FunctionSignature {
+                args: FunctionArgs::Static(vec![]),
+                arg_names: vec![],
+                ret: (ValType::Number, tinfo()),
+            }),
         );
+        assert_eq!(suggest_function(&ctx, "myfnc"), Some("myfunc".to_string()));
+        assert_eq!(suggest_function(&ctx, "qwerty"), None);
     }
 
     #[test]
@@ -608,10 +1442,169 @@ mod tests {
             .unwrap_err()
             .kind,
             CompileErrorKind::ArgTypeMismatch {
-                got: (Typ::List, TypInfo::Literal(Literal::List, spn())),
-                expected: ValType::Number
+                got: (
+                    Typ::List(ElemType::Number),
+                    TypInfo::Literal(Literal::List, spn())
+                ),
+                expected: ValType::Number,
+                index: Some(0),
+            }
+        );
+    }
+
+    #[test]
+    fn variadic_arg_count() {
+        let err = compile_src("gcd()").unwrap_err();
+        assert_eq!(
+            err,
+            CompileErrorKind::WrongArgCount {
+                got: 0,
+                expected: ExpectedArgCount::NonZero
             }
         );
+        assert_eq!(
+            err.as_msg(),
+            "Expected at least 1 argument for a variadic function but got 0"
+        );
+        // fixed arity functions say how many arguments they take
+        assert_eq!(
+            compile_src("sin()").unwrap_err().as_msg(),
+            "Expected 1 arguments but got 0"
+        );
+        let (l, t) = compile_src("gcd(1, 2, 3, 4)").unwrap();
+        assert_eq!(latex::latex_to_str(l), "\\gcd\\left(1,2,3,4\\right)");
+        assert_eq!(t, Typ::Num);
+        assert_eq!(compile_src("gcd([1, 2, 3])").unwrap().1, Typ::Num);
+    }
+
+    #[test]
+    fn arg_index() {
+        let err = |src: &str| {
+            let (span, stmt) = parser::lex_and_parse(0, src.to_string())
+                .0
+                .unwrap()
+                .remove(0);
+            match stmt {
+                ast::Statement::Expression(e) => compile_expr(&mut new_ctx(), (span, e)),
+                s => panic!("Expected expression, got {:?}", s),
+            }
+            .unwrap_err()
+        };
+        let e = err("quantile(5, [1, 2, 3])");
+        assert!(matches!(
+            e.kind,
+            CompileErrorKind::ArgTypeMismatch {
+                got: (Typ::Num, _),
+                expected: ValType::List(ElemType::Number),
+                index: Some(0),
+            }
+        ));
+        assert_eq!(e.span.range, 9..10);
+        assert_eq!(
+            e.kind.as_msg(),
+            "Expected type list for argument 1 but got number"
+        );
+        let e = err("quantile([1, 2, 3], [1])");
+        assert!(matches!(
+            e.kind,
+            CompileErrorKind::ArgTypeMismatch { index: Some(1), .. }
+        ));
+        assert_eq!(e.span.range, 20..23);
+    }
+
+    #[test]
+    fn mapped_call() {
+        let mut stmts = parser::lex_and_parse(0, "L = [1, 2]\nsin(@L)".to_string())
+            .0
+            .unwrap();
+        let (span, last) = stmts.pop().unwrap();
+        let mut ctx = new_ctx();
+        crate::compile_stmts(&mut ctx, stmts).unwrap();
+        let e = match last {
+            ast::Statement::Expression(e) => e,
+            s => panic!("Expected expression, got {:?}", s),
+        };
+        let (l, t, i) = compile_expr(&mut ctx, (span, e)).unwrap();
+        assert_eq!(latex::latex_to_str(l), "\\sin\\left(L\\right)");
+        assert_eq!(t, Typ::MappedList);
+        // the call remembers which argument it was mapped over
+        match i {
+            TypInfo::MappedCall {
+                func, mapped_arg, ..
+            } => {
+                assert_eq!(
+                    func,
+                    ast::Function::Normal {
+                        name: "sin".to_string()
+                    }
+                );
+                assert!(matches!(*mapped_arg, TypInfo::Map(_)));
+            }
+            i => panic!("Expected a mapped call, got {:?}", i),
+        }
+
+        // a mapped result can't be mixed with an unmapped list
+        assert!(matches!(
+            compile_src("L = [1, 2]\nsin(@L) + L"),
+            Err(CompileErrorKind::ExpectedSameTypes {
+                left: (Typ::MappedList, _),
+                right: (Typ::List(ElemType::Number), _),
+            })
+        ));
+        assert_eq!(
+            compile_src("L = [1, 2]\nsin(@L) + 1").map(|(_, t)| t),
+            Ok(Typ::MappedList)
+        );
+    }
+
+    #[test]
+    fn polygon() {
+        assert_eq!(
+            BUILTIN_FUNCTIONS.get("polygon").unwrap().args,
+            types::Args::Variadic(ElemType::Point)
+        );
+        let (l, t) = compile_src("polygon((0, 0), (1, 0), (1, 1))").unwrap();
+        assert_eq!(
+            latex::latex_to_str(l),
+            "\\operatorname{polygon}\\left(\\left(0,0\\right),\\left(1,0\\right),\\left(1,1\\right)\\right)"
+        );
+        assert_eq!(t, Typ::Polygon);
+        assert_eq!(
+            compile_src("P = [(0, 0), (1, 0)]\npolygon(P)").map(|(_, t)| t),
+            Ok(Typ::Polygon)
+        );
+        assert_eq!(
+            compile_src("A = polygon((0, 0), (1, 0), (1, 1))\nA").map(|(_, t)| t),
+            Ok(Typ::Polygon)
+        );
+
+        assert!(matches!(
+            compile_src("polygon((0, 0), 1)"),
+            Err(CompileErrorKind::ArgTypeMismatch {
+                got: (Typ::Num, _),
+                expected: ValType::Point,
+                index: Some(1),
+            })
+        ));
+        assert!(matches!(
+            compile_src("P = [(0, 0), (1, 0)]\npolygon(P, (1, 1))"),
+            Err(CompileErrorKind::ArgTypeMismatch {
+                got: (Typ::List(ElemType::Point), _),
+                expected: ValType::Point,
+                index: Some(0),
+            })
+        ));
+        assert_eq!(
+            compile_src("polygon()"),
+            Err(CompileErrorKind::WrongArgCount {
+                got: 0,
+                expected: ExpectedArgCount::NonZero,
+            })
+        );
+        assert_eq!(
+            compile_src("polygon((0, 0), (1, 0), (1, 1)) + 1"),
+            Err(CompileErrorKind::PointArithmetic)
+        );
     }
 
     #[test]
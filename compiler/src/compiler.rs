@@ -3,7 +3,8 @@ use std::convert::TryInto;
 use crate::types::{binop_exprs, reduce_with_binop_exprs, Cesult, Literal, Typ, TypInfo};
 
 use super::{
-    error::{CompileError, CompileErrorKind},
+    builtins::{BINDING_FUNCTIONS, BUILTIN_FUNCTIONS},
+    error::{CompileError, CompileErrorKind, CompileWarning, CompileWarningKind},
     types::{Context, FunctionArgs, FunctionSignature, InlineFunction},
 };
 use ast::{
@@ -13,13 +14,14 @@ use latex::{
     BinaryOperator as LatexBinaryOperator, Cond, Latex, LatexStatement,
     UnaryOperator as LatexUnaryOperator,
 };
-use types::ValType;
+use types::{CompareOperator, ElemType, ValType};
 
 pub fn resolve_variable(ctx: &Context, var: String) -> Option<(ValType, TypInfo)> {
-    if let Some(r) = ctx.variables.get::<str>(var.as_ref()) {
+    // summation variables shadow outer variables
+    if let Some(r) = ctx.locals.get::<str>(var.as_ref()) {
         return Some(r.clone());
     }
-    if let Some(r) = ctx.locals.get::<str>(var.as_ref()) {
+    if let Some(r) = ctx.variables.get::<str>(var.as_ref()) {
         return Some(r.clone());
     }
     None
@@ -77,35 +79,71 @@ pub fn comp_expect_num_strict(
     comp_expect(ctx, expr, |t| t == Typ::Num, |_, _| kind)
 }
 
-pub fn comp_expect_num(
-    ctx: &mut Context,
-    expr: LocatedExpression,
-    kind: CompileErrorKind,
-) -> Cesult<(Latex, Typ, TypInfo)> {
-    comp_expect(ctx, expr, |t| t.is_num_weak(), |_, _| kind)
-}
-
-pub fn comp_expect_list_strict<K>(
-    ctx: &mut Context,
-    expr: LocatedExpression,
-    kind: K,
-) -> Cesult<(Latex, Typ, TypInfo)>
-where
-    K: FnOnce(Typ, TypInfo) -> CompileErrorKind,
-{
-    comp_expect(ctx, expr, |t| t == Typ::List, kind)
+// The length of a list whose length is known before it is evaluated
+fn literal_list_len(e: &Expression) -> Option<usize> {
+    match e {
+        Expression::List(items) => Some(items.len()),
+        Expression::Range {
+            first,
+            second: None,
+            end,
+        } => match (&first.1, &end.1) {
+            (Expression::Num(first), Expression::Num(end)) => {
+                let (first, end) = (first.parse::<usize>().ok()?, end.parse::<usize>().ok()?);
+                Some(end.checked_sub(first)? + 1)
+            }
+            _ => None,
+        },
+        _ => None,
+    }
 }
 
+// Operations on two lists are element-wise and on a list and a number apply to each
+//  element, just like in Desmos
 pub fn comp_binop(
     ctx: &mut Context,
     left: LocatedExpression,
     right: LocatedExpression,
 ) -> Cesult<(Latex, Latex, Typ, TypInfo)> {
     let ls = left.0.clone();
-    let rs = left.0.clone();
+    let rs = right.0.clone();
+    // Desmos truncates the result to the shorter list
+    if let (Some(l), Some(r)) = (literal_list_len(&left.1), literal_list_len(&right.1)) {
+        if l != r {
+            ctx.warnings.push(CompileWarning {
+                kind: CompileWarningKind::ListLengthMismatch(l, r),
+                span: ls.with_end_of(&rs).unwrap_or_else(|| ls.clone()),
+            });
+        }
+    }
     let (lv, lt, li) = compile_expr(ctx, left)?;
     let (rv, rt, ri) = compile_expr(ctx, right)?;
-    if !lt.eq_weak(rt) {
+    let (_s, t, i) = check_binop((ls, lt, li), (rs, rt, ri))?;
+    Ok((lv, rv, t, i))
+}
+
+fn check_binop(
+    (ls, lt, li): (types::Span, Typ, TypInfo),
+    (rs, rt, ri): (types::Span, Typ, TypInfo),
+) -> Cesult<(types::Span, Typ, TypInfo)> {
+    if lt.is_point() || rt.is_point() {
+        return Err(CompileError {
+            kind: CompileErrorKind::PointArithmetic,
+            span: ls.with_end_of(&rs).unwrap_or(ls),
+        });
+    }
+    if lt == Typ::Bool || rt == Typ::Bool {
+        return Err(CompileError {
+            kind: CompileErrorKind::BoolArithmetic,
+            span: ls.with_end_of(&rs).unwrap_or(ls),
+        });
+    }
+    // a number applies to each element of a list
+    let broadcast = matches!(
+        (lt, rt),
+        (Typ::List(ElemType::Number), Typ::Num) | (Typ::Num, Typ::List(ElemType::Number))
+    );
+    if !broadcast && !lt.eq_weak(rt) {
         return Err(CompileError {
             kind: CompileErrorKind::ExpectedSameTypes {
                 left: (lt, li),
@@ -114,23 +152,70 @@ pub fn comp_binop(
             span: ls.with_end_of(&rs).unwrap_or(ls),
         });
     }
-    let (_s, t, i) = binop_exprs((ls, lt, li), (rs, rt, ri));
-    Ok((lv, rv, t, i))
+    binop_exprs((ls, lt, li), (rs, rt, ri))
+}
+
+// Both halves of `a < b < c` are typechecked like `a < b` and `b < c`
+fn comp_double_comparison(
+    ctx: &mut Context,
+    left: LocatedExpression,
+    middle: LocatedExpression,
+    right: LocatedExpression,
+) -> Cesult<(Latex, Latex, Latex)> {
+    let (ls, ms, rs) = (left.0.clone(), middle.0.clone(), right.0.clone());
+    let (lv, lt, li) = compile_expr(ctx, left)?;
+    let (mv, mt, mi) = compile_expr(ctx, middle)?;
+    let (rv, rt, ri) = compile_expr(ctx, right)?;
+    check_binop((ls, lt, li), (ms.clone(), mt, mi.clone()))?;
+    check_binop((ms, mt, mi), (rs, rt, ri))?;
+    Ok((lv, mv, rv))
 }
 
 pub fn branch_to_cond(
     ctx: &mut Context,
-    (spn, branch): ast::Spanned<ast::Branch>,
+    (_, branch): ast::Spanned<ast::Branch>,
 ) -> Cesult<(Cond, (types::Span, Typ, TypInfo))> {
-    let (left, right, t, i) = comp_binop(ctx, branch.cond_left, branch.cond_right)?;
+    let (left, op, right, chain) = match branch.cond {
+        (_, Expression::Comparison { left, op, right }) => {
+            let (left, right, _t, _i) = comp_binop(ctx, *left, *right)?;
+            (left, op, right, None)
+        }
+        (
+            _,
+            Expression::DoubleComparison {
+                left,
+                left_op,
+                middle,
+                right_op,
+                right,
+            },
+        ) => {
+            let (left, middle, right) = comp_double_comparison(ctx, *left, *middle, *right)?;
+            (left, left_op, middle, Some((right_op, right)))
+        }
+        // booleans are stored as 1 or 0, see Expression::Comparison
+        cond => {
+            let (l, _, _) = comp_expect(
+                ctx,
+                cond,
+                |t| t == Typ::Bool,
+                CompileErrorKind::ExpectedBool,
+            )?;
+            (l, CompareOperator::Equal, Latex::Num("1".to_string()), None)
+        }
+    };
+    // the branch evaluates to its value, so that's what gets typechecked
+    let vspan = branch.val.0.clone();
+    let (result, t, i) = compile_expr(ctx, branch.val)?;
     Ok((
         Cond {
-            left: left,
-            op: branch.cond,
-            right: right,
-            result: compile_expr(ctx, branch.val)?.0,
+            left,
+            op,
+            right,
+            chain,
+            result,
         },
-        (spn, t, i),
+        (vspan, t, i),
     ))
 }
 
@@ -151,15 +236,43 @@ pub fn compile_variable_ref(
     })
 }
 
+// The parser keeps the exponent of very large or small numbers, `1e400` is `1\cdot10^{400}`
+fn num_latex(val: String) -> Latex {
+    match val.split_once('e') {
+        Some((mantissa, exp)) => Latex::BinaryExpression {
+            left: Box::new(Latex::Num(mantissa.to_string())),
+            operator: LatexBinaryOperator::Multiply,
+            right: Box::new(Latex::BinaryExpression {
+                left: Box::new(Latex::Num("10".to_string())),
+                operator: LatexBinaryOperator::Exponent,
+                right: Box::new(Latex::Num(exp.to_string())),
+            }),
+        },
+        None => Latex::Num(val),
+    }
+}
+
 // Ideally this would be functional and ctx would not need to be mutable, but rust
 //  support for immutable hashmaps isn't built in and mutation is much simpler.
 pub fn compile_expr(ctx: &mut Context, expr: LocatedExpression) -> Cesult<(Latex, Typ, TypInfo)> {
+    let span = expr.0.clone();
+    let (l, t, ti) = compile_expr_latex(ctx, expr)?;
+    if ctx.source_map {
+        return Ok((Latex::Spanned(span, Box::new(l)), t, ti));
+    }
+    Ok((l, t, ti))
+}
+
+fn compile_expr_latex(ctx: &mut Context, expr: LocatedExpression) -> Cesult<(Latex, Typ, TypInfo)> {
     let span = expr.0;
 
     match expr.1 {
-        Expression::Error => unimplemented!(),
+        Expression::Error => Err(CompileError {
+            kind: CompileErrorKind::InvalidSyntax,
+            span,
+        }),
         Expression::Num(val) => Ok((
-            Latex::Num(val.to_string()),
+            num_latex(val),
             Typ::Num,
             TypInfo::Literal(Literal::Numeric, span),
         )),
@@ -192,6 +305,12 @@ pub fn compile_expr(ctx: &mut Context, expr: LocatedExpression) -> Cesult<(Latex
             operator: op,
         } => {
             let (l, t, i) = compile_expr(ctx, *v)?;
+            if t == Typ::Bool {
+                return Err(CompileError {
+                    kind: CompileErrorKind::BoolArithmetic,
+                    span,
+                });
+            }
             if !t.is_num_weak() {
                 return Err(CompileError {
                     kind: match op {
@@ -215,7 +334,7 @@ pub fn compile_expr(ctx: &mut Context, expr: LocatedExpression) -> Cesult<(Latex
         }
         Expression::Map(val) => {
             let (v, t, _) = compile_expr(ctx, *val)?;
-            if t != Typ::List {
+            if t != Typ::List(ElemType::Number) {
                 return Err(CompileError {
                     kind: CompileErrorKind::MapNonList,
                     span,
@@ -223,6 +342,16 @@ pub fn compile_expr(ctx: &mut Context, expr: LocatedExpression) -> Cesult<(Latex
             }
             Ok((v, Typ::MappedList, TypInfo::Map(span)))
         }
+        // user definitions shadow these like they shadow other builtins
+        Expression::Call {
+            func: ast::Function::Normal { name },
+            args,
+        } if BINDING_FUNCTIONS.contains(&name.as_str())
+            && !ctx.defined_functions.contains_key(name.as_str())
+            && !ctx.inline_fns.contains_key(name.as_str()) =>
+        {
+            super::call::compile_binding_call(ctx, span, name, args)
+        }
         Expression::Call { func, args } => {
             let compiled_args = args
                 .into_iter()
@@ -234,16 +363,108 @@ pub fn compile_expr(ctx: &mut Context, expr: LocatedExpression) -> Cesult<(Latex
             super::call::compile_call(ctx, span, func, compiled_args)
         }
         Expression::List(values) => {
+            // the first item decides whether this is a list of numbers or of points
+            let mut item_type: Option<(Typ, TypInfo)> = None;
             let items = values
                 .into_iter()
                 .map(|e| -> Cesult<Latex> {
-                    Ok(comp_expect_num_strict(ctx, e, CompileErrorKind::NoNestedList)?.0)
+                    let item_span = e.0.clone();
+                    let (l, t, ti) = comp_expect(
+                        ctx,
+                        e,
+                        |t| t == Typ::Num || t == Typ::Point,
+                        |_, _| CompileErrorKind::NoNestedList,
+                    )?;
+                    match &item_type {
+                        Some((first, fi)) if *first != t => Err(CompileError {
+                            kind: CompileErrorKind::ExpectedSameTypes {
+                                left: (*first, fi.clone()),
+                                right: (t, ti),
+                            },
+                            span: item_span,
+                        }),
+                        Some(_) => Ok(l),
+                        None => {
+                            item_type = Some((t, ti));
+                            Ok(l)
+                        }
+                    }
                 })
                 .collect::<Cesult<Vec<Latex>>>()?;
 
             Ok((
                 Latex::List(items),
-                Typ::List,
+                match item_type {
+                    Some((t, _)) => t.list_of().unwrap(),
+                    // an empty list is a list of numbers
+                    None => Typ::List(ElemType::Number),
+                },
+                TypInfo::Literal(Literal::List, span),
+            ))
+        }
+        Expression::Point { x, y } => {
+            let mut coord = |e| {
+                comp_expect(
+                    ctx,
+                    e,
+                    |t| t == Typ::Num,
+                    |t, ti| CompileErrorKind::ArgTypeMismatch {
+                        got: (t, ti),
+                        expected: ValType::Number,
+                        index: None,
+                    },
+                )
+            };
+            let (x, _, _) = coord(*x)?;
+            let (y, _, _) = coord(*y)?;
+            Ok((
+                Latex::Point {
+                    x: Box::new(x),
+                    y: Box::new(y),
+                },
+                Typ::Point,
+                TypInfo::Literal(Literal::Point, span),
+            ))
+        }
+        Expression::Comprehension {
+            body,
+            var: (var_span, var),
+            list,
+        } => {
+            let (list, lt, _) = comp_expect(
+                ctx,
+                *list,
+                |t| matches!(t, Typ::List(_)),
+                |t, ti| CompileErrorKind::ArgTypeMismatch {
+                    got: (t, ti),
+                    expected: ValType::List(ElemType::Number),
+                    index: None,
+                },
+            )?;
+            // the variable takes each element of the list
+            let elem = match lt {
+                Typ::List(e) => ValType::from(e),
+                _ => unreachable!("checked by comp_expect"),
+            };
+            ctx.push_scope(vec![(
+                var.clone(),
+                (elem, TypInfo::InlineFuncArg(var_span)),
+            )]);
+            let body = comp_expect(
+                ctx,
+                *body,
+                |t| t == Typ::Num || t == Typ::Point,
+                |_, _| CompileErrorKind::NoNestedList,
+            );
+            ctx.pop_scope();
+            let (body, bt, _) = body?;
+            Ok((
+                Latex::Comprehension {
+                    body: Box::new(body),
+                    var,
+                    list: Box::new(list),
+                },
+                bt.list_of().unwrap(),
                 TypInfo::Literal(Literal::List, span),
             ))
         }
@@ -263,7 +484,11 @@ pub fn compile_expr(ctx: &mut Context, expr: LocatedExpression) -> Cesult<(Latex
                     comp_expect_num_strict(ctx, *end, CompileErrorKind::RangeExpectNumber)?.0,
                 ),
             };
-            Ok((range, Typ::List, TypInfo::Literal(Literal::Range, span)))
+            Ok((
+                range,
+                Typ::List(ElemType::Number),
+                TypInfo::Literal(Literal::Range, span),
+            ))
         }
         Expression::Piecewise {
             first,
@@ -279,13 +504,25 @@ pub fn compile_expr(ctx: &mut Context, expr: LocatedExpression) -> Cesult<(Latex
                 .unzip();
             let dspan = default.0.clone();
             let (default, dt, di) = compile_expr(ctx, *default)?;
-            // FIXME: Fix TypInfos here
-            let (_s, t, ti) = reduce_with_binop_exprs(
-                std::iter::once(ft)
-                    .chain(rest_types)
-                    .chain(std::iter::once((dspan, dt, di))),
-            )
-            .unwrap();
+            let branch_types = std::iter::once(ft)
+                .chain(rest_types)
+                .chain(std::iter::once((dspan, dt, di)))
+                .collect::<Vec<_>>();
+            // numbers and lists broadcast, but points can't be mixed with them
+            let (_, first_t, first_i) = branch_types[0].clone();
+            if let Some((s, t, i)) = branch_types
+                .iter()
+                .find(|(_, t, _)| (first_t.is_point() || t.is_point()) && first_t != *t)
+            {
+                return Err(CompileError {
+                    kind: CompileErrorKind::ExpectedSameTypes {
+                        left: (first_t, first_i),
+                        right: (*t, i.clone()),
+                    },
+                    span: s.clone(),
+                });
+            }
+            let (_s, t, ti) = reduce_with_binop_exprs(branch_types)?.unwrap();
             Ok((
                 Latex::Piecewise {
                     first: Box::new(first),
@@ -296,21 +533,55 @@ pub fn compile_expr(ctx: &mut Context, expr: LocatedExpression) -> Cesult<(Latex
                 ti,
             ))
         }
+        // Desmos has no booleans, so outside of a condition they're 1 or 0
+        cond @ (Expression::Comparison { .. } | Expression::DoubleComparison { .. }) => {
+            let branch = ast::Branch {
+                cond: (span.clone(), cond),
+                val: (span.clone(), Expression::Num("1".to_string())),
+            };
+            let (cond, _) = branch_to_cond(ctx, (span.clone(), branch))?;
+            Ok((
+                Latex::Piecewise {
+                    first: Box::new(cond),
+                    rest: vec![],
+                    default: Box::new(Latex::Num("0".to_string())),
+                },
+                Typ::Bool,
+                TypInfo::Comparison(span),
+            ))
+        }
         Expression::RawLatex(ty, l) => Ok((Latex::Raw(l), ty.into(), TypInfo::RawLatex(span))),
         Expression::Index { val, ind } => {
-            let (r, rt, ri) = comp_expect_num(ctx, *ind, CompileErrorKind::IndexWithNonNumber)?;
+            // Indexing with a range slices the list and indexing with a list of indices
+            //  picks out those elements, so the result is a list too
+            let (r, rt, ri) = match ind.1 {
+                Expression::Range { .. } => compile_expr(ctx, *ind)?,
+                _ => comp_expect(
+                    ctx,
+                    *ind,
+                    |t| t.is_num_weak() || t == Typ::List(ElemType::Number),
+                    |_, _| CompileErrorKind::IndexWithNonNumber,
+                )?,
+            };
+            let (l, lt, _) = comp_expect(
+                ctx,
+                *val,
+                |t| matches!(t, Typ::List(_)),
+                CompileErrorKind::IndexNonList,
+            )?;
             Ok((
                 Latex::BinaryExpression {
-                    left: Box::new(
-                        comp_expect_list_strict(ctx, *val, |t, ti| {
-                            CompileErrorKind::IndexNonList(t, ti)
-                        })?
-                        .0,
-                    ),
+                    left: Box::new(l),
                     operator: LatexBinaryOperator::Index,
                     right: Box::new(r),
                 },
-                rt,
+                match rt {
+                    Typ::Num => lt.element().unwrap(),
+                    // mapping over the indices maps over the elements
+                    Typ::MappedList if lt == Typ::List(ElemType::Number) => Typ::MappedList,
+                    // a slice or a list of indices picks out a list of the same kind
+                    _ => lt,
+                },
                 ri,
             ))
         }
@@ -321,27 +592,59 @@ pub type CompileResult = Cesult<Vec<LatexStatement>>;
 
 pub fn compile_stmt(ctx: &mut Context, expr: LocatedStatement) -> CompileResult {
     let s = expr.0;
+    let stmt = match ctx.fold_constants {
+        true => super::fold::fold_stmt(expr.1, &s),
+        false => expr.1,
+    };
 
-    match expr.1 {
+    match stmt {
         Statement::Expression(e) => Ok(vec![LatexStatement::Expression(
             compile_expr(ctx, (s, e))?.0,
         )]),
         Statement::FuncDef(fdef, e) => {
-            // Add args into locals
-            for (aspan, aname, atype) in fdef.args.iter() {
-                if ctx.variables.contains_key(aname) || ctx.locals.contains_key(aname) {
+            // calls would silently resolve to the user's function
+            if BUILTIN_FUNCTIONS.contains_key(fdef.name.as_str())
+                || BINDING_FUNCTIONS.contains(&fdef.name.as_str())
+            {
+                if ctx.deny_builtin_shadowing {
+                    return Err(CompileError {
+                        kind: CompileErrorKind::ShadowsBuiltin { name: fdef.name },
+                        span: s,
+                    });
+                }
+                ctx.warnings.push(CompileWarning {
+                    kind: CompileWarningKind::ShadowsBuiltin(fdef.name.clone()),
+                    span: s.clone(),
+                });
+            }
+            for (i, (aspan, aname, _)) in fdef.args.iter().enumerate() {
+                if fdef.args[..i].iter().any(|(_, prev, _)| prev == aname) {
                     return Err(CompileError {
                         kind: CompileErrorKind::DuplicateVariable(aname.clone()),
                         span: aspan.clone(),
                     });
                 }
-                ctx.locals.insert(
-                    aname.clone(),
-                    (*atype, TypInfo::InlineFuncArg(aspan.clone())),
-                );
             }
-            // Evaluate the body with the new ctx
-            let (body, rt, ri) = compile_expr(ctx, e)?;
+            // Args shadow variables in the body only
+            ctx.push_scope(
+                fdef.args
+                    .iter()
+                    .map(|(aspan, aname, atype)| {
+                        (
+                            aname.clone(),
+                            (*atype, TypInfo::InlineFuncArg(aspan.clone())),
+                        )
+                    })
+                    .collect(),
+            );
+            let body_span = e.0.clone();
+            if fdef.inline {
+                ctx.inline_def = Some(fdef.name.clone());
+            }
+            let body = compile_expr(ctx, e);
+            ctx.inline_def = None;
+            ctx.pop_scope();
+            let (body, rt, ri) = body?;
             // Validate the return type annotation
             if let Some(retann) = fdef.ret_annotation {
                 if !rt.eq_weak(retann.into()) {
@@ -354,10 +657,7 @@ pub fn compile_stmt(ctx: &mut Context, expr: LocatedStatement) -> CompileResult
                     });
                 };
             }
-            // remove args from scope
-            for (_span, aname, _atyp) in fdef.args.iter() {
-                ctx.locals.remove(aname);
-            }
+            ctx.def_spans.insert(fdef.name.clone(), s.clone());
 
             if fdef.inline {
                 ctx.inline_fns.insert(
@@ -382,10 +682,15 @@ pub fn compile_stmt(ctx: &mut Context, expr: LocatedStatement) -> CompileResult
                         .map(|(_span, _name, typ)| typ.clone())
                         .collect(),
                 ),
+                arg_names: fdef
+                    .args
+                    .iter()
+                    .map(|(_span, name, _typ)| name.clone())
+                    .collect(),
                 ret: (
                     rt.try_into().map_err(|_| CompileError {
                         kind: CompileErrorKind::ReturnMap,
-                        span: s,
+                        span: body_span,
                     })?,
                     ri,
                 ),
@@ -414,6 +719,7 @@ pub fn compile_stmt(ctx: &mut Context, expr: LocatedStatement) -> CompileResult
             };
             let val_span = val.0.clone();
             let (val_latex, t, ti) = compile_expr(ctx, val)?;
+            ctx.def_spans.insert(name.clone(), s.clone());
             if inline {
                 ctx.inline_vals.insert(name.clone(), (val_latex, t, ti));
                 return Ok(vec![]);
@@ -434,15 +740,72 @@ pub fn compile_stmt(ctx: &mut Context, expr: LocatedStatement) -> CompileResult
             )])
         }
         Statement::Import(import) => super::import::handle_import(ctx, s, import),
+        Statement::Note(text) => Ok(vec![LatexStatement::Note(text)]),
+        // the ticker isn't part of the expression list, see stmts_to_graph
+        Statement::Ticker { handler, min_step } => {
+            if ctx.ticker.is_some() {
+                return Err(CompileError {
+                    kind: CompileErrorKind::DuplicateTicker,
+                    span: s,
+                });
+            }
+            let (handler, _, _) = compile_expr(ctx, handler)?;
+            let min_step = match min_step {
+                Some(step) => Some(
+                    comp_expect(
+                        ctx,
+                        step,
+                        |t| t == Typ::Num,
+                        |t, ti| CompileErrorKind::ArgTypeMismatch {
+                            got: (t, ti),
+                            expected: ValType::Number,
+                            index: None,
+                        },
+                    )?
+                    .0,
+                ),
+                None => None,
+            };
+            ctx.ticker = Some((
+                latex::latex_to_str(handler),
+                min_step.map(latex::latex_to_str),
+            ));
+            Ok(vec![])
+        }
+        Statement::With(style, body) => {
+            if let Some(color) = &style.color {
+                color.parse::<graph::Color>().map_err(|e| CompileError {
+                    kind: CompileErrorKind::InvalidColor(e),
+                    span: s.clone(),
+                })?;
+            }
+            Ok(compile_stmts(ctx, body)?
+                .into_iter()
+                .map(|l| match l {
+                    // a nested block's style overrides this one
+                    LatexStatement::Styled(inner, l) => {
+                        LatexStatement::Styled(inner.inherit(&style), l)
+                    }
+                    l => LatexStatement::Styled(style.clone(), Box::new(l)),
+                })
+                .collect())
+        }
     }
 }
 
+// The statements to compile. Statements the parser recovered from were already reported,
+//  the rest still compile.
+pub fn compiled_stmts(
+    ast: Vec<ast::Spanned<ast::Statement>>,
+) -> impl Iterator<Item = ast::Spanned<ast::Statement>> {
+    ast.into_iter().filter(|(_, s)| !s.has_error())
+}
+
 pub fn compile_stmts(
     ctx: &mut Context,
     ast: Vec<ast::Spanned<ast::Statement>>,
 ) -> Cesult<Vec<LatexStatement>> {
-    Ok(ast
-        .into_iter()
+    Ok(compiled_stmts(ast)
         .map(|s| compile_stmt(ctx, s))
         .collect::<Result<Vec<_>, _>>()?
         .into_iter()
@@ -450,22 +813,108 @@ pub fn compile_stmts(
         .collect())
 }
 
+pub fn latex_stmt_to_value(l: LatexStatement) -> graph::ExpressionValue {
+    match l {
+        LatexStatement::Note(text) => graph::ExpressionValue::note(text),
+        // colors are checked when the with block is compiled
+        LatexStatement::Styled(style, l) => latex_stmt_to_value(*l)
+            .with_style(style.color.and_then(|c| c.parse().ok()), style.hidden),
+        l => {
+            let expression_type = expression_type(&l);
+            let v = graph::ExpressionValue::latex(latex::latex_stmt_to_str(l));
+            match expression_type {
+                Some(t) => v.plotted_as(t),
+                None => v,
+            }
+        }
+    }
+}
+
+// Points and polygons written out in the expression, or assigned to a variable
+fn expression_type(l: &LatexStatement) -> Option<graph::FormulaExpressionType> {
+    let val = match l {
+        LatexStatement::Expression(val) => val,
+        LatexStatement::Assignment(_, val) => val.as_ref(),
+        _ => return None,
+    };
+    match val.unspanned() {
+        Latex::Point { .. } => Some(graph::FormulaExpressionType::SinglePoint),
+        Latex::List(items)
+            if !items.is_empty()
+                && items
+                    .iter()
+                    .all(|i| matches!(i.unspanned(), Latex::Point { .. })) =>
+        {
+            Some(graph::FormulaExpressionType::PointList)
+        }
+        Latex::Call {
+            func: latex::Function::Normal { name },
+            is_builtin: true,
+            ..
+        } if name == "polygon" => Some(graph::FormulaExpressionType::Polygon),
+        _ => None,
+    }
+}
+
+// `a = 1` or `a = -1`, which desmos can show as a slider
+fn is_constant_definition(l: &LatexStatement) -> bool {
+    match l {
+        LatexStatement::Assignment(_, val) => match val.unspanned() {
+            Latex::Num(_) => true,
+            Latex::UnaryExpression {
+                left,
+                operator: LatexUnaryOperator::Negate,
+            } => matches!(left.unspanned(), Latex::Num(_)),
+            _ => false,
+        },
+        LatexStatement::Styled(_, l) => is_constant_definition(l),
+        _ => false,
+    }
+}
+
+pub fn latex_stmts_to_graph(stmts: Vec<LatexStatement>) -> graph::CalcState {
+    graph::CalcState {
+        expressions: graph::Expressions::from_values(
+            stmts.into_iter().map(latex_stmt_to_value).collect(),
+        ),
+        ..Default::default()
+    }
+}
+
 pub fn stmts_to_graph(
     ctx: &mut Context,
     stmts: Vec<ast::Spanned<ast::Statement>>,
 ) -> Cesult<graph::CalcState> {
-    let latex_exprs = stmts
-        .into_iter()
-        .map(|s| compile_stmt(ctx, s))
-        .collect::<Cesult<Vec<_>>>()?;
+    let viewport = if ctx.fit_viewport {
+        super::viewport::fit_viewport(&stmts)
+    } else {
+        None
+    };
+    // Most statements emit exactly one expression
+    let mut values = Vec::with_capacity(stmts.len());
+    for s in stmts {
+        values.extend(compile_stmt(ctx, s)?.into_iter().map(|l| {
+            let slider = ctx
+                .slider_bounds
+                .clone()
+                .filter(|_| is_constant_definition(&l));
+            let v = latex_stmt_to_value(l);
+            match slider {
+                Some(graph::SliderBounds { min, max, step }) => v.with_slider(min, max, step),
+                None => v,
+            }
+        }));
+    }
+    let expressions = graph::Expressions::from_values(values);
     Ok(graph::CalcState {
-        expressions: graph::Expressions::from_latex_strings(
-            latex_exprs
-                .into_iter()
-                .flatten()
-                .map(|l| latex::latex_stmt_to_str(l))
-                .collect(),
-        ),
+        expressions: match ctx.ticker.clone() {
+            Some((handler, min_step)) => expressions.with_ticker(handler, min_step),
+            None => expressions,
+        },
+        graph: match viewport {
+            Some(viewport) => Some(graph::Graph { viewport }),
+            None => graph::CalcState::default().graph,
+        },
         ..Default::default()
     })
 }
@@ -538,6 +987,9 @@ pub mod tests {
             Expression::Num("2.3".to_string()),
             Latex::Num("2.3".to_string()),
         );
+        let latex = |n: &str| latex::latex_to_str(compile(Expression::Num(n.to_string())).unwrap());
+        assert_eq!(latex("1e400"), "1\\cdot 10^{400}");
+        assert_eq!(latex("1.5e-21"), "1.5\\cdot 10^{-21}");
     }
 
     #[test]
@@ -606,6 +1058,44 @@ pub mod tests {
         );
     }
 
+    #[test]
+    fn mod_op_matches_call() {
+        let a = || (spn(), Expression::Variable("a".to_string()));
+        let b = || (spn(), Expression::Variable("b".to_string()));
+        let mut ctx = new_ctx();
+        ctx.variables
+            .insert("a".to_string(), (ValType::Number, tinfo()));
+        ctx.variables
+            .insert("b".to_string(), (ValType::Number, tinfo()));
+        let op = compile_with_ctx(
+            &mut ctx,
+            Expression::BinaryExpr {
+                left: Box::new(a()),
+                operator: BinaryOperator::Mod,
+                right: Box::new(b()),
+            },
+        )
+        .unwrap();
+        let call = compile_with_ctx(
+            &mut ctx,
+            Expression::Call {
+                func: ast::Function::Normal {
+                    name: "mod".to_string(),
+                },
+                args: vec![a(), b()],
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            latex::latex_to_str(op),
+            "\\operatorname{mod}\\left(a,b\\right)"
+        );
+        assert_eq!(
+            latex::latex_to_str(call),
+            "\\operatorname{mod}\\left(a,b\\right)"
+        );
+    }
+
     #[test]
     fn unary_expression() {
         check(
@@ -622,22 +1112,26 @@ pub mod tests {
 
     #[test]
     fn binexp_typecheck() {
-        assert_eq!(
-            compile(Expression::BinaryExpr {
-                left: Box::new((
-                    spn(),
-                    Expression::List(vec![(spn(), Expression::Num("1".to_string()))])
-                )),
-                operator: BinaryOperator::Add,
-                right: Box::new((spn(), Expression::Num("2".to_string())))
-            })
-            .unwrap_err()
-            .kind,
+        // numbers broadcast over lists, see list_arithmetic
+        assert!(compile(Expression::BinaryExpr {
+            left: Box::new((
+                spn(),
+                Expression::List(vec![(spn(), Expression::Num("1".to_string()))])
+            )),
+            operator: BinaryOperator::Add,
+            right: Box::new((spn(), Expression::Num("2".to_string())))
+        })
+        .is_ok());
+        // but it's ambiguous whether to map over the mapped list or the list
+        assert!(matches!(
+            compile_src(&mut new_ctx(), "L = [1, 2]\n@L + [1]")
+                .unwrap_err()
+                .kind,
             CompileErrorKind::ExpectedSameTypes {
-                left: (Typ::List, todo!()),
-                right: (Typ::Num, todo!())
+                left: (Typ::MappedList, _),
+                right: (Typ::List(ElemType::Number), _)
             }
-        );
+        ));
     }
 
     #[test]
@@ -723,7 +1217,7 @@ pub mod tests {
                 FunctionDefinition {
                     name: "f".to_string(),
                     args: vec![
-                        (spn(), "abc".to_string(), ValType::List),
+                        (spn(), "abc".to_string(), ValType::List(ElemType::Number)),
                         (spn(), "def".to_string(), ValType::Number),
                     ],
                     ret_annotation: None,
@@ -778,7 +1272,7 @@ pub mod tests {
                 FunctionDefinition {
                     name: "f".to_string(),
                     args: vec![(spn(), "a".to_string(), ValType::Number)],
-                    ret_annotation: Some(ValType::List),
+                    ret_annotation: Some(ValType::List(ElemType::Number)),
                     inline: false,
                 },
                 (spn(), Expression::Num("1".to_string())),
@@ -787,7 +1281,7 @@ pub mod tests {
             CompileError {
                 kind: CompileErrorKind::RetAnnMismatch {
                     got: (Typ::Num, tinfo()),
-                    expected: ValType::List
+                    expected: ValType::List(ElemType::Number)
                 },
                 span: spn()
             },
@@ -919,7 +1413,11 @@ pub mod tests {
                 span: spn(),
                 kind: CompileErrorKind::ArgTypeMismatch {
                     expected: ValType::Number,
-                    got: (Typ::List, todo!())
+                    got: (
+                        Typ::List(ElemType::Number),
+                        TypInfo::Literal(Literal::List, spn())
+                    ),
+                    index: Some(0),
                 }
             }
         );
@@ -929,20 +1427,25 @@ pub mod tests {
     fn funcdef_catch_shadow() {
         let mut ctx = new_ctx();
         ctx.variables
-            .insert("a".to_string(), (ValType::Number, tinfo()));
+            .insert("a".to_string(), (ValType::List(ElemType::Number), tinfo()));
+        let funcdef = |args: &[&str]| {
+            Statement::FuncDef(
+                FunctionDefinition {
+                    args: args
+                        .iter()
+                        .map(|a| (spn(), a.to_string(), ValType::Number))
+                        .collect(),
+                    name: "f".to_string(),
+                    ret_annotation: Some(ValType::Number),
+                    inline: false,
+                },
+                (spn(), Expression::Variable("a".to_string())),
+            )
+        };
+        // the argument shadows the variable in the body
+        assert!(compile_stmt_with_ctx(&mut ctx, funcdef(&["a"])).is_ok());
         assert_eq!(
-            compile_stmt_with_ctx(
-                &mut ctx,
-                Statement::FuncDef(
-                    FunctionDefinition {
-                        args: vec![(spn(), "a".to_string(), ValType::Number)],
-                        name: "f".to_string(),
-                        ret_annotation: None,
-                        inline: false,
-                    },
-                    (spn(), Expression::Num("1".to_string())),
-                )
-            ),
+            compile_stmt_with_ctx(&mut ctx, funcdef(&["b", "a", "a"])),
             Err(CompileError {
                 span: spn(),
                 kind: CompileErrorKind::DuplicateVariable("a".to_string()),
@@ -950,6 +1453,51 @@ pub mod tests {
         );
     }
 
+    fn compile_src(ctx: &mut Context, src: &str) -> Cesult<Vec<String>> {
+        let ast = parser::lex_and_parse(0, src.to_string()).0.unwrap();
+        Ok(compile_stmts(ctx, ast)?
+            .into_iter()
+            .map(latex::latex_stmt_to_str)
+            .collect())
+    }
+
+    #[test]
+    fn funcdef_arg_scope() {
+        let mut ctx = new_ctx();
+        assert_eq!(
+            compile_src(&mut ctx, "L = [1, 2]\nf(L) = L + 1\ntotal(L)"),
+            Ok(vec![
+                "L=\\left[1,2\\right]".to_string(),
+                "f\\left(L\\right)=(L)+1".to_string(),
+                "\\operatorname{total}\\left(L\\right)".to_string(),
+            ])
+        );
+        assert!(ctx.locals.is_empty());
+        // a sum's variable shadows the argument only inside the sum
+        assert!(compile_src(&mut ctx, "g(n: list): num = sum(n, 1, 3, n)").is_ok());
+        assert!(ctx.locals.is_empty());
+    }
+
+    #[test]
+    fn funcdef_arg_leakage() {
+        let mut ctx = new_ctx();
+        assert_eq!(
+            compile_src(&mut ctx, "f(x) = x\ng(y) = x")
+                .unwrap_err()
+                .kind,
+            CompileErrorKind::UndefinedVariable("x".to_string())
+        );
+        // args are out of scope even after the body fails to compile
+        let mut ctx = new_ctx();
+        assert!(compile_src(&mut ctx, "f(x) = x + c").is_err());
+        assert!(compile_src(&mut ctx, "f(x): list = x").is_err());
+        assert!(ctx.locals.is_empty());
+        assert_eq!(
+            compile_src(&mut ctx, "x").unwrap_err().kind,
+            CompileErrorKind::UndefinedVariable("x".to_string())
+        );
+    }
+
     #[test]
     fn piecewise_single() {
         let mut ctx = new_ctx();
@@ -963,9 +1511,14 @@ pub mod tests {
                     first: Box::new((
                         spn(),
                         Branch {
-                            cond_left: (spn(), Expression::Variable("a".to_string())),
-                            cond: CompareOperator::Equal,
-                            cond_right: (spn(), Expression::Num("1".to_string())),
+                            cond: (
+                                spn(),
+                                Expression::Comparison {
+                                    left: Box::new((spn(), Expression::Variable("a".to_string()))),
+                                    op: CompareOperator::Equal,
+                                    right: Box::new((spn(), Expression::Num("1".to_string())))
+                                }
+                            ),
                             val: (spn(), Expression::Num("2".to_string())),
                         }
                     )),
@@ -978,6 +1531,7 @@ pub mod tests {
                     left: Latex::Variable("a".to_string()),
                     op: CompareOperator::Equal,
                     right: Latex::Num("1".to_string()),
+                    chain: None,
                     result: Latex::Num("2".to_string())
                 }),
                 rest: vec![],
@@ -992,9 +1546,14 @@ pub mod tests {
         ctx.variables
             .insert("a".to_string(), (ValType::Number, tinfo()));
         let firstbranch = Branch {
-            cond_left: (spn(), Expression::Variable("a".to_string())),
-            cond: CompareOperator::GreaterThanEqual,
-            cond_right: (spn(), Expression::Num("1".to_string())),
+            cond: (
+                spn(),
+                Expression::Comparison {
+                    left: Box::new((spn(), Expression::Variable("a".to_string()))),
+                    op: CompareOperator::GreaterThanEqual,
+                    right: Box::new((spn(), Expression::Num("1".to_string()))),
+                },
+            ),
             val: (spn(), Expression::Num("2".to_string())),
         };
         let ast = Expression::Piecewise {
@@ -1003,27 +1562,42 @@ pub mod tests {
                 (
                     spn(),
                     Branch {
-                        cond_left: (spn(), Expression::Variable("a".to_string())),
-                        cond: CompareOperator::LessThanEqual,
-                        cond_right: (spn(), Expression::Num("3".to_string())),
+                        cond: (
+                            spn(),
+                            Expression::Comparison {
+                                left: Box::new((spn(), Expression::Variable("a".to_string()))),
+                                op: CompareOperator::LessThanEqual,
+                                right: Box::new((spn(), Expression::Num("3".to_string()))),
+                            },
+                        ),
                         val: (spn(), Expression::Num("4".to_string())),
                     },
                 ),
                 (
                     spn(),
                     Branch {
-                        cond_left: (spn(), Expression::Variable("a".to_string())),
-                        cond: CompareOperator::LessThan,
-                        cond_right: (spn(), Expression::Num("5".to_string())),
+                        cond: (
+                            spn(),
+                            Expression::Comparison {
+                                left: Box::new((spn(), Expression::Variable("a".to_string()))),
+                                op: CompareOperator::LessThan,
+                                right: Box::new((spn(), Expression::Num("5".to_string()))),
+                            },
+                        ),
                         val: (spn(), Expression::Num("6".to_string())),
                     },
                 ),
                 (
                     spn(),
                     Branch {
-                        cond_left: (spn(), Expression::Variable("a".to_string())),
-                        cond: CompareOperator::GreaterThan,
-                        cond_right: (spn(), Expression::Num("7".to_string())),
+                        cond: (
+                            spn(),
+                            Expression::Comparison {
+                                left: Box::new((spn(), Expression::Variable("a".to_string()))),
+                                op: CompareOperator::GreaterThan,
+                                right: Box::new((spn(), Expression::Num("7".to_string()))),
+                            },
+                        ),
                         val: (spn(), Expression::Num("8".to_string())),
                     },
                 ),
@@ -1038,6 +1612,7 @@ pub mod tests {
                     left: Latex::Variable("a".to_string()),
                     op: CompareOperator::GreaterThanEqual,
                     right: Latex::Num("1".to_string()),
+                    chain: None,
                     result: Latex::Num("2".to_string())
                 }),
                 rest: vec![
@@ -1045,18 +1620,21 @@ pub mod tests {
                         left: Latex::Variable("a".to_string()),
                         op: CompareOperator::LessThanEqual,
                         right: Latex::Num("3".to_string()),
+                        chain: None,
                         result: Latex::Num("4".to_string())
                     },
                     Cond {
                         left: Latex::Variable("a".to_string()),
                         op: CompareOperator::LessThan,
                         right: Latex::Num("5".to_string()),
+                        chain: None,
                         result: Latex::Num("6".to_string())
                     },
                     Cond {
                         left: Latex::Variable("a".to_string()),
                         op: CompareOperator::GreaterThan,
                         right: Latex::Num("7".to_string()),
+                        chain: None,
                         result: Latex::Num("8".to_string())
                     }
                 ],
@@ -1066,17 +1644,130 @@ pub mod tests {
     }
 
     #[test]
-    fn var_assign() {
-        let mut ctx = new_ctx();
+    fn double_inequality() {
+        let compile = |src: &str| compile_src(&mut new_ctx(), src).map_err(|e| e.kind);
         assert_eq!(
-            compile_with_ctx(&mut ctx, ast::Expression::Variable("test".to_string())),
-            Err(CompileError {
-                kind: CompileErrorKind::UndefinedVariable("test".to_string()),
-                span: spn(),
-            })
+            compile("f(x) = where 0 < x <= 1 -> x, else 0"),
+            Ok(vec![
+                "f\\left(x\\right)=\\left\\{0<x\\le1:x,0\\right\\}".to_string()
+            ])
         );
+        // outside of a piecewise it's a boolean
         assert_eq!(
-            compile_stmt_with_ctx(
+            compile("a = 1\nb = 2 > a >= 1"),
+            Ok(vec![
+                "a=1".to_string(),
+                "b=\\left\\{2>a\\ge1:1,0\\right\\}".to_string()
+            ])
+        );
+        // each half is typechecked
+        assert!(matches!(
+            compile("where 0 < (1, 2) < 1 -> 1, else 0"),
+            Err(CompileErrorKind::PointArithmetic)
+        ));
+        assert!(matches!(
+            compile("L = [1]\nwhere 0 < @L < [1] -> 1, else 0"),
+            Err(CompileErrorKind::ExpectedSameTypes { .. })
+        ));
+    }
+
+    #[test]
+    fn comprehension() {
+        let compile = |src: &str| compile_src(&mut new_ctx(), src).map_err(|e| e.kind);
+        assert_eq!(
+            compile("L = [n^2 for n = [1...3]]\nlength(L)"),
+            Ok(vec![
+                "L=\\left[(n)^{2}\\operatorname{for}n=\\left[1,...,3\\right]\\right]".to_string(),
+                "\\operatorname{length}\\left(L\\right)".to_string(),
+            ])
+        );
+        assert_eq!(
+            compile("[(n, 2n) for n = [1, 2]]"),
+            Ok(vec![
+                "\\left[\\left(n,2n\\right)\\operatorname{for}n=\\left[1,2\\right]\\right]"
+                    .to_string()
+            ])
+        );
+        // Desmos has no nested lists
+        assert_eq!(
+            compile("[[n] for n = [1...3]]"),
+            Err(CompileErrorKind::NoNestedList)
+        );
+        assert!(matches!(
+            compile("[n for n = 3]"),
+            Err(CompileErrorKind::ArgTypeMismatch {
+                expected: ValType::List(ElemType::Number),
+                ..
+            })
+        ));
+        // the variable is only bound in the body
+        assert!(matches!(
+            compile("[n for n = [1...3]]\nn"),
+            Err(CompileErrorKind::UndefinedVariable(_))
+        ));
+    }
+
+    #[test]
+    fn piecewise_branch_types() {
+        let branch = |val: Expression| {
+            (
+                spn(),
+                Branch {
+                    cond: (
+                        spn(),
+                        Expression::Comparison {
+                            left: Box::new((spn(), Expression::Num("1".to_string()))),
+                            op: CompareOperator::LessThan,
+                            right: Box::new((spn(), Expression::Num("2".to_string()))),
+                        },
+                    ),
+                    val: (spn(), val),
+                },
+            )
+        };
+        let list = Expression::List(vec![(spn(), Expression::Num("3".to_string()))]);
+        // the type comes from the values, not the conditions
+        let (_, t, _) = compile_expr(
+            &mut new_ctx(),
+            (
+                spn(),
+                Expression::Piecewise {
+                    first: Box::new(branch(list)),
+                    rest: vec![],
+                    default: Box::new((spn(), Expression::Num("4".to_string()))),
+                },
+            ),
+        )
+        .unwrap();
+        assert_eq!(t, Typ::List(ElemType::Number));
+        assert_eq!(
+            compile(Expression::Piecewise {
+                first: Box::new(branch(point("1", "2"))),
+                rest: vec![],
+                default: Box::new((spn(), Expression::Num("4".to_string()))),
+            }),
+            Err(CompileError {
+                kind: CompileErrorKind::ExpectedSameTypes {
+                    left: (Typ::Point, TypInfo::Literal(Literal::Point, spn())),
+                    right: (Typ::Num, tinfo()),
+                },
+                span: spn(),
+            })
+        );
+    }
+
+    #[test]
+    fn var_assign() {
+        let mut ctx = new_ctx();
+        assert_eq!(
+            compile_with_ctx(&mut ctx, ast::Expression::Variable("test".to_string())),
+            Err(CompileError {
+                kind: CompileErrorKind::UndefinedVariable("test".to_string()),
+                span: spn(),
+            })
+        );
+        assert_eq!(
+            compile_stmt_with_ctx(
                 &mut ctx,
                 ast::Statement::VarDef {
                     name: "test".to_string(),
@@ -1095,6 +1786,935 @@ pub mod tests {
         );
     }
 
+    fn compile_to_strs(ctx: &mut Context, stmts: Vec<Statement>) -> Cesult<Vec<String>> {
+        Ok(
+            compile_stmts(ctx, stmts.into_iter().map(|s| (spn(), s)).collect())?
+                .into_iter()
+                .map(latex::latex_stmt_to_str)
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn recovered_statements() {
+        let (ast, errs) = parser::lex_and_parse(0, "a = = 1;\nf(x) = x\nf(2)".to_string());
+        assert!(!errs.is_empty());
+        let mut ctx = new_ctx();
+        let out = compile_stmts(&mut ctx, ast.unwrap()).unwrap();
+        assert_eq!(
+            out.into_iter()
+                .map(latex::latex_stmt_to_str)
+                .collect::<Vec<_>>(),
+            vec!["f\\left(x\\right)=x", "f\\left(2\\right)"]
+        );
+        assert!(ctx.defined_functions.contains_key("f"));
+        assert_eq!(
+            compile(Expression::Error).unwrap_err().kind,
+            CompileErrorKind::InvalidSyntax
+        );
+    }
+
+    #[test]
+    fn graph_matches_compiled_strs() {
+        let stmts = (0..50)
+            .map(|i| Statement::VarDef {
+                name: format!("a{}", i),
+                val: (spn(), Expression::Num(i.to_string())),
+                inline: false,
+            })
+            .collect::<Vec<_>>();
+        let strs = compile_to_strs(&mut new_ctx(), stmts.clone()).unwrap();
+        let graph = stmts_to_graph(
+            &mut new_ctx(),
+            stmts.into_iter().map(|s| (spn(), s)).collect(),
+        )
+        .unwrap();
+        assert_eq!(
+            graph.expressions,
+            graph::Expressions::from_latex_strings(strs)
+        );
+    }
+
+    #[test]
+    fn shadows_builtin() {
+        let def = |name: &str| {
+            Statement::FuncDef(
+                FunctionDefinition {
+                    name: name.to_string(),
+                    args: vec![(spn(), "x".to_string(), ValType::Number)],
+                    ret_annotation: None,
+                    inline: false,
+                },
+                (spn(), Expression::Variable("x".to_string())),
+            )
+        };
+        let mut ctx = new_ctx();
+        compile_stmt_with_ctx(&mut ctx, def("f")).unwrap();
+        compile_stmt_with_ctx(&mut ctx, def("sin")).unwrap();
+        assert_eq!(
+            ctx.warnings,
+            vec![CompileWarning {
+                kind: CompileWarningKind::ShadowsBuiltin("sin".to_string()),
+                span: spn(),
+            }]
+        );
+        assert!(ctx.defined_functions.contains_key("sin"));
+
+        let mut ctx = new_ctx();
+        ctx.deny_builtin_shadowing = true;
+        compile_stmt_with_ctx(&mut ctx, def("f")).unwrap();
+        assert_eq!(
+            compile_stmt_with_ctx(&mut ctx, def("sin")),
+            Err(CompileError {
+                kind: CompileErrorKind::ShadowsBuiltin {
+                    name: "sin".to_string()
+                },
+                span: spn(),
+            })
+        );
+        assert!(!ctx.defined_functions.contains_key("sin"));
+    }
+
+    #[test]
+    fn shadows_binding_call() {
+        let def = |name: &str| {
+            Statement::FuncDef(
+                FunctionDefinition {
+                    name: name.to_string(),
+                    args: vec![(spn(), "L".to_string(), ValType::List(ElemType::Number))],
+                    ret_annotation: None,
+                    inline: false,
+                },
+                (
+                    spn(),
+                    Expression::Call {
+                        func: ast::Function::Normal {
+                            name: "total".to_string(),
+                        },
+                        args: vec![(spn(), Expression::Variable("L".to_string()))],
+                    },
+                ),
+            )
+        };
+        let call = |name: &str| {
+            Statement::Expression(Expression::Call {
+                func: ast::Function::Normal {
+                    name: name.to_string(),
+                },
+                args: vec![(
+                    spn(),
+                    Expression::List(vec![(spn(), Expression::Num("1".to_string()))]),
+                )],
+            })
+        };
+        let mut ctx = new_ctx();
+        for name in ["sum", "prod"] {
+            compile_stmt_with_ctx(&mut ctx, def(name)).unwrap();
+            // the user's function is called rather than the binding builtin
+            assert!(compile_stmt_with_ctx(&mut ctx, call(name)).is_ok());
+        }
+        assert_eq!(
+            ctx.warnings,
+            ["sum", "prod"]
+                .iter()
+                .map(|name| CompileWarning {
+                    kind: CompileWarningKind::ShadowsBuiltin(name.to_string()),
+                    span: spn(),
+                })
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn sliders() {
+        let def = |name: &str, val| {
+            (
+                spn(),
+                Statement::VarDef {
+                    name: name.to_string(),
+                    val: (spn(), val),
+                    inline: false,
+                },
+            )
+        };
+        let num = |n: &str| Expression::Num(n.to_string());
+        let stmts = vec![
+            def("a", num("1")),
+            def(
+                "b",
+                Expression::UnaryExpr {
+                    val: Box::new((spn(), num("2"))),
+                    operator: UnaryOperator::Negate,
+                },
+            ),
+            def("c", Expression::Variable("a".to_string())),
+            (spn(), Statement::Expression(num("3"))),
+        ];
+        // off by default
+        let graph = stmts_to_graph(&mut new_ctx(), stmts.clone()).unwrap();
+        assert_eq!(
+            graph.expressions,
+            graph::Expressions::from_latex_strings(
+                ["a=1", "b=-2", "c=a", "3"].map(String::from).to_vec()
+            )
+        );
+
+        let mut ctx = new_ctx();
+        ctx.slider_bounds = Some(graph::SliderBounds::new(
+            "0".to_string(),
+            "10".to_string(),
+            Some("1".to_string()),
+        ));
+        let graph = stmts_to_graph(&mut ctx, stmts).unwrap();
+        let slider = |l: &str| {
+            graph::ExpressionValue::latex(l.to_string()).with_slider(
+                "0".to_string(),
+                "10".to_string(),
+                Some("1".to_string()),
+            )
+        };
+        assert_eq!(
+            graph.expressions,
+            graph::Expressions::from_values(vec![
+                slider("a=1"),
+                slider("b=-2"),
+                graph::ExpressionValue::latex("c=a".to_string()),
+                graph::ExpressionValue::latex("3".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn note() {
+        let graph = stmts_to_graph(
+            &mut new_ctx(),
+            vec![
+                (spn(), Statement::Note("hello".to_string())),
+                (
+                    spn(),
+                    Statement::Expression(Expression::Num("1".to_string())),
+                ),
+            ],
+        )
+        .unwrap();
+        let values = graph
+            .expressions
+            .list
+            .into_iter()
+            .map(|e| e.value)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            vec![
+                graph::ExpressionValue::note("hello".to_string()),
+                graph::ExpressionValue::latex("1".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn polygon() {
+        let ast = parser::lex_and_parse(
+            0,
+            "P = [(0, 0), (1, 0)]\npolygon((0, 0), (1, 0), (1, 1))\npolygon(P)\n(1, 2)\n1"
+                .to_string(),
+        )
+        .0
+        .unwrap();
+        let graph = stmts_to_graph(&mut new_ctx(), ast).unwrap();
+        let value = |l: &str, t| graph::ExpressionValue::latex(l.to_string()).plotted_as(t);
+        assert_eq!(
+            graph.expressions,
+            graph::Expressions::from_values(vec![
+                value(
+                    "P=\\left[\\left(0,0\\right),\\left(1,0\\right)\\right]",
+                    graph::FormulaExpressionType::PointList
+                ),
+                value(
+                    "\\operatorname{polygon}\\left(\\left(0,0\\right),\\left(1,0\\right),\\left(1,1\\right)\\right)",
+                    graph::FormulaExpressionType::Polygon
+                ),
+                value(
+                    "\\operatorname{polygon}\\left(P\\right)",
+                    graph::FormulaExpressionType::Polygon
+                ),
+                value(
+                    "\\left(1,2\\right)",
+                    graph::FormulaExpressionType::SinglePoint
+                ),
+                graph::ExpressionValue::latex("1".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn ticker() {
+        let graph = |src: &str| {
+            let ast = parser::lex_and_parse(0, src.to_string()).0.unwrap();
+            stmts_to_graph(&mut new_ctx(), ast)
+        };
+        let state = graph("a = 0\n@ticker(raw<num>(\"a\\to a+1\"), 10 * 10)").unwrap();
+        assert_eq!(
+            state.expressions,
+            graph::Expressions::from_latex_strings(vec!["a=0".to_string()])
+                .with_ticker("a\\to a+1".to_string(), Some("10\\cdot 10".to_string()))
+        );
+        assert_eq!(graph("a = 0").unwrap().expressions.ticker, None);
+
+        assert!(matches!(
+            graph("@ticker(1, [1])").unwrap_err().kind,
+            CompileErrorKind::ArgTypeMismatch {
+                expected: ValType::Number,
+                ..
+            }
+        ));
+        assert_eq!(
+            graph("@ticker(1)\n@ticker(2)").unwrap_err().kind,
+            CompileErrorKind::DuplicateTicker
+        );
+    }
+
+    #[test]
+    fn with_block() {
+        let style = |color: &str| types::Style {
+            color: Some(color.to_string()),
+            hidden: None,
+        };
+        let num = |n: &str| (spn(), Statement::Expression(Expression::Num(n.to_string())));
+        let graph = stmts_to_graph(
+            &mut new_ctx(),
+            vec![(
+                spn(),
+                Statement::With(
+                    types::Style {
+                        hidden: Some(true),
+                        ..style("#f00")
+                    },
+                    vec![
+                        num("1"),
+                        (spn(), Statement::With(style("#00f"), vec![num("2")])),
+                        num("3"),
+                    ],
+                ),
+            )],
+        )
+        .unwrap();
+        let values = graph
+            .expressions
+            .list
+            .into_iter()
+            .map(|e| e.value)
+            .collect::<Vec<_>>();
+        let styled = |n: &str, color: &str| {
+            graph::ExpressionValue::latex(n.to_string())
+                .with_style(Some(color.parse().unwrap()), Some(true))
+        };
+        assert_eq!(
+            values,
+            vec![
+                styled("1", "#f00"),
+                styled("2", "#00f"),
+                styled("3", "#f00")
+            ]
+        );
+
+        assert_eq!(
+            compile_stmt(Statement::With(style("f00"), vec![num("1")]))
+                .unwrap_err()
+                .kind,
+            CompileErrorKind::InvalidColor(graph::ColorError::InvalidHex("f00".to_string()))
+        );
+    }
+
+    #[test]
+    fn function_signature_string() {
+        let mut ctx = new_ctx();
+        for (name, inline) in [("f", false), ("g", true)] {
+            compile_stmt_with_ctx(
+                &mut ctx,
+                Statement::FuncDef(
+                    FunctionDefinition {
+                        name: name.to_string(),
+                        args: vec![
+                            (spn(), "x".to_string(), ValType::Number),
+                            (spn(), "L".to_string(), ValType::List(ElemType::Number)),
+                        ],
+                        ret_annotation: None,
+                        inline,
+                    },
+                    (spn(), Expression::Variable("x".to_string())),
+                ),
+            )
+            .unwrap();
+        }
+        let sig = |name| ctx.function_signature_string(name);
+        assert_eq!(
+            sig("f"),
+            Some("f(x: number, L: list) -> number".to_string())
+        );
+        assert_eq!(
+            sig("g"),
+            Some("g(x: number, L: list) -> number".to_string())
+        );
+        assert_eq!(sig("mean"), Some("mean(list) -> number".to_string()));
+        assert_eq!(
+            sig("round"),
+            Some("round(number, number?) -> number".to_string())
+        );
+        assert_eq!(sig("h"), None);
+    }
+
+    #[test]
+    fn range() {
+        let num = |n: &str| Box::new((spn(), Expression::Num(n.to_string())));
+        let range = |second| Expression::Range {
+            first: num("1"),
+            second,
+            end: num("11"),
+        };
+        let (l, t, _) = compile_expr(&mut new_ctx(), (spn(), range(None))).unwrap();
+        assert_eq!(latex::latex_to_str(l), "\\left[1,...,11\\right]");
+        assert_eq!(t, Typ::List(ElemType::Number));
+        let l = compile(range(Some(num("3")))).unwrap();
+        assert_eq!(latex::latex_to_str(l), "\\left[1,3,...,11\\right]");
+    }
+
+    fn point(x: &str, y: &str) -> Expression {
+        Expression::Point {
+            x: Box::new((spn(), Expression::Num(x.to_string()))),
+            y: Box::new((spn(), Expression::Num(y.to_string()))),
+        }
+    }
+
+    #[test]
+    fn bool_function() {
+        let mut ctx = new_ctx();
+        let out = compile_stmt_with_ctx(
+            &mut ctx,
+            Statement::FuncDef(
+                FunctionDefinition {
+                    name: "f".to_string(),
+                    args: vec![(spn(), "x".to_string(), ValType::Number)],
+                    ret_annotation: Some(ValType::Bool),
+                    inline: false,
+                },
+                (
+                    spn(),
+                    Expression::Comparison {
+                        left: Box::new((spn(), Expression::Variable("x".to_string()))),
+                        op: CompareOperator::GreaterThan,
+                        right: Box::new((spn(), Expression::Num("1".to_string()))),
+                    },
+                ),
+            ),
+        )
+        .unwrap();
+        assert_eq!(
+            latex::latex_stmt_to_str(out[0].clone()),
+            "f\\left(x\\right)=\\left\\{x>1:1,0\\right\\}"
+        );
+        let call = Expression::Call {
+            func: ast::Function::Normal {
+                name: "f".to_string(),
+            },
+            args: vec![(spn(), Expression::Num("2".to_string()))],
+        };
+        let piecewise = |cond: Expression| Expression::Piecewise {
+            first: Box::new((
+                spn(),
+                Branch {
+                    cond: (spn(), cond),
+                    val: (spn(), Expression::Num("3".to_string())),
+                },
+            )),
+            rest: vec![],
+            default: Box::new((spn(), Expression::Num("4".to_string()))),
+        };
+        assert_eq!(
+            latex::latex_to_str(compile_with_ctx(&mut ctx, piecewise(call.clone())).unwrap()),
+            "\\left\\{f\\left(2\\right)=1:3,4\\right\\}"
+        );
+        assert_eq!(
+            compile_with_ctx(
+                &mut ctx,
+                Expression::BinaryExpr {
+                    left: Box::new((spn(), call)),
+                    operator: BinaryOperator::Add,
+                    right: Box::new((spn(), Expression::Num("1".to_string()))),
+                }
+            ),
+            Err(CompileError {
+                kind: CompileErrorKind::BoolArithmetic,
+                span: spn(),
+            })
+        );
+        assert_eq!(
+            compile(piecewise(Expression::Num("1".to_string()))),
+            Err(CompileError {
+                kind: CompileErrorKind::ExpectedBool(Typ::Num, tinfo()),
+                span: spn(),
+            })
+        );
+    }
+
+    #[test]
+    fn point_arg() {
+        let mut ctx = new_ctx();
+        compile_stmt_with_ctx(
+            &mut ctx,
+            Statement::FuncDef(
+                FunctionDefinition {
+                    name: "f".to_string(),
+                    args: vec![(spn(), "p".to_string(), ValType::Point)],
+                    ret_annotation: Some(ValType::Point),
+                    inline: false,
+                },
+                (spn(), Expression::Variable("p".to_string())),
+            ),
+        )
+        .unwrap();
+        let (l, t, _) = compile_expr(
+            &mut ctx,
+            (
+                spn(),
+                Expression::Call {
+                    func: ast::Function::Normal {
+                        name: "f".to_string(),
+                    },
+                    args: vec![(spn(), point("1", "2"))],
+                },
+            ),
+        )
+        .unwrap();
+        assert_eq!(latex::latex_to_str(l), "f\\left(\\left(1,2\\right)\\right)");
+        assert_eq!(t, Typ::Point);
+    }
+
+    #[test]
+    fn point_list() {
+        let list = |items: Vec<Expression>| {
+            Expression::List(items.into_iter().map(|e| (spn(), e)).collect())
+        };
+        let (_, t, _) = compile_expr(
+            &mut new_ctx(),
+            (spn(), list(vec![point("1", "2"), point("3", "4")])),
+        )
+        .unwrap();
+        assert_eq!(t, Typ::List(ElemType::Point));
+        assert_eq!(
+            compile(list(vec![
+                point("1", "2"),
+                Expression::Num("3".to_string())
+            ])),
+            Err(CompileError {
+                kind: CompileErrorKind::ExpectedSameTypes {
+                    left: (Typ::Point, TypInfo::Literal(Literal::Point, spn())),
+                    right: (Typ::Num, tinfo()),
+                },
+                span: spn(),
+            })
+        );
+        assert_eq!(
+            compile(Expression::BinaryExpr {
+                left: Box::new((spn(), point("1", "2"))),
+                operator: BinaryOperator::Add,
+                right: Box::new((spn(), point("3", "4"))),
+            }),
+            Err(CompileError {
+                kind: CompileErrorKind::PointArithmetic,
+                span: spn(),
+            })
+        );
+    }
+
+    #[test]
+    fn map_as_variable() {
+        let mut ctx = new_ctx();
+        ctx.variables
+            .insert("l".to_string(), (ValType::List(ElemType::Number), tinfo()));
+        let map = (
+            types::Span::new(1234, 4..6),
+            Expression::Map(Box::new((spn(), Expression::Variable("l".to_string())))),
+        );
+        assert_eq!(
+            compile_stmt_with_ctx(
+                &mut ctx,
+                Statement::VarDef {
+                    name: "a".to_string(),
+                    val: map.clone(),
+                    inline: false,
+                },
+            ),
+            Err(CompileError {
+                kind: CompileErrorKind::MapAsVariable,
+                span: map.0.clone(),
+            })
+        );
+        assert_eq!(
+            compile_stmt_with_ctx(
+                &mut ctx,
+                Statement::FuncDef(
+                    FunctionDefinition {
+                        name: "f".to_string(),
+                        args: vec![],
+                        ret_annotation: None,
+                        inline: false,
+                    },
+                    map.clone(),
+                ),
+            ),
+            Err(CompileError {
+                kind: CompileErrorKind::ReturnMap,
+                span: map.0,
+            })
+        );
+    }
+
+    #[test]
+    fn slice() {
+        let mut ctx = new_ctx();
+        ctx.variables
+            .insert("L".to_string(), (ValType::List(ElemType::Number), tinfo()));
+        let index = |ind| Expression::Index {
+            val: Box::new((spn(), Expression::Variable("L".to_string()))),
+            ind: Box::new((spn(), ind)),
+        };
+        let (l, t, _) = compile_expr(
+            &mut ctx,
+            (
+                spn(),
+                index(Expression::Range {
+                    first: Box::new((spn(), Expression::Num("2".to_string()))),
+                    second: None,
+                    end: Box::new((spn(), Expression::Num("5".to_string()))),
+                }),
+            ),
+        )
+        .unwrap();
+        assert_eq!(t, Typ::List(ElemType::Number));
+        assert_eq!(latex::latex_to_str(l), "(L)\\left[2...5\\right]");
+
+        let (l, t, _) =
+            compile_expr(&mut ctx, (spn(), index(Expression::Num("3".to_string())))).unwrap();
+        assert_eq!(t, Typ::Num);
+        assert_eq!(latex::latex_to_str(l), "(L)\\left[3\\right]");
+
+        let (l, t, _) = compile_expr(
+            &mut ctx,
+            (
+                spn(),
+                index(Expression::List(vec![
+                    (spn(), Expression::Num("1".to_string())),
+                    (spn(), Expression::Num("3".to_string())),
+                ])),
+            ),
+        )
+        .unwrap();
+        assert_eq!(t, Typ::List(ElemType::Number));
+        assert_eq!(
+            latex::latex_to_str(l),
+            "(L)\\left[\\left[1,3\\right]\\right]"
+        );
+
+        // only lists can be indexed
+        assert_eq!(
+            compile_with_ctx(
+                &mut ctx,
+                Expression::Index {
+                    val: Box::new((spn(), Expression::Num("1".to_string()))),
+                    ind: Box::new((spn(), Expression::Num("1".to_string()))),
+                },
+            ),
+            Err(CompileError {
+                kind: CompileErrorKind::IndexNonList(
+                    Typ::Num,
+                    TypInfo::Literal(Literal::Numeric, spn())
+                ),
+                span: spn(),
+            })
+        );
+        assert_eq!(
+            compile_with_ctx(
+                &mut ctx,
+                index(Expression::Point {
+                    x: Box::new((spn(), Expression::Num("1".to_string()))),
+                    y: Box::new((spn(), Expression::Num("2".to_string()))),
+                }),
+            )
+            .unwrap_err()
+            .kind,
+            CompileErrorKind::IndexWithNonNumber
+        );
+
+        // slice bounds must be numbers
+        assert_eq!(
+            compile_with_ctx(
+                &mut ctx,
+                index(Expression::Range {
+                    first: Box::new((spn(), Expression::Variable("L".to_string()))),
+                    second: None,
+                    end: Box::new((spn(), Expression::Num("5".to_string()))),
+                }),
+            )
+            .unwrap_err()
+            .kind,
+            CompileErrorKind::RangeExpectNumber
+        );
+    }
+
+    #[test]
+    fn list_arithmetic() {
+        let compile = |src: &str| {
+            let mut ctx = new_ctx();
+            let out = compile_src(&mut ctx, src).unwrap();
+            (out, ctx.warnings)
+        };
+        // element-wise
+        assert_eq!(
+            compile("[1, 2] + [3, 4]"),
+            (
+                vec!["(\\left[1,2\\right])+\\left[3,4\\right]".to_string()],
+                vec![]
+            )
+        );
+        // broadcast
+        assert_eq!(
+            compile("[1, 2] * 3\n3 - [1...5]"),
+            (
+                vec![
+                    "(\\left[1,2\\right])\\cdot 3".to_string(),
+                    "3-\\left[1,...,5\\right]".to_string()
+                ],
+                vec![]
+            )
+        );
+        assert_eq!(compile("[1...3] + [4, 5, 6]").1, vec![]);
+        let warning = |left, right, range| CompileWarning {
+            kind: CompileWarningKind::ListLengthMismatch(left, right),
+            span: types::Span::new(0, range),
+        };
+        assert_eq!(compile("[1, 2] + [3, 4, 5]").1, vec![warning(2, 3, 0..18)]);
+        assert_eq!(compile("[1...10] * [1, 2]").1, vec![warning(10, 2, 0..17)]);
+        assert_eq!(
+            compile("[1, 2] + [3, 4, 5]").1[0].kind.as_msg(),
+            "Combining lists of length 2 and 3 drops the extra elements of the longer one"
+        );
+        // the lengths of other lists aren't known
+        assert_eq!(
+            compile("L = [1, 2, 3]\n[1, 2] + L\n[1, 2] + [1...L[1]]").1,
+            vec![]
+        );
+    }
+
+    #[test]
+    fn list_element_types() {
+        let typ = |src: &str| {
+            let src = format!("L = [1, 2, 3]\nP = [(1, 2), (3, 4)]\n{}", src);
+            let mut stmts = parser::lex_and_parse(0, src).0.unwrap();
+            let (span, last) = stmts.pop().unwrap();
+            let mut ctx = new_ctx();
+            compile_stmts(&mut ctx, stmts).unwrap();
+            match last {
+                Statement::Expression(e) => compile_expr(&mut ctx, (span, e))
+                    .map(|(_, t, _)| t)
+                    .map_err(|e| e.kind),
+                s => panic!("Expected expression, got {:?}", s),
+            }
+        };
+        for (src, t) in [
+            ("L[1]", Typ::Num),
+            ("L[[1, 2]]", Typ::List(ElemType::Number)),
+            ("L[1...2]", Typ::List(ElemType::Number)),
+            ("L[L]", Typ::List(ElemType::Number)),
+            ("P[1]", Typ::Point),
+            ("P[[1, 2]]", Typ::List(ElemType::Point)),
+            ("P[1...2]", Typ::List(ElemType::Point)),
+            ("P[L]", Typ::List(ElemType::Point)),
+            ("(P[L])[1]", Typ::Point),
+            ("[L[1], 2]", Typ::List(ElemType::Number)),
+            ("[P[1], (0, 0)]", Typ::List(ElemType::Point)),
+            ("[(n, 1) for n = L]", Typ::List(ElemType::Point)),
+            ("sin(@L)", Typ::MappedList),
+            ("P[sin(@L)]", Typ::List(ElemType::Point)),
+            ("sin(L[1])", Typ::Num),
+            ("f(x) = (x, 1)\nf(L[2])", Typ::Point),
+            // comprehension variables are elements of the list
+            ("[p for p = P]", Typ::List(ElemType::Point)),
+            ("[n + 1 for n = L]", Typ::List(ElemType::Number)),
+            // function signatures keep the element type of what they return
+            ("g(x) = [(x, 1)]\ng(1)", Typ::List(ElemType::Point)),
+            ("g(x) = [(x, 1)]\ng(1)[1]", Typ::Point),
+            ("total(L)", Typ::Num),
+        ] {
+            assert_eq!(typ(src), Ok(t), "typing {}", src);
+        }
+        assert_eq!(typ("L[P]"), Err(CompileErrorKind::IndexWithNonNumber));
+        // builtins that reduce lists only take lists of numbers
+        assert!(matches!(
+            typ("total(P)"),
+            Err(CompileErrorKind::ArgTypeMismatch {
+                got: (Typ::List(ElemType::Point), _),
+                expected: ValType::List(ElemType::Number),
+                ..
+            })
+        ));
+        assert!(matches!(
+            typ("[p + 1 for p = P]"),
+            Err(CompileErrorKind::ExpectedSameTypes { .. })
+                | Err(CompileErrorKind::PointArithmetic)
+        ));
+        assert!(matches!(
+            typ("[L[1], P[1]]"),
+            Err(CompileErrorKind::ExpectedSameTypes { .. })
+        ));
+    }
+
+    #[test]
+    fn zero_arg_function() {
+        let mut ctx = new_ctx();
+        assert_eq!(
+            compile_to_strs(
+                &mut ctx,
+                vec![
+                    Statement::FuncDef(
+                        FunctionDefinition {
+                            name: "f".to_string(),
+                            args: vec![],
+                            ret_annotation: None,
+                            inline: false,
+                        },
+                        (spn(), Expression::Num("3".to_string())),
+                    ),
+                    Statement::Expression(Expression::Call {
+                        func: ast::Function::Normal {
+                            name: "f".to_string(),
+                        },
+                        args: vec![],
+                    }),
+                ]
+            ),
+            Ok(vec![
+                "f\\left(\\right)=3".to_string(),
+                "f\\left(\\right)".to_string()
+            ])
+        );
+        // a function is not a variable
+        assert_eq!(
+            compile_with_ctx(&mut ctx, Expression::Variable("f".to_string()))
+                .unwrap_err()
+                .kind,
+            CompileErrorKind::UndefinedVariable("f".to_string())
+        );
+    }
+
+    #[test]
+    fn zero_arg_function_vs_variable() {
+        let mut ctx = new_ctx();
+        assert_eq!(
+            compile_to_strs(
+                &mut ctx,
+                vec![
+                    Statement::VarDef {
+                        name: "x".to_string(),
+                        val: (spn(), Expression::Num("3".to_string())),
+                        inline: false,
+                    },
+                    Statement::Expression(Expression::Variable("x".to_string())),
+                ]
+            ),
+            Ok(vec!["x=3".to_string(), "x".to_string()])
+        );
+        // a variable is not a function
+        assert_eq!(
+            compile_with_ctx(
+                &mut ctx,
+                Expression::Call {
+                    func: ast::Function::Normal {
+                        name: "x".to_string(),
+                    },
+                    args: vec![],
+                }
+            )
+            .unwrap_err()
+            .kind,
+            CompileErrorKind::UnknownFunction {
+                func: ast::Function::Normal {
+                    name: "x".to_string()
+                },
+                suggestion: None,
+            }
+        );
+    }
+
+    #[test]
+    fn funcdef_name_formatted() {
+        assert_eq!(
+            compile_to_strs(
+                &mut new_ctx(),
+                vec![
+                    Statement::FuncDef(
+                        FunctionDefinition {
+                            name: "abc".to_string(),
+                            args: vec![],
+                            ret_annotation: None,
+                            inline: false,
+                        },
+                        (spn(), Expression::Num("3".to_string())),
+                    ),
+                    Statement::Expression(Expression::Call {
+                        func: ast::Function::Normal {
+                            name: "abc".to_string(),
+                        },
+                        args: vec![],
+                    }),
+                ]
+            ),
+            Ok(vec![
+                "a_{bc}\\left(\\right)=3".to_string(),
+                "a_{bc}\\left(\\right)".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn def_spans() {
+        let mut ctx = new_ctx();
+        let var_span = types::Span::new(1234, 0..5);
+        let func_span = types::Span::new(1234, 7..16);
+        super::compile_stmt(
+            &mut ctx,
+            (
+                var_span.clone(),
+                ast::Statement::VarDef {
+                    name: "a".to_string(),
+                    val: (spn(), ast::Expression::Num("1".to_string())),
+                    inline: false,
+                },
+            ),
+        )
+        .unwrap();
+        super::compile_stmt(
+            &mut ctx,
+            (
+                func_span.clone(),
+                Statement::FuncDef(
+                    FunctionDefinition {
+                        name: "f".to_string(),
+                        args: vec![(spn(), "x".to_string(), ValType::Number)],
+                        ret_annotation: None,
+                        inline: false,
+                    },
+                    (spn(), Expression::Variable("x".to_string())),
+                ),
+            ),
+        )
+        .unwrap();
+        assert_eq!(ctx.def_spans.get("a"), Some(&var_span));
+        assert_eq!(ctx.def_spans.get("f"), Some(&func_span));
+        // function arguments are not definitions
+        assert_eq!(ctx.def_spans.get("x"), None);
+    }
+
     #[test]
     fn module_reference() {
         let mut submodule = new_ctx();
@@ -1121,5 +2741,54 @@ pub mod tests {
             Ok(Latex::Variable("a".to_owned()))
         );
         assert_eq!(comp_var("b".to_owned()), Ok(Latex::Num("1".to_owned())));
+
+        for path in [
+            vec!["nope".to_owned()],
+            vec!["lib".to_owned(), "sub".to_owned()],
+        ] {
+            assert_eq!(
+                compile_with_ctx(
+                    &mut ctx.clone(),
+                    ast::Expression::FullyQualifiedVariable {
+                        path: path.clone(),
+                        item: "a".to_owned(),
+                    },
+                ),
+                Err(CompileError {
+                    kind: CompileErrorKind::UnresolvedNamespace(path),
+                    span: spn(),
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn raw_latex() {
+        let compile = |src: &str| {
+            let ast = parser::lex_and_parse(0, src.to_string()).0.unwrap();
+            compile_stmts(&mut new_ctx(), ast).map(|stmts| {
+                stmts
+                    .into_iter()
+                    .map(latex::latex_stmt_to_str)
+                    .collect::<Vec<_>>()
+            })
+        };
+        assert_eq!(
+            compile("a = raw<num>(\"\\pi\") + 1"),
+            Ok(vec!["a=(\\pi)+1".to_string()])
+        );
+        assert_eq!(
+            compile("raw<list>(\"L\")[2]"),
+            Ok(vec!["(L)\\left[2\\right]".to_string()])
+        );
+        // the declared type is checked like any other
+        assert!(matches!(
+            compile("raw<list>(\"L\") + (1, 2)").unwrap_err().kind,
+            CompileErrorKind::PointArithmetic
+        ));
+        assert!(matches!(
+            compile("raw<point>(\"P\")[1]").unwrap_err().kind,
+            CompileErrorKind::IndexNonList(..)
+        ));
     }
 }
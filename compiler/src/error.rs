@@ -7,11 +7,16 @@ use crate::types::{Typ, TypInfo};
 pub enum ExpectedArgCount {
     NonZero,
     Exact(ArgCount),
+    Between(ArgCount, ArgCount),
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum CompileErrorKind {
-    UnknownFunction(ast::Function),
+    UnknownFunction {
+        func: ast::Function,
+        // closest builtin or user defined function name
+        suggestion: Option<String>,
+    },
     WrongArgCount {
         got: ArgCount,
         expected: ExpectedArgCount,
@@ -19,6 +24,8 @@ pub enum CompileErrorKind {
     ArgTypeMismatch {
         got: (Typ, TypInfo),
         expected: ValType,
+        // zero-based position of the argument, when the value is an argument of a call
+        index: Option<usize>,
     },
     NegateList,
     FactorialList,
@@ -44,6 +51,25 @@ pub enum CompileErrorKind {
     ModuleNotFound(String),
     MapAsVariable,
     ReturnMap,
+    BoundVariableNotIdentifier,
+    PointArithmetic,
+    // An error node the parser recovered from, the parse error is reported separately
+    InvalidSyntax,
+    // chain is every module being imported, ending with the repeated path
+    CircularImport {
+        path: String,
+        chain: Vec<String>,
+    },
+    IncludeConflict(String),
+    BoolArithmetic,
+    ExpectedBool(Typ, TypInfo),
+    // only when Context.deny_builtin_shadowing is set, otherwise it's a warning
+    ShadowsBuiltin {
+        name: String,
+    },
+    RecursiveInline(String),
+    InvalidColor(graph::ColorError),
+    DuplicateTicker,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -58,10 +84,14 @@ struct DummyRuleType {}
 fn typinfo_labels(ti: TypInfo) -> (types::Span, String) {
     match ti {
         TypInfo::Literal(l, s) => (s, format!("{:#?} literal here", l)),
-        TypInfo::BinOp(l, r) => todo!(),
+        TypInfo::BinOp(l, r) => (
+            l.with_end_of(&r).unwrap_or(l),
+            "expression here".to_string(),
+        ),
         TypInfo::Map(s) => (s, "mapped list here".to_string()),
         TypInfo::Builtin(s, _) => (s, "call to builtin function".to_string()),
         TypInfo::RawLatex(s) => (s, "raw latex".to_string()),
+        TypInfo::Comparison(s) => (s, "comparison here".to_string()),
         TypInfo::InlineFuncArg(s) => (s, "inline function argument".to_string().to_string()),
         TypInfo::Call { call_span, ret: _ } => (call_span, "call here".to_string()), // FIXME: Expose ret better
         TypInfo::MappedCall {
@@ -85,7 +115,7 @@ fn typinfo_labels(ti: TypInfo) -> (types::Span, String) {
 impl CompileErrorKind {
     pub fn as_msg(&self) -> String {
         match self {
-            CompileErrorKind::UnknownFunction(func) => format!(
+            CompileErrorKind::UnknownFunction { func, .. } => format!(
                 "Unknown function '{}'",
                 // TODO: move this formatting into the AST crate
                 match func {
@@ -93,18 +123,35 @@ impl CompileErrorKind {
                     ast::Function::Log { base } => format!("log{}", base),
                 }
             ),
-            CompileErrorKind::WrongArgCount { got, expected } => {
-                let ex_fmt: Box<dyn std::fmt::Display> = match expected {
-                    ExpectedArgCount::NonZero => Box::new("1 or more"),
-                    ExpectedArgCount::Exact(n) => Box::new(n),
-                };
-                format!("Expected {} arguments but got {}", ex_fmt, got)
+            CompileErrorKind::WrongArgCount { got, expected } => match expected {
+                // variadic functions take any number of arguments, as long as there is one
+                ExpectedArgCount::NonZero => format!(
+                    "Expected at least 1 argument for a variadic function but got {}",
+                    got
+                ),
+                ExpectedArgCount::Exact(n) => format!("Expected {} arguments but got {}", n, got),
+                ExpectedArgCount::Between(min, max) => {
+                    format!("Expected {} to {} arguments but got {}", min, max, got)
+                }
+            },
+            CompileErrorKind::ArgTypeMismatch {
+                got: (gt, _gi),
+                expected,
+                index: None,
+            } => {
+                format!("Expected type {} but got {}", Typ::from(*expected), gt)
             }
             CompileErrorKind::ArgTypeMismatch {
                 got: (gt, _gi),
                 expected,
+                index: Some(i),
             } => {
-                format!("Expected type {:#?} but got {:#?}", expected, gt)
+                format!(
+                    "Expected type {} for argument {} but got {}",
+                    Typ::from(*expected),
+                    i + 1,
+                    gt
+                )
             }
             CompileErrorKind::NegateList => {
                 // TODO: there will be syntax to map list
@@ -127,15 +174,15 @@ impl CompileErrorKind {
             }
             CompileErrorKind::RetAnnMismatch { got, expected } => {
                 format!(
-                    "Expected type {:#?} due to return type annotation, but function returned {:#?}", 
-                    expected,
-                    got
+                    "Expected type {} due to return type annotation, but function returned {}",
+                    Typ::from(*expected),
+                    got.0
                 )
             }
             CompileErrorKind::ExpectedSameTypes { left, right } => {
                 format!(
-                    "Expected left type {:#?} to match right type {:#?}",
-                    left, right
+                    "Expected left type {} to match right type {}",
+                    left.0, right.0
                 )
             }
             CompileErrorKind::VariadicList => {
@@ -167,26 +214,55 @@ impl CompileErrorKind {
             CompileErrorKind::ReturnMap => {
                 format!("Cannot return a mapped list from a function: delete this map")
             }
+            CompileErrorKind::BoundVariableNotIdentifier => {
+                "Expected a variable name to bind".to_string()
+            }
+            CompileErrorKind::PointArithmetic => "Points cannot be used in arithmetic".to_string(),
+            CompileErrorKind::InvalidSyntax => "Invalid syntax".to_string(),
+            CompileErrorKind::CircularImport { path, chain } => {
+                format!("Circular import of '{}': {}", path, chain.join(" -> "))
+            }
+            CompileErrorKind::BoolArithmetic => {
+                "Booleans can only be used as conditions".to_string()
+            }
+            CompileErrorKind::ExpectedBool(t, _) => {
+                format!("Expected a condition but got a {}", t)
+            }
+            CompileErrorKind::IncludeConflict(name) => {
+                format!(
+                    "Included module defines '{}', which is already defined",
+                    name
+                )
+            }
+            CompileErrorKind::ShadowsBuiltin { name } => {
+                format!("Function '{}' shadows the builtin '{}'", name, name)
+            }
+            CompileErrorKind::RecursiveInline(name) => {
+                format!("Inline function '{}' cannot call itself", name)
+            }
+            CompileErrorKind::InvalidColor(e) => e.to_string(),
+            CompileErrorKind::DuplicateTicker => "A graph can only have one ticker".to_string(),
         }
     }
 
     pub fn help(&self) -> Vec<String> {
         match &self {
+            CompileErrorKind::UnknownFunction {
+                suggestion: Some(name),
+                ..
+            } => vec![format!("Did you mean '{}'?", name)],
             _ => vec![],
         }
     }
 
     pub fn typinfos(self) -> Vec<TypInfo> {
         match self {
-            CompileErrorKind::UnknownFunction(_) => vec![],
+            CompileErrorKind::UnknownFunction { .. } => vec![],
             CompileErrorKind::WrongArgCount {
                 got: _,
                 expected: _,
             } => vec![],
-            CompileErrorKind::ArgTypeMismatch {
-                got: (_, ti),
-                expected: _,
-            } => vec![ti],
+            CompileErrorKind::ArgTypeMismatch { got: (_, ti), .. } => vec![ti],
             CompileErrorKind::NegateList => vec![],
             CompileErrorKind::FactorialList => vec![],
             CompileErrorKind::RangeExpectNumber => vec![],
@@ -211,6 +287,17 @@ impl CompileErrorKind {
             CompileErrorKind::ModuleNotFound(_) => vec![],
             CompileErrorKind::MapAsVariable => vec![],
             CompileErrorKind::ReturnMap => vec![],
+            CompileErrorKind::BoundVariableNotIdentifier => vec![],
+            CompileErrorKind::PointArithmetic => vec![],
+            CompileErrorKind::InvalidSyntax => vec![],
+            CompileErrorKind::CircularImport { .. } => vec![],
+            CompileErrorKind::IncludeConflict(_) => vec![],
+            CompileErrorKind::BoolArithmetic => vec![],
+            CompileErrorKind::ExpectedBool(_, ti) => vec![ti],
+            CompileErrorKind::ShadowsBuiltin { .. } => vec![],
+            CompileErrorKind::RecursiveInline(_) => vec![],
+            CompileErrorKind::InvalidColor(_) => vec![],
+            CompileErrorKind::DuplicateTicker => vec![],
         }
     }
 
@@ -222,6 +309,44 @@ impl CompileErrorKind {
     }
 }
 
+// Warnings don't stop compilation, they are collected in the Context instead
+#[derive(Clone, Debug, PartialEq)]
+pub enum CompileWarningKind {
+    DegenerateRootDegree(String),
+    ShadowsBuiltin(String),
+    // lengths of the left and right list
+    ListLengthMismatch(usize, usize),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompileWarning {
+    pub kind: CompileWarningKind,
+    pub span: types::Span,
+}
+
+impl CompileWarningKind {
+    pub fn as_msg(&self) -> String {
+        match self {
+            CompileWarningKind::DegenerateRootDegree(degree) => {
+                format!("Taking a root of degree {} is degenerate", degree)
+            }
+            CompileWarningKind::ShadowsBuiltin(name) => {
+                format!("Function '{}' shadows the builtin '{}'", name, name)
+            }
+            CompileWarningKind::ListLengthMismatch(left, right) => format!(
+                "Combining lists of length {} and {} drops the extra elements of the longer one",
+                left, right
+            ),
+        }
+    }
+}
+
+impl fmt::Display for CompileWarningKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_msg())
+    }
+}
+
 impl fmt::Display for CompileErrorKind {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.as_msg())
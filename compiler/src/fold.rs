@@ -0,0 +1,225 @@
+use std::convert::TryFrom;
+
+use ast::{BinaryOperator, Expression, LocatedExpression, Statement, UnaryOperator};
+
+// Folding is exact integer arithmetic, so only integer literals are folded. Decimals, numbers
+//  that kept their exponent, anything that overflows and results that aren't integers (like
+//  1/3) are left for Desmos to evaluate.
+fn value(expr: &Expression) -> Option<i128> {
+    match expr {
+        Expression::Num(n) => n.parse().ok(),
+        Expression::UnaryExpr {
+            val,
+            operator: UnaryOperator::Negate,
+        } => value(&val.1)?.checked_neg(),
+        _ => None,
+    }
+}
+
+// Negative results become a negation so they get parenthesized like written ones
+fn literal(span: &types::Span, n: i128) -> Expression {
+    let num = Expression::Num(n.unsigned_abs().to_string());
+    match n < 0 {
+        true => Expression::UnaryExpr {
+            val: Box::new((span.clone(), num)),
+            operator: UnaryOperator::Negate,
+        },
+        false => num,
+    }
+}
+
+fn fold_binop(left: i128, operator: BinaryOperator, right: i128) -> Option<i128> {
+    match operator {
+        BinaryOperator::Add => left.checked_add(right),
+        BinaryOperator::Subtract => left.checked_sub(right),
+        BinaryOperator::Multiply => left.checked_mul(right),
+        BinaryOperator::Divide => match right != 0 && left % right == 0 {
+            true => left.checked_div(right),
+            false => None,
+        },
+        // Desmos's mod takes the sign of the divisor
+        BinaryOperator::Mod => {
+            let r = left.checked_rem(right)?;
+            match r != 0 && (r < 0) != (right < 0) {
+                true => Some(r + right),
+                false => Some(r),
+            }
+        }
+        BinaryOperator::Exponent => left.checked_pow(u32::try_from(right).ok()?),
+    }
+}
+
+fn factorial(n: i128) -> Option<i128> {
+    match n < 0 {
+        true => None,
+        false => (1..=n).try_fold(1i128, |acc, i| acc.checked_mul(i)),
+    }
+}
+
+fn fold_box(expr: LocatedExpression) -> Box<LocatedExpression> {
+    Box::new(fold_expr(expr))
+}
+
+pub fn fold_expr(expr: LocatedExpression) -> LocatedExpression {
+    let (span, e) = expr;
+    let e = match e {
+        Expression::BinaryExpr {
+            left,
+            operator,
+            right,
+        } => {
+            let (left, right) = (fold_box(*left), fold_box(*right));
+            match value(&left.1)
+                .zip(value(&right.1))
+                .and_then(|(l, r)| fold_binop(l, operator, r))
+            {
+                Some(n) => literal(&span, n),
+                None => Expression::BinaryExpr {
+                    left,
+                    operator,
+                    right,
+                },
+            }
+        }
+        Expression::UnaryExpr { val, operator } => {
+            let val = fold_box(*val);
+            let folded = match operator {
+                // a negated literal is already folded
+                UnaryOperator::Negate => match &val.1 {
+                    Expression::UnaryExpr { .. } => value(&val.1).and_then(i128::checked_neg),
+                    _ => None,
+                },
+                UnaryOperator::Factorial => value(&val.1).and_then(factorial),
+            };
+            match folded {
+                Some(n) => literal(&span, n),
+                None => Expression::UnaryExpr { val, operator },
+            }
+        }
+        Expression::Map(val) => Expression::Map(fold_box(*val)),
+        Expression::Call { func, args } => Expression::Call {
+            func,
+            args: args.into_iter().map(fold_expr).collect(),
+        },
+        Expression::List(items) => Expression::List(items.into_iter().map(fold_expr).collect()),
+        Expression::Range { first, second, end } => Expression::Range {
+            first: fold_box(*first),
+            second: second.map(|e| fold_box(*e)),
+            end: fold_box(*end),
+        },
+        Expression::Piecewise {
+            first,
+            rest,
+            default,
+        } => {
+            let fold_branch = |(span, b): ast::Spanned<ast::Branch>| {
+                (
+                    span,
+                    ast::Branch {
+                        cond: fold_expr(b.cond),
+                        val: fold_expr(b.val),
+                    },
+                )
+            };
+            Expression::Piecewise {
+                first: Box::new(fold_branch(*first)),
+                rest: rest.into_iter().map(fold_branch).collect(),
+                default: fold_box(*default),
+            }
+        }
+        Expression::Index { val, ind } => Expression::Index {
+            val: fold_box(*val),
+            ind: fold_box(*ind),
+        },
+        Expression::Comparison { left, op, right } => Expression::Comparison {
+            left: fold_box(*left),
+            op,
+            right: fold_box(*right),
+        },
+        Expression::DoubleComparison {
+            left,
+            left_op,
+            middle,
+            right_op,
+            right,
+        } => Expression::DoubleComparison {
+            left: fold_box(*left),
+            left_op,
+            middle: fold_box(*middle),
+            right_op,
+            right: fold_box(*right),
+        },
+        Expression::Comprehension { body, var, list } => Expression::Comprehension {
+            body: fold_box(*body),
+            var,
+            list: fold_box(*list),
+        },
+        Expression::Point { x, y } => Expression::Point {
+            x: fold_box(*x),
+            y: fold_box(*y),
+        },
+        e @ (Expression::Error
+        | Expression::Num(_)
+        | Expression::Variable(_)
+        | Expression::RawLatex(..)
+        | Expression::FullyQualifiedVariable { .. }) => e,
+    };
+    (span, e)
+}
+
+// Folds the expressions of a statement, `with` blocks are folded as their statements
+//  are compiled
+pub fn fold_stmt(stmt: Statement, span: &types::Span) -> Statement {
+    match stmt {
+        Statement::Expression(e) => Statement::Expression(fold_expr((span.clone(), e)).1),
+        Statement::VarDef { name, val, inline } => Statement::VarDef {
+            name,
+            val: fold_expr(val),
+            inline,
+        },
+        Statement::FuncDef(def, body) => Statement::FuncDef(def, fold_expr(body)),
+        Statement::Ticker { handler, min_step } => Statement::Ticker {
+            handler: fold_expr(handler),
+            min_step: min_step.map(fold_expr),
+        },
+        s @ (Statement::Import(_) | Statement::Note(_) | Statement::With(..)) => s,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{compile_stmts, Context};
+
+    fn compile(src: &str, fold_constants: bool) -> Vec<String> {
+        let ast = parser::lex_and_parse(0, src.to_string()).0.unwrap();
+        let mut ctx = Context::new();
+        ctx.fold_constants = fold_constants;
+        compile_stmts(&mut ctx, ast)
+            .unwrap()
+            .into_iter()
+            .map(latex::latex_stmt_to_str)
+            .collect()
+    }
+
+    #[test]
+    fn fold() {
+        assert_eq!(compile("2+3", true), vec!["5"]);
+        assert_eq!(compile("2+3", false), vec!["2+3"]);
+        assert_eq!(compile("-(-4)", true), vec!["4"]);
+        assert_eq!(compile("2^10 - 3!", true), vec!["1018"]);
+        assert_eq!(compile("1 - 2*3", true), vec!["-5"]);
+        assert_eq!(compile("a = 7 % 3\nb = -7 % 3", true), vec!["a=1", "b=2"]);
+        // inexact or overflowing results are left alone
+        assert_eq!(compile("1/3", true), vec!["\\frac{1}{3}"]);
+        assert_eq!(compile("2^200", true), vec!["2^{200}"]);
+    }
+
+    #[test]
+    fn no_fold_variable() {
+        assert_eq!(compile("a = 1\na + 2 + 3", true), vec!["a=1", "((a)+2)+3"]);
+        assert_eq!(
+            compile("a = 1\nf(x) = x * (2 + 3) + a", true),
+            vec!["a=1", "f\\left(x\\right)=((x)\\cdot 5)+a"]
+        );
+    }
+}
@@ -28,6 +28,22 @@ pub fn handle_import(
     span: types::Span,
     import: ast::Import,
 ) -> Result<Vec<latex::LatexStatement>, CompileError> {
+    // the same relative path can name different files depending on the importing module
+    let resolved = match path_type(import.path.as_str()) {
+        PathType::NonLocal => import.path.clone(),
+        PathType::Local => ctx.loader.resolve(import.path.as_str()),
+    };
+    if ctx.import_stack.contains(&resolved) {
+        let mut chain = ctx.import_stack.clone();
+        chain.push(resolved.clone());
+        return Err(CompileError {
+            kind: CompileErrorKind::CircularImport {
+                path: resolved,
+                chain,
+            },
+            span,
+        });
+    }
     let maybe_ast = match path_type(import.path.as_str()) {
         PathType::NonLocal => ctx
             .stdlib
@@ -43,20 +59,66 @@ pub fn handle_import(
             })
         }
     };
-    let mut mod_ctx = Context::new_with_loader(ctx.loader.clone());
+    let loader = match path_type(import.path.as_str()) {
+        PathType::NonLocal => ctx.loader.clone(),
+        PathType::Local => ctx.loader.for_module(import.path.as_str()),
+    };
+    let mut mod_ctx = Context::new_with_loader(loader);
+    mod_ctx.stdlib.enabled = ctx.stdlib.enabled;
+    mod_ctx.import_stack = ctx.import_stack.clone();
+    mod_ctx.import_stack.push(resolved);
     let out = compile_stmts(&mut mod_ctx, ast)?;
     Ok(match import.mode {
         ImportMode::Import { name } => {
             ctx.modules.insert(name, mod_ctx);
             out
         }
-        ImportMode::Include => out,
+        ImportMode::Include => {
+            include_module(ctx, span, mod_ctx)?;
+            out
+        }
     })
 }
 
+// Merges a module's definitions into ctx without a namespace
+fn include_module(
+    ctx: &mut Context,
+    span: types::Span,
+    module: Context,
+) -> Result<(), CompileError> {
+    let mut conflicts = module
+        .variables
+        .keys()
+        .chain(module.inline_vals.keys())
+        .filter(|name| ctx.variables.contains_key(*name) || ctx.inline_vals.contains_key(*name))
+        .chain(
+            module
+                .defined_functions
+                .keys()
+                .chain(module.inline_fns.keys())
+                .filter(|name| {
+                    ctx.defined_functions.contains_key(*name) || ctx.inline_fns.contains_key(*name)
+                }),
+        )
+        .collect::<Vec<_>>();
+    conflicts.sort();
+    if let Some(name) = conflicts.first() {
+        return Err(CompileError {
+            kind: CompileErrorKind::IncludeConflict(name.to_string()),
+            span,
+        });
+    }
+    ctx.variables.extend(module.variables);
+    ctx.inline_vals.extend(module.inline_vals);
+    ctx.defined_functions.extend(module.defined_functions);
+    ctx.inline_fns.extend(module.inline_fns);
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use crate::compiler::tests::*;
+    use crate::error::{CompileError, CompileErrorKind};
 
     #[test]
     fn stdlib_import() {
@@ -119,4 +181,174 @@ mod tests {
             )])
         );
     }
+
+    // ./defs defines a = 1 and f(x) = x
+    #[derive(Copy, Clone, Debug)]
+    struct DefsLoader;
+    impl crate::Loader for DefsLoader {
+        fn load(&self, path: &str) -> Option<ast::LStatements> {
+            if path != "./defs" {
+                return None;
+            }
+            Some(vec![
+                (
+                    spn(),
+                    ast::Statement::VarDef {
+                        name: "a".to_owned(),
+                        val: (spn(), ast::Expression::Num("1".to_owned())),
+                        inline: false,
+                    },
+                ),
+                (
+                    spn(),
+                    ast::Statement::FuncDef(
+                        ast::FunctionDefinition {
+                            name: "f".to_owned(),
+                            args: vec![(spn(), "x".to_owned(), types::ValType::Number)],
+                            ret_annotation: None,
+                            inline: false,
+                        },
+                        (spn(), ast::Expression::Variable("x".to_owned())),
+                    ),
+                ),
+            ])
+        }
+
+        fn parse_source(&self, _source: &str) -> Option<ast::LStatements> {
+            None
+        }
+    }
+
+    fn defs_import(mode: ast::ImportMode) -> ast::Statement {
+        ast::Statement::Import(ast::Import {
+            path: "./defs".to_owned(),
+            mode,
+        })
+    }
+
+    #[test]
+    fn include_merges_scope() {
+        let mut ctx = crate::Context::new_with_loader(Box::new(DefsLoader));
+        compile_stmt_with_ctx(&mut ctx, defs_import(ast::ImportMode::Include)).unwrap();
+        assert!(ctx.variables.contains_key("a"));
+        assert!(ctx.defined_functions.contains_key("f"));
+        assert!(ctx.modules.is_empty());
+
+        let mut ctx = crate::Context::new_with_loader(Box::new(DefsLoader));
+        compile_stmt_with_ctx(
+            &mut ctx,
+            defs_import(ast::ImportMode::Import {
+                name: "defs".to_owned(),
+            }),
+        )
+        .unwrap();
+        assert!(!ctx.variables.contains_key("a"));
+        assert!(!ctx.defined_functions.contains_key("f"));
+        assert!(ctx.modules["defs"].variables.contains_key("a"));
+        assert!(ctx.modules["defs"].defined_functions.contains_key("f"));
+    }
+
+    #[test]
+    fn include_conflict() {
+        let mut ctx = crate::Context::new_with_loader(Box::new(DefsLoader));
+        compile_stmt_with_ctx(
+            &mut ctx,
+            ast::Statement::VarDef {
+                name: "a".to_owned(),
+                val: (spn(), ast::Expression::Num("2".to_owned())),
+                inline: false,
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            compile_stmt_with_ctx(&mut ctx, defs_import(ast::ImportMode::Include)),
+            Err(CompileError {
+                kind: CompileErrorKind::IncludeConflict("a".to_owned()),
+                span: spn(),
+            })
+        );
+    }
+
+    #[test]
+    fn circular_import() {
+        // ./a and ./b include each other
+        #[derive(Copy, Clone, Debug)]
+        struct CycleLoader;
+        impl crate::Loader for CycleLoader {
+            fn load(&self, path: &str) -> Option<ast::LStatements> {
+                let other = match path {
+                    "./a" => "./b",
+                    "./b" => "./a",
+                    _ => return None,
+                };
+                Some(vec![(
+                    spn(),
+                    ast::Statement::Import(ast::Import {
+                        path: other.to_owned(),
+                        mode: ast::ImportMode::Include,
+                    }),
+                )])
+            }
+
+            fn parse_source(&self, _source: &str) -> Option<ast::LStatements> {
+                None
+            }
+        }
+
+        assert_eq!(
+            compile_stmt_with_ctx(
+                &mut crate::Context::new_with_loader(Box::new(CycleLoader)),
+                ast::Statement::Import(ast::Import {
+                    path: "./a".to_owned(),
+                    mode: ast::ImportMode::Import {
+                        name: "a".to_owned()
+                    },
+                })
+            ),
+            Err(CompileError {
+                kind: CompileErrorKind::CircularImport {
+                    path: "./a".to_owned(),
+                    chain: vec!["./a".to_owned(), "./b".to_owned(), "./a".to_owned()],
+                },
+                span: spn(),
+            })
+        );
+    }
+
+    #[test]
+    fn minimal_context() {
+        let stmts = || {
+            parser::lex_and_parse(0, "include \"test\"\ntest_fn(1)".to_string())
+                .0
+                .unwrap()
+        };
+        assert_eq!(
+            crate::compile_stmts(&mut crate::Context::new(), stmts())
+                .unwrap()
+                .into_iter()
+                .map(latex::latex_stmt_to_str)
+                .collect::<Vec<_>>(),
+            vec![
+                "t_{est_var}=1",
+                "t_{est_fn}\\left(x\\right)=(x)+1",
+                "t_{est_fn}\\left(1\\right)"
+            ]
+        );
+
+        let mut ctx = crate::Context::new_minimal();
+        assert!(ctx.stdlib.symbols().is_empty());
+        let mut stmts = stmts().into_iter();
+        assert_eq!(
+            crate::compile_stmt(&mut ctx, stmts.next().unwrap())
+                .unwrap_err()
+                .kind,
+            CompileErrorKind::ModuleNotFound("test".to_string())
+        );
+        assert!(matches!(
+            crate::compile_stmt(&mut ctx, stmts.next().unwrap())
+                .unwrap_err()
+                .kind,
+            CompileErrorKind::UnknownFunction { .. }
+        ));
+    }
 }
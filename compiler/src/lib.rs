@@ -2,10 +2,19 @@ mod builtins;
 mod call;
 mod compiler;
 pub mod error;
+mod fold;
 mod import;
+mod source;
+mod sourcemap;
 mod stdlib;
 mod types;
+mod viewport;
 
-pub use crate::compiler::{compile_stmt, compile_stmts, stmts_to_graph};
-pub use crate::types::{Context, Loader};
+pub use crate::builtins::{BINDING_FUNCTIONS, BUILTIN_FUNCTIONS};
+pub use crate::compiler::{
+    compile_stmt, compile_stmts, latex_stmt_to_value, latex_stmts_to_graph, stmts_to_graph,
+};
+pub use crate::source::{compile_expr_to_latex, compile_source, CompileSourceError, SourceLoader};
+pub use crate::sourcemap::{compile_stmts_with_source_map, LatexSpan, SourceMap};
+pub use crate::types::{Context, FunctionArgs, FunctionSignature, Loader, Typ, STDLIB_FILE_ID};
 pub use ast::LStatements; // required for loader signatures
@@ -0,0 +1,184 @@
+use crate::{
+    compiler::{compile_expr, stmts_to_graph},
+    error::CompileError,
+    types::Loader,
+    Context,
+};
+
+// Parses stdlib modules, there is no filesystem to load local imports from
+#[derive(Clone, Debug)]
+pub struct SourceLoader;
+
+impl Loader for SourceLoader {
+    fn load(&self, _path: &str) -> Option<ast::LStatements> {
+        None
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CompileSourceError {
+    Parse(parser::LexParseErrors),
+    Compile(CompileError),
+    // The source wasn't a single expression, spans all of it
+    ExpectedExpression(types::Span),
+}
+
+// Lexes, parses and compiles a whole file into a graph
+pub fn compile_source(
+    file_id: types::FileID,
+    source: &str,
+) -> Result<graph::CalcState, CompileSourceError> {
+    // compiling recurses as deep as the tree, which a long chain like `1+1+...` makes deep
+    parser::with_large_stack(|| {
+        let (ast, errs) = parser::lex_and_parse(file_id, source.to_string());
+        let ast = match ast {
+            // a recovered AST would compile without its broken statements, so don't output it
+            Some(ast) if errs.is_empty() => ast,
+            _ => return Err(CompileSourceError::Parse(errs)),
+        };
+        stmts_to_graph(&mut Context::new_with_loader(Box::new(SourceLoader)), ast)
+            .map_err(CompileSourceError::Compile)
+    })
+}
+
+// Compiles a single expression, like `sin(x) + 1`, to LaTeX. It can use anything already
+//  defined in ctx.
+pub fn compile_expr_to_latex(
+    ctx: &mut Context,
+    source: &str,
+) -> Result<String, CompileSourceError> {
+    let (ast, errs) = parser::lex_and_parse(0, source.to_string());
+    let ast = match ast {
+        Some(ast) if errs.is_empty() => ast,
+        _ => return Err(CompileSourceError::Parse(errs)),
+    };
+    let mut stmts = ast.into_iter();
+    match (stmts.next(), stmts.next()) {
+        (Some((span, ast::Statement::Expression(e))), None) => compile_expr(ctx, (span, e))
+            .map(|(l, _, _)| latex::latex_to_str(l))
+            .map_err(CompileSourceError::Compile),
+        _ => Err(CompileSourceError::ExpectedExpression(types::Span::new(
+            0,
+            0..source.chars().count(),
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::CompileErrorKind;
+
+    #[test]
+    fn compile_program() {
+        assert_eq!(
+            compile_source(0, "a = 1\nf(x) = x\n@note(\"hi\")\n"),
+            Ok(graph::CalcState {
+                expressions: graph::Expressions::from_values(vec![
+                    graph::ExpressionValue::latex("a=1".to_string()),
+                    graph::ExpressionValue::latex("f\\left(x\\right)=x".to_string()),
+                    graph::ExpressionValue::note("hi".to_string()),
+                ]),
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn index_precedence() {
+        let mut ctx = Context::new();
+        let ast = parser::lex_and_parse(0, "L = [1, 2]".to_string())
+            .0
+            .unwrap();
+        crate::compile_stmts(&mut ctx, ast).unwrap();
+        let mut latex = |src| compile_expr_to_latex(&mut ctx, src).unwrap();
+        // only the list is indexed
+        assert_eq!(latex("1 + L[2]"), "1+(L)\\left[2\\right]");
+        assert_eq!(latex("L[1] * 2"), "((L)\\left[1\\right])\\cdot 2");
+        assert_eq!(latex("-L[1]"), "-(L)\\left[1\\right]");
+        assert_eq!(latex("(1 + L)[2]"), "(1+L)\\left[2\\right]");
+        assert_eq!(latex("1 + L[1...2]"), "1+(L)\\left[1...2\\right]");
+    }
+
+    #[test]
+    fn errors() {
+        match compile_source(0, "a = (1") {
+            Err(CompileSourceError::Parse(errs)) => assert!(!errs.is_empty()),
+            r => panic!("expected a parse error, got {:?}", r),
+        }
+        assert_eq!(
+            compile_source(3, "a = b"),
+            Err(CompileSourceError::Compile(CompileError {
+                kind: CompileErrorKind::UndefinedVariable("b".to_string()),
+                span: types::Span::new(3, 4..5),
+            }))
+        );
+    }
+
+    #[test]
+    fn nesting_limit() {
+        let depth = parser::MAX_NESTING_DEPTH;
+        for source in [
+            format!("a = {}1{}", "(".repeat(depth), ")".repeat(depth)),
+            format!("a = {}1{}", "sin(".repeat(depth), ")".repeat(depth)),
+            format!("a = {}1", "2^".repeat(depth)),
+            // flat chains aren't limited however long they are
+            format!("a = {}1", "1+".repeat(10_000)),
+            format!("a = {}1", "2*".repeat(10_000)),
+        ] {
+            assert!(compile_source(0, &source).is_ok(), "{}", &source[..12]);
+        }
+        match compile_source(0, &format!("a = {}1", "2^".repeat(depth + 1))) {
+            Err(CompileSourceError::Parse(errs)) => assert_eq!(errs.parse_errors.len(), 1),
+            r => panic!("expected a parse error, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn log_base() {
+        assert_eq!(
+            compile_source(0, "a = log_2(8)\nb = log(100)"),
+            Ok(graph::CalcState {
+                expressions: graph::Expressions::from_latex_strings(vec![
+                    "a=\\log_{2}\\left(8\\right)".to_string(),
+                    "b=\\log\\left(100\\right)".to_string(),
+                ]),
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn expr_to_latex() {
+        let mut ctx = Context::new();
+        crate::compile_stmts(
+            &mut ctx,
+            parser::lex_and_parse(0, "x = 2".to_string()).0.unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            compile_expr_to_latex(&mut ctx, "sin(x) + 1"),
+            Ok("\\sin\\left(x\\right)+1".to_string())
+        );
+        assert_eq!(
+            compile_expr_to_latex(&mut ctx, "y"),
+            Err(CompileSourceError::Compile(CompileError {
+                kind: CompileErrorKind::UndefinedVariable("y".to_string()),
+                span: types::Span::new(0, 0..1),
+            }))
+        );
+        for source in ["a = 1", "1\n2", ""] {
+            assert_eq!(
+                compile_expr_to_latex(&mut ctx, source),
+                Err(CompileSourceError::ExpectedExpression(types::Span::new(
+                    0,
+                    0..source.len()
+                )))
+            );
+        }
+        assert!(matches!(
+            compile_expr_to_latex(&mut ctx, "(1"),
+            Err(CompileSourceError::Parse(_))
+        ));
+    }
+}
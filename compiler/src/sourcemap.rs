@@ -0,0 +1,265 @@
+use std::ops::Range;
+
+use ast::LocatedStatement;
+use latex::latex_stmt_to_str_with_spans;
+
+use crate::{
+    compiler::{compile_stmt, compiled_stmts},
+    types::Cesult,
+    Context,
+};
+
+// A byte range inside one of the emitted latex strings
+#[derive(Clone, Debug, PartialEq)]
+pub struct LatexSpan {
+    // Index of the emitted expression (same order as the expression list)
+    pub expr: usize,
+    pub range: Range<usize>,
+}
+
+pub type SourceMap = Vec<(LatexSpan, types::Span)>;
+
+// Like compile_stmts, but also stringifies the output and records which source
+//  span produced each fragment of it.
+pub fn compile_stmts_with_source_map(
+    ctx: &mut Context,
+    ast: Vec<LocatedStatement>,
+) -> Cesult<(Vec<String>, SourceMap)> {
+    let source_map = std::mem::replace(&mut ctx.source_map, true);
+    let result = map_stmts(ctx, ast);
+    ctx.source_map = source_map;
+    result
+}
+
+fn map_stmts(ctx: &mut Context, ast: Vec<LocatedStatement>) -> Cesult<(Vec<String>, SourceMap)> {
+    let mut out = vec![];
+    let mut map = vec![];
+    for stmt in compiled_stmts(ast) {
+        let stmt_span = stmt.0.clone();
+        // imports can emit many statements which all map back to the import itself
+        for l in compile_stmt(ctx, stmt)? {
+            let expr = out.len();
+            let (s, spans) = latex_stmt_to_str_with_spans(l);
+            map.push((
+                LatexSpan {
+                    expr,
+                    range: 0..s.len(),
+                },
+                stmt_span.clone(),
+            ));
+            for (span, range) in spans {
+                let entry = (LatexSpan { expr, range }, span);
+                // an expression statement is also its own outermost expression
+                if !map.contains(&entry) {
+                    map.push(entry);
+                }
+            }
+            out.push(s);
+        }
+    }
+    Ok((out, map))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ast::{Expression, Statement};
+
+    fn s(r: Range<usize>) -> types::Span {
+        types::Span::new(1234, r)
+    }
+
+    #[test]
+    fn source_map() {
+        // a = 1; 2 + a;
+        let (out, map) = compile_stmts_with_source_map(
+            &mut Context::new(),
+            vec![
+                (
+                    s(0..5),
+                    Statement::VarDef {
+                        name: "a".to_string(),
+                        val: (s(4..5), Expression::Num("1".to_string())),
+                        inline: false,
+                    },
+                ),
+                (
+                    s(7..12),
+                    Statement::Expression(Expression::BinaryExpr {
+                        left: Box::new((s(7..8), Expression::Num("2".to_string()))),
+                        operator: ast::BinaryOperator::Add,
+                        right: Box::new((s(11..12), Expression::Variable("a".to_string()))),
+                    }),
+                ),
+            ],
+        )
+        .unwrap();
+        assert_eq!(out, vec!["a=1".to_string(), "2+a".to_string()]);
+        assert_eq!(
+            map,
+            vec![
+                (
+                    LatexSpan {
+                        expr: 0,
+                        range: 0..3
+                    },
+                    s(0..5)
+                ),
+                (
+                    LatexSpan {
+                        expr: 0,
+                        range: 2..3
+                    },
+                    s(4..5)
+                ),
+                (
+                    LatexSpan {
+                        expr: 1,
+                        range: 0..3
+                    },
+                    s(7..12)
+                ),
+                (
+                    LatexSpan {
+                        expr: 1,
+                        range: 0..1
+                    },
+                    s(7..8)
+                ),
+                (
+                    LatexSpan {
+                        expr: 1,
+                        range: 2..3
+                    },
+                    s(11..12)
+                ),
+            ]
+        );
+    }
+
+    // The source text of each fragment that comes from the given output expression
+    fn fragments(src: &str, expr: usize) -> Vec<(String, String)> {
+        let ast = parser::lex_and_parse(0, src.to_string()).0.unwrap();
+        let (out, map) = compile_stmts_with_source_map(&mut Context::new(), ast).unwrap();
+        map.into_iter()
+            .filter(|(l, _)| l.expr == expr)
+            .map(|(l, span)| {
+                let source: String = src
+                    .chars()
+                    .skip(span.range.start)
+                    .take(span.range.len())
+                    .collect();
+                (out[expr][l.range].to_string(), source)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn nested_fragments() {
+        let pair = |l: &str, s: &str| (l.to_string(), s.to_string());
+        assert_eq!(
+            fragments("a = 1\nwith { color: \"red\" } { b = a * sin(a + 2) }", 1),
+            vec![
+                pair(
+                    "b=(a)\\sin\\left((a)+2\\right)",
+                    "with { color: \"red\" } { b = a * sin(a + 2) }"
+                ),
+                pair("a", "a"),
+                pair("a", "a"),
+                pair("2", "2"),
+                pair("(a)+2", "a + 2"),
+                pair("\\sin\\left((a)+2\\right)", "sin(a + 2)"),
+                pair("(a)\\sin\\left((a)+2\\right)", "a * sin(a + 2)"),
+            ]
+        );
+        // the same name twice maps to each use in order
+        let map = compile_stmts_with_source_map(
+            &mut Context::new(),
+            parser::lex_and_parse(0, "a = 1\na + a".to_string())
+                .0
+                .unwrap(),
+        )
+        .unwrap()
+        .1;
+        assert_eq!(
+            map.into_iter()
+                .filter(|(l, _)| l.expr == 1)
+                .map(|(l, s)| (l.range, s.range))
+                .collect::<Vec<_>>(),
+            vec![(0..5, 6..11), (1..2, 6..7), (4..5, 10..11)]
+        );
+    }
+
+    #[test]
+    fn repeated_fragments() {
+        // the arguments are substituted in the opposite order, each `a` still maps to the
+        //  argument it came from
+        let src = "a = 1\ninline f(x, y) = y - x\nf(a, a)";
+        let (out, map) = compile_stmts_with_source_map(
+            &mut Context::new(),
+            parser::lex_and_parse(0, src.to_string()).0.unwrap(),
+        )
+        .unwrap();
+        assert_eq!(out[1], "(a)-a");
+        assert_eq!(
+            map.into_iter()
+                .filter(|(l, s)| l.expr == 1 && s.range.start >= 29 && s.range.len() == 1)
+                .map(|(l, s)| (l.range, s.range))
+                .collect::<Vec<_>>(),
+            vec![(1..2, 34..35), (4..5, 31..32)]
+        );
+    }
+
+    #[test]
+    fn private_use_chars() {
+        let (out, map) = compile_stmts_with_source_map(
+            &mut Context::new(),
+            parser::lex_and_parse(
+                0,
+                "a = raw<num>(\"x\u{E001}\")\n@note(\"b \u{E000}\")".to_string(),
+            )
+            .0
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(out, vec!["a=x\u{E001}", "\"b \u{E000}"]);
+        assert!(map
+            .iter()
+            .any(|(l, _)| l.expr == 0 && l.range == (2..out[0].len())));
+    }
+
+    #[test]
+    fn recovered_statements() {
+        // the statement with an error node is skipped, like compile_stmts does
+        let (ast, errs) = parser::lex_and_parse(0, "1 + ) 2;\na = 1".to_string());
+        assert!(!errs.is_empty());
+        let ast = ast.unwrap();
+        let (out, map) = compile_stmts_with_source_map(&mut Context::new(), ast.clone()).unwrap();
+        assert_eq!(out, vec!["a=1".to_string()]);
+        assert!(map.iter().all(|(l, _)| l.expr == 0));
+        let plain = crate::compile_stmts(&mut Context::new(), ast)
+            .unwrap()
+            .into_iter()
+            .map(latex::latex_stmt_to_str)
+            .collect::<Vec<_>>();
+        assert_eq!(out, plain);
+    }
+
+    #[test]
+    fn same_output() {
+        // the spans only record where things went, the latex is the same as without them
+        let src =
+            "a = 1\nL = [1, 2, 3]\ninline f(x, y) = y - x\nb = -(a + 1)\nc = 2 * (a + 3) * 10^3\n\
+                   d = L[2...3]\ne = sum(n, 1, 10, n + a)\ng = nthroot(8, 1)\nh = (a, 2)\n\
+                   k = [(1, 2), (a, 3)]\nm = f(a + 1, a!) ^ 2\np = where a > 1 -> 2, else 3\n\
+                   q = [n^2 for n = L]\nr = integral(x, 0, 1, x + a)\ns = -1";
+        let ast = parser::lex_and_parse(0, src.to_string()).0.unwrap();
+        let (out, _) = compile_stmts_with_source_map(&mut Context::new(), ast.clone()).unwrap();
+        let plain = crate::compile_stmts(&mut Context::new(), ast)
+            .unwrap()
+            .into_iter()
+            .map(latex::latex_stmt_to_str)
+            .collect::<Vec<_>>();
+        assert_eq!(out, plain);
+    }
+}
@@ -1,13 +1,21 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, convert::TryFrom};
 
 use ast::LStatements;
 use phf::{phf_map, Map};
+use types::ValType;
 
-use crate::types::Loader;
+use crate::{
+    compile_stmts,
+    source::SourceLoader,
+    types::{FunctionArgs, FunctionSignature, Loader},
+    Context,
+};
 
 #[derive(Clone, Debug)]
 pub struct StdlibLoader {
     pub cache: HashMap<String, LStatements>,
+    // When false no stdlib modules exist, see Context::new_minimal
+    pub enabled: bool,
 }
 
 pub static STDLIB_SOURCES: Map<&'static str, &'static str> = phf_map! {
@@ -18,10 +26,21 @@ impl StdlibLoader {
     pub fn new() -> Self {
         Self {
             cache: HashMap::new(),
+            enabled: true,
+        }
+    }
+
+    pub fn disabled() -> Self {
+        Self {
+            enabled: false,
+            ..Self::new()
         }
     }
 
     pub fn load_lib(&mut self, loader: Box<dyn Loader>, name: &str) -> Option<LStatements> {
+        if !self.enabled {
+            return None;
+        }
         if let Some(ast) = self.cache.get(name) {
             return Some(ast.clone());
         };
@@ -36,6 +55,55 @@ impl StdlibLoader {
             None => None,
         }
     }
+
+    // Every function and constant defined by a stdlib module, sorted by name. Constants
+    //  are given as signatures without args.
+    pub fn symbols(&self) -> Vec<(String, FunctionSignature)> {
+        let mut symbols = Vec::new();
+        if !self.enabled {
+            return symbols;
+        }
+        for source_code in STDLIB_SOURCES.values() {
+            let ast = SourceLoader
+                .parse_source(source_code)
+                .expect("stdlib module should parse");
+            let mut ctx = Context::new_with_loader(Box::new(SourceLoader));
+            compile_stmts(&mut ctx, ast).expect("stdlib module should compile");
+            let constant = |ret| FunctionSignature {
+                args: FunctionArgs::Static(Vec::new()),
+                arg_names: Vec::new(),
+                ret,
+            };
+            symbols.extend(
+                ctx.variables
+                    .into_iter()
+                    .map(|(name, ret)| (name, constant(ret))),
+            );
+            symbols.extend(
+                ctx.inline_vals
+                    .into_iter()
+                    .filter_map(|(name, (_, t, info))| {
+                        Some((name, constant((ValType::try_from(t).ok()?, info))))
+                    }),
+            );
+            symbols.extend(
+                ctx.defined_functions
+                    .into_iter()
+                    .map(|(name, f)| (name, (*f).clone())),
+            );
+            symbols.extend(ctx.inline_fns.into_iter().filter_map(|(name, f)| {
+                let (arg_names, args) = f.args.iter().cloned().unzip();
+                let sig = FunctionSignature {
+                    args: FunctionArgs::Static(args),
+                    arg_names,
+                    ret: (ValType::try_from(f.ret.0).ok()?, f.ret.1.clone()),
+                };
+                Some((name, sig))
+            }));
+        }
+        symbols.sort_by(|(a, _), (b, _)| a.cmp(b));
+        symbols
+    }
 }
 
 impl Default for StdlibLoader {
@@ -43,3 +111,21 @@ impl Default for StdlibLoader {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn symbols() {
+        let symbols = StdlibLoader::new().symbols();
+        assert!(!symbols.is_empty());
+        let (_, test_var) = symbols
+            .iter()
+            .find(|(name, _)| name == "test_var")
+            .expect("test_var should be listed");
+        assert_eq!(test_var.args, FunctionArgs::Static(Vec::new()));
+        assert_eq!(test_var.ret.0, ValType::Number);
+        assert!(symbols.iter().any(|(name, _)| name == "inline_var"));
+    }
+}
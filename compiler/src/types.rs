@@ -1,15 +1,24 @@
 use ast::LStatements;
 use std::{collections::HashMap, convert::TryFrom, fmt::Debug, rc::Rc};
-use types::ValType;
+use types::{ElemType, ValType};
 
-use crate::{error::CompileError, stdlib::StdlibLoader};
+use crate::{
+    builtins,
+    error::{CompileError, CompileErrorKind, CompileWarning},
+    stdlib::StdlibLoader,
+};
 
 // ValType that supports list mapping
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Typ {
     Num,
-    List,
+    List(ElemType),
+    // Always a list of numbers, points are never mapped over
     MappedList,
+    Point,
+    // Only usable as a condition, see CompileErrorKind::BoolArithmetic
+    Bool,
+    Polygon,
 }
 
 impl std::fmt::Display for Typ {
@@ -19,8 +28,12 @@ impl std::fmt::Display for Typ {
             "{}",
             match self {
                 Self::Num => "number",
-                Self::List => "list",
+                Self::List(ElemType::Number) => "list",
+                Self::List(ElemType::Point) => "point list",
                 Self::MappedList => "mapped list",
+                Self::Point => "point",
+                Self::Bool => "bool",
+                Self::Polygon => "polygon",
             }
         )
     }
@@ -30,19 +43,31 @@ impl From<ValType> for Typ {
     fn from(v: ValType) -> Self {
         match v {
             ValType::Number => Self::Num,
-            ValType::List => Self::List,
+            ValType::List(e) => Self::List(e),
+            ValType::Point => Self::Point,
+            ValType::Bool => Self::Bool,
+            ValType::Polygon => Self::Polygon,
         }
     }
 }
 
+impl From<ElemType> for Typ {
+    fn from(e: ElemType) -> Self {
+        ValType::from(e).into()
+    }
+}
+
 impl TryFrom<Typ> for ValType {
     type Error = ();
 
     fn try_from(value: Typ) -> Result<Self, Self::Error> {
         match value {
             Typ::Num => Ok(ValType::Number),
-            Typ::List => Ok(ValType::List),
+            Typ::List(e) => Ok(ValType::List(e)),
             Typ::MappedList => Err(()),
+            Typ::Point => Ok(ValType::Point),
+            Typ::Bool => Ok(ValType::Bool),
+            Typ::Polygon => Ok(ValType::Polygon),
         }
     }
 }
@@ -51,16 +76,18 @@ impl Typ {
     pub fn is_num_weak(self) -> bool {
         match self {
             Self::Num => true,
-            Self::List => false,
+            Self::List(_) => false,
             Self::MappedList => true,
+            Self::Point | Self::Bool | Self::Polygon => false,
         }
     }
 
     pub fn is_list_weak(self) -> bool {
         match self {
             Self::Num => false,
-            Self::List => true,
+            Self::List(ElemType::Number) => true,
             Self::MappedList => true,
+            Self::List(ElemType::Point) | Self::Point | Self::Bool | Self::Polygon => false,
         }
     }
 
@@ -71,52 +98,88 @@ impl Typ {
     pub fn eq_weak(self, rhs: Self) -> bool {
         match self {
             Self::Num => rhs.is_num_weak(),
-            Self::List => rhs == Self::List,
-            // todo: reject redundant cmp of mappedlist to mappedlist?
+            // mapped lists are elementwise numbers, see binop_exprs
             Self::MappedList => rhs.is_num_weak(),
+            Self::List(_) | Self::Point | Self::Bool | Self::Polygon => rhs == self,
+        }
+    }
+
+    // Polygons are made of points, so they can't be used in arithmetic either
+    pub fn is_point(self) -> bool {
+        matches!(
+            self,
+            Self::Point | Self::List(ElemType::Point) | Self::Polygon
+        )
+    }
+
+    pub fn element(self) -> Option<Self> {
+        match self {
+            Self::List(e) => Some(e.into()),
+            Self::MappedList => Some(Self::Num),
+            Self::Num | Self::Point | Self::Bool | Self::Polygon => None,
+        }
+    }
+
+    // The type of a list of values of this type, see ElemType
+    pub fn list_of(self) -> Option<Self> {
+        match self {
+            Self::Num | Self::MappedList => Some(Self::List(ElemType::Number)),
+            Self::Point => Some(Self::List(ElemType::Point)),
+            Self::List(_) | Self::Bool | Self::Polygon => None,
         }
     }
 }
 
+// The type of combining two values, either with an operator or as branches of a
+//  piecewise. Numbers broadcast over lists and mapped lists, but a mapped list can't be
+//  combined with a list since it would be ambiguous which is mapped over.
 pub fn binop_exprs(
     left: (types::Span, Typ, TypInfo),
     right: (types::Span, Typ, TypInfo),
-) -> (types::Span, Typ, TypInfo) {
+) -> Cesult<(types::Span, Typ, TypInfo)> {
     let (ls, lt, li) = left;
     let (rs, rt, ri) = right;
+    let t = match (lt, rt) {
+        (Typ::MappedList, t) | (t, Typ::MappedList) if t.is_num_weak() => Typ::MappedList,
+        (Typ::List(ElemType::Number), t) | (t, Typ::List(ElemType::Number))
+            if t.is_num_strict() || t == Typ::List(ElemType::Number) =>
+        {
+            Typ::List(ElemType::Number)
+        }
+        (l, r) if l == r => l,
+        _ => {
+            return Err(CompileError {
+                span: ls.with_end_of(&rs).unwrap_or_else(|| ls.clone()),
+                kind: CompileErrorKind::ExpectedSameTypes {
+                    left: (lt, li),
+                    right: (rt, ri),
+                },
+            })
+        }
+    };
+    // the info of a list is where it came from
     if lt.is_list_weak() {
-        return (ls, Typ::List, li);
+        return Ok((ls, t, li));
     }
     if rt.is_list_weak() {
-        return (rs, Typ::List, ri);
+        return Ok((rs, t, ri));
     }
-    // only possibilities left:
-    debug_assert_eq!(lt, Typ::Num);
-    debug_assert_eq!(rt, Typ::Num);
-    (
+    Ok((
         ls.with_end_of(&rs).expect("Parsing same file"),
-        lt,
+        t,
         TypInfo::BinOp(ls, rs),
-    )
+    ))
 }
 
-pub fn combine_types(left: Typ, right: Typ) -> Typ {
-    if left.is_list_weak() || right.is_list_weak() {
-        return Typ::List;
-    }
-    // only possibilities left:
-    debug_assert_eq!(left, Typ::Num);
-    debug_assert_eq!(right, Typ::Num);
-    Typ::Num
-}
-
-pub fn reduce_with_binop_exprs<I>(types: I) -> Option<(types::Span, Typ, TypInfo)>
+pub fn reduce_with_binop_exprs<I>(types: I) -> Cesult<Option<(types::Span, Typ, TypInfo)>>
 where
     I: IntoIterator<Item = (types::Span, Typ, TypInfo)>,
 {
-    types
-        .into_iter()
-        .reduce(|left, right| binop_exprs(left, right))
+    let mut types = types.into_iter();
+    match types.next() {
+        Some(first) => types.try_fold(first, binop_exprs).map(Some),
+        None => Ok(None),
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -124,6 +187,7 @@ pub enum Literal {
     Numeric,
     List,
     Range,
+    Point,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -134,6 +198,7 @@ pub enum TypInfo {
     Builtin(types::Span, ast::Function),
     RawLatex(types::Span),
     InlineFuncArg(types::Span),
+    Comparison(types::Span),
     Call {
         call_span: types::Span,
         ret: Box<TypInfo>,
@@ -149,13 +214,16 @@ pub enum TypInfo {
 #[derive(Clone, Debug, PartialEq)]
 pub enum FunctionArgs {
     Static(Vec<ValType>),
-    Variadic,
+    Optional { args: Vec<ValType>, required: usize },
+    Variadic(ElemType),
 }
 
 // heap version of core::runtime::Function
 #[derive(Clone, Debug, PartialEq)]
 pub struct FunctionSignature {
     pub args: FunctionArgs,
+    // empty for builtins
+    pub arg_names: Vec<String>,
     pub ret: (ValType, TypInfo),
 }
 
@@ -166,10 +234,35 @@ pub struct InlineFunction {
     pub body: latex::Latex,
 }
 
+// Spans in sources parsed by the default Loader::parse_source, so they never point into
+//  the user's file
+pub const STDLIB_FILE_ID: types::FileID = types::FileID::MAX;
+
 pub trait Loader: LoaderClone + Debug {
     fn load(&self, path: &str) -> Option<LStatements>;
 
-    fn parse_source(&self, source: &str) -> Option<LStatements>;
+    // Only needs overriding for loaders that parse a different dialect or report errors.
+    //  A recovered AST isn't returned, since its broken statements would be skipped.
+    fn parse_source(&self, source: &str) -> Option<LStatements> {
+        let (ast, errs) = parser::lex_and_parse(STDLIB_FILE_ID, source.to_string());
+        if !errs.is_empty() {
+            return None;
+        }
+        ast
+    }
+
+    // The loader for the imports of the local module at path. Loaders that resolve
+    //  relative paths should resolve them against the module's location.
+    fn for_module(&self, _path: &str) -> Box<dyn Loader> {
+        self.box_clone()
+    }
+
+    // Identifies the local module at path, so that a module imported through different
+    //  relative paths is still recognized. Loaders that resolve relative paths should
+    //  return the resolved path.
+    fn resolve(&self, path: &str) -> String {
+        path.to_string()
+    }
 }
 
 // https://stackoverflow.com/a/30353928/9196137
@@ -199,10 +292,6 @@ impl Loader for UnimplementedLoader {
     fn load(&self, _path: &str) -> Option<LStatements> {
         unimplemented!()
     }
-
-    fn parse_source(&self, _source: &str) -> Option<LStatements> {
-        unimplemented!()
-    }
 }
 
 impl Default for Box<dyn Loader> {
@@ -211,15 +300,39 @@ impl Default for Box<dyn Loader> {
     }
 }
 
+type Local = (ValType, TypInfo);
+
 #[derive(Clone, Debug, Default)]
 pub struct Context {
     pub variables: HashMap<String, (ValType, TypInfo)>,
     pub locals: HashMap<String, (ValType, TypInfo)>,
+    // The locals each pushed scope shadowed, see push_scope
+    scopes: Vec<Vec<(String, Option<Local>)>>,
     pub defined_functions: HashMap<String, Rc<FunctionSignature>>,
     pub inline_vals: HashMap<String, (latex::Latex, Typ, TypInfo)>,
     pub inline_fns: HashMap<String, Rc<InlineFunction>>,
+    // Span of the statement that defined each variable or function
+    pub def_spans: HashMap<String, types::Span>,
+    pub warnings: Vec<CompileWarning>,
     // can't support submodules (yet)
     pub modules: HashMap<String, Context>,
+    // Modules currently being imported, outermost first, as resolved by their loader
+    pub import_stack: Vec<String>,
+    // Graph output gives definitions of constants these slider bounds
+    pub slider_bounds: Option<graph::SliderBounds>,
+    // Latex of the handler and min step of the graph's ticker, from `@ticker`
+    pub ticker: Option<(String, Option<String>)>,
+    // Make defining a function with the name of a builtin an error instead of a warning
+    pub deny_builtin_shadowing: bool,
+    // The inline function whose body is being compiled, it can't call itself since it
+    //  would be substituted forever
+    pub inline_def: Option<String>,
+    // Evaluate arithmetic on number literals at compile time
+    pub fold_constants: bool,
+    // Size the graph's viewport to fit the points given as number literals
+    pub fit_viewport: bool,
+    // Wrap the latex of every compiled expression in its span. Used for source maps.
+    pub source_map: bool,
     pub stdlib: StdlibLoader,
     pub loader: Box<dyn Loader>,
 }
@@ -231,12 +344,86 @@ impl Context {
         }
     }
 
+    // Without the stdlib, importing its modules fails as if they didn't exist
+    pub fn new_minimal() -> Self {
+        Self {
+            stdlib: StdlibLoader::disabled(),
+            ..Default::default()
+        }
+    }
+
     pub fn new_with_loader(loader: Box<dyn Loader>) -> Self {
         Self {
             loader,
             ..Default::default()
         }
     }
+
+    // Function arguments and bound variables are locals, which shadow variables only while
+    //  the body that binds them is compiled. Every push_scope must be matched by a
+    //  pop_scope, even if compiling the body fails.
+    pub fn push_scope(&mut self, locals: Vec<(String, (ValType, TypInfo))>) {
+        let shadowed = locals
+            .into_iter()
+            .map(|(name, local)| {
+                let outer = self.locals.insert(name.clone(), local);
+                (name, outer)
+            })
+            .collect();
+        self.scopes.push(shadowed);
+    }
+
+    pub fn pop_scope(&mut self) {
+        let shadowed = self.scopes.pop().expect("pop_scope without push_scope");
+        for (name, outer) in shadowed.into_iter().rev() {
+            match outer {
+                Some(outer) => self.locals.insert(name, outer),
+                None => self.locals.remove(&name),
+            };
+        }
+    }
+
+    // Human readable signature of a function, e.g. `f(x: number, L: list) -> number`.
+    //  Resolves names in the same order as calls do.
+    pub fn function_signature_string(&self, name: &str) -> Option<String> {
+        let (args, ret) = if let Some(f) = self.inline_fns.get(name) {
+            (
+                f.args
+                    .iter()
+                    .map(|(arg, t)| format!("{}: {}", arg, Typ::from(*t)))
+                    .collect(),
+                f.ret.0,
+            )
+        } else if let Some(f) = self.defined_functions.get(name) {
+            let args = match &f.args {
+                FunctionArgs::Static(args) | FunctionArgs::Optional { args, .. } => args
+                    .iter()
+                    .zip(f.arg_names.iter())
+                    .map(|(t, arg)| format!("{}: {}", arg, Typ::from(*t)))
+                    .collect(),
+                FunctionArgs::Variadic(_) => vec!["...".to_string()],
+            };
+            (args, f.ret.0.into())
+        } else {
+            let f = builtins::BUILTIN_FUNCTIONS.get(name)?;
+            let args = match f.args {
+                types::Args::Static(args) => {
+                    args.iter().map(|t| Typ::from(*t).to_string()).collect()
+                }
+                types::Args::Optional { args, required } => args
+                    .iter()
+                    .enumerate()
+                    .map(|(i, t)| {
+                        let opt = if i < required { "" } else { "?" };
+                        format!("{}{}", Typ::from(*t), opt)
+                    })
+                    .collect(),
+                types::Args::Variadic(_) => vec!["...".to_string()],
+            };
+            (args, f.ret.into())
+        };
+        Some(format!("{}({}) -> {}", name, args.join(", "), ret))
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -249,3 +436,115 @@ pub enum ResolvedFunction {
 }
 
 pub type Cesult<T> = Result<T, CompileError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn combine(left: Typ, right: Typ) -> Option<Typ> {
+        let info = |range| {
+            let span = types::Span::new(0, range);
+            (span.clone(), TypInfo::Literal(Literal::Numeric, span))
+        };
+        let ((ls, li), (rs, ri)) = (info(0..1), info(4..5));
+        binop_exprs((ls, left, li), (rs, right, ri))
+            .ok()
+            .map(|(_, t, _)| t)
+    }
+
+    #[test]
+    fn binop_types() {
+        use Typ::*;
+        let list = List(ElemType::Number);
+        let cases = [
+            (Num, Num, Some(Num)),
+            (Num, list, Some(list)),
+            (list, Num, Some(list)),
+            (list, list, Some(list)),
+            (Num, MappedList, Some(MappedList)),
+            (MappedList, Num, Some(MappedList)),
+            (MappedList, MappedList, Some(MappedList)),
+            (list, MappedList, None),
+            (MappedList, list, None),
+        ];
+        for (left, right, expected) in cases {
+            assert_eq!(combine(left, right), expected, "{} and {}", left, right);
+        }
+        assert_eq!(combine(Point, Point), Some(Point));
+        let points = List(ElemType::Point);
+        assert_eq!(combine(points, points), Some(points));
+        assert_eq!(combine(points, list), None);
+        assert_eq!(combine(points, Num), None);
+        assert_eq!(combine(Point, Num), None);
+        assert_eq!(combine(MappedList, Bool), None);
+    }
+
+    #[test]
+    fn binop_info() {
+        let span = |range| types::Span::new(0, range);
+        let map_info = TypInfo::Map(span(4..6));
+        let (s, t, i) = binop_exprs(
+            (
+                span(0..1),
+                Typ::Num,
+                TypInfo::Literal(Literal::Numeric, span(0..1)),
+            ),
+            (span(4..6), Typ::MappedList, map_info.clone()),
+        )
+        .unwrap();
+        // a mapped list keeps pointing at the map
+        assert_eq!((s, t, i), (span(4..6), Typ::MappedList, map_info));
+        let (s, _, i) = binop_exprs(
+            (
+                span(0..1),
+                Typ::Num,
+                TypInfo::Literal(Literal::Numeric, span(0..1)),
+            ),
+            (
+                span(4..5),
+                Typ::Num,
+                TypInfo::Literal(Literal::Numeric, span(4..5)),
+            ),
+        )
+        .unwrap();
+        assert_eq!((s, i), (span(0..5), TypInfo::BinOp(span(0..1), span(4..5))));
+    }
+
+    #[test]
+    fn default_parse_source() {
+        #[derive(Clone, Debug)]
+        struct TestLoader;
+
+        impl Loader for TestLoader {
+            fn load(&self, _path: &str) -> Option<LStatements> {
+                None
+            }
+        }
+
+        let ast = TestLoader.parse_source("a = 1\nf(x) = x + a").unwrap();
+        assert_eq!(
+            ast,
+            parser::lex_and_parse(STDLIB_FILE_ID, "a = 1\nf(x) = x + a".to_string())
+                .0
+                .unwrap()
+        );
+        assert_eq!(ast.len(), 2);
+        assert_eq!(ast[0].0.file_id, STDLIB_FILE_ID);
+        assert_eq!(TestLoader.parse_source("a = = 1\nf(x) = x"), None);
+    }
+
+    #[test]
+    fn element_types() {
+        for t in [Typ::Num, Typ::Point] {
+            assert_eq!(t.list_of().and_then(Typ::element), Some(t));
+        }
+        for t in [Typ::List(ElemType::Number), Typ::List(ElemType::Point)] {
+            assert_eq!(t.element().and_then(Typ::list_of), Some(t));
+            // no nested lists
+            assert_eq!(t.list_of(), None);
+        }
+        assert_eq!(Typ::MappedList.element(), Some(Typ::Num));
+        assert_eq!(Typ::Bool.list_of(), None);
+        assert_eq!(Typ::Bool.element(), None);
+    }
+}
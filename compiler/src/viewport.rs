@@ -0,0 +1,104 @@
+use ast::{Expression, LocatedStatement, Statement, UnaryOperator};
+
+// Space left around the plotted points, as a fraction of the extent of each axis
+const PADDING: f64 = 0.1;
+// Padding of an axis whose points all have the same coordinate
+const MIN_PADDING: f64 = 1.0;
+
+fn literal(e: &Expression) -> Option<f64> {
+    match e {
+        Expression::Num(n) => n.parse().ok(),
+        Expression::UnaryExpr {
+            val,
+            operator: UnaryOperator::Negate,
+        } => literal(&val.1).map(|v| -v),
+        _ => None,
+    }
+}
+
+fn collect_points(e: &Expression, points: &mut Vec<(f64, f64)>) {
+    match e {
+        Expression::Point { x, y } => {
+            if let (Some(x), Some(y)) = (literal(&x.1), literal(&y.1)) {
+                points.push((x, y));
+            }
+        }
+        Expression::List(items) => {
+            for (_, item) in items {
+                collect_points(item, points);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn collect_stmt_points(stmts: &[LocatedStatement], points: &mut Vec<(f64, f64)>) {
+    for (_, s) in stmts {
+        match s {
+            Statement::Expression(e) | Statement::VarDef { val: (_, e), .. } => {
+                collect_points(e, points)
+            }
+            Statement::With(_, stmts) => collect_stmt_points(stmts, points),
+            _ => {}
+        }
+    }
+}
+
+// Bounds around the points written as number literals, like `(50, 50)` or
+//  `[(1, 2), (3, -4)]`. None if there are none or they can't be fit.
+pub fn fit_viewport(stmts: &[LocatedStatement]) -> Option<graph::Viewport> {
+    let mut points = Vec::new();
+    collect_stmt_points(stmts, &mut points);
+    let axis = |coords: Vec<f64>| {
+        let min = coords.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = coords.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        let pad = ((max - min) * PADDING).max(MIN_PADDING);
+        (min - pad, max + pad)
+    };
+    if points.is_empty() {
+        return None;
+    }
+    let (xmin, xmax) = axis(points.iter().map(|p| p.0).collect());
+    let (ymin, ymax) = axis(points.iter().map(|p| p.1).collect());
+    graph::Viewport::new(xmin, xmax, ymin, ymax).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{stmts_to_graph, Context};
+
+    fn viewport(src: &str) -> graph::Viewport {
+        let ast = parser::lex_and_parse(0, src.to_string()).0.unwrap();
+        let mut ctx = Context::new();
+        ctx.fit_viewport = true;
+        stmts_to_graph(&mut ctx, ast)
+            .unwrap()
+            .graph
+            .unwrap()
+            .viewport
+    }
+
+    #[test]
+    fn fit() {
+        let v = viewport("(50, 50)");
+        assert!(v.xmin < 50.0 && 50.0 < v.xmax);
+        assert!(v.ymin < 50.0 && 50.0 < v.ymax);
+        let v = viewport("P = [(-20, 0), (10, 100)]");
+        assert_eq!((v.xmin, v.xmax), (-23.0, 13.0));
+        assert_eq!((v.ymin, v.ymax), (-10.0, 110.0));
+    }
+
+    #[test]
+    fn fallback() {
+        let default = graph::CalcState::default().graph.unwrap().viewport;
+        // nothing to fit
+        assert_eq!(viewport("a = 1\n(a, 2)"), default);
+        // the padded bounds overflow
+        let huge = format!("1{}", "0".repeat(308));
+        assert_eq!(viewport(&format!("({0}, {0})\n(-{0}, 0)", huge)), default);
+        // only fit when asked to
+        let ast = parser::lex_and_parse(0, "(50, 50)".to_string()).0.unwrap();
+        let state = stmts_to_graph(&mut Context::new(), ast).unwrap();
+        assert_eq!(state.graph.unwrap().viewport, default);
+    }
+}
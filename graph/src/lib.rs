@@ -1,9 +1,19 @@
 use std::collections::HashMap;
 
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
 use serde::{Deserialize, Serialize};
 
 pub const LATEST_STATE_VERSION: i8 = 9;
 
+pub const DESMOS_URL: &str = "https://www.desmos.com/calculator";
+
+// Everything but the unreserved characters from RFC 3986
+const URL_FRAGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct CalcState {
@@ -19,6 +29,11 @@ pub struct CalcState {
     #[serde(default)]
     pub random_seed: Option<String>,
 
+    // Trig functions use radians unless this is set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub degree_mode: Option<bool>,
+
     pub expressions: Expressions,
 }
 
@@ -29,14 +44,94 @@ pub struct Graph {
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Viewport {
+    // Desmos can't load NaN or infinite bounds (and JSON can't represent them)
+    #[serde(serialize_with = "serialize_finite")]
     pub xmin: f64,
+    #[serde(serialize_with = "serialize_finite")]
     pub xmax: f64,
 
+    #[serde(serialize_with = "serialize_finite")]
     pub ymin: f64,
+    #[serde(serialize_with = "serialize_finite")]
     pub ymax: f64,
 }
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum ViewportError {
+    NonFinite {
+        field: &'static str,
+        value: f64,
+    },
+    Inverted {
+        axis: &'static str,
+        min: f64,
+        max: f64,
+    },
+}
+
+impl std::fmt::Display for ViewportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NonFinite { field, value } => {
+                write!(f, "Viewport {} must be finite, got {}", field, value)
+            }
+            Self::Inverted { axis, min, max } => write!(
+                f,
+                "Viewport {}min must be less than {}max, got {} and {}",
+                axis, axis, min, max
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ViewportError {}
+
+fn serialize_finite<S>(v: &f64, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    if !v.is_finite() {
+        return Err(serde::ser::Error::custom(format!(
+            "Viewport bounds must be finite, got {}",
+            v
+        )));
+    }
+    serializer.serialize_f64(*v)
+}
+
+impl Viewport {
+    pub fn new(xmin: f64, xmax: f64, ymin: f64, ymax: f64) -> Result<Self, ViewportError> {
+        let v = Self {
+            xmin,
+            xmax,
+            ymin,
+            ymax,
+        };
+        v.validate()?;
+        Ok(v)
+    }
+
+    pub fn validate(&self) -> Result<(), ViewportError> {
+        for (field, value) in [
+            ("xmin", self.xmin),
+            ("xmax", self.xmax),
+            ("ymin", self.ymin),
+            ("ymax", self.ymax),
+        ] {
+            if !value.is_finite() {
+                return Err(ViewportError::NonFinite { field, value });
+            }
+        }
+        for (axis, min, max) in [("x", self.xmin, self.xmax), ("y", self.ymin, self.ymax)] {
+            if min >= max {
+                return Err(ViewportError::Inverted { axis, min, max });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct Expressions {
     pub list: Vec<Expression>,
 
@@ -45,7 +140,10 @@ pub struct Expressions {
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Expression {
+    // Desmos keys expressions by id in a JS object, so it must be made of letters, numbers
+    //  and underscores, see IdGenerator
     id: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     folder_id: Option<String>,
@@ -71,6 +169,13 @@ impl Expression {
             value,
         }
     }
+
+    pub fn in_folder(self, folder_id: String) -> Self {
+        Self {
+            folder_id: Some(folder_id),
+            ..self
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -127,13 +232,180 @@ pub enum DragMode {
     Auto,
 }
 
+// The colors in Desmos's default palette
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NamedColor {
+    Red,
+    Blue,
+    Green,
+    Purple,
+    Orange,
+    Black,
+}
+
+impl NamedColor {
+    pub const ALL: [NamedColor; 6] = [
+        Self::Red,
+        Self::Blue,
+        Self::Green,
+        Self::Purple,
+        Self::Orange,
+        Self::Black,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Red => "RED",
+            Self::Blue => "BLUE",
+            Self::Green => "GREEN",
+            Self::Purple => "PURPLE",
+            Self::Orange => "ORANGE",
+            Self::Black => "BLACK",
+        }
+    }
+
+    pub fn hex(self) -> &'static str {
+        match self {
+            Self::Red => "#c74440",
+            Self::Blue => "#2d70b3",
+            Self::Green => "#388c46",
+            Self::Purple => "#6042a6",
+            Self::Orange => "#fa7e19",
+            Self::Black => "#000000",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Color {
+    // `#rgb` or `#rrggbb`
+    Hex(String),
+    Named(NamedColor),
+    // Latex evaluating to a color, such as a variable defined with rgb()
+    Latex(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ColorError {
+    InvalidHex(String),
+}
+
+impl std::fmt::Display for ColorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidHex(s) => write!(
+                f,
+                "Invalid color '{}', expected a color name or a hex color like #c74440",
+                s
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ColorError {}
+
+impl Color {
+    pub fn hex(hex: String) -> Result<Self, ColorError> {
+        match hex.strip_prefix('#') {
+            Some(digits)
+                if matches!(digits.len(), 3 | 6)
+                    && digits.chars().all(|c| c.is_ascii_hexdigit()) =>
+            {
+                Ok(Self::Hex(hex))
+            }
+            _ => Err(ColorError::InvalidHex(hex)),
+        }
+    }
+
+    pub fn latex(latex: String) -> Self {
+        Self::Latex(latex)
+    }
+}
+
+// A palette name in any case, or a hex color
+impl std::str::FromStr for Color {
+    type Err = ColorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match NamedColor::ALL
+            .iter()
+            .find(|c| c.name().eq_ignore_ascii_case(s))
+        {
+            Some(c) => Ok(Self::Named(*c)),
+            None => Self::hex(s.to_string()),
+        }
+    }
+}
+
+// Desmos stores palette colors by their hex value and dynamic colors in a separate field
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ColorFields {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    color: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    color_latex: Option<String>,
+}
+
+impl Serialize for Color {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let (color, color_latex) = match self {
+            Self::Hex(hex) => (Some(hex.clone()), None),
+            Self::Named(c) => (Some(c.hex().to_string()), None),
+            Self::Latex(latex) => (None, Some(latex.clone())),
+        };
+        ColorFields { color, color_latex }.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Color {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        ColorFields::deserialize(deserializer)?
+            .into_color()
+            .map_err(serde::de::Error::custom)?
+            .ok_or_else(|| serde::de::Error::custom("missing color"))
+    }
+}
+
+impl ColorFields {
+    // None when neither field is set
+    fn into_color(self) -> Result<Option<Color>, ColorError> {
+        // the color is only a fallback when there is latex
+        match (self.color_latex, self.color) {
+            (Some(latex), _) => Ok(Some(Color::Latex(latex))),
+            (None, Some(hex)) => match NamedColor::ALL.iter().find(|c| c.hex() == hex) {
+                Some(c) => Ok(Some(Color::Named(*c))),
+                None => Color::hex(hex).map(Some),
+            },
+            (None, None) => Ok(None),
+        }
+    }
+}
+
+// A flattened Option swallows errors from its contents, so an invalid color would be read as
+//  no color. Reading the fields directly lets only their absence become None.
+fn deserialize_color<'de, D>(deserializer: D) -> Result<Option<Color>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    ColorFields::deserialize(deserializer)?
+        .into_color()
+        .map_err(serde::de::Error::custom)
+}
+
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SetExpression {
     #[serde(skip_serializing_if = "Option::is_none")]
     latex: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    color: Option<String>,
+    #[serde(flatten, deserialize_with = "deserialize_color")]
+    color: Option<Color>,
     #[serde(skip_serializing_if = "Option::is_none")]
     line_style: Option<LineStyle>,
     // TODO: This can also be a number
@@ -228,6 +500,7 @@ pub struct Clickable {
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct ValueExpression {
     #[serde(flatten)]
     set_expression: SetExpression,
@@ -282,11 +555,15 @@ pub struct ImageClickable {
     depressed_image: Option<String>,
 }
 
+// Desmos names the url field in snake case, unlike the rest of the item
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct ImageExpression {
+    #[serde(rename = "image_url")]
     image_url: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     angle: Option<String>,
+    // Latex for a point
     #[serde(skip_serializing_if = "Option::is_none")]
     center: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -298,8 +575,69 @@ pub struct ImageExpression {
     #[serde(skip_serializing_if = "Option::is_none")]
     opacity: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    #[serde(rename = "clickableInfo")]
-    clickable_info: Option<Clickable>,
+    hidden: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    draggable: Option<bool>,
+    // Drawn above graphed expressions instead of below them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    foreground: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    clickable_info: Option<ImageClickable>,
+}
+
+// Sizes, angle and opacity are latex
+impl ImageExpression {
+    pub fn new(image_url: String) -> Self {
+        Self {
+            image_url,
+            angle: None,
+            center: None,
+            height: None,
+            width: None,
+            name: None,
+            opacity: None,
+            hidden: None,
+            draggable: None,
+            foreground: None,
+            clickable_info: None,
+        }
+    }
+
+    pub fn named(self, name: String) -> Self {
+        Self {
+            name: Some(name),
+            ..self
+        }
+    }
+
+    pub fn at(self, center: String) -> Self {
+        Self {
+            center: Some(center),
+            ..self
+        }
+    }
+
+    pub fn sized(self, width: String, height: String) -> Self {
+        Self {
+            width: Some(width),
+            height: Some(height),
+            ..self
+        }
+    }
+
+    pub fn rotated(self, angle: String) -> Self {
+        Self {
+            angle: Some(angle),
+            ..self
+        }
+    }
+
+    pub fn with_opacity(self, opacity: String) -> Self {
+        Self {
+            opacity: Some(opacity),
+            ..self
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -310,27 +648,78 @@ pub enum ExpressionValue {
     Table {
         columns: Vec<TableColumn>,
     },
-    Text {
-        #[serde(skip_serializing_if = "Option::is_none")]
-        text: Option<String>,
+    // A text item, empty notes have no text field
+    #[serde(rename = "text")]
+    Note {
+        #[serde(default)]
+        #[serde(skip_serializing_if = "String::is_empty")]
+        text: String,
     },
     Image(ImageExpression),
-    // TODO: Folder
+    Folder {
+        title: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        collapsed: Option<bool>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        hidden: Option<bool>,
+    },
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct SliderBounds {
-    min: String,
-    max: String,
+    pub min: String,
+    pub max: String,
 
     #[serde(skip_serializing_if = "Option::is_none")]
-    step: Option<String>,
+    pub step: Option<String>,
+}
+
+impl SliderBounds {
+    pub fn new(min: String, max: String, step: Option<String>) -> Self {
+        Self { min, max, step }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct TableColumn {
     #[serde(skip_serializing_if = "Option::is_none")]
-    values: Option<Vec<String>>,
+    id: Option<String>,
+    pub latex: String,
+    // Each cell is latex too, empty cells are empty strings
+    #[serde(default)]
+    pub values: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hidden: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub points: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lines: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line_style: Option<LineStyle>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    point_style: Option<PointStyle>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    drag_mode: Option<DragMode>,
+}
+
+impl TableColumn {
+    pub fn new(latex: String, values: Vec<String>) -> Self {
+        Self {
+            id: None,
+            latex,
+            values,
+            color: None,
+            hidden: None,
+            points: None,
+            lines: None,
+            line_style: None,
+            point_style: None,
+            drag_mode: None,
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -349,19 +738,26 @@ pub struct Ticker {
     playing: Option<bool>,
 }
 
+impl Ticker {
+    pub fn new(handler_latex: String, min_step_latex: Option<String>) -> Self {
+        Self {
+            handler_latex: Some(handler_latex),
+            min_step_latex,
+            open: Some(true),
+            playing: None,
+        }
+    }
+}
+
 impl std::default::Default for CalcState {
     fn default() -> Self {
         Self {
             version: LATEST_STATE_VERSION,
             graph: Some(Graph {
-                viewport: Viewport {
-                    xmin: -10.0,
-                    xmax: 10.0,
-                    ymin: -10.0,
-                    ymax: 10.0,
-                },
+                viewport: Viewport::new(-10.0, 10.0, -10.0, 10.0).unwrap(),
             }),
             random_seed: None,
+            degree_mode: None,
             expressions: Expressions {
                 list: vec![],
                 ticker: None,
@@ -370,23 +766,815 @@ impl std::default::Default for CalcState {
     }
 }
 
+impl CalcState {
+    pub fn with_random_seed() -> Self {
+        Self {
+            random_seed: Some(generate_seed()),
+            ..Default::default()
+        }
+    }
+
+    // Fails when the state can't be serialized, like a viewport with NaN bounds
+    pub fn to_desmos_url(&self) -> Result<String, serde_json::Error> {
+        let json = serde_json::to_string(self)?;
+        Ok(format!(
+            "{}#{}",
+            DESMOS_URL,
+            utf8_percent_encode(&json, URL_FRAGMENT)
+        ))
+    }
+}
+
+// A 32 character hex string, the format desmos uses for randomSeed
+pub fn generate_seed() -> String {
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes).expect("Unable to get random bytes");
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+impl ExpressionValue {
+    pub fn latex(latex: String) -> Self {
+        ExpressionValue::Expression(ValueExpression::new(SetExpression {
+            latex: Some(latex),
+            ..SetExpression::new()
+        }))
+    }
+
+    pub fn table(columns: Vec<TableColumn>) -> Self {
+        ExpressionValue::Table { columns }
+    }
+
+    pub fn folder(title: String) -> Self {
+        ExpressionValue::Folder {
+            title,
+            collapsed: None,
+            hidden: None,
+        }
+    }
+
+    pub fn note(text: String) -> Self {
+        ExpressionValue::Note { text }
+    }
+
+    pub fn image(image: ImageExpression) -> Self {
+        ExpressionValue::Image(image)
+    }
+
+    // Only expressions can be styled, other values are returned unchanged
+    pub fn with_style(self, color: Option<Color>, hidden: Option<bool>) -> Self {
+        match self {
+            ExpressionValue::Expression(mut v) => {
+                v.set_expression.color = color.or(v.set_expression.color);
+                v.set_expression.hidden = hidden.or(v.set_expression.hidden);
+                ExpressionValue::Expression(v)
+            }
+            v => v,
+        }
+    }
+
+    // Bounds are latex, desmos only shows the slider if the expression defines a constant
+    pub fn with_slider(self, min: String, max: String, step: Option<String>) -> Self {
+        match self {
+            ExpressionValue::Expression(mut v) => {
+                v.slider_bounds = Some(SliderBounds::new(min, max, step));
+                ExpressionValue::Expression(v)
+            }
+            v => v,
+        }
+    }
+
+    // Desmos infers how to plot an expression, this only sets the fields it would show
+    //  as enabled, like the fill of a polygon
+    pub fn plotted_as(self, expression_type: FormulaExpressionType) -> Self {
+        match self {
+            ExpressionValue::Expression(mut v) => {
+                match expression_type {
+                    FormulaExpressionType::Polygon => {
+                        v.fill = Some(true);
+                        v.set_expression.lines = Some(true);
+                    }
+                    FormulaExpressionType::SinglePoint | FormulaExpressionType::PointList => {
+                        v.set_expression.points = Some(true);
+                    }
+                    _ => {}
+                }
+                ExpressionValue::Expression(v)
+            }
+            v => v,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum IdError {
+    InvalidPrefix(String),
+}
+
+impl std::fmt::Display for IdError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidPrefix(p) => write!(
+                f,
+                "Invalid id prefix '{}', expected only letters, numbers and underscores",
+                p
+            ),
+        }
+    }
+}
+
+impl std::error::Error for IdError {}
+
+// Counts up from 0, so the same expressions always get the same ids. The default has no
+//  prefix, giving ids "0", "1", ...
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct IdGenerator {
+    prefix: String,
+    next: usize,
+}
+
+impl IdGenerator {
+    pub fn new(prefix: String) -> Result<Self, IdError> {
+        if !prefix
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            return Err(IdError::InvalidPrefix(prefix));
+        }
+        Ok(Self { prefix, next: 0 })
+    }
+
+    pub fn next_id(&mut self) -> String {
+        use std::fmt::Write;
+
+        // Writes the number onto the end of the prefix, saving a format! per id
+        let len = self.prefix.len();
+        write!(self.prefix, "{}", self.next).unwrap();
+        let id = self.prefix.clone();
+        self.prefix.truncate(len);
+        self.next += 1;
+        id
+    }
+
+    // Continues after any ids in the list this generator could have made
+    pub fn skip_used(&mut self, list: &[Expression]) {
+        for e in list {
+            if let Some(n) =
+                e.id.strip_prefix(self.prefix.as_str())
+                    .and_then(|n| n.parse::<usize>().ok())
+            {
+                self.next = self.next.max(n + 1);
+            }
+        }
+    }
+}
+
+// Builds an expression list, giving each expression added a unique id
+#[derive(Clone, Debug, Default)]
+pub struct ExpressionsBuilder {
+    expressions: Expressions,
+    ids: IdGenerator,
+}
+
+impl ExpressionsBuilder {
+    pub fn new(ids: IdGenerator) -> Self {
+        Self {
+            expressions: Expressions::default(),
+            ids,
+        }
+    }
+
+    // When the number of expressions is known, so the list doesn't reallocate as it grows
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            expressions: Expressions {
+                list: Vec::with_capacity(capacity),
+                ticker: None,
+            },
+            ids: IdGenerator::default(),
+        }
+    }
+
+    // Returns the id, so later expressions can be put in a pushed folder
+    pub fn push(&mut self, value: ExpressionValue) -> String {
+        let id = self.ids.next_id();
+        self.expressions
+            .list
+            .push(Expression::new(id.clone(), value));
+        id
+    }
+
+    pub fn push_in_folder(&mut self, value: ExpressionValue, folder_id: String) {
+        let id = self.ids.next_id();
+        self.expressions
+            .list
+            .push(Expression::new(id, value).in_folder(folder_id));
+    }
+
+    // Appends the expressions of another list with new ids, so merged lists never collide.
+    //  The existing ticker is kept if both lists have one.
+    pub fn merge(&mut self, other: Expressions) {
+        // Ids are given out by position, as the incoming list may reuse an id
+        let new_ids = other
+            .list
+            .iter()
+            .map(|_| self.ids.next_id())
+            .collect::<Vec<_>>();
+        let mut first_ids = HashMap::new();
+        for (e, id) in other.list.iter().zip(&new_ids) {
+            first_ids.entry(e.id.clone()).or_insert_with(|| id.clone());
+        }
+        // A folder reference goes to the closest expression before it with that id, since
+        //  folders come before their contents
+        let mut seen_ids = HashMap::new();
+        self.expressions.list.reserve(other.list.len());
+        for (e, id) in other.list.into_iter().zip(new_ids) {
+            let folder_id = e.folder_id.map(|f| {
+                seen_ids
+                    .get(&f)
+                    .or_else(|| first_ids.get(&f))
+                    .cloned()
+                    .unwrap_or(f)
+            });
+            seen_ids.insert(e.id.clone(), id.clone());
+            self.expressions
+                .list
+                .push(Expression { id, folder_id, ..e });
+        }
+        if self.expressions.ticker.is_none() {
+            self.expressions.ticker = other.ticker;
+        }
+    }
+
+    pub fn build(self) -> Expressions {
+        self.expressions
+    }
+}
+
 impl Expressions {
-    pub fn from_latex_strings(latex_strings: Vec<String>) -> Self {
+    pub fn from_values(values: Vec<ExpressionValue>) -> Self {
+        let mut builder = ExpressionsBuilder::with_capacity(values.len());
+        for v in values {
+            builder.push(v);
+        }
+        builder.build()
+    }
+
+    // The handler runs every min_step milliseconds once the ticker is played
+    pub fn with_ticker(self, handler_latex: String, min_step: Option<String>) -> Self {
         Self {
-            list: latex_strings
+            ticker: Some(Ticker::new(handler_latex, min_step)),
+            ..self
+        }
+    }
+
+    pub fn from_latex_strings(latex_strings: Vec<String>) -> Self {
+        Self::from_values(
+            latex_strings
                 .into_iter()
-                .enumerate()
-                .map(|(i, l)| {
-                    Expression::new(
-                        i.to_string(),
-                        ExpressionValue::Expression(ValueExpression::new(SetExpression {
-                            latex: Some(l),
-                            ..SetExpression::new()
-                        })),
-                    )
-                })
+                .map(ExpressionValue::latex)
                 .collect(),
-            ticker: None,
+        )
+    }
+
+    pub fn table_from_columns(columns: Vec<TableColumn>) -> Self {
+        Self::from_values(vec![ExpressionValue::table(columns)])
+    }
+
+    // Ids used by the list are skipped, so appending never duplicates one
+    fn builder(&mut self) -> ExpressionsBuilder {
+        let mut ids = IdGenerator::default();
+        ids.skip_used(&self.list);
+        ExpressionsBuilder {
+            expressions: std::mem::take(self),
+            ids,
+        }
+    }
+
+    pub fn push_note(&mut self, text: String) {
+        let mut builder = self.builder();
+        builder.push(ExpressionValue::note(text));
+        *self = builder.build();
+    }
+
+    // Appends a folder followed by its children
+    pub fn push_folder(&mut self, folder: ExpressionValue, children: Vec<ExpressionValue>) {
+        let mut builder = self.builder();
+        let folder_id = builder.push(folder);
+        for v in children {
+            builder.push_in_folder(v, folder_id.clone());
+        }
+        *self = builder.build();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn viewport(xmin: f64) -> Viewport {
+        Viewport {
+            xmin,
+            xmax: 10.0,
+            ymin: -10.0,
+            ymax: 10.0,
+        }
+    }
+
+    #[test]
+    fn viewport_finite() {
+        assert_eq!(viewport(-10.0).validate(), Ok(()));
+        assert!(serde_json::to_string(&viewport(-10.0)).is_ok());
+    }
+
+    #[test]
+    fn viewport_nan() {
+        let v = viewport(f64::NAN);
+        match v.validate() {
+            Err(ViewportError::NonFinite { field, value }) => {
+                assert_eq!(field, "xmin");
+                assert!(value.is_nan());
+            }
+            r => panic!("expected non-finite error, got {:?}", r),
+        }
+        let err = serde_json::to_string(&v).unwrap_err();
+        assert_eq!(err.to_string(), "Viewport bounds must be finite, got NaN");
+        assert!(serde_json::to_string(&viewport(f64::INFINITY)).is_err());
+    }
+
+    #[test]
+    fn viewport_new() {
+        assert_eq!(Viewport::new(-10.0, 10.0, -10.0, 10.0), Ok(viewport(-10.0)));
+        assert_eq!(
+            Viewport::new(10.0, -10.0, -10.0, 10.0),
+            Err(ViewportError::Inverted {
+                axis: "x",
+                min: 10.0,
+                max: -10.0
+            })
+        );
+        let err = Viewport::new(-10.0, 10.0, 3.0, 3.0).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Viewport ymin must be less than ymax, got 3 and 3"
+        );
+        assert!(matches!(
+            Viewport::new(-10.0, 10.0, f64::NAN, 10.0),
+            Err(ViewportError::NonFinite { field: "ymin", .. })
+        ));
+    }
+
+    #[test]
+    fn random_seed() {
+        assert_eq!(CalcState::default().random_seed, None);
+        let seed = CalcState::with_random_seed().random_seed.unwrap();
+        assert_eq!(seed.len(), 32);
+        assert!(seed.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f')));
+        assert_ne!(generate_seed(), generate_seed());
+    }
+
+    #[test]
+    fn degree_mode() {
+        let state = CalcState::default();
+        assert!(serde_json::to_value(&state)
+            .unwrap()
+            .get("degreeMode")
+            .is_none());
+        let state = CalcState {
+            degree_mode: Some(true),
+            ..state
+        };
+        let json = serde_json::to_value(&state).unwrap();
+        assert_eq!(json["degreeMode"], serde_json::json!(true));
+        assert_eq!(serde_json::from_value::<CalcState>(json).unwrap(), state);
+    }
+
+    #[test]
+    fn desmos_url() {
+        let state = CalcState {
+            expressions: Expressions::from_latex_strings(vec![
+                "y=x^{2}".to_string(),
+                "a=\\left[1,2\\right]".to_string(),
+            ]),
+            ..Default::default()
+        };
+        let url = state.to_desmos_url().unwrap();
+        let tail = url
+            .strip_prefix("https://www.desmos.com/calculator#")
+            .unwrap();
+        assert!(!tail.contains(['{', '"', ' ', '#', '\\']));
+        let json = percent_encoding::percent_decode_str(tail)
+            .decode_utf8()
+            .unwrap();
+        assert_eq!(serde_json::from_str::<CalcState>(&json).unwrap(), state);
+    }
+
+    #[test]
+    fn desmos_url_nan_bounds() {
+        let state = CalcState {
+            graph: Some(Graph {
+                viewport: viewport(f64::NAN),
+            }),
+            ..Default::default()
+        };
+        assert!(state.to_desmos_url().is_err());
+    }
+
+    #[test]
+    fn table_roundtrip() {
+        // exported from the desmos calculator
+        let sample = serde_json::json!({
+            "type": "table",
+            "id": "1",
+            "columns": [
+                {
+                    "values": ["1", "2", "3", ""],
+                    "hidden": false,
+                    "id": "2",
+                    "color": "#388c46",
+                    "latex": "x_{1}"
+                },
+                {
+                    "values": ["2", "4", "6", ""],
+                    "id": "3",
+                    "color": "#6042a6",
+                    "latex": "y_{1}",
+                    "points": true,
+                    "lines": true,
+                    "lineStyle": "DASHED",
+                    "pointStyle": "OPEN",
+                    "dragMode": "XY"
+                }
+            ]
+        });
+        let expr: Expression = serde_json::from_value(sample.clone()).unwrap();
+        match &expr.value {
+            ExpressionValue::Table { columns } => {
+                assert_eq!(columns[0].latex, "x_{1}");
+                assert_eq!(columns[1].values, vec!["2", "4", "6", ""]);
+                assert_eq!(columns[1].points, Some(true));
+            }
+            v => panic!("expected a table, got {:?}", v),
+        }
+        assert_eq!(serde_json::to_value(&expr).unwrap(), sample);
+    }
+
+    #[test]
+    fn folder() {
+        let mut exprs = Expressions::from_latex_strings(vec!["a=1".to_string()]);
+        exprs.push_folder(
+            ExpressionValue::Folder {
+                title: "helpers".to_string(),
+                collapsed: Some(true),
+                hidden: None,
+            },
+            vec![
+                ExpressionValue::latex("b=2".to_string()),
+                ExpressionValue::latex("c=3".to_string()),
+            ],
+        );
+        let json = serde_json::to_value(&exprs).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "list": [
+                    { "id": "0", "type": "expression", "latex": "a=1" },
+                    { "id": "1", "type": "folder", "title": "helpers", "collapsed": true },
+                    { "id": "2", "folderId": "1", "type": "expression", "latex": "b=2" },
+                    { "id": "3", "folderId": "1", "type": "expression", "latex": "c=3" }
+                ]
+            })
+        );
+        assert_eq!(serde_json::from_value::<Expressions>(json).unwrap(), exprs);
+    }
+
+    #[test]
+    fn table_from_columns() {
+        let exprs = Expressions::table_from_columns(vec![
+            TableColumn::new("x_1".to_string(), vec!["1".to_string(), "2".to_string()]),
+            TableColumn {
+                points: Some(true),
+                ..TableColumn::new("y_1".to_string(), vec!["3".to_string(), "4".to_string()])
+            },
+        ]);
+        assert_eq!(
+            serde_json::to_value(&exprs).unwrap(),
+            serde_json::json!({
+                "list": [{
+                    "id": "0",
+                    "type": "table",
+                    "columns": [
+                        { "latex": "x_1", "values": ["1", "2"] },
+                        { "latex": "y_1", "values": ["3", "4"], "points": true }
+                    ]
+                }]
+            })
+        );
+    }
+
+    #[test]
+    fn slider_bounds() {
+        let a = ExpressionValue::latex("a=1".to_string());
+        let json = serde_json::to_value(&a).unwrap();
+        assert_eq!(json.get("sliderBounds"), None);
+
+        let json = serde_json::to_value(a.with_slider(
+            "-5".to_string(),
+            "5".to_string(),
+            Some("0.5".to_string()),
+        ))
+        .unwrap();
+        assert_eq!(
+            json["sliderBounds"],
+            serde_json::json!({"min": "-5", "max": "5", "step": "0.5"})
+        );
+        let b = ExpressionValue::latex("b=1".to_string()).with_slider(
+            "0".to_string(),
+            "1".to_string(),
+            None,
+        );
+        assert_eq!(
+            serde_json::to_value(b).unwrap()["sliderBounds"],
+            serde_json::json!({"min": "0", "max": "1"})
+        );
+        // only expressions have sliders
+        let note = ExpressionValue::note("hi".to_string());
+        assert_eq!(
+            note.clone()
+                .with_slider("0".to_string(), "1".to_string(), None),
+            note
+        );
+    }
+
+    #[test]
+    fn colors() {
+        let styled =
+            |color| ExpressionValue::latex("y=x".to_string()).with_style(Some(color), None);
+        let roundtrip = |v: ExpressionValue, json: serde_json::Value| {
+            let expr = Expression::new("1".to_string(), v);
+            let value = serde_json::to_value(&expr).unwrap();
+            assert_eq!(value, json);
+            assert_eq!(serde_json::from_value::<Expression>(value).unwrap(), expr);
+        };
+        roundtrip(
+            styled(Color::hex("#ff8000".to_string()).unwrap()),
+            serde_json::json!({"id": "1", "type": "expression", "latex": "y=x", "color": "#ff8000"}),
+        );
+        roundtrip(
+            styled("blue".parse().unwrap()),
+            serde_json::json!({"id": "1", "type": "expression", "latex": "y=x", "color": "#2d70b3"}),
+        );
+        roundtrip(
+            styled(Color::latex("c_{1}".to_string())),
+            serde_json::json!({"id": "1", "type": "expression", "latex": "y=x", "colorLatex": "c_{1}"}),
+        );
+        roundtrip(
+            ExpressionValue::latex("y=x".to_string()),
+            serde_json::json!({"id": "1", "type": "expression", "latex": "y=x"}),
+        );
+
+        // an invalid color is an error rather than no color
+        let err = serde_json::from_value::<Expression>(serde_json::json!(
+            {"id": "1", "type": "expression", "latex": "y=x", "color": "reddish"}
+        ))
+        .unwrap_err();
+        assert!(err.to_string().contains("Invalid color 'reddish'"));
+
+        assert_eq!("RED".parse(), Ok(Color::Named(NamedColor::Red)));
+        assert_eq!("#F00".parse(), Ok(Color::Hex("#F00".to_string())));
+        for invalid in ["f00", "#ff00", "#ggg", "reddish"] {
+            assert_eq!(
+                invalid.parse::<Color>(),
+                Err(ColorError::InvalidHex(invalid.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn image_roundtrip() {
+        // exported from the desmos calculator
+        let sample = serde_json::json!({
+            "type": "image",
+            "id": "3",
+            "image_url": "https://saved-work.desmos.com/calc_thumbs/production/logo.png",
+            "name": "logo",
+            "width": "10",
+            "height": "5",
+            "center": "\\left(1,2\\right)",
+            "angle": "\\pi",
+            "opacity": "0.5",
+            "draggable": true,
+            "foreground": true,
+            "clickableInfo": {
+                "enabled": true,
+                "latex": "a\\to a+1",
+                "hoveredImage": "https://saved-work.desmos.com/calc_thumbs/production/hover.png"
+            }
+        });
+        let expr: Expression = serde_json::from_value(sample.clone()).unwrap();
+        match &expr.value {
+            ExpressionValue::Image(image) => {
+                assert_eq!(image.name, Some("logo".to_string()));
+                assert_eq!(image.center, Some("\\left(1,2\\right)".to_string()));
+                assert_eq!(image.draggable, Some(true));
+            }
+            v => panic!("expected an image, got {:?}", v),
         }
+        assert_eq!(serde_json::to_value(&expr).unwrap(), sample);
+
+        let image = ImageExpression::new("https://example.com/a.png".to_string())
+            .named("a".to_string())
+            .at("\\left(0,0\\right)".to_string())
+            .sized("4".to_string(), "3".to_string())
+            .rotated("0".to_string())
+            .with_opacity("1".to_string());
+        assert_eq!(
+            serde_json::to_value(Expression::new(
+                "0".to_string(),
+                ExpressionValue::image(image)
+            ))
+            .unwrap(),
+            serde_json::json!({
+                "id": "0",
+                "type": "image",
+                "image_url": "https://example.com/a.png",
+                "name": "a",
+                "center": "\\left(0,0\\right)",
+                "width": "4",
+                "height": "3",
+                "angle": "0",
+                "opacity": "1"
+            })
+        );
+    }
+
+    #[test]
+    fn note() {
+        let mut exprs = Expressions::from_latex_strings(vec!["a=1".to_string()]);
+        exprs.push_note("a is one".to_string());
+        exprs.push_note(String::new());
+        let json = serde_json::to_value(&exprs).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "list": [
+                    { "id": "0", "type": "expression", "latex": "a=1" },
+                    { "id": "1", "type": "text", "text": "a is one" },
+                    { "id": "2", "type": "text" }
+                ]
+            })
+        );
+        assert_eq!(serde_json::from_value::<Expressions>(json).unwrap(), exprs);
+    }
+
+    #[test]
+    fn ticker() {
+        let exprs = Expressions::from_latex_strings(vec!["a=1".to_string()]);
+        assert_eq!(
+            serde_json::to_value(&exprs).unwrap()["ticker"],
+            serde_json::Value::Null
+        );
+        let exprs = exprs.with_ticker("a\\to a+1".to_string(), Some("100".to_string()));
+        let json = serde_json::to_value(&exprs).unwrap();
+        assert_eq!(
+            json["ticker"],
+            serde_json::json!({
+                "handlerLatex": "a\\to a+1",
+                "minStepLatex": "100",
+                "open": true
+            })
+        );
+        assert_eq!(serde_json::from_value::<Expressions>(json).unwrap(), exprs);
+    }
+
+    #[test]
+    fn plotted_as() {
+        let exprs = Expressions::from_values(vec![
+            ExpressionValue::latex("\\operatorname{polygon}\\left(P\\right)".to_string())
+                .plotted_as(FormulaExpressionType::Polygon),
+            ExpressionValue::latex("P=\\left[\\left(0,0\\right)\\right]".to_string())
+                .plotted_as(FormulaExpressionType::PointList),
+            ExpressionValue::note("a note".to_string()).plotted_as(FormulaExpressionType::Polygon),
+        ]);
+        let json = serde_json::to_value(&exprs).unwrap();
+        assert_eq!(
+            json["list"],
+            serde_json::json!([
+                {
+                    "id": "0",
+                    "type": "expression",
+                    "latex": "\\operatorname{polygon}\\left(P\\right)",
+                    "fill": true,
+                    "lines": true
+                },
+                {
+                    "id": "1",
+                    "type": "expression",
+                    "latex": "P=\\left[\\left(0,0\\right)\\right]",
+                    "points": true
+                },
+                { "id": "2", "type": "text", "text": "a note" }
+            ])
+        );
+        assert_eq!(serde_json::from_value::<Expressions>(json).unwrap(), exprs);
+    }
+
+    #[test]
+    fn merge_unique_ids() {
+        let first = Expressions::from_latex_strings(vec!["a=1".to_string(), "b=2".to_string()]);
+        let mut second = Expressions::from_latex_strings(vec!["c=3".to_string()]);
+        second.push_folder(
+            ExpressionValue::folder("f".to_string()),
+            vec![ExpressionValue::latex("d=4".to_string())],
+        );
+
+        let mut builder = ExpressionsBuilder::default();
+        builder.merge(first);
+        builder.merge(second);
+        let mut merged = builder.build();
+        merged.push_note("e".to_string());
+
+        let ids = merged
+            .list
+            .iter()
+            .map(|e| e.id.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec!["0", "1", "2", "3", "4", "5"]);
+        // the folder's child follows it to its new id
+        assert_eq!(merged.list[4].folder_id, Some("3".to_string()));
+        assert_eq!(
+            merged.list[4].value,
+            ExpressionValue::latex("d=4".to_string())
+        );
+    }
+
+    #[test]
+    fn merge_duplicate_ids() {
+        let other: Expressions = serde_json::from_value(serde_json::json!({
+            "list": [
+                { "id": "f", "type": "folder", "title": "one" },
+                { "id": "1", "type": "expression", "latex": "a=1", "folderId": "f" },
+                { "id": "f", "type": "folder", "title": "two" },
+                { "id": "1", "type": "expression", "latex": "b=2", "folderId": "f" },
+            ]
+        }))
+        .unwrap();
+
+        let mut builder = ExpressionsBuilder::default();
+        builder.push(ExpressionValue::latex("c=3".to_string()));
+        builder.merge(other);
+        let merged = builder.build();
+
+        let ids = merged
+            .list
+            .iter()
+            .map(|e| e.id.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec!["0", "1", "2", "3", "4"]);
+        // each child stays in the folder it followed
+        assert_eq!(merged.list[2].folder_id, Some("1".to_string()));
+        assert_eq!(merged.list[4].folder_id, Some("3".to_string()));
+        assert_eq!(
+            merged.list[4].value,
+            ExpressionValue::latex("b=2".to_string())
+        );
+    }
+
+    #[test]
+    fn from_values_capacity() {
+        let values = (0..100)
+            .map(|i| ExpressionValue::latex(format!("a={}", i)))
+            .collect::<Vec<_>>();
+        let mut builder = ExpressionsBuilder::default();
+        for v in values.clone() {
+            builder.push(v);
+        }
+        let exprs = Expressions::from_values(values);
+        // the list is sized once, and the output is the same as pushing one at a time
+        assert_eq!(exprs.list.capacity(), 100);
+        assert_eq!(exprs, builder.build());
+    }
+
+    #[test]
+    fn id_prefix() {
+        let mut builder = ExpressionsBuilder::new(IdGenerator::new("lib_".to_string()).unwrap());
+        builder.merge(Expressions::from_latex_strings(vec!["a=1".to_string()]));
+        builder.push(ExpressionValue::latex("b=2".to_string()));
+        let exprs = builder.build();
+        assert_eq!(
+            exprs.list.iter().map(|e| e.id.as_str()).collect::<Vec<_>>(),
+            vec!["lib_0", "lib_1"]
+        );
+        assert_eq!(
+            IdGenerator::new("my-lib".to_string()),
+            Err(IdError::InvalidPrefix("my-lib".to_string()))
+        );
+
+        // unprefixed ids continue after the ones already used
+        let mut exprs: Expressions = serde_json::from_value(serde_json::json!({
+            "list": [{ "id": "7", "type": "expression", "latex": "a=1" }]
+        }))
+        .unwrap();
+        exprs.push_note("b".to_string());
+        assert_eq!(exprs.list[1].id, "8");
     }
 }
@@ -1,3 +1,5 @@
+use std::ops::Range;
+
 use types::CompareOperator;
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -21,6 +23,8 @@ pub struct Cond {
     pub left: Latex,
     pub op: CompareOperator,
     pub right: Latex,
+    // A double inequality continues with `op latex` after right
+    pub chain: Option<(CompareOperator, Latex)>,
     pub result: Latex,
 }
 
@@ -30,6 +34,12 @@ pub enum Function {
     Log { base: String },
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SummationKind {
+    Sum,
+    Prod,
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum Latex {
     Variable(String),
@@ -60,6 +70,35 @@ pub enum Latex {
         default: Box<Latex>,
     },
     Raw(String),
+    // \sum_{var=lower}^{upper} body, var is only bound in body
+    Summation {
+        kind: SummationKind,
+        var: String,
+        lower: Box<Latex>,
+        upper: Box<Latex>,
+        body: Box<Latex>,
+    },
+    Point {
+        x: Box<Latex>,
+        y: Box<Latex>,
+    },
+    // \int_{lower}^{upper} body \, dvar
+    Integral {
+        var: String,
+        lower: Box<Latex>,
+        upper: Box<Latex>,
+        body: Box<Latex>,
+    },
+    // \left[body\operatorname{for}var=list\right], var is only bound in body
+    Comprehension {
+        body: Box<Latex>,
+        var: String,
+        list: Box<Latex>,
+    },
+    // \left(inner\right), keeps an expression that was substituted into another together
+    Group(Box<Latex>),
+    // The source span the compiler produced inner from, only added when building a source map
+    Spanned(types::Span, Box<Latex>),
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -71,6 +110,9 @@ pub enum LatexStatement {
         args: Vec<String>,
         body: Box<Latex>,
     },
+    Note(String),
+    // Only affects graph output
+    Styled(types::Style, Box<LatexStatement>),
 }
 
 pub fn format_latex_identifier(v: String) -> String {
@@ -90,43 +132,78 @@ pub fn format_latex_identifier(v: String) -> String {
     }
 }
 
+// Desmos displays `-0` as is, so drop the sign of zero along with any redundant `+`
+pub fn normalize_num(n: String) -> String {
+    let unsigned = n.strip_prefix('+').unwrap_or(&n);
+    if let Some(abs) = unsigned.strip_prefix('-') {
+        if abs.chars().all(|c| c == '0' || c == '.') {
+            return abs.to_string();
+        }
+    }
+    unsigned.to_string()
+}
+
 pub fn multi_latex_to_str(items: Vec<Latex>) -> Vec<String> {
     items.into_iter().map(latex_to_str).collect()
 }
 
+impl Latex {
+    // The node a source map span is attached to, emitted the same as the node itself
+    pub fn unspanned(&self) -> &Latex {
+        match self {
+            Latex::Spanned(_, inner) => inner.unspanned(),
+            l => l,
+        }
+    }
+}
+
 // attempt to save bytes by only parenthesizing when necessary
 pub fn needs_parens(left: &Latex, operator: BinaryOperator) -> bool {
     if operator == BinaryOperator::Divide {
         return false;
     }
-    match &left {
+    match left.unspanned() {
         Latex::Num(_) => false,
         Latex::Call { .. } => false,
         Latex::Piecewise { .. } => false,
+        Latex::Group(_) => false,
         Latex::BinaryExpression { operator, .. } => *operator != BinaryOperator::Divide,
         _ => true,
     }
 }
 
-pub fn binaryoperator_to_str(left: Latex, operator: BinaryOperator, right: Latex) -> String {
-    let l_raw = latex_to_str(left.clone());
-    let rs = latex_to_str(right.clone());
-    let ls = if needs_parens(&left, operator) {
-        format!("({})", l_raw)
-    } else {
-        l_raw
-    };
-
-    match operator {
-        BinaryOperator::Add => format!("{}+{}", ls, rs),
-        BinaryOperator::Subtract => format!("{}-{}", ls, rs),
-        BinaryOperator::Multiply => match (left, right) {
-            (Latex::Num(_), Latex::Num(_)) => format!("{}\\cdot {}", ls, rs),
-            _ => format!("{}{}", ls, rs),
-        },
-        BinaryOperator::Divide => format!("\\frac{{{}}}{{{}}}", ls, rs),
-        BinaryOperator::Exponent => format!("{}^{{{}}}", ls, rs),
-        BinaryOperator::Index => format!("{}\\left[{}\\right]", ls, rs),
+// The operand a product, power or index is written starting with, `10` in `10^{3}`
+fn leading_operand(l: &Latex) -> &Latex {
+    match l.unspanned() {
+        Latex::BinaryExpression {
+            left,
+            operator:
+                operator @ (BinaryOperator::Multiply | BinaryOperator::Exponent | BinaryOperator::Index),
+            ..
+        } if !needs_parens(left, *operator) => leading_operand(left),
+        l => l,
+    }
+}
+
+// Whether a product has to be written with \cdot because juxtaposing the factors would
+//  change their meaning
+pub fn needs_explicit_mult(left: &Latex, right: &Latex) -> bool {
+    match (left.unspanned(), leading_operand(right)) {
+        // `23` would read as a single number
+        (Latex::Num(_), Latex::Num(_)) => true,
+        // digits after a factor are ambiguous, `x2` could be read as a subscript
+        (_, Latex::Num(_)) => true,
+        // `a-b` would be a subtraction
+        (
+            _,
+            Latex::UnaryExpression {
+                operator: UnaryOperator::Negate,
+                ..
+            },
+        ) => true,
+        // `a\left[b\right]` would index a
+        (_, Latex::List(_) | Latex::Range { .. } | Latex::Comprehension { .. }) => true,
+        _ => false,
     }
 }
 
@@ -135,119 +212,339 @@ pub fn compareop_to_str(op: CompareOperator) -> &'static str {
         CompareOperator::Equal => "=",
         CompareOperator::GreaterThan => ">", // or \gt
         CompareOperator::LessThan => "<",    // or \lt
-        CompareOperator::GreaterThanEqual => "\\le",
-        CompareOperator::LessThanEqual => "\\ge",
+        CompareOperator::GreaterThanEqual => "\\ge",
+        CompareOperator::LessThanEqual => "\\le",
+        CompareOperator::NotEqual => "\\ne",
     }
 }
 
-pub fn cond_to_str(cond: Cond) -> String {
-    format!(
-        "{}{}{}:{}",
-        latex_to_str(cond.left),
-        compareop_to_str(cond.op),
-        latex_to_str(cond.right),
-        latex_to_str(cond.result)
-    )
+// Builtins that LaTeX has a command for, the rest are written with \operatorname
+const LATEX_OPERATORS: &[&str] = &[
+    "sin", "cos", "tan", "cot", "sec", "csc", "arcsin", "arccos", "arctan", "sinh", "cosh", "tanh",
+    "coth", "exp", "ln", "log", "min", "max", "gcd",
+];
+
+// Nothing is around the value of a statement, so a group there needs no parens
+fn ungroup(l: Latex) -> Latex {
+    match l {
+        Latex::Group(inner) => ungroup(*inner),
+        Latex::Spanned(span, inner) => Latex::Spanned(span, Box::new(ungroup(*inner))),
+        l => l,
+    }
 }
 
-fn latex_call_to_str(func: Function, is_builtin: bool, args: Vec<Latex>) -> String {
-    if let Function::Normal { name } = &func {
-        if is_builtin && (name == "sqrt" || name == "nthroot") {
-            // there should only be one arg in case of sqrt, two in case of nthroot
-            return format!("\\sqrt{{{}}}", multi_latex_to_str(args).join("}{"));
+// Emits latex into a single string, recording the byte range each spanned node was written to
+#[derive(Default)]
+struct Writer {
+    out: String,
+    spans: Vec<(types::Span, Range<usize>)>,
+}
+
+impl Writer {
+    fn push(&mut self, s: &str) {
+        self.out.push_str(s);
+    }
+
+    fn separated(&mut self, items: Vec<Latex>, sep: &str) {
+        for (i, l) in items.into_iter().enumerate() {
+            if i > 0 {
+                self.push(sep);
+            }
+            self.node(l);
+        }
+    }
+
+    fn parenthesized(&mut self, l: Latex, open: &str, close: &str) {
+        self.push(open);
+        self.node(l);
+        self.push(close);
+    }
+
+    fn binary(&mut self, left: Latex, operator: BinaryOperator, right: Latex) {
+        let left_parens = needs_parens(&left, operator);
+        let write_left = |w: &mut Self, left| {
+            if left_parens {
+                w.parenthesized(left, "(", ")");
+            } else {
+                w.node(left);
+            }
+        };
+
+        // slices are written without the brackets of the range (`L\left[2...5\right]`)
+        if let (
+            BinaryOperator::Index,
+            Latex::Range {
+                first,
+                second: None,
+                end,
+            },
+        ) = (operator, right.unspanned())
+        {
+            let (first, end) = ((**first).clone(), (**end).clone());
+            write_left(self, left);
+            self.push("\\left[");
+            self.node(first);
+            self.push("...");
+            self.node(end);
+            self.push("\\right]");
+            return;
+        }
+
+        match operator {
+            BinaryOperator::Add | BinaryOperator::Subtract => {
+                write_left(self, left);
+                self.push(if operator == BinaryOperator::Add {
+                    "+"
+                } else {
+                    "-"
+                });
+                self.node(right);
+            }
+            BinaryOperator::Multiply => {
+                let explicit = needs_explicit_mult(&left, &right);
+                // a sum on the right is not covered by needs_parens
+                let right_parens = matches!(
+                    right.unspanned(),
+                    Latex::BinaryExpression {
+                        operator: BinaryOperator::Add | BinaryOperator::Subtract,
+                        ..
+                    }
+                );
+                write_left(self, left);
+                if explicit {
+                    self.push("\\cdot ");
+                }
+                if right_parens {
+                    self.parenthesized(right, "(", ")");
+                } else {
+                    self.node(right);
+                }
+            }
+            BinaryOperator::Divide => {
+                self.push("\\frac{");
+                write_left(self, left);
+                self.parenthesized(right, "}{", "}");
+            }
+            BinaryOperator::Exponent => {
+                write_left(self, left);
+                self.parenthesized(right, "^{", "}");
+            }
+            BinaryOperator::Index => {
+                write_left(self, left);
+                self.parenthesized(right, "\\left[", "\\right]");
+            }
+        }
+    }
+
+    fn cond(&mut self, cond: Cond) {
+        self.node(cond.left);
+        self.push(compareop_to_str(cond.op));
+        self.node(cond.right);
+        if let Some((op, l)) = cond.chain {
+            self.push(compareop_to_str(op));
+            self.node(l);
         }
+        self.push(":");
+        self.node(cond.result);
     }
-    format!(
-        "{}{}\\left({}\\right)",
-        if is_builtin { "\\" } else { "" },
+
+    fn call(&mut self, func: Function, is_builtin: bool, mut args: Vec<Latex>) {
+        if let Function::Normal { name } = &func {
+            if is_builtin && name == "sqrt" && args.len() == 1 {
+                self.push("\\sqrt{");
+                self.separated(args, ",");
+                self.push("}");
+                return;
+            }
+            if is_builtin && name == "abs" && args.len() == 1 {
+                self.push("\\left|");
+                self.separated(args, ",");
+                self.push("\\right|");
+                return;
+            }
+            // nthroot(x, n) is the nth root of x
+            if is_builtin && name == "nthroot" && args.len() == 2 {
+                let degree = args.pop().unwrap();
+                let radicand = args.pop().unwrap();
+                self.parenthesized(degree, "\\sqrt[", "]");
+                self.parenthesized(radicand, "{", "}");
+                return;
+            }
+        }
         match func {
-            Function::Normal { name } =>
-                if is_builtin {
-                    name
+            Function::Normal { name } => {
+                if !is_builtin {
+                    self.push(&format_latex_identifier(name));
+                } else if LATEX_OPERATORS.contains(&name.as_str()) {
+                    self.push(&format!("\\{}", name));
                 } else {
-                    format_latex_identifier(name)
-                },
+                    self.push(&format!("\\operatorname{{{}}}", name));
+                }
+            }
             Function::Log { base } => {
                 if base.is_empty() {
-                    "log".to_string()
+                    self.push("\\log");
                 } else {
-                    format!("log_{{{}}}", base)
+                    self.push(&format!("\\log_{{{}}}", base));
                 }
             }
-        },
-        multi_latex_to_str(args).join(",")
-    )
-}
+        }
+        self.push("\\left(");
+        self.separated(args, ",");
+        self.push("\\right)");
+    }
 
-pub fn latex_to_str(l: Latex) -> String {
-    match l {
-        Latex::Variable(s) => format_latex_identifier(s),
-        Latex::Num(s) => s.to_string(),
-        Latex::Call {
-            func,
-            is_builtin,
-            args,
-        } => latex_call_to_str(func, is_builtin, args),
-        Latex::BinaryExpression {
-            left,
-            operator,
-            right,
-        } => binaryoperator_to_str(*left, operator, *right),
-        Latex::UnaryExpression { left, operator } => match operator {
-            UnaryOperator::Negate => format!("-{}", latex_to_str(*left)),
-            UnaryOperator::Factorial => format!("{}!", latex_to_str(*left),),
-        },
-        Latex::List(items) => format!("\\left[{}\\right]", multi_latex_to_str(items).join(",")),
-        Latex::Range { first, second, end } => {
-            if let Some(second) = second {
-                format!(
-                    "\\left[{},{},...,{}\\right]",
-                    latex_to_str(*first),
-                    latex_to_str(*second),
-                    latex_to_str(*end)
-                )
-            } else {
-                format!(
-                    "\\left[{},...,{}\\right]",
-                    latex_to_str(*first),
-                    latex_to_str(*end)
-                )
+    fn node(&mut self, l: Latex) {
+        match l {
+            Latex::Variable(s) => self.push(&format_latex_identifier(s)),
+            Latex::Num(s) => self.push(&normalize_num(s)),
+            Latex::Call {
+                func,
+                is_builtin,
+                args,
+            } => self.call(func, is_builtin, args),
+            Latex::BinaryExpression {
+                left,
+                operator,
+                right,
+            } => self.binary(*left, operator, *right),
+            Latex::UnaryExpression { left, operator } => match operator {
+                UnaryOperator::Negate => {
+                    self.push("-");
+                    self.node(*left);
+                }
+                UnaryOperator::Factorial => {
+                    self.node(*left);
+                    self.push("!");
+                }
+            },
+            Latex::List(items) => {
+                self.push("\\left[");
+                self.separated(items, ",");
+                self.push("\\right]");
+            }
+            Latex::Range { first, second, end } => {
+                self.push("\\left[");
+                self.node(*first);
+                if let Some(second) = second {
+                    self.parenthesized(*second, ",", "");
+                }
+                self.parenthesized(*end, ",...,", "\\right]");
+            }
+            Latex::Piecewise {
+                first,
+                rest,
+                default,
+            } => {
+                self.push("\\left\\{");
+                self.cond(*first);
+                self.push(",");
+                for cond in rest {
+                    self.cond(cond);
+                    self.push(",");
+                }
+                self.parenthesized(*default, "", "\\right\\}");
+            }
+            Latex::Raw(l) => self.push(&l),
+            Latex::Summation {
+                kind,
+                var,
+                lower,
+                upper,
+                body,
+            } => {
+                self.push(match kind {
+                    SummationKind::Sum => "\\sum",
+                    SummationKind::Prod => "\\prod",
+                });
+                self.push(&format!("_{{{}=", format_latex_identifier(var)));
+                self.node(*lower);
+                self.parenthesized(*upper, "}^{", "}");
+                // the summation only extends over the next term
+                match body.unspanned() {
+                    Latex::BinaryExpression {
+                        operator: BinaryOperator::Add | BinaryOperator::Subtract,
+                        ..
+                    } => self.parenthesized(*body, "\\left(", "\\right)"),
+                    _ => self.node(*body),
+                }
+            }
+            Latex::Point { x, y } => {
+                self.parenthesized(*x, "\\left(", ",");
+                self.parenthesized(*y, "", "\\right)");
+            }
+            Latex::Comprehension { body, var, list } => {
+                self.parenthesized(*body, "\\left[", "\\operatorname{for}");
+                self.push(&format_latex_identifier(var));
+                self.parenthesized(*list, "=", "\\right]");
+            }
+            // the integrand extends up to the differential, so it never needs parens
+            Latex::Integral {
+                var,
+                lower,
+                upper,
+                body,
+            } => {
+                self.parenthesized(*lower, "\\int_{", "}");
+                self.parenthesized(*upper, "^{", "}");
+                self.node(*body);
+                self.push(&format!("\\,d{}", format_latex_identifier(var)));
+            }
+            Latex::Group(inner) => self.parenthesized(*inner, "\\left(", "\\right)"),
+            Latex::Spanned(span, inner) => {
+                let start = self.out.len();
+                self.node(*inner);
+                self.spans.push((span, start..self.out.len()));
             }
         }
-        Latex::Piecewise {
-            first,
-            rest,
-            default,
-        } => format!(
-            "\\left\\{{{},{}{}\\right\\}}",
-            cond_to_str(*first),
-            rest.into_iter()
-                .map(|cond| cond_to_str(cond) + ",")
-                .collect::<String>(),
-            latex_to_str(*default)
-        ),
-        Latex::Raw(l) => l,
     }
-}
 
-pub fn latex_stmt_to_str(stmt: LatexStatement) -> String {
-    match stmt {
-        LatexStatement::Expression(e) => latex_to_str(e),
-        LatexStatement::Assignment(left, right) => {
-            format!("{}={}", latex_to_str(*left), latex_to_str(*right))
+    fn stmt(&mut self, stmt: LatexStatement) {
+        match stmt {
+            LatexStatement::Expression(e) => self.node(ungroup(e)),
+            LatexStatement::Assignment(left, right) => {
+                self.node(*left);
+                self.push("=");
+                self.node(ungroup(*right));
+            }
+            LatexStatement::FuncDef { name, args, body } => {
+                self.push(&format!(
+                    "{}\\left({}\\right)=",
+                    format_latex_identifier(name),
+                    args.into_iter()
+                        .map(format_latex_identifier)
+                        .collect::<Vec<String>>()
+                        .join(",")
+                ));
+                self.node(ungroup(*body));
+            }
+            // Typing `"` into an empty Desmos expression turns it into a note
+            LatexStatement::Note(text) => self.push(&format!("\"{}", text)),
+            LatexStatement::Styled(_, stmt) => self.stmt(*stmt),
         }
-        LatexStatement::FuncDef { name, args, body } => format!(
-            "{}\\left({}\\right)={}",
-            name,
-            args.into_iter()
-                .map(format_latex_identifier)
-                .collect::<Vec<String>>()
-                .join(","),
-            latex_to_str(*body)
-        ),
     }
 }
 
+pub fn latex_to_str(l: Latex) -> String {
+    let mut w = Writer::default();
+    w.node(l);
+    w.out
+}
+
+pub fn latex_stmt_to_str(stmt: LatexStatement) -> String {
+    latex_stmt_to_str_with_spans(stmt).0
+}
+
+// Like latex_stmt_to_str, but also gives the byte range of the output of every spanned node,
+//  with nodes before the nodes containing them
+pub fn latex_stmt_to_str_with_spans(
+    stmt: LatexStatement,
+) -> (String, Vec<(types::Span, Range<usize>)>) {
+    let mut w = Writer::default();
+    w.stmt(stmt);
+    (w.out, w.spans)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -264,6 +561,7 @@ mod tests {
                     left: Latex::Num("1".to_string()),
                     op: CompareOperator::Equal,
                     right: Latex::Num("2".to_string()),
+                    chain: None,
                     result: Latex::Num("3".to_string()),
                 }),
                 rest: vec![],
@@ -281,12 +579,14 @@ mod tests {
                     left: Latex::Num("1".to_string()),
                     op: CompareOperator::Equal,
                     right: Latex::Num("2".to_string()),
+                    chain: None,
                     result: Latex::Num("3".to_string()),
                 }),
                 rest: vec![Cond {
                     left: Latex::Num("4".to_string()),
                     op: CompareOperator::LessThan,
                     right: Latex::Num("5".to_string()),
+                    chain: None,
                     result: Latex::Num("6".to_string()),
                 }],
                 default: Box::new(Latex::Num("7".to_string())),
@@ -295,6 +595,339 @@ mod tests {
         )
     }
 
+    #[test]
+    fn piecewise_inclusive() {
+        check(
+            Latex::Piecewise {
+                first: Box::new(Cond {
+                    left: Latex::Variable("x".to_string()),
+                    op: CompareOperator::GreaterThanEqual,
+                    right: Latex::Num("1".to_string()),
+                    chain: None,
+                    result: Latex::Num("2".to_string()),
+                }),
+                rest: vec![Cond {
+                    left: Latex::Variable("x".to_string()),
+                    op: CompareOperator::LessThanEqual,
+                    right: Latex::Num("-1".to_string()),
+                    chain: None,
+                    result: Latex::Num("3".to_string()),
+                }],
+                default: Box::new(Latex::Num("4".to_string())),
+            },
+            "\\left\\{x\\ge1:2,x\\le-1:3,4\\right\\}",
+        )
+    }
+
+    #[test]
+    fn piecewise_not_equal() {
+        check(
+            Latex::Piecewise {
+                first: Box::new(Cond {
+                    left: Latex::Variable("x".to_string()),
+                    op: CompareOperator::NotEqual,
+                    right: Latex::Num("0".to_string()),
+                    chain: None,
+                    result: Latex::Num("1".to_string()),
+                }),
+                rest: vec![],
+                default: Box::new(Latex::Num("0".to_string())),
+            },
+            "\\left\\{x\\ne0:1,0\\right\\}",
+        )
+    }
+
+    #[test]
+    fn summation_body_parens() {
+        check(
+            Latex::Summation {
+                kind: SummationKind::Sum,
+                var: "n".to_string(),
+                lower: Box::new(Latex::Num("1".to_string())),
+                upper: Box::new(Latex::Variable("N".to_string())),
+                body: Box::new(Latex::BinaryExpression {
+                    left: Box::new(Latex::Num("1".to_string())),
+                    operator: BinaryOperator::Add,
+                    right: Box::new(Latex::Variable("n".to_string())),
+                }),
+            },
+            "\\sum_{n=1}^{N}\\left(1+n\\right)",
+        )
+    }
+
+    #[test]
+    fn group() {
+        let group = || {
+            Latex::Group(Box::new(Latex::BinaryExpression {
+                left: Box::new(Latex::Num("1".to_string())),
+                operator: BinaryOperator::Subtract,
+                right: Box::new(Latex::Variable("x".to_string())),
+            }))
+        };
+        check(
+            Latex::UnaryExpression {
+                left: Box::new(group()),
+                operator: UnaryOperator::Factorial,
+            },
+            "\\left(1-x\\right)!",
+        );
+        check(
+            Latex::BinaryExpression {
+                left: Box::new(group()),
+                operator: BinaryOperator::Exponent,
+                right: Box::new(Latex::Num("2".to_string())),
+            },
+            "\\left(1-x\\right)^{2}",
+        );
+        // a statement's value is written without its group
+        assert_eq!(
+            latex_stmt_to_str(LatexStatement::Assignment(
+                Box::new(Latex::Variable("a".to_string())),
+                Box::new(group()),
+            )),
+            "a=1-x"
+        );
+    }
+
+    #[test]
+    fn signed_zero() {
+        check(Latex::Num("-0".to_string()), "0");
+        check(Latex::Num("+0".to_string()), "0");
+        check(Latex::Num("+5".to_string()), "5");
+        check(Latex::Num("-5".to_string()), "-5");
+        check(Latex::Num("-0.0".to_string()), "0.0");
+    }
+
+    #[test]
+    fn nthroot() {
+        check(
+            Latex::Call {
+                func: Function::Normal {
+                    name: "nthroot".to_string(),
+                },
+                args: vec![Latex::Num("8".to_string()), Latex::Num("3".to_string())],
+                is_builtin: true,
+            },
+            "\\sqrt[3]{8}",
+        );
+    }
+
+    #[test]
+    fn sqrt() {
+        let sqrt = |is_builtin| Latex::Call {
+            func: Function::Normal {
+                name: "sqrt".to_string(),
+            },
+            args: vec![Latex::Variable("x".to_string())],
+            is_builtin,
+        };
+        check(sqrt(true), "\\sqrt{x}");
+        // a user function named sqrt is a normal call
+        check(sqrt(false), "s_{qrt}\\left(x\\right)");
+    }
+
+    #[test]
+    fn abs() {
+        let abs = |arg, is_builtin| Latex::Call {
+            func: Function::Normal {
+                name: "abs".to_string(),
+            },
+            args: vec![arg],
+            is_builtin,
+        };
+        check(
+            abs(Latex::Variable("x".to_string()), true),
+            "\\left|x\\right|",
+        );
+        check(
+            abs(abs(Latex::Variable("x".to_string()), true), true),
+            "\\left|\\left|x\\right|\\right|",
+        );
+        check(
+            abs(Latex::Variable("x".to_string()), false),
+            "a_{bs}\\left(x\\right)",
+        );
+    }
+
+    #[test]
+    fn operatorname() {
+        check(
+            Latex::Call {
+                func: Function::Normal {
+                    name: "mod".to_string(),
+                },
+                args: vec![Latex::Num("5".to_string()), Latex::Num("3".to_string())],
+                is_builtin: true,
+            },
+            "\\operatorname{mod}\\left(5,3\\right)",
+        );
+        check(
+            Latex::Call {
+                func: Function::Normal {
+                    name: "sin".to_string(),
+                },
+                args: vec![Latex::Num("1".to_string())],
+                is_builtin: true,
+            },
+            "\\sin\\left(1\\right)",
+        );
+    }
+
+    #[test]
+    fn slice() {
+        check(
+            Latex::BinaryExpression {
+                left: Box::new(Latex::List(vec![Latex::Num("1".to_string())])),
+                operator: BinaryOperator::Index,
+                right: Box::new(Latex::Range {
+                    first: Box::new(Latex::Num("2".to_string())),
+                    second: None,
+                    end: Box::new(Latex::Num("5".to_string())),
+                }),
+            },
+            "(\\left[1\\right])\\left[2...5\\right]",
+        );
+    }
+
+    #[test]
+    fn explicit_mult() {
+        let num = || Latex::Num("2".to_string());
+        let var = || Latex::Variable("x".to_string());
+        let call = || Latex::Call {
+            func: Function::Normal {
+                name: "sin".to_string(),
+            },
+            is_builtin: true,
+            args: vec![var()],
+        };
+        let sum = || Latex::BinaryExpression {
+            left: Box::new(var()),
+            operator: BinaryOperator::Add,
+            right: Box::new(num()),
+        };
+        let neg = || Latex::UnaryExpression {
+            left: Box::new(var()),
+            operator: UnaryOperator::Negate,
+        };
+        let list = || Latex::List(vec![num(), num()]);
+        let range = || Latex::Range {
+            first: Box::new(num()),
+            second: None,
+            end: Box::new(var()),
+        };
+        let comprehension = || Latex::Comprehension {
+            body: Box::new(var()),
+            var: "x".to_string(),
+            list: Box::new(list()),
+        };
+        // `2^{x}`, which starts with a digit
+        let power = || Latex::BinaryExpression {
+            left: Box::new(num()),
+            operator: BinaryOperator::Exponent,
+            right: Box::new(var()),
+        };
+        let cases = [
+            ("num num", num(), num(), true),
+            ("num var", num(), var(), false),
+            ("var num", var(), num(), true),
+            ("var var", var(), var(), false),
+            ("num call", num(), call(), false),
+            ("call var", call(), var(), false),
+            ("call num", call(), num(), true),
+            ("call call", call(), call(), false),
+            ("paren var", sum(), var(), false),
+            ("paren paren", sum(), sum(), false),
+            ("var negate", var(), neg(), true),
+            ("num power", num(), power(), true),
+            ("var power", var(), power(), true),
+            ("var list", var(), list(), true),
+            ("list list", list(), list(), true),
+            ("num range", num(), range(), true),
+            ("call comprehension", call(), comprehension(), true),
+            ("list var", list(), var(), false),
+        ];
+        for (name, left, right, expected) in cases {
+            assert_eq!(needs_explicit_mult(&left, &right), expected, "{}", name);
+        }
+
+        check(
+            Latex::BinaryExpression {
+                left: Box::new(sum()),
+                operator: BinaryOperator::Multiply,
+                right: Box::new(sum()),
+            },
+            "((x)+2)((x)+2)",
+        );
+        check(
+            Latex::BinaryExpression {
+                left: Box::new(var()),
+                operator: BinaryOperator::Multiply,
+                right: Box::new(num()),
+            },
+            "(x)\\cdot 2",
+        );
+        check(
+            Latex::BinaryExpression {
+                left: Box::new(num()),
+                operator: BinaryOperator::Multiply,
+                right: Box::new(power()),
+            },
+            "2\\cdot 2^{x}",
+        );
+        check(
+            Latex::BinaryExpression {
+                left: Box::new(list()),
+                operator: BinaryOperator::Multiply,
+                right: Box::new(list()),
+            },
+            "(\\left[2,2\\right])\\cdot \\left[2,2\\right]",
+        );
+    }
+
+    #[test]
+    fn spans() {
+        let span = |r| types::Span::new(0, r);
+        let spanned = |r, l| Latex::Spanned(span(r), Box::new(l));
+        let (s, spans) = latex_stmt_to_str_with_spans(LatexStatement::Assignment(
+            Box::new(Latex::Variable("a".to_string())),
+            Box::new(spanned(
+                4..10,
+                Latex::BinaryExpression {
+                    left: Box::new(spanned(4..5, Latex::Num("2".to_string()))),
+                    operator: BinaryOperator::Multiply,
+                    right: Box::new(spanned(
+                        8..10,
+                        Latex::Group(Box::new(Latex::BinaryExpression {
+                            left: Box::new(spanned(8..9, Latex::Variable("b".to_string()))),
+                            operator: BinaryOperator::Add,
+                            right: Box::new(spanned(8..9, Latex::Variable("b".to_string()))),
+                        })),
+                    )),
+                },
+            )),
+        ));
+        assert_eq!(s, "a=2\\left((b)+b\\right)");
+        assert_eq!(
+            spans,
+            vec![
+                (span(4..5), 2..3),
+                (span(8..9), 10..11),
+                (span(8..9), 13..14),
+                (span(8..10), 3..21),
+                (span(4..10), 2..21),
+            ]
+        );
+        // markers or text in the latex can't confuse the ranges
+        let (s, spans) = latex_stmt_to_str_with_spans(LatexStatement::Expression(spanned(
+            0..1,
+            Latex::Raw("x\u{E001}".to_string()),
+        )));
+        assert_eq!(s, "x\u{E001}");
+        assert_eq!(spans, vec![(span(0..1), 0..s.len())]);
+        // spans don't change the output
+        check(spanned(0..1, Latex::Num("1".to_string())), "1");
+    }
+
     #[test]
     fn log() {
         check(
@@ -0,0 +1,324 @@
+use ast::{
+    print::{type_str, Printer, FACTORIAL, PIECEWISE, POWER},
+    BinaryOperator, Expression, ImportMode, LStatements, LocatedExpression, LocatedStatement,
+    Statement,
+};
+use types::ValType;
+
+const INDENT: &str = "    ";
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+struct Formatter {
+    chars: Vec<char>,
+}
+
+impl Printer for Formatter {
+    fn implicit_mult(&self, e: &Expression) -> Option<String> {
+        self.implicit_factors(e)
+            .and_then(|factors| self.join_implicit(&factors))
+    }
+}
+
+impl Formatter {
+    fn text(&self, range: std::ops::Range<usize>) -> String {
+        self.chars[range].iter().collect()
+    }
+
+    // The factors of a product that was written without `*`, like `2x y`
+    fn implicit_factors<'e>(&self, e: &'e Expression) -> Option<Vec<&'e LocatedExpression>> {
+        match e {
+            Expression::BinaryExpr {
+                left,
+                operator: BinaryOperator::Multiply,
+                right,
+            } if !self
+                .text(left.0.range.end..right.0.range.start)
+                .contains('*') =>
+            {
+                let mut factors = self
+                    .implicit_factors(&left.1)
+                    .unwrap_or_else(|| vec![left.as_ref()]);
+                factors.push(right);
+                Some(factors)
+            }
+            _ => None,
+        }
+    }
+
+    // Only a number or parenthesized group can start an implicit product and only a name
+    //  or parenthesized group can continue it, otherwise `*` is needed
+    fn join_implicit(&self, factors: &[&LocatedExpression]) -> Option<String> {
+        let mut out = String::new();
+        for (i, (_, f)) in factors.iter().enumerate() {
+            // a power in the middle would take the following factors as its exponent
+            let s = self.expr(
+                f,
+                if i == factors.len() - 1 {
+                    POWER
+                } else {
+                    FACTORIAL
+                },
+            );
+            let first = s.chars().next()?;
+            if i == 0 {
+                if !(first.is_ascii_digit() || first == '(') {
+                    return None;
+                }
+            } else {
+                if !(first.is_ascii_alphabetic() || first == '(') {
+                    return None;
+                }
+                let ends_name = out.chars().last().is_some_and(is_ident_char)
+                    && !matches!(factors[i - 1].1, Expression::Num(_));
+                if ends_name {
+                    // `a(b)` is a call
+                    if first == '(' {
+                        return None;
+                    }
+                    out.push(' ');
+                }
+            }
+            out.push_str(&s);
+        }
+        Some(out)
+    }
+
+    fn stmt(&self, (span, s): &LocatedStatement, depth: usize) -> String {
+        let text = self.text(span.range.clone());
+        // comments aren't part of the AST, so keep the statement as written
+        if text.contains("//") || text.contains("/*") {
+            return text;
+        }
+        let inline = |inline: &bool| if *inline { "inline " } else { "" };
+        match s {
+            Statement::VarDef {
+                name,
+                val,
+                inline: i,
+            } => {
+                format!("{}{} = {}", inline(i), name, self.expr(&val.1, PIECEWISE))
+            }
+            Statement::FuncDef(def, body) => {
+                let args = def
+                    .args
+                    .iter()
+                    .map(|(_, name, t)| match t {
+                        // the default
+                        ValType::Number => name.clone(),
+                        t => format!("{}: {}", name, type_str(*t)),
+                    })
+                    .collect::<Vec<_>>();
+                format!(
+                    "{}{}({}){} = {}",
+                    inline(&def.inline),
+                    def.name,
+                    args.join(", "),
+                    def.ret_annotation
+                        .map(|t| format!(": {}", type_str(t)))
+                        .unwrap_or_default(),
+                    self.expr(&body.1, PIECEWISE)
+                )
+            }
+            Statement::Expression(e) => self.expr(e, PIECEWISE),
+            Statement::Import(import) => match &import.mode {
+                ImportMode::Import { name } => format!("import {} from \"{}\"", name, import.path),
+                ImportMode::Include => format!("include \"{}\"", import.path),
+            },
+            Statement::Note(text) => format!("@note(\"{}\")", text),
+            Statement::Ticker { handler, min_step } => match min_step {
+                Some(step) => format!(
+                    "@ticker({}, {})",
+                    self.expr(&handler.1, PIECEWISE),
+                    self.expr(&step.1, PIECEWISE)
+                ),
+                None => format!("@ticker({})", self.expr(&handler.1, PIECEWISE)),
+            },
+            Statement::With(style, body) => {
+                let mut props = Vec::new();
+                if let Some(color) = &style.color {
+                    props.push(format!("color: \"{}\"", color));
+                }
+                if let Some(hidden) = style.hidden {
+                    props.push(format!("hidden: {}", hidden));
+                }
+                let props = if props.is_empty() {
+                    "{}".to_string()
+                } else {
+                    format!("{{ {} }}", props.join(", "))
+                };
+                if body.is_empty() {
+                    return format!("with {} {{}}", props);
+                }
+                let indent = INDENT.repeat(depth + 1);
+                let body = body
+                    .iter()
+                    .map(|s| format!("{}{}\n", indent, self.stmt(s, depth + 1)))
+                    .collect::<String>();
+                format!("with {} {{\n{}{}}}", props, body, INDENT.repeat(depth))
+            }
+        }
+    }
+}
+
+// Separates items by at most one blank line, a comment that was on the same line as the
+//  previous item stays there
+fn push_item(out: &mut String, newlines: usize, item: &str, trailing: bool) {
+    if !out.is_empty() {
+        if newlines == 0 && trailing {
+            out.push(' ');
+        } else {
+            out.push_str(&"\n".repeat(newlines.clamp(1, 2)));
+        }
+    }
+    out.push_str(item);
+}
+
+// Only whitespace, separators and comments can be between statements. Returns the number
+//  of newlines after the last comment.
+fn push_gap(out: &mut String, gap: &[char]) -> usize {
+    let mut newlines = 0;
+    let mut i = 0;
+    while i < gap.len() {
+        let end = match (gap[i], gap.get(i + 1)) {
+            ('/', Some('/')) => gap[i..]
+                .iter()
+                .position(|c| *c == '\n')
+                .map_or(gap.len(), |n| i + n),
+            ('/', Some('*')) => gap[i + 2..]
+                .windows(2)
+                .position(|w| w == ['*', '/'])
+                .map_or(gap.len(), |n| i + 2 + n + 2),
+            (c, _) => {
+                if c == '\n' {
+                    newlines += 1;
+                }
+                i += 1;
+                continue;
+            }
+        };
+        let comment = gap[i..end].iter().collect::<String>();
+        push_item(out, newlines, comment.trim_end(), true);
+        newlines = 0;
+        i = end;
+    }
+    newlines
+}
+
+// Pretty prints a parsed document, one statement per line. Comments between statements
+//  are kept but statements containing comments are left as they are.
+pub fn format_source(text: &str, stmts: &LStatements) -> String {
+    let f = Formatter {
+        chars: text.chars().collect(),
+    };
+    let mut out = String::new();
+    let mut prev_end = 0;
+    for stmt in stmts {
+        let newlines = push_gap(&mut out, &f.chars[prev_end..stmt.0.range.start]);
+        push_item(&mut out, newlines, &f.stmt(stmt, 0), false);
+        prev_end = stmt.0.range.end;
+    }
+    push_gap(&mut out, &f.chars[prev_end..]);
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(text: &str) -> String {
+        let (ast, errs) = parser::lex_and_parse(0, text.to_string());
+        assert!(errs.is_empty(), "{:?}", errs);
+        format_source(text, &ast.unwrap())
+    }
+
+    // formatting is idempotent and doesn't change what the code means
+    fn check(text: &str, expected: &str) {
+        let formatted = format(text);
+        assert_eq!(formatted, expected);
+        assert_eq!(format(&formatted), formatted);
+        assert_eq!(
+            compiler::compile_source(0, &formatted),
+            compiler::compile_source(0, text)
+        );
+    }
+
+    #[test]
+    fn spacing() {
+        check("a=1;b=a+2*3", "a = 1\nb = a + 2 * 3\n");
+        check("f(x,L:list)=x", "f(x, L: list) = x\n");
+        check("c=[1,2,3]\nd=sin(c[1])", "c = [1, 2, 3]\nd = sin(c[1])\n");
+        check(
+            "e=[1...10]\ng=[1,3,...,9]",
+            "e = [1...10]\ng = [1, 3, ..., 9]\n",
+        );
+        check("p=(1,2)", "p = (1, 2)\n");
+        check(
+            "h(x)=where x>1->2,x<=0->1,else 0",
+            "h(x) = where x > 1 -> 2, x <= 0 -> 1, else 0\n",
+        );
+        check(
+            "h(x)=where 0<x<=1->x,else 0",
+            "h(x) = where 0 < x <= 1 -> x, else 0\n",
+        );
+    }
+
+    #[test]
+    fn parens() {
+        check("a=(1+2)*3", "a = (1 + 2) * 3\n");
+        check("a=1-(2-3)", "a = 1 - (2 - 3)\n");
+        check("a=(1-2)-3", "a = 1 - 2 - 3\n");
+        check("a=(2^3)^4\nb=2^3^4", "a = (2^3)^4\nb = 2^3^4\n");
+        check("a=-(2^2)\nb=(-2)^2", "a = -2^2\nb = (-2)^2\n");
+        check("a=(3!)!\nb=(1+2)!", "a = 3!!\nb = (1 + 2)!\n");
+        check("a = 2^-1\nb = 1 - -a", "a = 2^-1\nb = 1 - -a\n");
+        check(
+            "L=[1,2]\na=(1+L)[2]\nb=1+L[2]\nc=-L[1]",
+            "L = [1, 2]\na = (1 + L)[2]\nb = 1 + L[2]\nc = -L[1]\n",
+        );
+    }
+
+    #[test]
+    fn implicit_mult() {
+        check("x=1\na=2x\nb=2 * x", "x = 1\na = 2x\nb = 2 * x\n");
+        check("x=1\na=2(x+1)\nb=-2x^2", "x = 1\na = 2(x + 1)\nb = -2x^2\n");
+        check("x=1\ny=2\na=2x y", "x = 1\ny = 2\na = 2x y\n");
+        // `x^2` would take `y` into its exponent
+        check("x=1\ny=2\na=(2x^2)y", "x = 1\ny = 2\na = 2(x^2)y\n");
+    }
+
+    #[test]
+    fn raw_latex() {
+        check("a=raw<num>(\"x\")", "a = latex \"x\"\n");
+        check("p=raw<Point>(\"P\")", "p = raw<point>(\"P\")\n");
+        check("b=raw<bool>(\"B\")", "b = raw<bool>(\"B\")\n");
+    }
+
+    #[test]
+    fn comprehension() {
+        check("a=[n^2 for n=[1...3]]", "a = [n^2 for n = [1...3]]\n");
+    }
+
+    #[test]
+    fn blocks() {
+        check(
+            "with{color:red}{a=1\nwith {hidden: true} {b=2}}",
+            "with { color: \"red\" } {\n    a = 1\n    with { hidden: true } {\n        b = 2\n    }\n}\n",
+        );
+        check("@note(\"hi\")\n\n\n\nb=1", "@note(\"hi\")\n\nb = 1\n");
+        check("a=0\n@ticker(a+1,100)", "a = 0\n@ticker(a + 1, 100)\n");
+    }
+
+    #[test]
+    fn comments() {
+        check(
+            "// header\na=1 // one\n\n/* two */ b=2;;\nc=(1+\n// kept\n2)\n",
+            "// header\na = 1 // one\n\n/* two */\nb = 2\nc=(1+\n// kept\n2)\n",
+        );
+    }
+}
@@ -44,38 +44,67 @@
 use std::error::Error;
 
 use compiler::error::CompileError;
-use compiler::Context;
-use lsp_types::notification::{DidChangeTextDocument, DidOpenTextDocument};
-use lsp_types::request::{Completion, Initialize};
+use compiler::{compile_stmts, Context, Typ};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidOpenTextDocument, Notification as _, PublishDiagnostics,
+};
+use lsp_types::request::{
+    Completion, DocumentSymbolRequest, Formatting, GotoDefinition, HoverRequest, Initialize,
+    References, SemanticTokensFullRequest, SignatureHelpRequest,
+};
 use lsp_types::{
-    CompletionItem, CompletionOptions, CompletionParams, CompletionResponse, InitializeResult,
-    OneOf, TextDocumentSyncCapability, TextDocumentSyncKind, TextDocumentSyncOptions,
+    CompletionItem, CompletionItemKind, CompletionOptions, CompletionParams, CompletionResponse,
+    Diagnostic, DiagnosticSeverity, DocumentFormattingParams, DocumentSymbol, DocumentSymbolParams,
+    DocumentSymbolResponse, GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverContents,
+    HoverParams, HoverProviderCapability, InitializeResult, InsertTextFormat, Location,
+    MarkedString, OneOf, Position, PublishDiagnosticsParams, Range, ReferenceParams,
+    SemanticTokens, SemanticTokensFullOptions, SemanticTokensOptions, SemanticTokensParams,
+    SemanticTokensResult, SemanticTokensServerCapabilities, SignatureHelp, SignatureHelpOptions,
+    SignatureHelpParams, SignatureInformation, SymbolKind, TextDocumentSyncCapability,
+    TextDocumentSyncKind, TextDocumentSyncOptions, TextEdit, Url,
 };
 use lsp_types::{InitializeParams, ServerCapabilities};
 
-use lsp_server::{Connection, Message, Response};
+use lsp_server::{Connection, Message, Notification, Response};
 use parser::LexParseErrors;
+use references::{collect_references, global_use_at};
+use semantic_tokens::semantic_tokens;
+
+pub use format::format_source;
+
+mod format;
+mod references;
+mod semantic_tokens;
 
 pub fn start(connection: Connection) -> Result<(), Box<dyn Error + Sync + Send>> {
     // Run the server and wait for the two threads to end (typically by trigger LSP Exit event).
-    let server_capabilities = serde_json::to_value(&ServerCapabilities {
-        definition_provider: Some(OneOf::Left(true)),
-        ..Default::default()
-    })
-    .unwrap();
+    let server_capabilities = serde_json::to_value(server_capabilities()).unwrap();
     let initialization_params = connection.initialize(server_capabilities)?;
-    main_loop(connection, initialization_params)?;
+    // documents are parsed and compiled on this thread, long chains make both recurse deeply
+    parser::with_large_stack(move || main_loop(connection, initialization_params))?;
     Ok(())
 }
 
-pub type State = Option<StateVal>;
+pub type State = Option<Document>;
+
+// The last version of the open document and the result of compiling it
+#[derive(Clone, Debug)]
+pub struct Document {
+    pub uri: Url,
+    pub text: String,
+    // May be a recovered AST if there were parse errors
+    pub ast: Option<ast::LStatements>,
+    pub parse_errors: LexParseErrors,
+    // The statements that parsed are compiled even if others didn't
+    pub result: StateVal,
+}
 
 #[derive(Clone, Debug)]
 pub enum StateVal {
-    ParseErr(LexParseErrors),
+    // Nothing could be recovered, see Document::parse_errors
+    ParseErr,
     CompileErr(CompileError),
     Success(Context),
-    Test2,
 }
 
 pub fn main_loop(
@@ -87,9 +116,25 @@ pub fn main_loop(
     let mut state: State = None;
     for msg in &connection.receiver {
         eprintln!("got msg: {:?}", msg);
+        // waits for the exit notification that follows
+        if let Message::Request(req) = &msg {
+            if connection.handle_shutdown(req)? {
+                return Ok(());
+            }
+        }
+        let content_changed = matches!(
+            &msg,
+            Message::Notification(n) if n.method == DidOpenTextDocument::METHOD
+                || n.method == DidChangeTextDocument::METHOD
+        );
         if let Some(resp) = handle_request(&mut state, msg) {
             connection.sender.send(Message::Response(resp))?;
         }
+        if let (true, Some(doc)) = (content_changed, &state) {
+            connection
+                .sender
+                .send(Message::Notification(publish_diagnostics(doc)))?;
+        }
     }
     Ok(())
 }
@@ -163,54 +208,566 @@ impl<'a> RequestDispatcher<'a> {
     }
 }
 
+// `sin(${1:x})`, only required arguments get a placeholder
+fn builtin_snippet(name: &str, f: &types::Function) -> String {
+    let args = match f.args {
+        types::Args::Static(args) => args,
+        types::Args::Optional { args, required } => &args[..required],
+        types::Args::Variadic(types::ElemType::Number) => {
+            &[types::ValType::List(types::ElemType::Number)]
+        }
+        types::Args::Variadic(types::ElemType::Point) => {
+            &[types::ValType::List(types::ElemType::Point)]
+        }
+    };
+    let placeholders = args
+        .iter()
+        .enumerate()
+        .map(|(i, t)| {
+            let arg = match t {
+                types::ValType::Number => "x",
+                types::ValType::List(_) => "L",
+                types::ValType::Point => "p",
+                types::ValType::Bool => "b",
+                types::ValType::Polygon => "P",
+            };
+            format!("${{{}:{}}}", i + 1, arg)
+        })
+        .collect::<Vec<_>>();
+    format!("{}({})", name, placeholders.join(", "))
+}
+
+fn builtin_completions(ctx: &Context) -> Vec<CompletionItem> {
+    let mut builtins = compiler::BUILTIN_FUNCTIONS
+        .entries()
+        // user definitions shadow builtins
+        .filter(|(name, _)| {
+            !ctx.variables.contains_key(**name)
+                && !ctx.defined_functions.contains_key(**name)
+                && !ctx.inline_fns.contains_key(**name)
+        })
+        .map(|(name, f)| CompletionItem {
+            label: name.to_string(),
+            kind: Some(CompletionItemKind::FUNCTION),
+            detail: ctx.function_signature_string(name),
+            insert_text: Some(builtin_snippet(name, f)),
+            insert_text_format: Some(InsertTextFormat::SNIPPET),
+            ..Default::default()
+        })
+        .collect::<Vec<_>>();
+    builtins.sort_by(|a, b| a.label.cmp(&b.label));
+    builtins
+}
+
+// Stdlib definitions, available after importing their module
+fn stdlib_completions(ctx: &Context) -> Vec<CompletionItem> {
+    ctx.stdlib
+        .symbols()
+        .into_iter()
+        // already in scope when the module was included
+        .filter(|(name, _)| {
+            !ctx.variables.contains_key(name)
+                && !ctx.inline_vals.contains_key(name)
+                && !ctx.defined_functions.contains_key(name)
+                && !ctx.inline_fns.contains_key(name)
+        })
+        .map(|(name, f)| {
+            let ret = compiler::Typ::from(f.ret.0);
+            let (kind, detail) = match &f.args {
+                compiler::FunctionArgs::Static(args) if args.is_empty() => {
+                    (CompletionItemKind::CONSTANT, ret.to_string())
+                }
+                _ => (
+                    CompletionItemKind::FUNCTION,
+                    format!("{}({}) -> {}", name, f.arg_names.join(", "), ret),
+                ),
+            };
+            CompletionItem {
+                label: name,
+                kind: Some(kind),
+                detail: Some(detail),
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
 pub fn completion_handler(
     state: &mut State,
     _params: &CompletionParams,
 ) -> Option<Option<CompletionResponse>> {
     match state {
-        None => Some(None),
-        Some(s) => match &s {
-            StateVal::Success(ctx) => Some(Some(CompletionResponse::Array(
-                ctx.variables
-                    .iter()
-                    .map(|(v, typ)| CompletionItem::new_simple(v.clone(), format!("{:#?}", typ)))
-                    .collect(),
-            ))),
-            _ => Some(None),
-        },
+        Some(Document {
+            result: StateVal::Success(ctx),
+            ..
+        }) => Some(Some(CompletionResponse::Array(
+            ctx.variables
+                .iter()
+                .map(|(v, typ)| CompletionItem::new_simple(v.clone(), format!("{:#?}", typ)))
+                .chain(
+                    ctx.defined_functions
+                        .keys()
+                        .chain(ctx.inline_fns.keys())
+                        .map(|name| CompletionItem {
+                            label: name.clone(),
+                            kind: Some(CompletionItemKind::FUNCTION),
+                            detail: ctx.function_signature_string(name),
+                            ..Default::default()
+                        }),
+                )
+                .chain(builtin_completions(ctx))
+                .chain(stdlib_completions(ctx))
+                .collect(),
+        ))),
+        _ => Some(None),
     }
 }
 
-pub fn handle_new_content(_state: &mut State, _content: String) {
-    // todo
+// Spans count chars from the start of the document, LSP positions count UTF-16 code units
+//  from the start of a line.
+pub fn offset_to_position(text: &str, offset: usize) -> Position {
+    let mut pos = Position::new(0, 0);
+    for c in text.chars().take(offset) {
+        if c == '\n' {
+            pos.line += 1;
+            pos.character = 0;
+        } else {
+            pos.character += c.len_utf16() as u32;
+        }
+    }
+    pos
+}
+
+pub fn position_to_offset(text: &str, position: Position) -> Option<usize> {
+    let mut pos = Position::new(0, 0);
+    for (i, c) in text.chars().enumerate() {
+        if pos == position {
+            return Some(i);
+        }
+        if c == '\n' {
+            if pos.line == position.line {
+                return None;
+            }
+            pos.line += 1;
+            pos.character = 0;
+        } else {
+            pos.character += c.len_utf16() as u32;
+        }
+    }
+    (pos == position).then(|| text.chars().count())
+}
+
+pub fn span_to_range(text: &str, span: &types::Span) -> Range {
+    Range::new(
+        offset_to_position(text, span.range.start),
+        offset_to_position(text, span.range.end),
+    )
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+// The identifier touching a char offset, if any
+pub fn ident_at(text: &str, offset: usize) -> Option<String> {
+    let chars: Vec<char> = text.chars().collect();
+    // the cursor can be just after the last char of the identifier
+    let at = if chars.get(offset).copied().is_some_and(is_ident_char) {
+        offset
+    } else {
+        offset.checked_sub(1)?
+    };
+    if !chars.get(at).copied().is_some_and(is_ident_char) {
+        return None;
+    }
+    let start = chars[..at]
+        .iter()
+        .rposition(|c| !is_ident_char(*c))
+        .map_or(0, |i| i + 1);
+    let end = chars[at..]
+        .iter()
+        .position(|c| !is_ident_char(*c))
+        .map_or(chars.len(), |i| at + i);
+    let ident: String = chars[start..end].iter().collect();
+    // numbers are not identifiers
+    if ident.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    Some(ident)
+}
+
+fn diagnostic(
+    text: &str,
+    span: &types::Span,
+    severity: DiagnosticSeverity,
+    message: String,
+) -> Diagnostic {
+    Diagnostic {
+        range: span_to_range(text, span),
+        severity: Some(severity),
+        source: Some("desmosc".to_string()),
+        message,
+        ..Default::default()
+    }
+}
+
+// Recovery can report errors out of order, editors should list them top to bottom.
+//  Diagnostics are published per document so the position is the whole key.
+pub fn sort_diagnostics(diags: &mut [Diagnostic]) {
+    diags.sort_by_key(|d| d.range.start);
+}
+
+pub fn document_diagnostics(doc: &Document) -> Vec<Diagnostic> {
+    let text = &doc.text;
+    let mut diags = doc
+        .parse_errors
+        .messages()
+        .into_iter()
+        .map(|(span, msg)| diagnostic(text, &span, DiagnosticSeverity::ERROR, msg))
+        .collect::<Vec<_>>();
+    diags.extend(match &doc.result {
+        StateVal::ParseErr => vec![],
+        StateVal::CompileErr(e) => vec![diagnostic(
+            text,
+            &e.span,
+            DiagnosticSeverity::ERROR,
+            e.kind.to_string(),
+        )],
+        StateVal::Success(ctx) => ctx
+            .warnings
+            .iter()
+            .map(|w| {
+                diagnostic(
+                    text,
+                    &w.span,
+                    DiagnosticSeverity::WARNING,
+                    w.kind.to_string(),
+                )
+            })
+            .collect(),
+    });
+    sort_diagnostics(&mut diags);
+    diags
+}
+
+// An empty list clears the diagnostics of a document that compiles cleanly
+pub fn publish_diagnostics(doc: &Document) -> Notification {
+    Notification::new(
+        PublishDiagnostics::METHOD.to_string(),
+        PublishDiagnosticsParams::new(doc.uri.clone(), document_diagnostics(doc), None),
+    )
+}
+
+// The global variable or function defined or used at a position. Names rebound by a function
+//  argument, comprehension variable or bound variable are locals, and text outside of
+//  expressions, like a note, doesn't name anything.
+fn global_at(text: &str, ast: &ast::LStatements, position: Position) -> Option<String> {
+    let defined = document_symbols(text, ast)
+        .into_iter()
+        .find(|s| s.selection_range.start <= position && position <= s.selection_range.end);
+    match defined {
+        Some(symbol) => Some(symbol.name),
+        None => global_use_at(ast, position_to_offset(text, position)?),
+    }
+}
+
+pub fn definition_handler(
+    state: &mut State,
+    params: &GotoDefinitionParams,
+) -> Option<Option<GotoDefinitionResponse>> {
+    let doc = match state {
+        Some(doc) => doc,
+        None => return Some(None),
+    };
+    let (ctx, ast) = match (&doc.result, &doc.ast) {
+        (StateVal::Success(ctx), Some(ast)) => (ctx, ast),
+        _ => return Some(None),
+    };
+    let position = params.text_document_position_params.position;
+    // builtins and unknown names have no definition
+    let span = global_at(&doc.text, ast, position).and_then(|name| ctx.def_spans.get(&name));
+    Some(span.map(|span| {
+        GotoDefinitionResponse::Scalar(Location::new(
+            doc.uri.clone(),
+            span_to_range(&doc.text, span),
+        ))
+    }))
+}
+
+// Type of a variable or signature of a function, e.g. `a: number`
+fn describe_symbol(ctx: &Context, name: &str) -> Option<String> {
+    match ctx.variables.get(name) {
+        Some((t, _)) => Some(format!("{}: {}", name, Typ::from(*t))),
+        None => ctx.function_signature_string(name),
+    }
+}
+
+pub fn hover_handler(state: &mut State, params: &HoverParams) -> Option<Option<Hover>> {
+    let doc = match state {
+        Some(doc) => doc,
+        None => return Some(None),
+    };
+    let (ctx, ast) = match (&doc.result, &doc.ast) {
+        (StateVal::Success(ctx), Some(ast)) => (ctx, ast),
+        _ => return Some(None),
+    };
+    let position = params.text_document_position_params.position;
+    let description =
+        global_at(&doc.text, ast, position).and_then(|name| describe_symbol(ctx, &name));
+    Some(description.map(|d| Hover {
+        contents: HoverContents::Scalar(MarkedString::String(d)),
+        range: None,
+    }))
+}
+
+// The function whose call arguments contain a char offset, and the index of the argument
+pub fn call_at(text: &str, offset: usize) -> Option<(String, u32)> {
+    let chars: Vec<char> = text.chars().take(offset).collect();
+    let mut depth: usize = 0;
+    let mut arg = 0;
+    for (i, c) in chars.iter().enumerate().rev() {
+        match c {
+            ')' | ']' => depth += 1,
+            '(' | '[' if depth > 0 => depth -= 1,
+            // a parenthesized expression has no name before it
+            '(' => return ident_at(text, i).map(|name| (name, arg)),
+            '[' | ';' => return None,
+            ',' if depth == 0 => arg += 1,
+            _ => (),
+        }
+    }
+    None
+}
+
+pub fn signature_help_handler(
+    state: &mut State,
+    params: &SignatureHelpParams,
+) -> Option<Option<SignatureHelp>> {
+    let doc = match state {
+        Some(doc) => doc,
+        None => return Some(None),
+    };
+    let ctx = match &doc.result {
+        StateVal::Success(ctx) => ctx,
+        _ => return Some(None),
+    };
+    let position = params.text_document_position_params.position;
+    let help = position_to_offset(&doc.text, position)
+        .and_then(|offset| call_at(&doc.text, offset))
+        .and_then(|(name, arg)| {
+            Some(SignatureHelp {
+                signatures: vec![SignatureInformation {
+                    label: ctx.function_signature_string(&name)?,
+                    documentation: None,
+                    parameters: None,
+                    active_parameter: None,
+                }],
+                active_signature: Some(0),
+                active_parameter: Some(arg),
+            })
+        });
+    Some(help)
+}
+
+// Replaces the whole document, or nothing if it is already formatted or doesn't parse
+pub fn format_edits(text: &str) -> Vec<TextEdit> {
+    let formatted = match parser::lex_and_parse(0, text.to_string()) {
+        (Some(ast), errs) if errs.is_empty() => format_source(text, &ast),
+        _ => return vec![],
+    };
+    if formatted == text {
+        return vec![];
+    }
+    vec![TextEdit::new(
+        Range::new(
+            Position::new(0, 0),
+            offset_to_position(text, text.chars().count()),
+        ),
+        formatted,
+    )]
+}
+
+pub fn formatting_handler(
+    state: &mut State,
+    _params: &DocumentFormattingParams,
+) -> Option<Option<Vec<TextEdit>>> {
+    Some(state.as_ref().map(|doc| format_edits(&doc.text)))
+}
+
+// The first occurence of name as a whole identifier inside a statement, which is where it
+//  is defined
+fn name_span(text: &str, span: &types::Span, name: &str) -> types::Span {
+    let stmt = text
+        .chars()
+        .skip(span.range.start)
+        .take(span.range.len())
+        .collect::<String>();
+    let found = stmt.match_indices(name).find(|(i, _)| {
+        !stmt[..*i].chars().next_back().is_some_and(is_ident_char)
+            && !stmt[i + name.len()..]
+                .chars()
+                .next()
+                .is_some_and(is_ident_char)
+    });
+    let start = match found {
+        Some((i, _)) => span.range.start + stmt[..i].chars().count(),
+        None => span.range.start,
+    };
+    types::Span::new(span.file_id, start..start + name.chars().count())
+}
+
+// Definitions inside `with` blocks are still global, so they are listed as top-level
+fn document_symbols(text: &str, stmts: &ast::LStatements) -> Vec<DocumentSymbol> {
+    stmts
+        .iter()
+        .flat_map(|(span, stmt)| {
+            let (name, kind) = match stmt {
+                ast::Statement::VarDef { name, .. } => (name, SymbolKind::VARIABLE),
+                ast::Statement::FuncDef(def, _) => (&def.name, SymbolKind::FUNCTION),
+                ast::Statement::With(_, body) => return document_symbols(text, body),
+                _ => return vec![],
+            };
+            #[allow(deprecated)]
+            let symbol = DocumentSymbol {
+                name: name.clone(),
+                detail: None,
+                kind,
+                tags: None,
+                deprecated: None,
+                range: span_to_range(text, span),
+                selection_range: span_to_range(text, &name_span(text, span, name)),
+                children: None,
+            };
+            vec![symbol]
+        })
+        .collect()
+}
+
+pub fn document_symbol_handler(
+    state: &mut State,
+    _params: &DocumentSymbolParams,
+) -> Option<Option<DocumentSymbolResponse>> {
+    Some(state.as_ref().and_then(|doc| {
+        let ast = doc.ast.as_ref()?;
+        Some(DocumentSymbolResponse::Nested(document_symbols(
+            &doc.text, ast,
+        )))
+    }))
+}
+
+pub fn references_handler(
+    state: &mut State,
+    params: &ReferenceParams,
+) -> Option<Option<Vec<Location>>> {
+    let doc = match state {
+        Some(doc) => doc,
+        None => return Some(None),
+    };
+    let ast = match &doc.ast {
+        Some(ast) => ast,
+        None => return Some(None),
+    };
+    // locals aren't tracked, so they have no references
+    let name = match global_at(&doc.text, ast, params.text_document_position.position) {
+        Some(name) => name,
+        None => return Some(None),
+    };
+    let declaration = document_symbols(&doc.text, ast)
+        .into_iter()
+        .find(|s| s.name == name)
+        .filter(|_| params.context.include_declaration)
+        .map(|s| s.selection_range);
+    let locations = declaration
+        .into_iter()
+        .chain(
+            collect_references(ast, &name)
+                .iter()
+                .map(|span| span_to_range(&doc.text, span)),
+        )
+        .map(|range| Location::new(doc.uri.clone(), range))
+        .collect();
+    Some(Some(locations))
+}
+
+pub fn semantic_tokens_handler(
+    state: &mut State,
+    _params: &SemanticTokensParams,
+) -> Option<Option<SemanticTokensResult>> {
+    Some(state.as_ref().and_then(|doc| {
+        let ast = doc.ast.as_ref()?;
+        Some(SemanticTokensResult::Tokens(SemanticTokens {
+            result_id: None,
+            data: semantic_tokens(&doc.text, ast),
+        }))
+    }))
+}
+
+pub fn handle_new_content(state: &mut State, uri: Url, content: String) {
+    let (ast, errs) = parser::lex_and_parse(0, content.clone());
+    let result = match ast.clone() {
+        // statements with error nodes are skipped, so one typo keeps the later definitions
+        Some(ast) => {
+            let mut ctx = Context::new_with_loader(Box::new(compiler::SourceLoader));
+            match compile_stmts(&mut ctx, ast) {
+                Ok(_) => StateVal::Success(ctx),
+                Err(e) => StateVal::CompileErr(e),
+            }
+        }
+        None => StateVal::ParseErr,
+    };
+    *state = Some(Document {
+        uri,
+        text: content,
+        ast,
+        parse_errors: errs,
+        result,
+    });
+}
+
+// Sent by `start` during initialization and in response to an `initialize` request
+pub fn server_capabilities() -> ServerCapabilities {
+    ServerCapabilities {
+        definition_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        signature_help_provider: Some(SignatureHelpOptions {
+            trigger_characters: Some(vec!["(".to_string(), ",".to_string()]),
+            ..Default::default()
+        }),
+        completion_provider: Some(CompletionOptions {
+            ..Default::default()
+        }),
+        document_formatting_provider: Some(OneOf::Left(true)),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        references_provider: Some(OneOf::Left(true)),
+        semantic_tokens_provider: Some(SemanticTokensServerCapabilities::SemanticTokensOptions(
+            SemanticTokensOptions {
+                legend: semantic_tokens::legend(),
+                full: Some(SemanticTokensFullOptions::Bool(true)),
+                ..Default::default()
+            },
+        )),
+        text_document_sync: Some(TextDocumentSyncCapability::Options(
+            TextDocumentSyncOptions {
+                open_close: Some(true),
+                change: Some(TextDocumentSyncKind::FULL),
+                ..Default::default()
+            },
+        )),
+        ..Default::default()
+    }
 }
 
 pub fn handle_request(state: &mut State, msg: Message) -> Option<Response> {
     let mut dispatcher = RequestDispatcher::new(state, msg);
     dispatcher
         .on::<Initialize>(|_state, _params| {
-            let capabilities = ServerCapabilities {
-                definition_provider: Some(OneOf::Left(true)),
-                completion_provider: Some(CompletionOptions {
-                    ..Default::default()
-                }),
-                text_document_sync: Some(TextDocumentSyncCapability::Options(
-                    TextDocumentSyncOptions {
-                        open_close: Some(true),
-                        change: Some(TextDocumentSyncKind::FULL),
-                        ..Default::default()
-                    },
-                )),
-                ..Default::default()
-            };
             Some(InitializeResult {
-                capabilities,
+                capabilities: server_capabilities(),
                 ..Default::default()
             })
         })
         .on_notif::<DidOpenTextDocument>(|state, params| {
-            *state = Some(StateVal::Test2);
-            handle_new_content(state, params.text_document.text)
+            handle_new_content(state, params.text_document.uri, params.text_document.text)
         })
         .on_notif::<DidChangeTextDocument>(|state, params| {
             if params.content_changes.len() != 1 {
@@ -218,9 +775,605 @@ pub fn handle_request(state: &mut State, msg: Message) -> Option<Response> {
             }
             handle_new_content(
                 state,
+                params.text_document.uri,
                 params.content_changes.into_iter().nth(0).unwrap().text,
             )
         })
-        .on::<Completion>(completion_handler);
+        .on::<Completion>(completion_handler)
+        .on::<GotoDefinition>(definition_handler)
+        .on::<HoverRequest>(hover_handler)
+        .on::<SignatureHelpRequest>(signature_help_handler)
+        .on::<Formatting>(formatting_handler)
+        .on::<DocumentSymbolRequest>(document_symbol_handler)
+        .on::<References>(references_handler)
+        .on::<SemanticTokensFullRequest>(semantic_tokens_handler);
     dispatcher.resp
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lsp_types::{ReferenceContext, TextDocumentIdentifier, TextDocumentPositionParams};
+
+    fn uri() -> Url {
+        Url::parse("file:///test.desmos").unwrap()
+    }
+
+    fn goto(state: &mut State, line: u32, character: u32) -> Option<GotoDefinitionResponse> {
+        definition_handler(
+            state,
+            &GotoDefinitionParams {
+                text_document_position_params: TextDocumentPositionParams::new(
+                    TextDocumentIdentifier::new(uri()),
+                    Position::new(line, character),
+                ),
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            },
+        )
+        .unwrap()
+    }
+
+    fn hover(state: &mut State, line: u32, character: u32) -> Option<String> {
+        hover_handler(
+            state,
+            &HoverParams {
+                text_document_position_params: TextDocumentPositionParams::new(
+                    TextDocumentIdentifier::new(uri()),
+                    Position::new(line, character),
+                ),
+                work_done_progress_params: Default::default(),
+            },
+        )
+        .unwrap()
+        .map(|h| match h.contents {
+            HoverContents::Scalar(MarkedString::String(s)) => s,
+            c => panic!("Unexpected hover contents {:?}", c),
+        })
+    }
+
+    #[test]
+    fn positions() {
+        let text = "ab\nc\u{1F600}d\n";
+        assert_eq!(offset_to_position(text, 0), Position::new(0, 0));
+        assert_eq!(offset_to_position(text, 3), Position::new(1, 0));
+        // the emoji is two UTF-16 code units
+        assert_eq!(offset_to_position(text, 5), Position::new(1, 3));
+        for offset in 0..=text.chars().count() {
+            assert_eq!(
+                position_to_offset(text, offset_to_position(text, offset)),
+                Some(offset)
+            );
+        }
+        assert_eq!(position_to_offset(text, Position::new(0, 5)), None);
+    }
+
+    #[test]
+    fn span_ranges() {
+        // spans count chars: `é` is 2 bytes and 1 UTF-16 unit, the emoji 4 bytes and 2 units
+        let text = "é = 1\nb = \u{1F600}é + é";
+        let range = |r| span_to_range(text, &types::Span::new(0, r));
+        assert_eq!(
+            range(0..1),
+            Range::new(Position::new(0, 0), Position::new(0, 1))
+        );
+        assert_eq!(
+            range(4..5),
+            Range::new(Position::new(0, 4), Position::new(0, 5))
+        );
+        assert_eq!(
+            range(10..12),
+            Range::new(Position::new(1, 4), Position::new(1, 7))
+        );
+        assert_eq!(
+            range(15..16),
+            Range::new(Position::new(1, 10), Position::new(1, 11))
+        );
+        // spans crossing a line
+        assert_eq!(
+            range(2..8),
+            Range::new(Position::new(0, 2), Position::new(1, 2))
+        );
+    }
+
+    #[test]
+    fn ident() {
+        let text = "abc + d1(2)";
+        assert_eq!(ident_at(text, 0), Some("abc".to_string()));
+        assert_eq!(ident_at(text, 3), Some("abc".to_string()));
+        assert_eq!(ident_at(text, 4), None);
+        assert_eq!(ident_at(text, 7), Some("d1".to_string()));
+        assert_eq!(ident_at(text, 9), None);
+    }
+
+    fn diagnostics(text: &str) -> Vec<Diagnostic> {
+        let mut state = None;
+        handle_new_content(&mut state, uri(), text.to_string());
+        document_diagnostics(state.as_ref().unwrap())
+    }
+
+    #[test]
+    fn parse_diagnostics() {
+        let diags = diagnostics("a = 1\nb = )");
+        assert!(!diags.is_empty());
+        assert_eq!(diags[0].severity, Some(DiagnosticSeverity::ERROR));
+        assert_eq!(diags[0].range.start.line, 1);
+    }
+
+    #[test]
+    fn astral_diagnostics() {
+        // the emoji is two UTF-16 units, so `)` is at column 13
+        let diags = diagnostics("a = 1\n/* \u{1F600} */ b = )");
+        assert_eq!(
+            diags[0].range,
+            Range::new(Position::new(1, 13), Position::new(1, 14))
+        );
+        let diags = diagnostics("a = 1\n/* \u{1F600} */ b = (1");
+        assert_eq!(diags[0].range.start, Position::new(1, 15));
+        // compile errors too
+        let diags = diagnostics("/* \u{1F600} */ b = c");
+        assert_eq!(
+            diags[0].range,
+            Range::new(Position::new(0, 13), Position::new(0, 14))
+        );
+    }
+
+    #[test]
+    fn sorted_diagnostics() {
+        let at = |line, character| Diagnostic {
+            range: Range::new(
+                Position::new(line, character),
+                Position::new(line, character + 1),
+            ),
+            ..Default::default()
+        };
+        let mut diags = vec![at(2, 0), at(0, 5), at(1, 3), at(0, 1)];
+        sort_diagnostics(&mut diags);
+        assert_eq!(diags, vec![at(0, 1), at(0, 5), at(1, 3), at(2, 0)]);
+
+        let diags = diagnostics("a = )\nb = (1 2\nc = ]");
+        assert!(diags.len() > 1);
+        assert!(diags
+            .windows(2)
+            .all(|w| w[0].range.start <= w[1].range.start));
+    }
+
+    #[test]
+    fn compile_diagnostics() {
+        let diags = diagnostics("a = 1\nb = c + 1");
+        assert_eq!(diags.len(), 1);
+        assert_eq!(
+            diags[0].range,
+            Range::new(Position::new(1, 4), Position::new(1, 5))
+        );
+        assert_eq!(diags[0].message, "Undefined variable 'c'");
+    }
+
+    #[test]
+    fn clean_diagnostics() {
+        let mut state = None;
+        handle_new_content(&mut state, uri(), "a = 1".to_string());
+        let notif = publish_diagnostics(state.as_ref().unwrap());
+        assert_eq!(notif.method, "textDocument/publishDiagnostics");
+        let params: PublishDiagnosticsParams = serde_json::from_value(notif.params).unwrap();
+        assert_eq!(params.uri, uri());
+        assert_eq!(params.diagnostics, vec![]);
+    }
+
+    #[test]
+    fn recovered_definitions() {
+        let mut state = None;
+        handle_new_content(&mut state, uri(), "1 + ) 2;\nf(x) = x\nf(1)".to_string());
+        let doc = state.as_ref().unwrap();
+        match &doc.result {
+            StateVal::Success(ctx) => assert!(ctx.defined_functions.contains_key("f")),
+            r => panic!("Expected the valid statements to compile, got {:?}", r),
+        }
+        // only the parse errors are reported, the skipped statement isn't a compile error
+        let diags = document_diagnostics(doc);
+        assert_eq!(diags.len(), doc.parse_errors.messages().len());
+        assert!(diags.iter().all(|d| d.range.start.line == 0));
+    }
+
+    #[test]
+    fn definition() {
+        let mut state = None;
+        handle_new_content(
+            &mut state,
+            uri(),
+            "a = 1\nf(x) = x + a\nsin(f(a))".to_string(),
+        );
+        let location = |range| Some(GotoDefinitionResponse::Scalar(Location::new(uri(), range)));
+        let a = Range::new(Position::new(0, 0), Position::new(0, 5));
+        let f = Range::new(Position::new(1, 0), Position::new(1, 12));
+        assert_eq!(goto(&mut state, 1, 11), location(a));
+        assert_eq!(goto(&mut state, 2, 4), location(f));
+        assert_eq!(goto(&mut state, 2, 6), location(a));
+        // builtins and arguments
+        assert_eq!(goto(&mut state, 2, 1), None);
+        assert_eq!(goto(&mut state, 1, 2), None);
+        // not compiled
+        handle_new_content(&mut state, uri(), "a = ".to_string());
+        assert_eq!(goto(&mut state, 0, 0), None);
+    }
+
+    #[test]
+    fn shadowed_definition() {
+        let mut state = None;
+        handle_new_content(
+            &mut state,
+            uri(),
+            "x = 1\nf(x) = x\ng = [x for x = [x]]\n@note(\"x\")".to_string(),
+        );
+        let x = Some(GotoDefinitionResponse::Scalar(Location::new(
+            uri(),
+            Range::new(Position::new(0, 0), Position::new(0, 5)),
+        )));
+        // the argument and the comprehension variable are locals
+        assert_eq!(goto(&mut state, 1, 7), None);
+        assert_eq!(goto(&mut state, 2, 5), None);
+        assert_eq!(goto(&mut state, 2, 16), x);
+        assert_eq!(goto(&mut state, 3, 7), None);
+        assert_eq!(goto(&mut state, 0, 0), x);
+    }
+
+    #[test]
+    fn signature_help() {
+        let mut state = None;
+        handle_new_content(
+            &mut state,
+            uri(),
+            "f(x, L: list) = x\nf(sin(1), [1, 2])".to_string(),
+        );
+        let mut help = |character| {
+            signature_help_handler(
+                &mut state,
+                &SignatureHelpParams {
+                    context: None,
+                    text_document_position_params: TextDocumentPositionParams::new(
+                        TextDocumentIdentifier::new(uri()),
+                        Position::new(1, character),
+                    ),
+                    work_done_progress_params: Default::default(),
+                },
+            )
+            .unwrap()
+            .map(|h| (h.signatures[0].label.clone(), h.active_parameter))
+        };
+        let f = "f(x: number, L: list) -> number".to_string();
+        assert_eq!(help(2), Some((f.clone(), Some(0))));
+        assert_eq!(
+            help(6),
+            Some(("sin(number) -> number".to_string(), Some(0)))
+        );
+        assert_eq!(help(10), Some((f.clone(), Some(1))));
+        assert_eq!(help(16), Some((f, Some(1))));
+        // outside of a call or inside a list
+        assert_eq!(help(0), None);
+        assert_eq!(help(14), None);
+    }
+
+    #[test]
+    fn hover_types() {
+        let mut state = None;
+        handle_new_content(
+            &mut state,
+            uri(),
+            "a = [1, 2]\nf(x, L: list) = x * 2\nf(1, a)".to_string(),
+        );
+        assert_eq!(hover(&mut state, 0, 0), Some("a: list".to_string()));
+        assert_eq!(
+            hover(&mut state, 2, 0),
+            Some("f(x: number, L: list) -> number".to_string())
+        );
+        // arguments and whitespace
+        assert_eq!(hover(&mut state, 1, 2), None);
+        assert_eq!(hover(&mut state, 0, 3), None);
+    }
+
+    #[test]
+    fn shadowed_hover() {
+        let mut state = None;
+        handle_new_content(
+            &mut state,
+            uri(),
+            "L = [1, 2]\nf(L) = L + 1\ns = sum(L, 1, 2, L)\n@note(\"L\")\nL".to_string(),
+        );
+        // the argument is a number, not the global list
+        assert_eq!(hover(&mut state, 1, 7), None);
+        assert_eq!(hover(&mut state, 2, 17), None);
+        assert_eq!(hover(&mut state, 3, 7), None);
+        assert_eq!(hover(&mut state, 4, 0), Some("L: list".to_string()));
+    }
+
+    #[test]
+    fn formatting() {
+        let text = "a=1;b=a+2\n";
+        let edits = format_edits(text);
+        assert_eq!(
+            edits,
+            vec![TextEdit::new(
+                Range::new(Position::new(0, 0), Position::new(1, 0)),
+                "a = 1\nb = a + 2\n".to_string()
+            )]
+        );
+        assert_eq!(format_edits(&edits[0].new_text), vec![]);
+        // parse errors
+        assert_eq!(format_edits("a = (1"), vec![]);
+    }
+
+    fn completions(text: &str) -> Vec<CompletionItem> {
+        let mut state = None;
+        handle_new_content(&mut state, uri(), text.to_string());
+        match completion_handler(
+            &mut state,
+            &CompletionParams {
+                text_document_position: TextDocumentPositionParams::new(
+                    TextDocumentIdentifier::new(uri()),
+                    Position::new(0, 0),
+                ),
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+                context: None,
+            },
+        ) {
+            Some(Some(CompletionResponse::Array(items))) => items,
+            r => panic!("Unexpected completion response {:?}", r),
+        }
+    }
+
+    #[test]
+    fn builtin_completion() {
+        let items = completions("");
+        let item = |label: &str| items.iter().find(|i| i.label == label).cloned();
+        let sin = item("sin").unwrap();
+        assert_eq!(sin.kind, Some(CompletionItemKind::FUNCTION));
+        assert_eq!(sin.insert_text, Some("sin(${1:x})".to_string()));
+        assert_eq!(sin.detail, Some("sin(number) -> number".to_string()));
+        assert_eq!(
+            item("total").unwrap().insert_text,
+            Some("total(${1:L})".to_string())
+        );
+        assert_eq!(
+            item("round").unwrap().insert_text,
+            Some("round(${1:x})".to_string())
+        );
+        let polygon = item("polygon").unwrap();
+        assert_eq!(polygon.insert_text, Some("polygon(${1:L})".to_string()));
+        assert_eq!(polygon.detail, Some("polygon(...) -> polygon".to_string()));
+
+        // a user function replaces the builtin
+        let items = completions("total(x) = x");
+        let totals = items
+            .iter()
+            .filter(|i| i.label == "total")
+            .collect::<Vec<_>>();
+        assert_eq!(totals.len(), 1);
+        assert_eq!(totals[0].insert_text, None);
+    }
+
+    #[test]
+    fn stdlib_completion() {
+        let items = completions("");
+        let test_var = items.iter().find(|i| i.label == "test_var").unwrap();
+        assert_eq!(test_var.kind, Some(CompletionItemKind::CONSTANT));
+        assert_eq!(test_var.detail, Some("number".to_string()));
+
+        // included definitions are only listed once
+        let items = completions("include \"test\"");
+        assert_eq!(items.iter().filter(|i| i.label == "test_var").count(), 1);
+    }
+
+    #[test]
+    fn document_symbol() {
+        let mut state = None;
+        handle_new_content(
+            &mut state,
+            uri(),
+            "a = 1\ninline in(x) = x + a\n".to_string(),
+        );
+        let symbols = match document_symbol_handler(
+            &mut state,
+            &DocumentSymbolParams {
+                text_document: TextDocumentIdentifier::new(uri()),
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            },
+        ) {
+            Some(Some(DocumentSymbolResponse::Nested(symbols))) => symbols,
+            r => panic!("Unexpected document symbol response {:?}", r),
+        };
+        let summary = symbols
+            .iter()
+            .map(|s| (s.name.as_str(), s.kind, s.range, s.selection_range))
+            .collect::<Vec<_>>();
+        let range =
+            |line, start, end| Range::new(Position::new(line, start), Position::new(line, end));
+        assert_eq!(
+            summary,
+            vec![
+                ("a", SymbolKind::VARIABLE, range(0, 0, 5), range(0, 0, 1)),
+                ("in", SymbolKind::FUNCTION, range(1, 0, 20), range(1, 7, 9)),
+            ]
+        );
+    }
+
+    #[test]
+    fn references() {
+        let mut state = None;
+        handle_new_content(
+            &mut state,
+            uri(),
+            "a = 1\nf(x) = x + a\nb = f(a)\n".to_string(),
+        );
+        let mut refs = |line, character, include_declaration| {
+            references_handler(
+                &mut state,
+                &ReferenceParams {
+                    text_document_position: TextDocumentPositionParams::new(
+                        TextDocumentIdentifier::new(uri()),
+                        Position::new(line, character),
+                    ),
+                    work_done_progress_params: Default::default(),
+                    partial_result_params: Default::default(),
+                    context: ReferenceContext {
+                        include_declaration,
+                    },
+                },
+            )
+            .unwrap()
+            .unwrap()
+            .into_iter()
+            .map(|l| {
+                assert_eq!(l.uri, uri());
+                (
+                    l.range.start.line,
+                    l.range.start.character,
+                    l.range.end.character,
+                )
+            })
+            .collect::<Vec<_>>()
+        };
+        assert_eq!(refs(0, 0, false), vec![(1, 11, 12), (2, 6, 7)]);
+        assert_eq!(refs(2, 6, true), vec![(0, 0, 1), (1, 11, 12), (2, 6, 7)]);
+        assert_eq!(refs(2, 4, false), vec![(2, 4, 5)]);
+        assert_eq!(refs(1, 0, true), vec![(1, 0, 1), (2, 4, 5)]);
+    }
+
+    #[test]
+    fn shadowed_references() {
+        let mut state = None;
+        handle_new_content(
+            &mut state,
+            uri(),
+            "x = 1\nf(x) = x + 1\ng = [x for x = [x]]\nh = x\n@note(\"x\")".to_string(),
+        );
+        let mut refs = |line, character| {
+            references_handler(
+                &mut state,
+                &ReferenceParams {
+                    text_document_position: TextDocumentPositionParams::new(
+                        TextDocumentIdentifier::new(uri()),
+                        Position::new(line, character),
+                    ),
+                    work_done_progress_params: Default::default(),
+                    partial_result_params: Default::default(),
+                    context: ReferenceContext {
+                        include_declaration: false,
+                    },
+                },
+            )
+            .unwrap()
+            .map(|locations| {
+                locations
+                    .into_iter()
+                    .map(|l| (l.range.start.line, l.range.start.character))
+                    .collect::<Vec<_>>()
+            })
+        };
+        // the argument and the comprehension variable are locals
+        assert_eq!(refs(1, 7), None);
+        assert_eq!(refs(1, 2), None);
+        assert_eq!(refs(2, 5), None);
+        assert_eq!(refs(4, 7), None);
+        let x = Some(vec![(2, 16), (3, 4)]);
+        assert_eq!(refs(2, 16), x);
+        assert_eq!(refs(3, 4), x);
+        assert_eq!(refs(0, 0), x);
+    }
+
+    // Runs the server on a thread, talking to it over an in-memory connection
+    struct TestServer {
+        client: Connection,
+        thread: std::thread::JoinHandle<Result<(), Box<dyn Error + Sync + Send>>>,
+        next_id: i32,
+    }
+
+    impl TestServer {
+        // A response that takes longer than this fails the test instead of hanging it
+        const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+        fn new() -> Self {
+            let (server, client) = Connection::memory();
+            Self {
+                client,
+                thread: std::thread::spawn(move || start(server)),
+                next_id: 0,
+            }
+        }
+
+        fn recv(&self) -> Message {
+            self.client
+                .receiver
+                .recv_timeout(Self::TIMEOUT)
+                .expect("Server didn't respond in time")
+        }
+
+        // Skips the notifications sent before the response
+        fn request<R: lsp_types::request::Request>(&mut self, params: R::Params) -> R::Result {
+            self.next_id += 1;
+            let id = lsp_server::RequestId::from(self.next_id);
+            self.client
+                .sender
+                .send(lsp_server::Request::new(id.clone(), R::METHOD.to_string(), params).into())
+                .unwrap();
+            loop {
+                match self.recv() {
+                    Message::Response(resp) if resp.id == id => {
+                        assert!(resp.error.is_none(), "{:?}", resp.error);
+                        return serde_json::from_value(resp.result.unwrap()).unwrap();
+                    }
+                    Message::Notification(_) => {}
+                    msg => panic!("Unexpected message {:?}", msg),
+                }
+            }
+        }
+
+        fn notify<N: lsp_types::notification::Notification>(&self, params: N::Params) {
+            self.client
+                .sender
+                .send(Notification::new(N::METHOD.to_string(), params).into())
+                .unwrap();
+        }
+
+        fn shutdown(mut self) {
+            self.request::<lsp_types::request::Shutdown>(());
+            self.notify::<lsp_types::notification::Exit>(());
+            self.thread.join().unwrap().unwrap();
+        }
+    }
+
+    #[test]
+    fn server_completion() {
+        let mut server = TestServer::new();
+        let init = server.request::<Initialize>(InitializeParams::default());
+        assert!(init.capabilities.completion_provider.is_some());
+        server.notify::<lsp_types::notification::Initialized>(lsp_types::InitializedParams {});
+        server.notify::<DidOpenTextDocument>(lsp_types::DidOpenTextDocumentParams {
+            text_document: lsp_types::TextDocumentItem::new(
+                uri(),
+                "desmos".to_string(),
+                1,
+                "a = 1\nb = a + 1".to_string(),
+            ),
+        });
+        let completions = server.request::<Completion>(CompletionParams {
+            text_document_position: TextDocumentPositionParams::new(
+                TextDocumentIdentifier::new(uri()),
+                Position::new(1, 0),
+            ),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+            context: None,
+        });
+        let labels = match completions {
+            Some(CompletionResponse::Array(items)) => {
+                items.into_iter().map(|i| i.label).collect::<Vec<_>>()
+            }
+            c => panic!("Expected completions, got {:?}", c),
+        };
+        assert!(labels.contains(&"a".to_string()));
+        assert!(labels.contains(&"b".to_string()));
+        server.shutdown();
+    }
+}
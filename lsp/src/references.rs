@@ -0,0 +1,226 @@
+use ast::{Expression, LStatements, LocatedExpression, Statement};
+
+// Spans of every use of a global variable or function
+pub fn collect_references(stmts: &LStatements, name: &str) -> Vec<types::Span> {
+    let mut refs = Vec::new();
+    visit_global_uses(stmts, &mut |span, used| {
+        if used == name {
+            refs.push(span.clone())
+        }
+    });
+    refs
+}
+
+// The name of the global used at a char offset, if any. The cursor can be just after the name.
+pub fn global_use_at(stmts: &LStatements, offset: usize) -> Option<String> {
+    let mut found = None;
+    visit_global_uses(stmts, &mut |span, name| {
+        if span.range.start <= offset && offset <= span.range.end {
+            found = Some(name.to_string());
+        }
+    });
+    found
+}
+
+// Calls f with the span and name of every use of a global variable or function. Uses where a
+//  function argument, comprehension variable or bound variable rebinds the name are skipped.
+pub fn visit_global_uses(stmts: &LStatements, f: &mut impl FnMut(&types::Span, &str)) {
+    let mut defined = Vec::new();
+    collect_functions(stmts, &mut defined);
+    visit_stmts(stmts, &defined, f);
+}
+
+// Names of the functions defined in the file, including inside `with` blocks
+fn collect_functions(stmts: &LStatements, defined: &mut Vec<String>) {
+    for (_, stmt) in stmts {
+        match stmt {
+            Statement::FuncDef(def, _) => defined.push(def.name.clone()),
+            Statement::With(_, body) => collect_functions(body, defined),
+            _ => {}
+        }
+    }
+}
+
+// `defined` is the functions defined in the file, which shadow `sum`, `prod` and `integral`
+//  like other builtins
+fn visit_stmts(stmts: &LStatements, defined: &[String], f: &mut impl FnMut(&types::Span, &str)) {
+    for (span, stmt) in stmts {
+        match stmt {
+            Statement::VarDef { val, .. } => visit_uses(val, &mut vec![], defined, f),
+            Statement::FuncDef(def, body) => {
+                let mut bound = def.args.iter().map(|(_, arg, _)| arg.clone()).collect();
+                visit_uses(body, &mut bound, defined, f);
+            }
+            // the statement span is the span of its expression
+            Statement::Expression(e) => {
+                visit_uses(&(span.clone(), e.clone()), &mut vec![], defined, f)
+            }
+            Statement::Ticker { handler, min_step } => {
+                visit_uses(handler, &mut vec![], defined, f);
+                if let Some(step) = min_step {
+                    visit_uses(step, &mut vec![], defined, f);
+                }
+            }
+            Statement::With(_, body) => visit_stmts(body, defined, f),
+            Statement::Import(_) | Statement::Note(_) => {}
+        }
+    }
+}
+
+// Visits `body` with `var` bound, so uses of it inside refer to the local
+fn visit_bound(
+    var: &str,
+    body: &LocatedExpression,
+    bound: &mut Vec<String>,
+    defined: &[String],
+    f: &mut impl FnMut(&types::Span, &str),
+) {
+    bound.push(var.to_string());
+    visit_uses(body, bound, defined, f);
+    bound.pop();
+}
+
+fn visit_uses(
+    (span, e): &LocatedExpression,
+    bound: &mut Vec<String>,
+    defined: &[String],
+    f: &mut impl FnMut(&types::Span, &str),
+) {
+    match e {
+        Expression::Variable(v) if !bound.contains(v) => f(span, v),
+        Expression::Call {
+            func: ast::Function::Normal { name },
+            args,
+        } => match args.as_slice() {
+            // `sum(n, 1, 10, n^2)` binds n in its body, but not in its bounds
+            [(_, Expression::Variable(var)), lower, upper, body]
+                if compiler::BINDING_FUNCTIONS.contains(&name.as_str())
+                    && !defined.contains(name) =>
+            {
+                visit_uses(lower, bound, defined, f);
+                visit_uses(upper, bound, defined, f);
+                visit_bound(var, body, bound, defined, f);
+            }
+            _ => {
+                if !bound.contains(name) {
+                    // calls start with the function name
+                    let start = span.range.start;
+                    let name_span =
+                        types::Span::new(span.file_id, start..start + name.chars().count());
+                    f(&name_span, name);
+                }
+                for arg in args {
+                    visit_uses(arg, bound, defined, f);
+                }
+            }
+        },
+        Expression::Comprehension { body, var, list } => {
+            visit_uses(list, bound, defined, f);
+            visit_bound(&var.1, body, bound, defined, f);
+        }
+        _ => {
+            for child in e.children() {
+                visit_uses(child, bound, defined, f);
+            }
+        }
+    }
+}
+
+// Calls f on an expression and then on each of its subexpressions, in source order
+pub fn visit_expr(expr: &LocatedExpression, f: &mut impl FnMut(&LocatedExpression)) {
+    f(expr);
+    match &expr.1 {
+        Expression::Error
+        | Expression::Num(_)
+        | Expression::Variable(_)
+        | Expression::RawLatex(..)
+        | Expression::FullyQualifiedVariable { .. } => {}
+        Expression::BinaryExpr { left, right, .. } | Expression::Comparison { left, right, .. } => {
+            visit_expr(left, f);
+            visit_expr(right, f);
+        }
+        Expression::DoubleComparison {
+            left,
+            middle,
+            right,
+            ..
+        } => {
+            visit_expr(left, f);
+            visit_expr(middle, f);
+            visit_expr(right, f);
+        }
+        Expression::UnaryExpr { val, .. } | Expression::Map(val) => visit_expr(val, f),
+        Expression::Call { args: items, .. } | Expression::List(items) => {
+            for item in items {
+                visit_expr(item, f);
+            }
+        }
+        Expression::Range { first, second, end } => {
+            visit_expr(first, f);
+            if let Some(second) = second {
+                visit_expr(second, f);
+            }
+            visit_expr(end, f);
+        }
+        Expression::Piecewise {
+            first,
+            rest,
+            default,
+        } => {
+            for (_, branch) in std::iter::once(first.as_ref()).chain(rest.iter()) {
+                visit_expr(&branch.cond, f);
+                visit_expr(&branch.val, f);
+            }
+            visit_expr(default, f);
+        }
+        Expression::Index { val, ind } => {
+            visit_expr(val, f);
+            visit_expr(ind, f);
+        }
+        Expression::Comprehension { body, list, .. } => {
+            visit_expr(body, f);
+            visit_expr(list, f);
+        }
+        Expression::Point { x, y } => {
+            visit_expr(x, f);
+            visit_expr(y, f);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn refs(src: &str, name: &str) -> Vec<std::ops::Range<usize>> {
+        let ast = parser::lex_and_parse(0, src.to_string()).0.unwrap();
+        collect_references(&ast, name)
+            .into_iter()
+            .map(|s| s.range)
+            .collect()
+    }
+
+    #[test]
+    fn references() {
+        assert_eq!(refs("a = 1\nb = a + [a]\n", "a"), vec![10..11, 15..16]);
+        assert_eq!(refs("f(x) = x\nf(2) + f@([1])\n", "f"), vec![9..10, 16..17]);
+        // the argument shadows the global
+        assert_eq!(refs("x = 1\nf(x) = x\ny = x\n", "x"), vec![19..20]);
+        assert_eq!(
+            refs("a = 1\nwith {} { where a > 0 -> a, else 1 }\n", "a"),
+            vec![22..23, 31..32]
+        );
+        // comprehension and bound variables shadow the global only in the body they bind
+        assert_eq!(refs("n = 1\nL = [n for n = [n]]\n", "n"), vec![22..23]);
+        assert_eq!(refs("n = 1\ns = sum(n, n, 10, n^2)\n", "n"), vec![17..18]);
+        assert_eq!(
+            refs("t = 2\ni = integral(t, 0, t, t) + t\n", "t"),
+            vec![25..26, 33..34]
+        );
+        // a user's `sum` doesn't bind its first argument
+        assert_eq!(
+            refs("n = 1\nsum(a, b, c, d) = a\ns = sum(n, 1, 2, n)\n", "n"),
+            vec![34..35, 43..44]
+        );
+    }
+}
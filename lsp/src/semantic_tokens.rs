@@ -0,0 +1,254 @@
+use std::collections::HashSet;
+
+use ast::{Expression, LStatements, Statement};
+use lsp_types::{SemanticToken, SemanticTokenModifier, SemanticTokenType, SemanticTokensLegend};
+
+use crate::{name_span, offset_to_position, references::visit_expr};
+
+// Indices into the legend
+const NUMBER: u32 = 0;
+const VARIABLE: u32 = 1;
+const FUNCTION: u32 = 2;
+const OPERATOR: u32 = 3;
+// Builtins are functions from the default library
+const DEFAULT_LIBRARY: u32 = 1 << 0;
+
+pub fn legend() -> SemanticTokensLegend {
+    SemanticTokensLegend {
+        token_types: vec![
+            SemanticTokenType::NUMBER,
+            SemanticTokenType::VARIABLE,
+            SemanticTokenType::FUNCTION,
+            SemanticTokenType::OPERATOR,
+        ],
+        token_modifiers: vec![SemanticTokenModifier::DEFAULT_LIBRARY],
+    }
+}
+
+// Token span, type and modifiers
+type Token = (std::ops::Range<usize>, u32, u32);
+
+fn func_names(stmts: &LStatements, names: &mut HashSet<String>) {
+    for (_, stmt) in stmts {
+        match stmt {
+            Statement::FuncDef(def, _) => {
+                names.insert(def.name.clone());
+            }
+            Statement::With(_, body) => func_names(body, names),
+            _ => {}
+        }
+    }
+}
+
+// The first operator in a range of text, which is between two operands
+fn operator_in(text: &[char], range: std::ops::Range<usize>) -> Option<std::ops::Range<usize>> {
+    let is_op = |c: &char| "+-*/%^!<>=".contains(*c);
+    let start = range.start + text.get(range.clone())?.iter().position(is_op)?;
+    let len = text[start..range.end]
+        .iter()
+        .take_while(|c| is_op(c))
+        .count();
+    Some(start..start + len)
+}
+
+fn expr_tokens(
+    text: &[char],
+    funcs: &HashSet<String>,
+    expr: &ast::LocatedExpression,
+    tokens: &mut Vec<Token>,
+) {
+    visit_expr(expr, &mut |(span, e)| {
+        let range = span.range.clone();
+        match e {
+            Expression::Num(_) => tokens.push((range, NUMBER, 0)),
+            Expression::Variable(_) | Expression::FullyQualifiedVariable { .. } => {
+                tokens.push((range, VARIABLE, 0))
+            }
+            Expression::Call { func, .. } => {
+                let name = match func {
+                    ast::Function::Normal { name } => name.clone(),
+                    ast::Function::Log { base } => format!("log_{}", base),
+                };
+                let modifiers = match func {
+                    ast::Function::Normal { name }
+                        if funcs.contains(name)
+                            || !compiler::BUILTIN_FUNCTIONS.contains_key(name.as_str()) =>
+                    {
+                        0
+                    }
+                    _ => DEFAULT_LIBRARY,
+                };
+                let start = range.start;
+                tokens.push((start..start + name.chars().count(), FUNCTION, modifiers));
+            }
+            Expression::BinaryExpr { left, right, .. }
+            | Expression::Comparison { left, right, .. } => {
+                // implicit multiplication has no operator
+                if let Some(op) = operator_in(text, left.0.range.end..right.0.range.start) {
+                    tokens.push((op, OPERATOR, 0));
+                }
+            }
+            Expression::DoubleComparison {
+                left,
+                middle,
+                right,
+                ..
+            } => {
+                for gap in [
+                    left.0.range.end..middle.0.range.start,
+                    middle.0.range.end..right.0.range.start,
+                ] {
+                    if let Some(op) = operator_in(text, gap) {
+                        tokens.push((op, OPERATOR, 0));
+                    }
+                }
+            }
+            Expression::UnaryExpr { val, operator } => {
+                let gap = match operator {
+                    ast::UnaryOperator::Negate => range.start..val.0.range.start,
+                    ast::UnaryOperator::Factorial => val.0.range.end..range.end,
+                };
+                if let Some(op) = operator_in(text, gap) {
+                    tokens.push((op, OPERATOR, 0));
+                }
+            }
+            _ => {}
+        }
+    });
+}
+
+fn stmt_tokens(
+    text: &str,
+    chars: &[char],
+    funcs: &HashSet<String>,
+    stmts: &LStatements,
+    tokens: &mut Vec<Token>,
+) {
+    for (span, stmt) in stmts {
+        match stmt {
+            Statement::VarDef { name, val, .. } => {
+                tokens.push((name_span(text, span, name).range, VARIABLE, 0));
+                expr_tokens(chars, funcs, val, tokens);
+            }
+            Statement::FuncDef(def, body) => {
+                tokens.push((name_span(text, span, &def.name).range, FUNCTION, 0));
+                for (arg_span, arg, _) in &def.args {
+                    let start = arg_span.range.start;
+                    tokens.push((start..start + arg.chars().count(), VARIABLE, 0));
+                }
+                expr_tokens(chars, funcs, body, tokens);
+            }
+            Statement::Expression(e) => {
+                expr_tokens(chars, funcs, &(span.clone(), e.clone()), tokens)
+            }
+            Statement::Ticker { handler, min_step } => {
+                expr_tokens(chars, funcs, handler, tokens);
+                if let Some(step) = min_step {
+                    expr_tokens(chars, funcs, step, tokens);
+                }
+            }
+            Statement::With(_, body) => stmt_tokens(text, chars, funcs, body, tokens),
+            Statement::Import(_) | Statement::Note(_) => {}
+        }
+    }
+}
+
+// Tokens are sorted and each position is relative to the previous token
+pub fn semantic_tokens(text: &str, stmts: &LStatements) -> Vec<SemanticToken> {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut funcs = HashSet::new();
+    func_names(stmts, &mut funcs);
+    let mut tokens = Vec::new();
+    stmt_tokens(text, &chars, &funcs, stmts, &mut tokens);
+    tokens.sort_by_key(|(range, _, _)| range.start);
+
+    let mut prev = lsp_types::Position::new(0, 0);
+    tokens
+        .into_iter()
+        .filter_map(|(range, token_type, token_modifiers_bitset)| {
+            let start = offset_to_position(text, range.start);
+            let end = offset_to_position(text, range.end);
+            // tokens can't span lines
+            if start.line != end.line || range.is_empty() {
+                return None;
+            }
+            let delta_start = match start.line == prev.line {
+                true => start.character - prev.character,
+                false => start.character,
+            };
+            let token = SemanticToken {
+                delta_line: start.line - prev.line,
+                delta_start,
+                length: end.character - start.character,
+                token_type,
+                token_modifiers_bitset,
+            };
+            prev = start;
+            Some(token)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Absolute (line, start, length, type, modifiers) of each token
+    fn tokens(src: &str) -> Vec<(u32, u32, u32, u32, u32)> {
+        let ast = parser::lex_and_parse(0, src.to_string()).0.unwrap();
+        let (mut line, mut start) = (0, 0);
+        semantic_tokens(src, &ast)
+            .into_iter()
+            .map(|t| {
+                if t.delta_line > 0 {
+                    start = 0;
+                }
+                line += t.delta_line;
+                start += t.delta_start;
+                (
+                    line,
+                    start,
+                    t.length,
+                    t.token_type,
+                    t.token_modifiers_bitset,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn function_definition() {
+        assert_eq!(
+            tokens("f(x) = sin(x) + 1"),
+            vec![
+                (0, 0, 1, FUNCTION, 0),
+                (0, 2, 1, VARIABLE, 0),
+                (0, 7, 3, FUNCTION, DEFAULT_LIBRARY),
+                (0, 11, 1, VARIABLE, 0),
+                (0, 14, 1, OPERATOR, 0),
+                (0, 16, 1, NUMBER, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn operators() {
+        assert_eq!(
+            tokens("a = 1\nb = -a! >= 2a\nf(a)"),
+            vec![
+                (0, 0, 1, VARIABLE, 0),
+                (0, 4, 1, NUMBER, 0),
+                (1, 0, 1, VARIABLE, 0),
+                (1, 4, 1, OPERATOR, 0),
+                (1, 5, 1, VARIABLE, 0),
+                (1, 6, 1, OPERATOR, 0),
+                (1, 8, 2, OPERATOR, 0),
+                (1, 11, 1, NUMBER, 0),
+                (1, 12, 1, VARIABLE, 0),
+                // an undefined function is not a builtin
+                (2, 0, 1, FUNCTION, 0),
+                (2, 2, 1, VARIABLE, 0),
+            ]
+        );
+    }
+}
@@ -17,17 +17,22 @@ pub enum Token {
     OpCmpGt,
     OpCmpGe,
     OpCmpEq,
+    OpCmpNe,
     OpExp,
     OpEq,
     OpColon,
+    OpFactorial,
     CtrlLParen,
     CtrlRParen,
     CtrlLBrac,
     CtrlRBrac,
+    CtrlLBrace,
+    CtrlRBrace,
     CtrlComma,
     CtrlMap,
     CtrlThen,
     CtrlSemi,
+    Newline,
     CtrlGci,
     CtrlEllipses,
     KeywordWhere,
@@ -38,6 +43,8 @@ pub enum Token {
     KeywordInclude,
     KeywordLatex,
     KeywordLatexList,
+    KeywordWith,
+    KeywordFor,
 }
 
 impl Token {
@@ -57,17 +64,22 @@ impl Token {
             OpCmpGt => "`>`",
             OpCmpGe => "`>=`",
             OpCmpEq => "`=`",
-            OpExp => "`**`",
+            OpCmpNe => "`!=`",
+            OpExp => "`^`",
             OpEq => "`=`",
             OpColon => "`:`",
+            OpFactorial => "`!`",
             CtrlLParen => "`(`",
             CtrlRParen => "`)`",
             CtrlLBrac => "`[`",
             CtrlRBrac => "`]`",
+            CtrlLBrace => "`{`",
+            CtrlRBrace => "`}`",
             CtrlComma => "`,`",
             CtrlMap => "`@`",
             CtrlThen => "`->`",
             CtrlSemi => "`;`",
+            Newline => "newline",
             CtrlGci => "`.`",
             CtrlEllipses => "`...`",
             KeywordWhere => "`where`",
@@ -78,12 +90,96 @@ impl Token {
             KeywordInclude => "`include`",
             KeywordLatex => "`latex`",
             KeywordLatexList => "`latex_list`",
+            KeywordWith => "`with`",
+            KeywordFor => "`for`",
         }
     }
 }
 
+// Numbers with larger exponents keep them, like `1e400`, instead of being written out in full
+const MAX_EXPANDED_EXPONENT: i32 = 20;
+// Far outside of what Desmos can represent, so most likely a typo
+const MAX_EXPONENT: i32 = 1000;
+
+// Moves the decimal point of `mantissa` by `exp` places, `1.5e-3` is `0.0015`
+fn expand_exponent(mantissa: &str, exp: i32) -> String {
+    let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let digits = format!("{}{}", int, frac);
+    let point = int.len() as i32 + exp;
+    let (int, frac) = if point <= 0 {
+        (
+            String::new(),
+            format!("{}{}", "0".repeat(-point as usize), digits),
+        )
+    } else if point as usize >= digits.len() {
+        (
+            format!("{}{}", digits, "0".repeat(point as usize - digits.len())),
+            String::new(),
+        )
+    } else {
+        let (int, frac) = digits.split_at(point as usize);
+        (int.to_string(), frac.to_string())
+    };
+    let int = match int.trim_start_matches('0') {
+        "" => "0",
+        int => int,
+    };
+    match frac.trim_end_matches('0') {
+        "" => int.to_string(),
+        frac => format!("{}.{}", int, frac),
+    }
+}
+
 fn lexer() -> impl Parser<char, Vec<ast::Spanned<Token>>, Error = LexErr> {
-    let int = text::int(10).map(Token::Num);
+    // Digits may be grouped with single underscores (`1_000`), which are dropped
+    let digits = text::digits(10)
+        .chain::<char, _, _>(filter(|c: &char| c.is_ascii_digit() || *c == '_').repeated())
+        .collect::<String>()
+        .validate(|raw, span, emit| {
+            if raw.starts_with('_') || raw.ends_with('_') || raw.contains("__") {
+                emit(Simple::custom(
+                    span,
+                    format!("invalid digit separator in `{}`", raw),
+                ));
+            }
+            raw.replace('_', "")
+        });
+    // A digit has to follow the `.`, so `1...5` is still a range
+    let frac = just('.').ignore_then(digits);
+    let exponent = just('e')
+        .ignore_then(one_of("+-").or_not())
+        .then(text::digits(10));
+    // `3.14`, `.5` and `1e3`. A leading `-` is always the unary operator, not part of the
+    //  number.
+    let num = digits
+        .then(frac.or_not())
+        .or(frac.map(|frac| (String::new(), Some(frac))))
+        .then(exponent.or_not())
+        .validate(|((int, frac), exponent), span, emit| {
+            let mantissa = match frac {
+                Some(frac) => format!("{}.{}", int, frac),
+                None => int,
+            };
+            let exponent = match exponent {
+                Some((sign, digits)) => format!("{}{}", sign.unwrap_or('+'), digits),
+                None => return Token::Num(mantissa),
+            };
+            match exponent.parse::<i32>() {
+                // Desmos can't read exponents, so the number is written out in full when that
+                //  is short and compiled to a power of ten otherwise
+                Ok(exp) if exp.abs() <= MAX_EXPANDED_EXPONENT => {
+                    Token::Num(expand_exponent(&mantissa, exp))
+                }
+                Ok(exp) if exp.abs() <= MAX_EXPONENT => Token::Num(format!("{}e{}", mantissa, exp)),
+                _ => {
+                    emit(Simple::custom(
+                        span,
+                        format!("exponent of `{}e{}` is too large", mantissa, exponent),
+                    ));
+                    Token::Num(mantissa)
+                }
+            }
+        });
 
     let p_str = just('\"')
         .ignore_then(filter(|c| *c != '"' && *c != '\n').repeated())
@@ -95,10 +191,12 @@ fn lexer() -> impl Parser<char, Vec<ast::Spanned<Token>>, Error = LexErr> {
     let mkops = |s: &'static str, t: Token| just(s).to(t);
     let op = just("<=")
         .to(Token::OpCmpLe)
-        .or(mkop('>', Token::OpCmpGt))
         .or(mkops(">=", Token::OpCmpGe))
+        .or(mkop('>', Token::OpCmpGt))
         .or(mkops("==", Token::OpCmpEq))
+        .or(mkops("!=", Token::OpCmpNe))
         .or(mkops("**", Token::OpExp))
+        .or(mkop('^', Token::OpExp))
         .or(mkop('-', Token::OpMinus))
         .or(mkop('+', Token::OpPlus))
         .or(mkop('*', Token::OpMult))
@@ -106,7 +204,8 @@ fn lexer() -> impl Parser<char, Vec<ast::Spanned<Token>>, Error = LexErr> {
         .or(mkop('%', Token::OpMod))
         .or(mkop('<', Token::OpCmpLt))
         .or(mkop('=', Token::OpEq))
-        .or(mkop(':', Token::OpColon));
+        .or(mkop(':', Token::OpColon))
+        .or(mkop('!', Token::OpFactorial));
 
     let ctrl = just("->")
         .to(Token::CtrlThen)
@@ -114,40 +213,111 @@ fn lexer() -> impl Parser<char, Vec<ast::Spanned<Token>>, Error = LexErr> {
         .or(mkop(')', Token::CtrlRParen))
         .or(mkop('[', Token::CtrlLBrac))
         .or(mkop(']', Token::CtrlRBrac))
+        .or(mkop('{', Token::CtrlLBrace))
+        .or(mkop('}', Token::CtrlRBrace))
         .or(mkop(',', Token::CtrlComma))
         .or(mkop('@', Token::CtrlMap))
         .or(mkop(';', Token::CtrlSemi))
+        .or(mkop('\n', Token::Newline))
         .or(mkops("...", Token::CtrlEllipses))
         .or(mkop('.', Token::CtrlGci));
 
-    let ident = text::ident().map(|i: String| match i.as_str() {
-        "where" => Token::KeywordWhere,
-        "else" => Token::KeywordElse,
-        "inline" => Token::KeywordInline,
-        "import" => Token::KeywordImport,
-        "from" => Token::KeywordFrom,
-        "include" => Token::KeywordInclude,
-        "latex" => Token::KeywordLatex,
-        "latex_list" => Token::KeywordLatexList,
-        _ => Token::Ident(i),
-    });
+    let ident = text::ident()
+        // `_1` is a misplaced digit separator rather than an identifier
+        .validate(|i: String, span, emit| {
+            if i.trim_start_matches('_')
+                .starts_with(|c: char| c.is_ascii_digit())
+            {
+                emit(Simple::custom(
+                    span,
+                    format!("invalid digit separator in `{}`", i),
+                ));
+            }
+            i
+        })
+        .map(|i: String| match i.as_str() {
+            "where" => Token::KeywordWhere,
+            "else" => Token::KeywordElse,
+            "inline" => Token::KeywordInline,
+            "import" => Token::KeywordImport,
+            "from" => Token::KeywordFrom,
+            "include" => Token::KeywordInclude,
+            "latex" => Token::KeywordLatex,
+            "latex_list" => Token::KeywordLatexList,
+            "with" => Token::KeywordWith,
+            "for" => Token::KeywordFor,
+            _ => Token::Ident(i),
+        });
 
-    let token = int
+    let token = num
         .or(p_str)
         .or(ctrl)
         .or(op)
         .or(ident)
         .recover_with(skip_then_retry_until([]));
 
+    // Line comments stop before the newline so that it still ends the statement
+    let line_comment = just("//")
+        .then(filter(|c: &char| *c != '\n').repeated())
+        .ignored();
+    let block_comment = just("/*").then(take_until(just("*/"))).ignored();
+    // newlines are tokens, see end_statements
+    let pad = filter(|c: &char| c.is_whitespace() && *c != '\n')
+        .ignored()
+        .or(line_comment)
+        .or(block_comment)
+        .repeated();
     token
         .map_with_span(|t, span| (span, t))
-        .padded()
+        .padded_by(pad)
         .repeated()
         .then_ignore(end())
 }
 
 pub type ParseErr = Simple<Token, types::Span>;
 
+fn binop_str(op: ast::BinaryOperator) -> &'static str {
+    match op {
+        ast::BinaryOperator::Add => Token::OpPlus,
+        ast::BinaryOperator::Subtract => Token::OpMinus,
+        ast::BinaryOperator::Multiply => Token::OpMult,
+        ast::BinaryOperator::Divide => Token::OpDiv,
+        ast::BinaryOperator::Mod => Token::OpMod,
+        ast::BinaryOperator::Exponent => Token::OpExp,
+    }
+    .to_str()
+}
+
+fn val_type(typ: String, span: types::Span) -> Result<types::ValType, ParseErr> {
+    match typ.as_str() {
+        "num" | "Number" => Ok(types::ValType::Number),
+        "list" | "List" => Ok(types::ValType::List(types::ElemType::Number)),
+        "point" | "Point" => Ok(types::ValType::Point),
+        "bool" | "Bool" => Ok(types::ValType::Bool),
+        _ => Err(Simple::custom(
+            span,
+            format!(
+                "Invalid type '{}', expected 'num', 'list', 'point' or 'bool'",
+                typ
+            ),
+        )),
+    }
+}
+
+// What follows the items in brackets, see expr_parser
+#[derive(Clone)]
+enum BracketTail {
+    Range(ast::LocatedExpression),
+    Generator(ast::Spanned<String>, ast::LocatedExpression),
+}
+
+// An operator that follows an atom, see expr_parser
+#[derive(Clone)]
+enum Postfix {
+    Factorial,
+    Index(ast::LocatedExpression),
+}
+
 fn expr_parser() -> impl Parser<Token, ast::LocatedExpression, Error = ParseErr> + Clone {
     recursive(|expr: Recursive<Token, ast::LocatedExpression, _>| {
         let call = select! {
@@ -158,52 +328,109 @@ fn expr_parser() -> impl Parser<Token, ast::LocatedExpression, Error = ParseErr>
                 .separated_by(just(Token::CtrlComma))
                 .delimited_by(just(Token::CtrlLParen), just(Token::CtrlRParen)),
         )
-        .map_with_span(|(func, args), s| {
+        .map_with_span(|(name, args), s| {
+            // `log_2(x)` is the base 2 logarithm
+            let func = match name.strip_prefix("log_") {
+                Some(base) if !base.is_empty() && base.chars().all(|c| c.is_ascii_digit()) => {
+                    ast::Function::Log {
+                        base: base.to_string(),
+                    }
+                }
+                _ => ast::Function::Normal { name },
+            };
+            (s, ast::Expression::Call { func, args })
+        });
+
+        // `f@(L)` maps a function over a list, it's shorthand for `f(@L)`
+        let map_call = select! {
+            Token::Ident(name) => name,
+        }
+        .then_ignore(just(Token::CtrlMap))
+        .then(
+            expr.clone()
+                .delimited_by(just(Token::CtrlLParen), just(Token::CtrlRParen)),
+        )
+        .map_with_span(|(name, arg), s| {
             (
                 s,
                 ast::Expression::Call {
-                    func: ast::Function::Normal { name: func },
-                    args,
+                    func: ast::Function::Normal { name },
+                    args: vec![(arg.0.clone(), ast::Expression::Map(Box::new(arg)))],
                 },
             )
         });
 
-        let range = expr
-            .clone()
-            .map(Box::new)
-            .then_ignore(just(Token::CtrlComma))
-            .then(
-                expr.clone()
-                    .then_ignore(just(Token::CtrlComma))
-                    .map(Box::new)
-                    .map(Option::Some)
-                    .or(empty().to(Option::None)),
-            )
-            .then_ignore(just(Token::CtrlEllipses))
-            .then_ignore(just(Token::CtrlComma))
-            .then(expr.clone().map(Box::new))
-            .delimited_by(just(Token::CtrlLBrac), just(Token::CtrlRBrac))
-            .map_with_span(|((first, second), end), s| {
-                (s, ast::Expression::Range { first, second, end })
-            });
+        let ident = select! {
+            Token::Ident(i) => i,
+        };
 
-        let list = expr
+        // The contents of brackets are parsed once and then decided to be a list, a range or
+        //  a comprehension, trying each in turn would re-parse nested brackets every time.
+        // Like Desmos, the commas around `...` are optional: `[1...10]`, `[1,3,...,11]`
+        let range_end = just(Token::CtrlComma)
+            .or_not()
+            .ignore_then(just(Token::CtrlEllipses))
+            .ignore_then(just(Token::CtrlComma).or_not())
+            .ignore_then(expr.clone())
+            .map(BracketTail::Range);
+        // `[n^2 for n = [1...10]]` has a single generator
+        let generator = just(Token::KeywordFor)
+            .ignore_then(ident.map_with_span(|v, s| (s, v)))
+            .then_ignore(just(Token::OpEq))
+            .then(expr.clone())
+            .map(|(var, list)| BracketTail::Generator(var, list));
+        let brackets = expr
             .clone()
             .separated_by(just(Token::CtrlComma))
+            .then(range_end.or(generator).or_not())
             .delimited_by(just(Token::CtrlLBrac), just(Token::CtrlRBrac))
-            .map_with_span(|v, s| (s, ast::Expression::List(v)));
+            .try_map(|(items, tail), s: types::Span| {
+                let len = items.len();
+                let mut items = items.into_iter();
+                let e = match (tail, items.next(), items.next(), items.next()) {
+                    (None, first, second, third) => ast::Expression::List(
+                        first
+                            .into_iter()
+                            .chain(second)
+                            .chain(third)
+                            .chain(items)
+                            .collect(),
+                    ),
+                    (Some(BracketTail::Range(end)), Some(first), second, None) => {
+                        ast::Expression::Range {
+                            first: Box::new(first),
+                            second: second.map(Box::new),
+                            end: Box::new(end),
+                        }
+                    }
+                    (Some(BracketTail::Generator(var, list)), Some(body), None, None) => {
+                        ast::Expression::Comprehension {
+                            body: Box::new(body),
+                            var,
+                            list: Box::new(list),
+                        }
+                    }
+                    (Some(BracketTail::Range(_)), ..) => {
+                        return Err(Simple::custom(
+                            s,
+                            format!("expected 1 or 2 values before `...`, found {}", len),
+                        ))
+                    }
+                    (Some(BracketTail::Generator(..)), ..) => {
+                        return Err(Simple::custom(
+                            s,
+                            format!("expected 1 value before `for`, found {}", len),
+                        ))
+                    }
+                };
+                Ok((s, e))
+            });
 
-        let ident = select! {
-            Token::Ident(i) => i,
-        };
         // this isn't the best
         let qualified_var = ident
             .then_ignore(just(Token::CtrlGci))
-            .then(ident.separated_by(just(Token::CtrlGci)))
+            .then(ident.separated_by(just(Token::CtrlGci)).at_least(1))
             .map_with_span(|(first, rest), s| {
-                if rest.is_empty() {
-                    return (s, ast::Expression::Variable(first));
-                }
                 let mut path = rest;
                 path.insert(0, first);
                 let (last, path) = path.split_last().unwrap();
@@ -222,14 +449,44 @@ fn expr_parser() -> impl Parser<Token, ast::LocatedExpression, Error = ParseErr>
         }
         .map_with_span(|v, s| (s, v));
 
-        let atom = range
-            .or(list)
-            .or(call)
-            .or(qualified_var)
-            .or(val)
-            .or(expr
-                .clone()
-                .delimited_by(just(Token::CtrlLParen), just(Token::CtrlRParen)))
+        // `raw<num>("\pi")` is latex emitted as is, with a declared type
+        let raw = select! {
+            Token::Ident(i) if i == "raw" => (),
+        }
+        .ignore_then(
+            select! {
+                Token::Ident(i) => i,
+            }
+            .try_map(val_type)
+            .delimited_by(just(Token::OpCmpLt), just(Token::OpCmpGt)),
+        )
+        .then(
+            select! {
+                Token::Str(s) => s,
+            }
+            .delimited_by(just(Token::CtrlLParen), just(Token::CtrlRParen)),
+        )
+        .map_with_span(|(ty, l), s| (s, ast::Expression::RawLatex(ty, l)));
+
+        // `(x, y)` is a point and `(x)` a group, decided after parsing x once
+        let parens = expr
+            .clone()
+            .then(just(Token::CtrlComma).ignore_then(expr.clone()).or_not())
+            .delimited_by(just(Token::CtrlLParen), just(Token::CtrlRParen))
+            .map_with_span(|(x, y), s| match y {
+                Some(y) => (
+                    s,
+                    ast::Expression::Point {
+                        x: Box::new(x),
+                        y: Box::new(y),
+                    },
+                ),
+                // include the parens in the span
+                None => (s, x.1),
+            });
+
+        // a single choice keeps the stack shallower than chaining `or`
+        let atom = choice((brackets, raw, map_call, call, qualified_var, val, parens))
             .recover_with(nested_delimiters(
                 Token::CtrlLParen,
                 Token::CtrlRParen,
@@ -243,18 +500,146 @@ fn expr_parser() -> impl Parser<Token, ast::LocatedExpression, Error = ParseErr>
                 |span| (span, ast::Expression::Error),
             ));
 
-        let negate = just(Token::OpMinus)
-            .ignore_then(atom.clone())
-            .map_with_span(|v, s| {
-                (
-                    s,
-                    ast::Expression::UnaryExpr {
-                        val: Box::new(v),
-                        operator: ast::UnaryOperator::Negate,
+        // `L[2]` indexes a list and `L[2...5]` slices it, the index of a slice is a range
+        //  without brackets
+        let index = expr
+            .clone()
+            .then(
+                just(Token::CtrlComma)
+                    .or_not()
+                    .ignore_then(just(Token::CtrlEllipses))
+                    .ignore_then(just(Token::CtrlComma).or_not())
+                    .ignore_then(expr.clone())
+                    .or_not(),
+            )
+            .delimited_by(just(Token::CtrlLBrac), just(Token::CtrlRBrac))
+            .map(|(first, end)| match end {
+                Some(end) => (
+                    first
+                        .0
+                        .with_end_of(&end.0)
+                        .unwrap_or_else(|| first.0.clone()),
+                    ast::Expression::Range {
+                        first: Box::new(first),
+                        second: None,
+                        end: Box::new(end),
                     },
+                ),
+                None => first,
+            });
+
+        // `^` is right associative and binds tighter than prefix `-`, so `-2^2` is
+        //  `-(2^2)` and `2^-1` is `2^(-1)`. Prefix `-` and `+` can be stacked (`- -1`).
+        //  Unary plus is a no-op, so it only widens the span of its operand.
+        // Postfix `!` and indexing bind tighter than any other operator, so `2^3!` is
+        //  `2^(3!)` and `1 + L[2]` is `1 + (L[2])`
+        let postfix = atom
+            .then(
+                just(Token::OpFactorial)
+                    .to(Postfix::Factorial)
+                    .or(index.map(Postfix::Index))
+                    .map_with_span(|op, s: types::Span| (s, op))
+                    .repeated(),
+            )
+            .foldl(|v: ast::LocatedExpression, (op_span, op)| {
+                let s = v.0.with_end_of(&op_span).unwrap_or_else(|| v.0.clone());
+                match op {
+                    Postfix::Factorial => (
+                        s,
+                        ast::Expression::UnaryExpr {
+                            val: Box::new(v),
+                            operator: ast::UnaryOperator::Factorial,
+                        },
+                    ),
+                    Postfix::Index(ind) => (
+                        s,
+                        ast::Expression::Index {
+                            val: Box::new(v),
+                            ind: Box::new(ind),
+                        },
+                    ),
+                }
+            });
+
+        let negate = recursive(|negate| {
+            let power = postfix
+                .clone()
+                .then(
+                    just(Token::OpExp)
+                        .map_with_span(|_, s: types::Span| s)
+                        .then(negate.or_not())
+                        // recover from a dangling operator (`2^`)
+                        .validate(|(op_span, r), _, emit| {
+                            r.unwrap_or_else(|| {
+                                emit(Simple::custom(
+                                    op_span.clone(),
+                                    format!(
+                                        "expected expression after {}",
+                                        binop_str(ast::BinaryOperator::Exponent)
+                                    ),
+                                ));
+                                (op_span, ast::Expression::Error)
+                            })
+                        })
+                        .or_not(),
+                )
+                .map(|(l, r): (ast::LocatedExpression, _)| match r {
+                    Some(r) => (
+                        l.0.with_end_of(&r.0).unwrap_or_else(|| l.0.clone()),
+                        ast::Expression::BinaryExpr {
+                            left: Box::new(l),
+                            operator: ast::BinaryOperator::Exponent,
+                            right: Box::new(r),
+                        },
+                    ),
+                    None => l,
+                });
+
+            // A number or parenthesized group followed by a name or `(` is implicitly
+            //  multiplied (`2x^2`, `3(x+1)`), binding tighter than `*` and `/`.
+            //  Names can't be multiplied this way since `f(x)` is a call.
+            let implicit_mult = filter(|t: &Token| matches!(t, Token::Num(_) | Token::CtrlLParen))
+                .rewind()
+                .ignore_then(
+                    power.clone().then(
+                        filter(|t: &Token| matches!(t, Token::Ident(_) | Token::CtrlLParen))
+                            .rewind()
+                            .ignore_then(power.clone())
+                            .repeated(),
+                    ),
                 )
-            })
-            .or(atom.clone());
+                .foldl(|l, r| {
+                    (
+                        l.0.with_end_of(&r.0).unwrap_or_else(|| l.0.clone()),
+                        ast::Expression::BinaryExpr {
+                            left: Box::new(l),
+                            operator: ast::BinaryOperator::Multiply,
+                            right: Box::new(r),
+                        },
+                    )
+                })
+                .or(power);
+
+            just(Token::OpMinus)
+                .to(Some(ast::UnaryOperator::Negate))
+                .or(just(Token::OpPlus).to(None))
+                .map_with_span(|op, s: types::Span| (s, op))
+                .repeated()
+                .then(implicit_mult)
+                .foldr(|(op_span, op), v| {
+                    let s = op_span.with_end_of(&v.0).unwrap_or(op_span);
+                    match op {
+                        Some(operator) => (
+                            s,
+                            ast::Expression::UnaryExpr {
+                                val: Box::new(v),
+                                operator,
+                            },
+                        ),
+                        None => (s, v.1),
+                    }
+                })
+        });
 
         let map = just(Token::CtrlMap)
             .ignore_then(negate.clone())
@@ -265,10 +650,25 @@ fn expr_parser() -> impl Parser<Token, ast::LocatedExpression, Error = ParseErr>
             ($prev:expr, $op:expr) => {
                 $prev
                     .clone()
-                    .then($op.then($prev).repeated())
+                    .then(
+                        $op.map_with_span(|op, s: types::Span| (s, op))
+                            .then($prev.or_not())
+                            // recover from a dangling operator (`1 +`)
+                            .validate(|((op_span, op), r), _, emit| {
+                                let r = r.unwrap_or_else(|| {
+                                    emit(Simple::custom(
+                                        op_span.clone(),
+                                        format!("expected expression after {}", binop_str(op)),
+                                    ));
+                                    (op_span, ast::Expression::Error)
+                                });
+                                (op, r)
+                            })
+                            .repeated(),
+                    )
                     .foldl(|l, (op, r)| {
                         (
-                            l.0.with_end_of(&r.0).expect("Parsing the same file"),
+                            l.0.with_end_of(&r.0).unwrap_or_else(|| l.0.clone()),
                             ast::Expression::BinaryExpr {
                                 left: Box::new(l),
                                 operator: op,
@@ -279,10 +679,8 @@ fn expr_parser() -> impl Parser<Token, ast::LocatedExpression, Error = ParseErr>
             };
         }
 
-        let exponent = binop!(map, just(Token::OpExp).to(ast::BinaryOperator::Exponent));
-
         let product = binop!(
-            exponent,
+            map,
             just(Token::OpMult)
                 .to(ast::BinaryOperator::Multiply)
                 .or(just(Token::OpDiv).to(ast::BinaryOperator::Divide))
@@ -296,51 +694,65 @@ fn expr_parser() -> impl Parser<Token, ast::LocatedExpression, Error = ParseErr>
                 .or(just(Token::OpMinus).to(ast::BinaryOperator::Subtract))
         );
 
-        let ind = sum
-            .clone()
-            .then(
-                just(Token::CtrlLBrac)
-                    .ignore_then(sum)
-                    .then_ignore(just(Token::CtrlRBrac))
-                    .map(Option::Some)
-                    .or(empty().to(Option::None)),
-            )
-            .map_with_span(|(sum, maybe_ind), s| match maybe_ind {
-                Some(ind) => (
-                    s,
-                    ast::Expression::Index {
-                        val: Box::new(sum),
-                        ind: Box::new(ind),
-                    },
-                ),
-                None => sum,
-            });
-
         let cond_op = just(Token::OpCmpLt)
             .to(types::CompareOperator::LessThan)
             .or(just(Token::OpCmpLe).to(types::CompareOperator::LessThanEqual))
             .or(just(Token::OpCmpGt).to(types::CompareOperator::GreaterThan))
-            .or(just(Token::OpCmpGt).to(types::CompareOperator::GreaterThanEqual))
-            .or(just(Token::OpCmpEq).to(types::CompareOperator::Equal));
-        let cond = expr
+            .or(just(Token::OpCmpGe).to(types::CompareOperator::GreaterThanEqual))
+            .or(just(Token::OpCmpEq).to(types::CompareOperator::Equal))
+            .or(just(Token::OpCmpNe).to(types::CompareOperator::NotEqual));
+        // comparisons produce booleans, which can be used anywhere but are
+        //  mostly used as piecewise conditions. Like Desmos, two can be chained into a
+        //  double inequality such as `0 < x <= 1`.
+        let comparison =
+            sum.clone()
+                .then(cond_op.then(sum).repeated())
+                .try_map(|(l, mut rest), s| match rest.len() {
+                    0 => Ok(l),
+                    1 => {
+                        let (op, r) = rest.remove(0);
+                        Ok((
+                            s,
+                            ast::Expression::Comparison {
+                                left: Box::new(l),
+                                op,
+                                right: Box::new(r),
+                            },
+                        ))
+                    }
+                    2 => {
+                        let (right_op, r) = rest.remove(1);
+                        let (left_op, m) = rest.remove(0);
+                        use types::CompareOperator::*;
+                        let direction = |op| match op {
+                            LessThan | LessThanEqual => Some(true),
+                            GreaterThan | GreaterThanEqual => Some(false),
+                            Equal | NotEqual => None,
+                        };
+                        match (direction(left_op), direction(right_op)) {
+                            (Some(a), Some(b)) if a == b => Ok((
+                                s,
+                                ast::Expression::DoubleComparison {
+                                    left: Box::new(l),
+                                    left_op,
+                                    middle: Box::new(m),
+                                    right_op,
+                                    right: Box::new(r),
+                                },
+                            )),
+                            _ => Err(Simple::custom(
+                                s,
+                                "A double inequality must use `<` and `<=` or `>` and `>=`",
+                            )),
+                        }
+                    }
+                    _ => Err(Simple::custom(s, "At most two comparisons can be chained")),
+                });
+        let branch = expr
             .clone()
-            .then(cond_op)
-            .then(expr.clone())
-            .map(|((l, op), r)| (l, op, r));
-        let branch = cond
             .then_ignore(just(Token::CtrlThen))
             .then(expr.clone())
-            .map_with_span(|((cond_left, cond, cond_right), val), s| {
-                (
-                    s,
-                    ast::Branch {
-                        cond_left,
-                        cond,
-                        cond_right,
-                        val,
-                    },
-                )
-            });
+            .map_with_span(|(cond, val), s| (s, ast::Branch { cond, val }));
         let else_branch = just(Token::KeywordElse).ignore_then(expr);
         let where_block = just(Token::KeywordWhere)
             .ignore_then(branch.clone())
@@ -362,11 +774,11 @@ fn expr_parser() -> impl Parser<Token, ast::LocatedExpression, Error = ParseErr>
         };
         let latex = just(Token::KeywordLatex)
             .to(types::ValType::Number)
-            .or(just(Token::KeywordLatexList).to(types::ValType::List))
+            .or(just(Token::KeywordLatexList).to(types::ValType::List(types::ElemType::Number)))
             .then(p_str)
             .map_with_span(|(ty, l), s| (s, ast::Expression::RawLatex(ty, l)));
 
-        where_block.or(ind).or(latex)
+        choice((where_block, comparison, latex))
     })
 }
 
@@ -381,16 +793,13 @@ fn statement_parser() -> impl Parser<Token, Vec<ast::Spanned<ast::Statement>>, E
     };
     let type_annotation = just(Token::OpColon)
         .ignore_then(ident.clone())
-        .try_map(|typ, span| match typ.as_str() {
-            "num" => Ok(types::ValType::Number),
-            "list" => Ok(types::ValType::List),
-            _ => Err(Simple::custom(
-                span,
-                format!("Invalid type '{}', expected 'num' or 'list'", typ),
-            )),
-        });
+        .try_map(val_type);
     let arg = ident
-        .then(type_annotation.or(empty().to(types::ValType::Number)))
+        .then(
+            type_annotation
+                .clone()
+                .or(empty().to(types::ValType::Number)),
+        )
         .map_with_span(|(name, ty), s| (s, name, ty));
     let inline = just(Token::KeywordInline).or_not().map(|t| t.is_some());
     let func_dec = inline
@@ -400,16 +809,17 @@ fn statement_parser() -> impl Parser<Token, Vec<ast::Spanned<ast::Statement>>, E
             arg.separated_by(just(Token::CtrlComma))
                 .delimited_by(just(Token::CtrlLParen), just(Token::CtrlRParen)),
         )
+        .then(type_annotation.or_not())
         .then_ignore(just(Token::OpEq))
         .then(expr.clone())
-        .map_with_span(|(((inline, name), args), expr), s| {
+        .map_with_span(|((((inline, name), args), ret_annotation), expr), s| {
             (
                 s,
                 ast::Statement::FuncDef(
                     ast::FunctionDefinition {
                         name,
                         args,
-                        ret_annotation: None,
+                        ret_annotation,
                         inline,
                     },
                     expr,
@@ -419,7 +829,7 @@ fn statement_parser() -> impl Parser<Token, Vec<ast::Spanned<ast::Statement>>, E
     let declaration = inline
         .then(ident)
         .then_ignore(just(Token::OpEq))
-        .then(expr)
+        .then(expr.clone())
         .map_with_span(|((inline, name), val), s| {
             (s, ast::Statement::VarDef { name, val, inline })
         });
@@ -452,20 +862,108 @@ fn statement_parser() -> impl Parser<Token, Vec<ast::Spanned<ast::Statement>>, E
             )
         });
 
-    let line = import
-        .or(include)
-        .or(func_dec)
-        .or(declaration)
-        .or(expr_stmt);
+    let directive = |expected: &'static str| {
+        just(Token::CtrlMap).ignore_then(ident.try_map(move |name, span| {
+            if name == expected {
+                Ok(())
+            } else {
+                Err(Simple::custom(
+                    span,
+                    format!("Unknown directive '@{}'", name),
+                ))
+            }
+        }))
+    };
+    let note = directive("note")
+        .ignore_then(p_str.delimited_by(just(Token::CtrlLParen), just(Token::CtrlRParen)))
+        .map_with_span(|text, s| (s, ast::Statement::Note(text)));
+    let ticker = directive("ticker")
+        .ignore_then(
+            expr.clone()
+                .then(just(Token::CtrlComma).ignore_then(expr.clone()).or_not())
+                .delimited_by(just(Token::CtrlLParen), just(Token::CtrlRParen)),
+        )
+        .map_with_span(|(handler, min_step), s| (s, ast::Statement::Ticker { handler, min_step }));
+
+    let style_value = select! {
+        Token::Str(s) => s,
+        Token::Ident(s) => s,
+    };
+    let style = ident
+        .then_ignore(just(Token::OpColon))
+        .then(style_value)
+        .separated_by(just(Token::CtrlComma))
+        .allow_trailing()
+        .delimited_by(just(Token::CtrlLBrace), just(Token::CtrlRBrace))
+        .try_map(|entries, span| {
+            let mut style = types::Style::default();
+            for (key, val) in entries {
+                match (key.as_str(), val.as_str()) {
+                    ("color", _) => style.color = Some(val),
+                    ("hidden", "true") => style.hidden = Some(true),
+                    ("hidden", "false") => style.hidden = Some(false),
+                    ("hidden", _) => {
+                        return Err(Simple::custom(span, "`hidden` must be true or false"))
+                    }
+                    _ => {
+                        return Err(Simple::custom(
+                            span,
+                            format!("Unknown style property '{}'", key),
+                        ))
+                    }
+                }
+            }
+            Ok(style)
+        });
+
+    let separator = just(Token::CtrlSemi).or(just(Token::Newline));
+    // any number of separators may appear between statements
+    let separators = separator.clone().repeated();
+    let line = recursive(|line| {
+        let with = just(Token::KeywordWith)
+            .ignore_then(style)
+            .then(
+                separators
+                    .clone()
+                    .ignore_then(
+                        // don't let a statement's error recovery swallow the closing brace
+                        none_of([Token::CtrlRBrace])
+                            .rewind()
+                            .ignore_then(line)
+                            .then_ignore(separators.clone())
+                            .repeated(),
+                    )
+                    .delimited_by(just(Token::CtrlLBrace), just(Token::CtrlRBrace)),
+            )
+            .map_with_span(|(style, body), s| (s, ast::Statement::With(style, body)));
+
+        // A statement must be followed by a separator, otherwise the whole statement is an
+        //  error and parsing resumes at the next one.
+        import
+            .or(include)
+            .or(note)
+            .or(ticker)
+            .or(with)
+            .or(func_dec)
+            .or(declaration)
+            .or(expr_stmt)
+            .then_ignore(
+                separator
+                    .clone()
+                    .ignored()
+                    .or(just(Token::CtrlRBrace).ignored())
+                    .or(end())
+                    .rewind(),
+            )
+            .recover_with(skip_until([Token::CtrlSemi, Token::Newline], |span| {
+                (span, ast::Statement::Expression(ast::Expression::Error))
+            }))
+    });
 
-    line.clone()
-        .then(just(Token::CtrlSemi).ignore_then(line.or_not()).repeated())
+    separators
+        .clone()
+        .ignore_then(line.then_ignore(separators).repeated())
         .then_ignore(end())
-        .map(|(first, rest)| {
-            std::iter::once(first)
-                .chain(rest.into_iter().flatten())
-                .collect()
-        })
 }
 
 pub type LexErrors = Vec<LexErr>;
@@ -474,22 +972,197 @@ pub type LexResult = (Option<Tokens>, LexErrors);
 pub type ParseErrors = Vec<ParseErr>;
 pub type ParseResult = (Option<ast::LStatements>, ParseErrors);
 
+// Spans count chars rather than bytes, editors convert them to their own positions (see
+//  lsp::offset_to_position)
 pub fn lex(source: types::FileID, input: String) -> LexResult {
+    let end = input.chars().count();
     let s: chumsky::Stream<'_, char, types::Span, _> = chumsky::Stream::from_iter(
-        types::Span::new(source, input.len()..input.len() + 1),
+        types::Span::new(source, end..end + 1),
         input
             .chars()
             .enumerate()
             .map(|(i, x)| (x, types::Span::new(source, i..i + 1))),
     );
-    lexer().parse_recovery(s)
+    let (tokens, errs) = lexer().parse_recovery(s);
+    (tokens.map(end_statements), errs)
+}
+
+// A newline ends a statement only if it could end there, like Go's automatic semicolons.
+//  Everywhere else (inside brackets, after an operator or `where`) it is dropped so that
+//  expressions can span multiple lines.
+fn end_statements(tokens: Tokens) -> Tokens {
+    let mut depth: usize = 0;
+    let mut can_end = false;
+    let mut out = Vec::with_capacity(tokens.len());
+    for (span, t) in tokens {
+        match t {
+            Token::Newline => {
+                if depth == 0 && can_end {
+                    can_end = false;
+                    out.push((span, t));
+                }
+                continue;
+            }
+            Token::CtrlLParen | Token::CtrlLBrac => depth += 1,
+            Token::CtrlRParen | Token::CtrlRBrac => depth = depth.saturating_sub(1),
+            // so that the last entry of a multiline style can be followed by the closing brace
+            Token::CtrlRBrace if matches!(out.last(), Some((_, Token::Newline))) => {
+                out.pop();
+            }
+            _ => (),
+        }
+        can_end = matches!(
+            t,
+            Token::Num(_)
+                | Token::Str(_)
+                | Token::Ident(_)
+                | Token::CtrlRParen
+                | Token::CtrlRBrac
+                | Token::CtrlRBrace
+                | Token::OpFactorial
+        );
+        out.push((span, t));
+    }
+    out
+}
+
+// The parser and compiler are recursive, so deeply nested input can overflow the stack.
+//  Parsing runs on its own thread with a stack big enough for this many levels, even in
+//  debug builds. Chains like `1+1+...` are folded in a loop so their length isn't limited.
+pub const MAX_NESTING_DEPTH: usize = 2000;
+#[cfg(not(target_arch = "wasm32"))]
+const LARGE_STACK_SIZE: usize = 1024 * 1024 * 1024;
+
+// A bracketed group or `where` block while checking nesting
+struct NestFrame {
+    // a `where` block has no closing token, it ends with the group around it
+    delimited: bool,
+    // `^` is right associative, so each one in a chain nests the parser a level deeper
+    powers: usize,
+}
+
+// Only counts what the parser recurses into: brackets, `where` blocks and `^` chains
+fn check_nesting(tokens: &Tokens) -> Result<(), ParseErr> {
+    fn close_where(frames: &mut Vec<NestFrame>) {
+        while frames.len() > 1 && !frames.last().unwrap().delimited {
+            frames.pop();
+        }
+    }
+
+    let mut frames = vec![NestFrame {
+        delimited: true,
+        powers: 0,
+    }];
+    // `with` blocks span statements, so they aren't reset by separators
+    let mut blocks: usize = 0;
+    let mut prev: Option<&Token> = None;
+    for (span, t) in tokens {
+        // `-` after an operand ends the `^` chain before it, after an operator it is a prefix
+        let after_operand = matches!(
+            prev,
+            Some(
+                Token::Num(_)
+                    | Token::Ident(_)
+                    | Token::Str(_)
+                    | Token::CtrlRParen
+                    | Token::CtrlRBrac
+                    | Token::OpFactorial
+            )
+        );
+        match t {
+            Token::CtrlLParen | Token::CtrlLBrac => frames.push(NestFrame {
+                delimited: true,
+                powers: 0,
+            }),
+            Token::KeywordWhere => frames.push(NestFrame {
+                delimited: false,
+                powers: 0,
+            }),
+            Token::CtrlRParen | Token::CtrlRBrac => {
+                close_where(&mut frames);
+                if frames.len() > 1 {
+                    frames.pop();
+                }
+            }
+            Token::CtrlLBrace => blocks += 1,
+            Token::CtrlRBrace => {
+                close_where(&mut frames);
+                blocks = blocks.saturating_sub(1);
+            }
+            Token::CtrlSemi | Token::Newline => {
+                frames.truncate(1);
+                frames[0].powers = 0;
+            }
+            Token::OpExp => frames.last_mut().unwrap().powers += 1,
+            Token::OpMinus | Token::OpPlus if !after_operand => (),
+            Token::OpMinus
+            | Token::OpPlus
+            | Token::OpMult
+            | Token::OpDiv
+            | Token::OpMod
+            | Token::OpCmpLt
+            | Token::OpCmpLe
+            | Token::OpCmpGt
+            | Token::OpCmpGe
+            | Token::OpCmpEq
+            | Token::OpCmpNe
+            | Token::OpEq
+            | Token::CtrlComma
+            | Token::CtrlThen
+            | Token::CtrlEllipses
+            | Token::KeywordElse
+            | Token::KeywordFor => frames.last_mut().unwrap().powers = 0,
+            _ => (),
+        }
+        let depth = frames.len() - 1 + blocks + frames.iter().map(|f| f.powers).sum::<usize>();
+        if depth > MAX_NESTING_DEPTH {
+            return Err(Simple::custom(
+                span.clone(),
+                format!("Nesting deeper than {} levels", MAX_NESTING_DEPTH),
+            ));
+        }
+        prev = Some(t);
+    }
+    Ok(())
+}
+
+// Runs f on a thread with a stack big enough for the deepest input the parser accepts, and
+//  for compiling it. The stack is only reserved address space, pages are committed as they
+//  are touched.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn with_large_stack<T: Send>(f: impl FnOnce() -> T + Send) -> T {
+    std::thread::scope(|scope| {
+        match std::thread::Builder::new()
+            .name("large stack".to_string())
+            .stack_size(LARGE_STACK_SIZE)
+            .spawn_scoped(scope, f)
+            .expect("failed to spawn large stack thread")
+            .join()
+        {
+            Ok(r) => r,
+            Err(e) => std::panic::resume_unwind(e),
+        }
+    })
+}
+
+// wasm has no threads, so the nesting limit is all that guards the stack there
+#[cfg(target_arch = "wasm32")]
+pub fn with_large_stack<T>(f: impl FnOnce() -> T) -> T {
+    f()
 }
 
 pub fn parse(source: types::FileID, tokens: Vec<ast::Spanned<Token>>) -> ParseResult {
-    statement_parser().parse_recovery(chumsky::Stream::from_iter(
-        types::Span::new(source, tokens.len()..tokens.len() + 1),
-        tokens.into_iter().map(|(s, t)| (t, s)),
-    ))
+    if let Err(e) = check_nesting(&tokens) {
+        return (None, vec![e]);
+    }
+    with_large_stack(move || {
+        // the end of input is just after the last token, in chars like every other span
+        let end = tokens.last().map_or(0, |(s, _)| s.range.end);
+        statement_parser().parse_recovery(chumsky::Stream::from_iter(
+            types::Span::new(source, end..end + 1),
+            tokens.into_iter().map(|(s, t)| (t, s)),
+        ))
+    })
 }
 
 #[derive(Clone, Debug, PartialEq, Default)]
@@ -509,6 +1182,61 @@ impl LexParseErrors {
     pub fn is_empty(&self) -> bool {
         self.lex_errors.is_empty() && self.parse_errors.is_empty()
     }
+
+    // One message per error such as "expected `)` but found `+`", sorted by position
+    pub fn messages(&self) -> Vec<(types::Span, String)> {
+        let mut messages = self
+            .lex_errors
+            .iter()
+            .map(|e| error_message(e, |c| format!("`{}`", c.escape_default())))
+            .chain(
+                self.parse_errors
+                    .iter()
+                    .map(|e| error_message(e, |t| t.clone().to_str().to_string())),
+            )
+            .collect::<Vec<_>>();
+        messages.sort_by_key(|(span, _)| (span.file_id, span.range.start));
+        messages
+    }
+}
+
+fn error_message<I: std::hash::Hash + Eq>(
+    e: &Simple<I, types::Span>,
+    fmt: impl Fn(&I) -> String,
+) -> (types::Span, String) {
+    let found = match e.found() {
+        Some(found) => fmt(found),
+        None => "end of input".to_string(),
+    };
+    let message = match e.reason() {
+        chumsky::error::SimpleReason::Custom(msg) => msg.clone(),
+        chumsky::error::SimpleReason::Unclosed { delimiter, .. } => {
+            format!("unclosed delimiter {}, found {}", fmt(delimiter), found)
+        }
+        chumsky::error::SimpleReason::Unexpected => {
+            // expected is a set, so sort it to keep messages stable
+            let mut expected = e
+                .expected()
+                .map(|t| match t {
+                    Some(t) => fmt(t),
+                    None => "end of input".to_string(),
+                })
+                .collect::<Vec<_>>();
+            expected.sort();
+            expected.dedup();
+            match expected.split_last() {
+                None => format!("unexpected {}", found),
+                Some((last, [])) => format!("expected {} but found {}", last, found),
+                Some((last, rest)) => format!(
+                    "expected {} or {} but found {}",
+                    rest.join(", "),
+                    last,
+                    found
+                ),
+            }
+        }
+    };
+    (e.span(), message)
 }
 
 impl From<LexErrors> for LexParseErrors {
@@ -541,6 +1269,14 @@ pub fn lex_and_parse(source: types::FileID, input: String) -> LexParseResult {
     (ast, errs.into())
 }
 
+// Entry point for fuzzing: accepts arbitrary bytes and never panics
+pub fn parse_fuzz_safe(input: &[u8]) -> Result<ast::LStatements, LexParseErrors> {
+    match lex_and_parse(0, String::from_utf8_lossy(input).into_owned()) {
+        (Some(ast), errs) if errs.is_empty() => Ok(ast),
+        (_, errs) => Err(errs),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -611,118 +1347,945 @@ mod tests {
     }
 
     #[test]
-    fn precedence() {
+    fn subtract_negative() {
         check(
-            "1*2 - 3/4 + 5%6;",
+            "1 - -2;",
             (
-                s(0..15),
+                s(0..6),
                 ast::Expression::BinaryExpr {
-                    left: Box::new((
-                        s(0..9),
-                        ast::Expression::BinaryExpr {
-                            left: Box::new((
-                                s(0..3),
-                                ast::Expression::BinaryExpr {
-                                    left: Box::new((s(0..1), num("1"))),
-                                    operator: ast::BinaryOperator::Multiply,
-                                    right: Box::new((s(2..3), num("2"))),
-                                },
-                            )),
-                            operator: ast::BinaryOperator::Subtract,
-                            right: Box::new((
-                                s(6..9),
-                                ast::Expression::BinaryExpr {
-                                    left: Box::new((s(6..7), num("3"))),
-                                    operator: ast::BinaryOperator::Divide,
-                                    right: Box::new((s(8..9), num("4"))),
-                                },
-                            )),
-                        },
-                    )),
-                    operator: ast::BinaryOperator::Add,
+                    left: Box::new((s(0..1), num("1"))),
+                    operator: ast::BinaryOperator::Subtract,
                     right: Box::new((
-                        s(12..15),
-                        ast::Expression::BinaryExpr {
-                            left: Box::new((s(12..13), num("5"))),
-                            operator: ast::BinaryOperator::Mod,
-                            right: Box::new((s(14..15), num("6"))),
+                        s(4..6),
+                        ast::Expression::UnaryExpr {
+                            val: Box::new((s(5..6), num("2"))),
+                            operator: ast::UnaryOperator::Negate,
                         },
                     )),
                 },
             ),
-        )
+        );
     }
 
     #[test]
-    fn variable() {
-        check("a;", (s(0..1), var("a")));
-        check("_1;", (s(0..2), var("_1")));
+    fn negate_parens() {
+        check(
+            "-(1+2);",
+            (
+                s(0..6),
+                ast::Expression::UnaryExpr {
+                    val: Box::new((
+                        s(1..6),
+                        ast::Expression::BinaryExpr {
+                            left: Box::new((s(2..3), num("1"))),
+                            operator: ast::BinaryOperator::Add,
+                            right: Box::new((s(4..5), num("2"))),
+                        },
+                    )),
+                    operator: ast::UnaryOperator::Negate,
+                },
+            ),
+        );
     }
 
     #[test]
-    fn call() {
-        assert_parses("a() = 1;");
+    fn double_negate() {
         check(
-            "_a1(1+2, 3*4);",
+            "- -1;",
             (
-                s(0..13),
-                ast::Expression::Call {
-                    func: ast::Function::Normal {
-                        name: "_a1".to_string(),
-                    },
-                    args: vec![
-                        (
-                            s(4..7),
-                            ast::Expression::BinaryExpr {
-                                left: Box::new((s(4..5), num("1"))),
-                                operator: ast::BinaryOperator::Add,
-                                right: Box::new((s(6..7), num("2"))),
-                            },
-                        ),
-                        (
-                            s(9..12),
-                            ast::Expression::BinaryExpr {
-                                left: Box::new((s(9..10), num("3"))),
-                                operator: ast::BinaryOperator::Multiply,
-                                right: Box::new((s(11..12), num("4"))),
-                            },
-                        ),
-                    ],
+                s(0..4),
+                ast::Expression::UnaryExpr {
+                    val: Box::new((
+                        s(2..4),
+                        ast::Expression::UnaryExpr {
+                            val: Box::new((s(3..4), num("1"))),
+                            operator: ast::UnaryOperator::Negate,
+                        },
+                    )),
+                    operator: ast::UnaryOperator::Negate,
+                },
+            ),
+        );
+    }
+
+    fn pow(l: ast::LocatedExpression, r: ast::LocatedExpression) -> ast::Expression {
+        ast::Expression::BinaryExpr {
+            left: Box::new(l),
+            operator: ast::BinaryOperator::Exponent,
+            right: Box::new(r),
+        }
+    }
+
+    #[test]
+    fn exponent() {
+        check(
+            "2^3;",
+            (s(0..3), pow((s(0..1), num("2")), (s(2..3), num("3")))),
+        );
+        check(
+            "2**3;",
+            (s(0..4), pow((s(0..1), num("2")), (s(3..4), num("3")))),
+        );
+    }
+
+    #[test]
+    fn exponent_negate() {
+        check(
+            "-2^2;",
+            (
+                s(0..4),
+                ast::Expression::UnaryExpr {
+                    val: Box::new((s(1..4), pow((s(1..2), num("2")), (s(3..4), num("2"))))),
+                    operator: ast::UnaryOperator::Negate,
+                },
+            ),
+        );
+        check(
+            "2^-1;",
+            (
+                s(0..4),
+                pow(
+                    (s(0..1), num("2")),
+                    (
+                        s(2..4),
+                        ast::Expression::UnaryExpr {
+                            val: Box::new((s(3..4), num("1"))),
+                            operator: ast::UnaryOperator::Negate,
+                        },
+                    ),
+                ),
+            ),
+        );
+    }
+
+    #[test]
+    fn exponent_right_assoc() {
+        check(
+            "2^2^3;",
+            (
+                s(0..5),
+                pow(
+                    (s(0..1), num("2")),
+                    (s(2..5), pow((s(2..3), num("2")), (s(4..5), num("3")))),
+                ),
+            ),
+        );
+    }
+
+    #[test]
+    fn exponent_precedence() {
+        check(
+            "2*3^2;",
+            (
+                s(0..5),
+                ast::Expression::BinaryExpr {
+                    left: Box::new((s(0..1), num("2"))),
+                    operator: ast::BinaryOperator::Multiply,
+                    right: Box::new((s(2..5), pow((s(2..3), num("3")), (s(4..5), num("2"))))),
+                },
+            ),
+        );
+    }
+
+    #[test]
+    fn digit_separators() {
+        check("1_000;", (s(0..5), num("1000")));
+        check("1_2_3;", (s(0..5), num("123")));
+        assert_does_not_parse("_1;");
+        assert_does_not_parse("1__0;");
+        assert_does_not_parse("1_;");
+        assert_parses("_a;");
+    }
+
+    #[test]
+    fn decimals() {
+        check("3.14;", (s(0..4), num("3.14")));
+        check(".5;", (s(0..2), num(".5")));
+        check("1_000.000_1;", (s(0..11), num("1000.0001")));
+        // a leading `-` is the unary operator
+        check(
+            "-2;",
+            (
+                s(0..2),
+                ast::Expression::UnaryExpr {
+                    val: Box::new((s(1..2), num("2"))),
+                    operator: ast::UnaryOperator::Negate,
+                },
+            ),
+        );
+        // the dots of a range aren't a decimal point
+        check(
+            "[1...5];",
+            (
+                s(0..7),
+                ast::Expression::Range {
+                    first: Box::new((s(1..2), num("1"))),
+                    second: None,
+                    end: Box::new((s(5..6), num("5"))),
+                },
+            ),
+        );
+        assert_does_not_parse("1.;");
+        assert_does_not_parse("1.2.3;");
+    }
+
+    #[test]
+    fn scientific_notation() {
+        check("1e3;", (s(0..3), num("1000")));
+        check("1.5e-3;", (s(0..6), num("0.0015")));
+        check("2.50e+1;", (s(0..7), num("25")));
+        check(".5e1;", (s(0..4), num("5")));
+        check("12e-1;", (s(0..5), num("1.2")));
+        check("0e5;", (s(0..3), num("0")));
+        check("1e20;", (s(0..4), num("100000000000000000000")));
+        // long exponents are kept for the compiler to turn into a power of ten
+        check("1e400;", (s(0..5), num("1e400")));
+        check("1.5e-021;", (s(0..8), num("1.5e-21")));
+        // without an exponent `e` is the constant
+        check(
+            "2e;",
+            (
+                s(0..2),
+                ast::Expression::BinaryExpr {
+                    left: Box::new((s(0..1), num("2"))),
+                    operator: ast::BinaryOperator::Multiply,
+                    right: Box::new((s(1..2), var("e"))),
+                },
+            ),
+        );
+        assert_does_not_parse("1e10000;");
+    }
+
+    fn fact(v: ast::LocatedExpression) -> ast::Expression {
+        ast::Expression::UnaryExpr {
+            val: Box::new(v),
+            operator: ast::UnaryOperator::Factorial,
+        }
+    }
+
+    #[test]
+    fn factorial() {
+        check("5!;", (s(0..2), fact((s(0..1), num("5")))));
+        check(
+            "2 + 3!;",
+            (
+                s(0..6),
+                ast::Expression::BinaryExpr {
+                    left: Box::new((s(0..1), num("2"))),
+                    operator: ast::BinaryOperator::Add,
+                    right: Box::new((s(4..6), fact((s(4..5), num("3"))))),
+                },
+            ),
+        );
+        check(
+            "3!!;",
+            (s(0..3), fact((s(0..2), fact((s(0..1), num("3")))))),
+        );
+        check(
+            "-3!;",
+            (
+                s(0..3),
+                ast::Expression::UnaryExpr {
+                    val: Box::new((s(1..3), fact((s(1..2), num("3"))))),
+                    operator: ast::UnaryOperator::Negate,
+                },
+            ),
+        );
+        check(
+            "2^3!;",
+            (
+                s(0..4),
+                pow((s(0..1), num("2")), (s(2..4), fact((s(2..3), num("3"))))),
+            ),
+        );
+    }
+
+    #[test]
+    fn unary_plus() {
+        check("+1;", (s(0..2), num("1")));
+        check(
+            "1 + +2;",
+            (
+                s(0..6),
+                ast::Expression::BinaryExpr {
+                    left: Box::new((s(0..1), num("1"))),
+                    operator: ast::BinaryOperator::Add,
+                    right: Box::new((s(4..6), num("2"))),
+                },
+            ),
+        );
+    }
+
+    #[test]
+    fn trailing_operator() {
+        let (ast, errs) = eval("1 +; 2;");
+        assert_eq!(
+            ast,
+            Some(vec![
+                (
+                    s(0..3),
+                    ast::Statement::Expression(ast::Expression::BinaryExpr {
+                        left: Box::new((s(0..1), num("1"))),
+                        operator: ast::BinaryOperator::Add,
+                        right: Box::new((s(2..3), ast::Expression::Error)),
+                    }),
+                ),
+                (s(5..6), ast::Statement::Expression(num("2"))),
+            ])
+        );
+        assert!(errs.lex_errors.is_empty());
+        assert_eq!(errs.parse_errors.len(), 1);
+        let err = &errs.parse_errors[0];
+        assert_eq!(err.span(), s(2..3));
+        assert_eq!(
+            err.reason(),
+            &chumsky::error::SimpleReason::Custom("expected expression after `+`".to_string())
+        );
+    }
+
+    #[test]
+    fn precedence() {
+        check(
+            "1*2 - 3/4 + 5%6;",
+            (
+                s(0..15),
+                ast::Expression::BinaryExpr {
+                    left: Box::new((
+                        s(0..9),
+                        ast::Expression::BinaryExpr {
+                            left: Box::new((
+                                s(0..3),
+                                ast::Expression::BinaryExpr {
+                                    left: Box::new((s(0..1), num("1"))),
+                                    operator: ast::BinaryOperator::Multiply,
+                                    right: Box::new((s(2..3), num("2"))),
+                                },
+                            )),
+                            operator: ast::BinaryOperator::Subtract,
+                            right: Box::new((
+                                s(6..9),
+                                ast::Expression::BinaryExpr {
+                                    left: Box::new((s(6..7), num("3"))),
+                                    operator: ast::BinaryOperator::Divide,
+                                    right: Box::new((s(8..9), num("4"))),
+                                },
+                            )),
+                        },
+                    )),
+                    operator: ast::BinaryOperator::Add,
+                    right: Box::new((
+                        s(12..15),
+                        ast::Expression::BinaryExpr {
+                            left: Box::new((s(12..13), num("5"))),
+                            operator: ast::BinaryOperator::Mod,
+                            right: Box::new((s(14..15), num("6"))),
+                        },
+                    )),
+                },
+            ),
+        )
+    }
+
+    #[test]
+    fn note() {
+        check_stmt(
+            "@note(\"hello world\");",
+            (s(0..20), ast::Statement::Note("hello world".to_string())),
+        );
+    }
+
+    #[test]
+    fn ticker() {
+        check_stmt(
+            "@ticker(f(), 100);",
+            (
+                s(0..17),
+                ast::Statement::Ticker {
+                    handler: (
+                        s(8..11),
+                        ast::Expression::Call {
+                            func: ast::Function::Normal {
+                                name: "f".to_string(),
+                            },
+                            args: vec![],
+                        },
+                    ),
+                    min_step: Some((s(13..16), num("100"))),
+                },
+            ),
+        );
+        assert_parses("@ticker(a + 1);");
+    }
+
+    #[test]
+    fn with_block() {
+        check_stmt(
+            "with {color: \"#f00\", hidden: true} {a; b}",
+            (
+                s(0..41),
+                ast::Statement::With(
+                    types::Style {
+                        color: Some("#f00".to_string()),
+                        hidden: Some(true),
+                    },
+                    vec![
+                        (s(36..37), ast::Statement::Expression(var("a"))),
+                        (s(39..40), ast::Statement::Expression(var("b"))),
+                    ],
+                ),
+            ),
+        );
+        assert_parses(
+            "with {\n  color: \"#f00\",\n  hidden: false\n} {\n  a\n  with {} {\n    b\n  }\n}\nc",
+        );
+        assert_does_not_parse("with {size: 1} {a}");
+        assert_does_not_parse("with {hidden: \"yes\"} {a}");
+        assert_does_not_parse("with {color: \"#f00\"} {a");
+    }
+
+    #[test]
+    fn variable() {
+        check("a;", (s(0..1), var("a")));
+        check("_a1;", (s(0..3), var("_a1")));
+        check("x;", (s(0..1), var("x")));
+        check("w3c;", (s(0..3), var("w3c")));
+        check(
+            "x + 1;",
+            (
+                s(0..5),
+                ast::Expression::BinaryExpr {
+                    left: Box::new((s(0..1), var("x"))),
+                    operator: ast::BinaryOperator::Add,
+                    right: Box::new((s(4..5), num("1"))),
+                },
+            ),
+        );
+        // `3wc` is implicit multiplication, but names can't be coefficients
+        assert_does_not_parse("wc 3;");
+        assert_does_not_parse("x y;");
+    }
+
+    #[test]
+    fn implicit_mult() {
+        let mult =
+            |l: ast::LocatedExpression, r: ast::LocatedExpression| ast::Expression::BinaryExpr {
+                left: Box::new(l),
+                operator: ast::BinaryOperator::Multiply,
+                right: Box::new(r),
+            };
+        check(
+            "2x;",
+            (s(0..2), mult((s(0..1), num("2")), (s(1..2), var("x")))),
+        );
+        check(
+            "2(x+1);",
+            (
+                s(0..6),
+                mult(
+                    (s(0..1), num("2")),
+                    (
+                        s(1..6),
+                        ast::Expression::BinaryExpr {
+                            left: Box::new((s(2..3), var("x"))),
+                            operator: ast::BinaryOperator::Add,
+                            right: Box::new((s(4..5), num("1"))),
+                        },
+                    ),
+                ),
+            ),
+        );
+        // binds tighter than `^` on the right and `-` on the left
+        check(
+            "-2x^2;",
+            (
+                s(0..5),
+                ast::Expression::UnaryExpr {
+                    val: Box::new((
+                        s(1..5),
+                        mult(
+                            (s(1..2), num("2")),
+                            (
+                                s(2..5),
+                                ast::Expression::BinaryExpr {
+                                    left: Box::new((s(2..3), var("x"))),
+                                    operator: ast::BinaryOperator::Exponent,
+                                    right: Box::new((s(4..5), num("2"))),
+                                },
+                            ),
+                        ),
+                    )),
+                    operator: ast::UnaryOperator::Negate,
+                },
+            ),
+        );
+        check(
+            "(a)(b);",
+            (s(0..6), mult((s(0..3), var("a")), (s(3..6), var("b")))),
+        );
+        // a name followed by parens is still a call
+        check(
+            "2f(x);",
+            (
+                s(0..5),
+                mult(
+                    (s(0..1), num("2")),
+                    (
+                        s(1..5),
+                        ast::Expression::Call {
+                            func: ast::Function::Normal {
+                                name: "f".to_string(),
+                            },
+                            args: vec![(s(3..4), var("x"))],
+                        },
+                    ),
+                ),
+            ),
+        );
+        // `2 - x` is still subtraction
+        check(
+            "2 -x;",
+            (
+                s(0..4),
+                ast::Expression::BinaryExpr {
+                    left: Box::new((s(0..1), num("2"))),
+                    operator: ast::BinaryOperator::Subtract,
+                    right: Box::new((s(3..4), var("x"))),
+                },
+            ),
+        );
+    }
+
+    #[test]
+    fn call() {
+        assert_parses("a() = 1;");
+        check(
+            "_a1(1+2, 3*4);",
+            (
+                s(0..13),
+                ast::Expression::Call {
+                    func: ast::Function::Normal {
+                        name: "_a1".to_string(),
+                    },
+                    args: vec![
+                        (
+                            s(4..7),
+                            ast::Expression::BinaryExpr {
+                                left: Box::new((s(4..5), num("1"))),
+                                operator: ast::BinaryOperator::Add,
+                                right: Box::new((s(6..7), num("2"))),
+                            },
+                        ),
+                        (
+                            s(9..12),
+                            ast::Expression::BinaryExpr {
+                                left: Box::new((s(9..10), num("3"))),
+                                operator: ast::BinaryOperator::Multiply,
+                                right: Box::new((s(11..12), num("4"))),
+                            },
+                        ),
+                    ],
+                },
+            ),
+        );
+    }
+
+    #[test]
+    fn map_call() {
+        check(
+            "sin@([1]);",
+            (
+                s(0..9),
+                ast::Expression::Call {
+                    func: ast::Function::Normal {
+                        name: "sin".to_string(),
+                    },
+                    args: vec![(
+                        s(5..8),
+                        ast::Expression::Map(Box::new((
+                            s(5..8),
+                            ast::Expression::List(vec![(s(6..7), num("1"))]),
+                        ))),
+                    )],
+                },
+            ),
+        );
+        // only one list can be mapped over
+        assert_does_not_parse("f@(a, b);");
+        assert_does_not_parse("f@;");
+    }
+
+    #[test]
+    fn log_base() {
+        check(
+            "log_2(8);",
+            (
+                s(0..8),
+                ast::Expression::Call {
+                    func: ast::Function::Log {
+                        base: "2".to_string(),
+                    },
+                    args: vec![(s(6..7), num("8"))],
+                },
+            ),
+        );
+        // only a number can be a base
+        check(
+            "log_b(8);",
+            (
+                s(0..8),
+                ast::Expression::Call {
+                    func: ast::Function::Normal {
+                        name: "log_b".to_string(),
+                    },
+                    args: vec![(s(6..7), num("8"))],
+                },
+            ),
+        );
+        check(
+            "log(8);",
+            (
+                s(0..6),
+                ast::Expression::Call {
+                    func: ast::Function::Normal {
+                        name: "log".to_string(),
+                    },
+                    args: vec![(s(4..5), num("8"))],
+                },
+            ),
+        );
+    }
+
+    #[test]
+    fn type_annotations() {
+        assert_parses("a ( x , y : num , z : list) = 1;");
+        assert_does_not_parse("a ( x : str ) = 0;");
+    }
+
+    #[test]
+    fn list() {
+        check(
+            "[ 1 , 2 ];",
+            (
+                s(0..9),
+                ast::Expression::List(vec![(s(2..3), num("1")), (s(6..7), num("2"))]),
+            ),
+        );
+        check(
+            "[ a( 1 ) ];",
+            (
+                s(0..10),
+                ast::Expression::List(vec![(
+                    s(2..8),
+                    ast::Expression::Call {
+                        func: ast::Function::Normal {
+                            name: "a".to_string(),
+                        },
+                        args: vec![(s(5..6), num("1"))],
+                    },
+                )]),
+            ),
+        )
+    }
+
+    #[test]
+    fn point() {
+        check(
+            "(1, a);",
+            (
+                s(0..6),
+                ast::Expression::Point {
+                    x: Box::new((s(1..2), num("1"))),
+                    y: Box::new((s(4..5), var("a"))),
+                },
+            ),
+        );
+        check(
+            "[(1, 2)];",
+            (
+                s(0..8),
+                ast::Expression::List(vec![(
+                    s(1..7),
+                    ast::Expression::Point {
+                        x: Box::new((s(2..3), num("1"))),
+                        y: Box::new((s(5..6), num("2"))),
+                    },
+                )]),
+            ),
+        );
+        // a parenthesized expression is not a point
+        check("(1);", (s(0..3), num("1")));
+        assert_does_not_parse("(1, 2, 3);");
+    }
+
+    #[test]
+    fn newline_separated() {
+        check_result(
+            "a\n1 + 2",
+            (
+                Some(vec![
+                    (s(0..1), ast::Statement::Expression(var("a"))),
+                    (
+                        s(2..7),
+                        ast::Statement::Expression(ast::Expression::BinaryExpr {
+                            left: Box::new((s(2..3), num("1"))),
+                            operator: ast::BinaryOperator::Add,
+                            right: Box::new((s(6..7), num("2"))),
+                        }),
+                    ),
+                ]),
+                LexParseErrors::new(),
+            ),
+        );
+        // blank lines and trailing separators
+        assert_eq!(eval("\n\na = 1;\n\nb = 2\n").0.unwrap().len(), 2);
+        assert_eq!(eval("a;\nb;\n").0.unwrap().len(), 2);
+    }
+
+    #[test]
+    fn separators_interchangeable() {
+        let semi = eval("a=1;b=2");
+        assert_eq!(semi.1, LexParseErrors::new());
+        assert_eq!(semi.0.as_ref().unwrap().len(), 2);
+        assert_eq!(eval("a=1\nb=2"), semi);
+        assert_eq!(eval("a=1;\nb=2").0.unwrap().len(), 2);
+        assert_eq!(eval("a=1;;\n\n;b=2;;").0.unwrap().len(), 2);
+        assert_parses("a=1;;\n\n;b=2;;");
+        assert_parses("");
+    }
+
+    #[test]
+    fn multiline_expression() {
+        assert_eq!(eval("f(x) = x +\n  1\n").0.unwrap().len(), 1);
+        assert_eq!(eval("a = [\n  1,\n  2\n]\n").0.unwrap().len(), 1);
+        assert_eq!(
+            eval("a = where\n  b > 1 -> 2,\n  else 3\n")
+                .0
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn recover_statement() {
+        let f = (
+            s(9..15),
+            ast::Statement::FuncDef(
+                ast::FunctionDefinition {
+                    name: "f".to_string(),
+                    args: vec![(s(11..12), "x".to_string(), types::ValType::Number)],
+                    ret_annotation: None,
+                    inline: false,
+                },
+                (s(14..15), var("x")),
+            ),
+        );
+        for input in ["1 + ) 2;\nf(x)=x", "a = = 1;\nf(x)=x", "a b c 1;\nf(x)=x"] {
+            let (ast, errs) = eval(input);
+            assert_ne!(errs, LexParseErrors::new());
+            let ast = ast.unwrap();
+            assert_eq!(ast.len(), 2);
+            assert_eq!(ast[0].1, ast::Statement::Expression(ast::Expression::Error));
+            assert_eq!(ast[1], f);
+        }
+    }
+
+    #[test]
+    fn comments() {
+        let sum = |o: usize| {
+            (
+                s(o..o + 5),
+                ast::Expression::BinaryExpr {
+                    left: Box::new((s(o..o + 1), num("1"))),
+                    operator: ast::BinaryOperator::Add,
+                    right: Box::new((s(o + 4..o + 5), num("2"))),
+                },
+            )
+        };
+        check("1 + 2;", sum(0));
+        check("// one\n1 + 2;", sum(7));
+        check("/* one */ 1 + 2;", sum(10));
+        check("/* one\n two */1 + 2 // three", sum(14));
+        check(
+            "1 /* plus */ + 2;",
+            (
+                s(0..16),
+                ast::Expression::BinaryExpr {
+                    left: Box::new((s(0..1), num("1"))),
+                    operator: ast::BinaryOperator::Add,
+                    right: Box::new((s(15..16), num("2"))),
+                },
+            ),
+        );
+        assert_eq!(eval("a = 1 // one\nb = 2").0.unwrap().len(), 2);
+        assert_does_not_parse("/* unterminated");
+    }
+
+    #[test]
+    fn slice() {
+        check(
+            "L[2...5];",
+            (
+                s(0..8),
+                ast::Expression::Index {
+                    val: Box::new((s(0..1), var("L"))),
+                    ind: Box::new((
+                        s(2..7),
+                        ast::Expression::Range {
+                            first: Box::new((s(2..3), num("2"))),
+                            second: None,
+                            end: Box::new((s(6..7), num("5"))),
+                        },
+                    )),
+                },
+            ),
+        );
+        check(
+            "L[3];",
+            (
+                s(0..4),
+                ast::Expression::Index {
+                    val: Box::new((s(0..1), var("L"))),
+                    ind: Box::new((s(2..3), num("3"))),
+                },
+            ),
+        );
+        assert_parses("L[1,...,n];");
+        // only the list is sliced
+        check(
+            "1 + L[1...3];",
+            (
+                s(0..12),
+                ast::Expression::BinaryExpr {
+                    left: Box::new((s(0..1), num("1"))),
+                    operator: ast::BinaryOperator::Add,
+                    right: Box::new((
+                        s(4..12),
+                        ast::Expression::Index {
+                            val: Box::new((s(4..5), var("L"))),
+                            ind: Box::new((
+                                s(6..11),
+                                ast::Expression::Range {
+                                    first: Box::new((s(6..7), num("1"))),
+                                    second: None,
+                                    end: Box::new((s(10..11), num("3"))),
+                                },
+                            )),
+                        },
+                    )),
+                },
+            ),
+        );
+        check(
+            "a[b];",
+            (
+                s(0..4),
+                ast::Expression::Index {
+                    val: Box::new((s(0..1), var("a"))),
+                    ind: Box::new((s(2..3), var("b"))),
+                },
+            ),
+        );
+        check(
+            "a[[1, 2]];",
+            (
+                s(0..9),
+                ast::Expression::Index {
+                    val: Box::new((s(0..1), var("a"))),
+                    ind: Box::new((
+                        s(2..8),
+                        ast::Expression::List(vec![(s(3..4), num("1")), (s(6..7), num("2"))]),
+                    )),
                 },
             ),
         );
     }
 
     #[test]
-    fn type_annotations() {
-        assert_parses("a ( x , y : num , z : list) = 1;");
-        assert_does_not_parse("a ( x : str ) = 0;");
+    fn index_precedence() {
+        let l1 = |o: usize| {
+            (
+                s(o..o + 4),
+                ast::Expression::Index {
+                    val: Box::new((s(o..o + 1), var("L"))),
+                    ind: Box::new((s(o + 2..o + 3), num("1"))),
+                },
+            )
+        };
+        // indexing is postfix, so it binds tighter than any operator around it
+        check(
+            "2 + L[1];",
+            (
+                s(0..8),
+                ast::Expression::BinaryExpr {
+                    left: Box::new((s(0..1), num("2"))),
+                    operator: ast::BinaryOperator::Add,
+                    right: Box::new(l1(4)),
+                },
+            ),
+        );
+        check(
+            "L[1] * 2;",
+            (
+                s(0..8),
+                ast::Expression::BinaryExpr {
+                    left: Box::new(l1(0)),
+                    operator: ast::BinaryOperator::Multiply,
+                    right: Box::new((s(7..8), num("2"))),
+                },
+            ),
+        );
+        check(
+            "-L[1];",
+            (
+                s(0..5),
+                ast::Expression::UnaryExpr {
+                    val: Box::new(l1(1)),
+                    operator: ast::UnaryOperator::Negate,
+                },
+            ),
+        );
+        check(
+            "L[1]^2;",
+            (
+                s(0..6),
+                ast::Expression::BinaryExpr {
+                    left: Box::new(l1(0)),
+                    operator: ast::BinaryOperator::Exponent,
+                    right: Box::new((s(5..6), num("2"))),
+                },
+            ),
+        );
+        // a group is indexed as a whole
+        assert_parses("(1 + L)[2];");
+        assert_parses("L[1][2];");
+        assert_parses("f(x)[1]!;");
     }
 
     #[test]
-    fn list() {
+    fn qualified_variable() {
+        check("a;", (s(0..1), var("a")));
         check(
-            "[ 1 , 2 ];",
+            "foo.bar;",
             (
-                s(0..9),
-                ast::Expression::List(vec![(s(2..3), num("1")), (s(6..7), num("2"))]),
+                s(0..7),
+                ast::Expression::FullyQualifiedVariable {
+                    path: vec!["foo".to_string()],
+                    item: "bar".to_string(),
+                },
             ),
         );
         check(
-            "[ a( 1 ) ];",
+            "foo.bar.baz;",
             (
-                s(0..10),
-                ast::Expression::List(vec![(
-                    s(2..8),
-                    ast::Expression::Call {
-                        func: ast::Function::Normal {
-                            name: "a".to_string(),
-                        },
-                        args: vec![(s(5..6), num("1"))],
-                    },
-                )]),
+                s(0..11),
+                ast::Expression::FullyQualifiedVariable {
+                    path: vec!["foo".to_string(), "bar".to_string()],
+                    item: "baz".to_string(),
+                },
             ),
-        )
+        );
+        assert_does_not_parse("foo.;");
     }
 
     #[test]
@@ -735,18 +2298,28 @@ mod tests {
                     first: Box::new((
                         s(6..16),
                         ast::Branch {
-                            cond_left: (s(6..7), var("a")),
-                            cond: types::CompareOperator::GreaterThan,
-                            cond_right: (s(10..11), num("1")),
+                            cond: (
+                                s(6..11),
+                                ast::Expression::Comparison {
+                                    left: Box::new((s(6..7), var("a"))),
+                                    op: types::CompareOperator::GreaterThan,
+                                    right: Box::new((s(10..11), num("1"))),
+                                },
+                            ),
                             val: (s(15..16), var("b")),
                         },
                     )),
                     rest: vec![(
                         s(19..29),
                         ast::Branch {
-                            cond_left: (s(19..20), var("c")),
-                            cond: types::CompareOperator::LessThan,
-                            cond_right: (s(23..24), num("2")),
+                            cond: (
+                                s(19..24),
+                                ast::Expression::Comparison {
+                                    left: Box::new((s(19..20), var("c"))),
+                                    op: types::CompareOperator::LessThan,
+                                    right: Box::new((s(23..24), num("2"))),
+                                },
+                            ),
                             val: (s(28..29), var("d")),
                         },
                     )],
@@ -756,6 +2329,112 @@ mod tests {
         );
     }
 
+    #[test]
+    fn piecewise_single() {
+        check(
+            "where a >= 1 -> b , else c;",
+            (
+                s(0..26),
+                ast::Expression::Piecewise {
+                    first: Box::new((
+                        s(6..17),
+                        ast::Branch {
+                            cond: (
+                                s(6..12),
+                                ast::Expression::Comparison {
+                                    left: Box::new((s(6..7), var("a"))),
+                                    op: types::CompareOperator::GreaterThanEqual,
+                                    right: Box::new((s(11..12), num("1"))),
+                                },
+                            ),
+                            val: (s(16..17), var("b")),
+                        },
+                    )),
+                    rest: vec![],
+                    default: Box::new((s(25..26), var("c"))),
+                },
+            ),
+        );
+    }
+
+    #[test]
+    fn piecewise_not_equal() {
+        check(
+            "where a != 1 -> b , else c;",
+            (
+                s(0..26),
+                ast::Expression::Piecewise {
+                    first: Box::new((
+                        s(6..17),
+                        ast::Branch {
+                            cond: (
+                                s(6..12),
+                                ast::Expression::Comparison {
+                                    left: Box::new((s(6..7), var("a"))),
+                                    op: types::CompareOperator::NotEqual,
+                                    right: Box::new((s(11..12), num("1"))),
+                                },
+                            ),
+                            val: (s(16..17), var("b")),
+                        },
+                    )),
+                    rest: vec![],
+                    default: Box::new((s(25..26), var("c"))),
+                },
+            ),
+        );
+        // `!` is still factorial
+        assert_parses("a = 3!;");
+    }
+
+    #[test]
+    fn piecewise_double_inequality() {
+        check(
+            "where 0 < x <= 1 -> x, else 0;",
+            (
+                s(0..29),
+                ast::Expression::Piecewise {
+                    first: Box::new((
+                        s(6..21),
+                        ast::Branch {
+                            cond: (
+                                s(6..16),
+                                ast::Expression::DoubleComparison {
+                                    left: Box::new((s(6..7), num("0"))),
+                                    left_op: types::CompareOperator::LessThan,
+                                    middle: Box::new((s(10..11), var("x"))),
+                                    right_op: types::CompareOperator::LessThanEqual,
+                                    right: Box::new((s(15..16), num("1"))),
+                                },
+                            ),
+                            val: (s(20..21), var("x")),
+                        },
+                    )),
+                    rest: vec![],
+                    default: Box::new((s(28..29), num("0"))),
+                },
+            ),
+        );
+        assert_parses("where 2 > x >= 1 -> x, else 0;");
+        let messages = |src: &str| {
+            lex_and_parse(0, src.to_string())
+                .1
+                .messages()
+                .into_iter()
+                .map(|(span, msg)| (span.range, msg))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            messages("where 1 < x > 2 -> x, else 0"),
+            vec![(
+                6..15,
+                "A double inequality must use `<` and `<=` or `>` and `>=`".to_string()
+            )]
+        );
+        assert_does_not_parse("where 1 < x = 2 -> x, else 0;");
+        assert_does_not_parse("where 1 < x < 2 < 3 -> x, else 0;");
+    }
+
     #[test]
     fn funcdef() {
         check_stmt(
@@ -767,7 +2446,11 @@ mod tests {
                         name: "func".to_string(),
                         args: vec![
                             (s(6..14), "xy".to_string(), types::ValType::Number),
-                            (s(17..26), "yz".to_string(), types::ValType::List),
+                            (
+                                s(17..26),
+                                "yz".to_string(),
+                                types::ValType::List(types::ElemType::Number),
+                            ),
                         ],
                         ret_annotation: None,
                         inline: false,
@@ -778,6 +2461,107 @@ mod tests {
         );
     }
 
+    #[test]
+    fn funcdef_unannotated() {
+        check_stmt(
+            "f(a,b)=1;",
+            (
+                s(0..8),
+                ast::Statement::FuncDef(
+                    ast::FunctionDefinition {
+                        name: "f".to_string(),
+                        args: vec![
+                            (s(2..3), "a".to_string(), types::ValType::Number),
+                            (s(4..5), "b".to_string(), types::ValType::Number),
+                        ],
+                        ret_annotation: None,
+                        inline: false,
+                    },
+                    (s(7..8), num("1")),
+                ),
+            ),
+        );
+    }
+
+    #[test]
+    fn funcdef_ret_annotation() {
+        check_stmt(
+            "f(a: Number, b: List): Number = 1;",
+            (
+                s(0..33),
+                ast::Statement::FuncDef(
+                    ast::FunctionDefinition {
+                        name: "f".to_string(),
+                        args: vec![
+                            (s(2..11), "a".to_string(), types::ValType::Number),
+                            (
+                                s(13..20),
+                                "b".to_string(),
+                                types::ValType::List(types::ElemType::Number),
+                            ),
+                        ],
+                        ret_annotation: Some(types::ValType::Number),
+                        inline: false,
+                    },
+                    (s(32..33), num("1")),
+                ),
+            ),
+        );
+        assert_does_not_parse("f(a): str = 1;");
+    }
+
+    #[test]
+    fn bool_function() {
+        check_stmt(
+            "f(x: num): bool = x > 1;",
+            (
+                s(0..23),
+                ast::Statement::FuncDef(
+                    ast::FunctionDefinition {
+                        name: "f".to_string(),
+                        args: vec![(s(2..8), "x".to_string(), types::ValType::Number)],
+                        ret_annotation: Some(types::ValType::Bool),
+                        inline: false,
+                    },
+                    (
+                        s(18..23),
+                        ast::Expression::Comparison {
+                            left: Box::new((s(18..19), var("x"))),
+                            op: types::CompareOperator::GreaterThan,
+                            right: Box::new((s(22..23), num("1"))),
+                        },
+                    ),
+                ),
+            ),
+        );
+        // any expression can be a condition
+        check(
+            "where f(2) -> 1 , else 0;",
+            (
+                s(0..24),
+                ast::Expression::Piecewise {
+                    first: Box::new((
+                        s(6..15),
+                        ast::Branch {
+                            cond: (
+                                s(6..10),
+                                ast::Expression::Call {
+                                    func: ast::Function::Normal {
+                                        name: "f".to_string(),
+                                    },
+                                    args: vec![(s(8..9), num("2"))],
+                                },
+                            ),
+                            val: (s(14..15), num("1")),
+                        },
+                    )),
+                    rest: vec![],
+                    default: Box::new((s(23..24), num("0"))),
+                },
+            ),
+        );
+    }
+
     #[test]
     fn inline_funcdef() {
         check_stmt(
@@ -789,7 +2573,11 @@ mod tests {
                         name: "func".to_string(),
                         args: vec![
                             (s(13..21), "xy".to_string(), types::ValType::Number),
-                            (s(24..33), "yz".to_string(), types::ValType::List),
+                            (
+                                s(24..33),
+                                "yz".to_string(),
+                                types::ValType::List(types::ElemType::Number),
+                            ),
                         ],
                         ret_annotation: None,
                         inline: true,
@@ -821,6 +2609,94 @@ mod tests {
         assert_parses("sin@([0])[1]");
     }
 
+    #[test]
+    fn fuzz_safe() {
+        // xorshift, to avoid depending on a rng crate
+        let mut state: u32 = 2463534242;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state
+        };
+        let fragments: [&[u8]; 16] = [
+            b"1", b"x", b"(", b")", b"[", b"]", b",", b"...", b"+", b"-", b"@", b";", b"=",
+            b"where", b"->", b"\xff",
+        ];
+        for _ in 0..500 {
+            let len = next() % 32;
+            let bytes: Vec<u8> = (0..len)
+                .flat_map(|_| {
+                    let n = next();
+                    if n % 4 == 0 {
+                        vec![(n >> 8) as u8]
+                    } else {
+                        fragments[(n >> 8) as usize % fragments.len()].to_vec()
+                    }
+                })
+                .collect();
+            let _ = parse_fuzz_safe(&bytes);
+        }
+        assert_eq!(parse_fuzz_safe(b"1 + 2;").map(|ast| ast.len()), Ok(1));
+    }
+
+    #[test]
+    fn nesting_limit() {
+        // the trees are deep too, so drop them on a stack that fits them
+        with_large_stack(|| {
+            let nested = |open: &str, close: &str, n: usize| {
+                format!("{}1{};", open.repeat(n), close.repeat(n))
+            };
+            for (open, close) in [("(", ")"), ("[", "]"), ("sin(", ")"), ("1^", "")] {
+                let (ast, errs) = lex_and_parse(FILENO, nested(open, close, MAX_NESTING_DEPTH));
+                assert!(errs.is_empty(), "{:?}", errs);
+                assert!(ast.is_some());
+
+                let (ast, errs) = lex_and_parse(FILENO, nested(open, close, MAX_NESTING_DEPTH + 1));
+                assert_eq!(ast, None);
+                assert_eq!(errs.parse_errors.len(), 1);
+            }
+            // the limit doesn't depend on how long the input is, only on how deep it goes
+            let (ast, errs) = lex_and_parse(FILENO, "(1+2);".repeat(1000));
+            assert!(errs.is_empty(), "{:?}", errs);
+            assert_eq!(ast.map(|ast| ast.len()), Some(1000));
+            let (ast, errs) = lex_and_parse(FILENO, "x^2 + ".repeat(5000) + "1");
+            assert!(errs.is_empty(), "{:?}", errs);
+            assert!(ast.is_some());
+        })
+    }
+
+    #[test]
+    fn fuzz_long_chains() {
+        with_large_stack(|| {
+            let n = 5_000;
+            // far deeper than the limit, these used to overflow the stack
+            let nested =
+                |open: &str, close: &str| format!("{}1{}", open.repeat(n), close.repeat(n));
+            for deep in [
+                nested("(", ")"),
+                nested("[", "]"),
+                nested("sin(", ")"),
+                nested("where 1 -> ", ", else 1"),
+                format!("{}1", "1^".repeat(n)),
+                format!("{}1", "2^-".repeat(n)),
+            ] {
+                assert!(parse_fuzz_safe(deep.as_bytes()).is_err(), "{}", &deep[..12]);
+            }
+            // as long but flat, these are folded in a loop and aren't limited
+            for flat in [
+                format!("{}1", "-".repeat(n)),
+                format!("{}1", "+".repeat(n)),
+                format!("{}1", "1+".repeat(n)),
+                format!("1{}", "!".repeat(n)),
+                format!("{}x", "2x*".repeat(n)),
+                "(1)".repeat(n),
+            ] {
+                assert!(parse_fuzz_safe(flat.as_bytes()).is_ok(), "{}", &flat[..12]);
+            }
+        })
+    }
+
     #[test]
     fn range() {
         check(
@@ -845,8 +2721,186 @@ mod tests {
                 },
             ),
         );
+        check(
+            "[1...10]",
+            (
+                s(0..8),
+                ast::Expression::Range {
+                    first: Box::new((s(1..2), num("1"))),
+                    second: None,
+                    end: Box::new((s(5..7), num("10"))),
+                },
+            ),
+        );
+        check(
+            "[1, 3...11]",
+            (
+                s(0..11),
+                ast::Expression::Range {
+                    first: Box::new((s(1..2), num("1"))),
+                    second: Some(Box::new((s(4..5), num("3")))),
+                    end: Box::new((s(8..10), num("11"))),
+                },
+            ),
+        );
         assert_does_not_parse("[1,2,3...,4]");
         assert_does_not_parse("[1,...,2,3");
         assert_does_not_parse("[1,...,2,...,3]");
+        // missing end
+        assert_does_not_parse("[1...]");
+        assert_does_not_parse("[1, 3...]");
+    }
+
+    #[test]
+    fn display_round_trip() {
+        let display = |src: &str| match lex_and_parse(0, src.to_string()).0.unwrap().pop() {
+            Some((_, ast::Statement::Expression(e))) => e.to_string(),
+            s => panic!("Expected an expression, got {:?}", s),
+        };
+        for (src, expected) in [
+            ("2 * (3 + 4)", "2 * (3 + 4)"),
+            ("(2 * 3) + 4", "2 * 3 + 4"),
+            ("1 - (2 - 3)", "1 - (2 - 3)"),
+            ("(1 - 2) - 3", "1 - 2 - 3"),
+            ("2^(3^4)", "2^3^4"),
+            ("(2^3)^4", "(2^3)^4"),
+            ("-(x^2)", "-x^2"),
+            ("(-x)^2", "(-x)^2"),
+            ("2x", "2 * x"),
+            ("(n!)!", "n!!"),
+            ("sin(a, [1, 2]) % b", "sin(a, [1, 2]) % b"),
+            ("L[(1 + 2)]", "L[1 + 2]"),
+            ("[1...10]", "[1...10]"),
+            ("(1, -2)", "(1, -2)"),
+            (
+                "where a < b <= 3 -> 1, else 0",
+                "where a < b <= 3 -> 1, else 0",
+            ),
+            ("[n^2 for n = [1, 2]]", "[n^2 for n = [1, 2]]"),
+        ] {
+            assert_eq!(display(src), expected, "displaying {}", src);
+            // displaying the result again is stable
+            assert_eq!(display(expected), expected);
+        }
+    }
+
+    #[test]
+    fn comprehension() {
+        check(
+            "[n * 2 for n = [1...3]]",
+            (
+                s(0..23),
+                ast::Expression::Comprehension {
+                    body: Box::new((
+                        s(1..6),
+                        ast::Expression::BinaryExpr {
+                            left: Box::new((s(1..2), var("n"))),
+                            operator: ast::BinaryOperator::Multiply,
+                            right: Box::new((s(5..6), num("2"))),
+                        },
+                    )),
+                    var: (s(11..12), "n".to_string()),
+                    list: Box::new((
+                        s(15..22),
+                        ast::Expression::Range {
+                            first: Box::new((s(16..17), num("1"))),
+                            second: None,
+                            end: Box::new((s(20..21), num("3"))),
+                        },
+                    )),
+                },
+            ),
+        );
+        // only a single generator is supported
+        assert_does_not_parse("[n + m for n = [1...3], m = [1...3]]");
+        assert_does_not_parse("[n for 1 = [1...3]]");
+        assert_does_not_parse("[n for n]");
+    }
+
+    #[test]
+    fn raw_latex() {
+        check(
+            "raw<num>(\"\\pi\") + 1;",
+            (
+                s(0..19),
+                ast::Expression::BinaryExpr {
+                    left: Box::new((
+                        s(0..15),
+                        ast::Expression::RawLatex(types::ValType::Number, "\\pi".to_string()),
+                    )),
+                    operator: ast::BinaryOperator::Add,
+                    right: Box::new((s(18..19), num("1"))),
+                },
+            ),
+        );
+        check(
+            "raw<List>(\"[1,2]\");",
+            (
+                s(0..18),
+                ast::Expression::RawLatex(
+                    types::ValType::List(types::ElemType::Number),
+                    "[1,2]".to_string(),
+                ),
+            ),
+        );
+        // `raw` is still a variable name
+        assert_parses("raw < 1;");
+        assert_does_not_parse("raw<text>(\"a\");");
+    }
+
+    #[test]
+    fn error_messages() {
+        let messages = |src: &str| {
+            lex_and_parse(0, src.to_string())
+                .1
+                .messages()
+                .into_iter()
+                .map(|(span, msg)| (span.range, msg))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            messages("a = (1 + 2"),
+            vec![(
+                10..11,
+                "expected `!=`, `!`, `%`, `)`, `*`, `+`, `,`, `-`, `/`, `<=`, `<`, `=`, `>=`, `>`, \
+                 `[` or `^` but found end of input"
+                    .to_string()
+            )]
+        );
+        assert_eq!(
+            messages("a = 1 + )"),
+            vec![(
+                8..9,
+                "expected `!=`, `(`, `+`, `-`, `<=`, `<`, `=`, `>=`, `>`, `@` or `[` but found `)`"
+                    .to_string()
+            )]
+        );
+        let lex = messages("a = $");
+        assert_eq!(lex.len(), 1);
+        // newlines are escaped
+        assert!(lex[0].1.contains("`\\n`"));
+        assert!(lex[0].1.ends_with("but found `$`"));
+        assert_eq!(messages("a = 1"), vec![]);
+    }
+
+    #[test]
+    fn char_spans() {
+        let errors = |src: &str| {
+            lex_and_parse(0, src.to_string())
+                .1
+                .messages()
+                .into_iter()
+                .map(|(span, _)| span.range)
+                .collect::<Vec<_>>()
+        };
+        // the emoji is one char but four bytes and two UTF-16 units
+        check_stmt(
+            "/* \u{1F600} */ a;",
+            (s(8..9), ast::Statement::Expression(var("a"))),
+        );
+        assert_eq!(errors("/* \u{1F600} */ a = )"), vec![12..13]);
+        // the end of input is after the last char rather than the last byte or token
+        assert_eq!(errors("/* \u{1F600} */ a = (1"), vec![14..15]);
+        assert_eq!(errors("a = \"\u{1F600}\u{1F600}"), vec![7..8]);
     }
 }
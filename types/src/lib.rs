@@ -1,15 +1,41 @@
 pub type ArgCount = usize;
 
+// What a list holds. Desmos has no nested lists, so a list can't hold lists.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ElemType {
+    Number,
+    Point,
+}
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum ValType {
     Number,
-    List,
+    List(ElemType),
+    Point,
+    Bool,
+    // Only made by the polygon builtin
+    Polygon,
+}
+
+impl From<ElemType> for ValType {
+    fn from(e: ElemType) -> Self {
+        match e {
+            ElemType::Number => ValType::Number,
+            ElemType::Point => ValType::Point,
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Args<'a> {
     Static(&'a [ValType]),
-    Variadic,
+    // Only the first `required` args must be passed
+    Optional {
+        args: &'a [ValType],
+        required: ArgCount,
+    },
+    // Any number of the element type, or one list of it
+    Variadic(ElemType),
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -25,6 +51,24 @@ pub enum CompareOperator {
     LessThan,
     GreaterThanEqual,
     LessThanEqual,
+    NotEqual,
+}
+
+// Graph styling set by a `with` block, unset fields are left to Desmos
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Style {
+    pub color: Option<String>,
+    pub hidden: Option<bool>,
+}
+
+impl Style {
+    // Fill in fields this style doesn't set from an enclosing one
+    pub fn inherit(self, outer: &Style) -> Style {
+        Style {
+            color: self.color.or_else(|| outer.color.clone()),
+            hidden: self.hidden.or(outer.hidden),
+        }
+    }
 }
 
 pub type FileID = usize;